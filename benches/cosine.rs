@@ -0,0 +1,83 @@
+//! Scoring a seed against a large local store: one cosine similarity at a time with a
+//! plain scalar loop, against `cosine_similarity_batch`.
+//!
+//! ```text
+//! cargo bench --bench cosine
+//! ```
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use spotify_search::spotify::{cosine_similarity_batch, MockSpotifyApi};
+
+const DIMS: usize = 12;
+const ROUNDS: u32 = 20;
+
+/// The kernel `cosine_similarity_batch` replaced.
+fn scalar(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// Deterministic embeddings spread around the mock's mid-range features.
+fn embeddings(n: usize) -> Vec<Vec<f32>> {
+    let base = MockSpotifyApi::audio_features("seed").to_embedding();
+    let mut state = 0x2545_f491_u32;
+    (0..n)
+        .map(|_| {
+            base.iter()
+                .map(|x| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    (x + (state as f32 / u32::MAX as f32 - 0.5) * 0.5).clamp(0.0, 1.0)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let seed = MockSpotifyApi::audio_features("seed").to_embedding();
+    assert_eq!(seed.len(), DIMS);
+    for n in [1_000, 10_000, 100_000] {
+        let store = embeddings(n);
+        let candidates: Vec<&[f32]> = store.iter().map(Vec::as_slice).collect();
+
+        let expected: Vec<f32> = candidates.iter().map(|c| scalar(&seed, c)).collect();
+        let batched = cosine_similarity_batch(&seed, &candidates);
+        let max_error = expected.iter().zip(&batched).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+
+        let scalar_time = time(|| {
+            black_box(candidates.iter().map(|c| scalar(black_box(&seed), c)).collect::<Vec<_>>());
+        });
+        let batch_time = time(|| {
+            black_box(cosine_similarity_batch(black_box(&seed), &candidates));
+        });
+        println!(
+            "{:>7} x {}-dim  scalar {:>10.1?}  batch {:>10.1?}  {:.2}x  (max |diff| {:.1e})",
+            n,
+            DIMS,
+            scalar_time,
+            batch_time,
+            scalar_time.as_secs_f64() / batch_time.as_secs_f64(),
+            max_error
+        );
+    }
+}
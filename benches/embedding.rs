@@ -0,0 +1,47 @@
+//! Embedding computation: audio features to the `v1` embedding, the `v3` genre
+//! component, and scoring a seed against a page of candidates.
+//!
+//! ```text
+//! cargo bench --bench embedding
+//! ```
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use spotify_search::spotify::{cosine_similarity, genre_component, AudioFeatures, MockSpotifyApi};
+
+const TRACKS: usize = 50;
+
+fn features() -> Vec<AudioFeatures> {
+    (0..TRACKS)
+        .map(|i| MockSpotifyApi::audio_features(&format!("BenchTrack{:012}", i)))
+        .collect()
+}
+
+fn to_embedding(c: &mut Criterion) {
+    let features = features();
+    c.bench_function("to_embedding/1", |b| b.iter(|| black_box(&features[0]).to_embedding()));
+    c.bench_function("to_embedding/50", |b| {
+        b.iter(|| black_box(&features).iter().map(AudioFeatures::to_embedding).collect::<Vec<_>>())
+    });
+}
+
+fn genres(c: &mut Criterion) {
+    let genres = ["synthwave", "retrowave", "electronic", "indie pop", "dream pop"];
+    c.bench_function("genre_component/5", |b| b.iter(|| genre_component(black_box(genres))));
+}
+
+fn similarity(c: &mut Criterion) {
+    let embeddings: Vec<_> = features().iter().map(AudioFeatures::to_embedding).collect();
+    let seed = embeddings[0].clone();
+    c.bench_function("cosine_similarity/50", |b| {
+        b.iter_batched(
+            || embeddings.clone(),
+            |candidates| candidates.iter().map(|e| cosine_similarity(&seed, e)).collect::<Vec<_>>(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, to_embedding, genres, similarity);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+//! Allocations made mapping a 50-track search result to the HTTP response and
+//! serializing it, with and without the `metadata` map.
+//!
+//! ```text
+//! cargo bench --bench response_alloc
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use spotify_search::handlers::TrackResponse;
+use spotify_search::spotify::{MockSpotifyApi, TrackWithFeatures};
+
+const TRACKS: usize = 50;
+const ROUNDS: usize = 1_000;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn tracks() -> Vec<TrackWithFeatures> {
+    (0..TRACKS)
+        .map(|i| {
+            let id = format!("BenchTrack{:012}", i);
+            let features = MockSpotifyApi::audio_features(&id);
+            TrackWithFeatures {
+                track: MockSpotifyApi::track(&id, &format!("Bench Track {}", i)),
+                embedding: Some(features.to_embedding()),
+                audio_features: Some(features),
+            }
+        })
+        .collect()
+}
+
+/// Mean allocations and bytes allocated per response, not counting building the input.
+fn measure(metadata: bool) -> (usize, usize) {
+    let (mut allocations, mut bytes) = (0, 0);
+    for _ in 0..ROUNDS {
+        let input = tracks();
+        let (a, b) = (ALLOCATIONS.load(Ordering::Relaxed), BYTES.load(Ordering::Relaxed));
+        let response: Vec<TrackResponse> = input.into_iter().map(|t| TrackResponse::new(t, metadata)).collect();
+        let body = serde_json::to_vec(&response).expect("serialize");
+        allocations += ALLOCATIONS.load(Ordering::Relaxed) - a;
+        bytes += BYTES.load(Ordering::Relaxed) - b;
+        drop(std::hint::black_box(body));
+    }
+    (allocations / ROUNDS, bytes / ROUNDS)
+}
+
+fn main() {
+    println!("{} tracks per response, mean of {} rounds", TRACKS, ROUNDS);
+    for metadata in [false, true] {
+        let (allocations, bytes) = measure(metadata);
+        println!(
+            "include_metadata={:<5}  {:>5} allocations  {:>7} bytes",
+            metadata, allocations, bytes
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Mapping a 50-track result to responses: the HTTP `TrackResponse`s and their JSON, with
+//! and without the `metadata` map, and the gRPC messages and their encoding.
+//! `response_alloc` counts the allocations of the same HTTP mapping.
+//!
+//! ```text
+//! cargo bench --bench response_mapping
+//! ```
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use spotify_search::handlers::TrackResponse;
+use spotify_search::spotify::{MockSpotifyApi, TrackWithFeatures};
+
+const TRACKS: usize = 50;
+
+fn tracks() -> Vec<TrackWithFeatures> {
+    (0..TRACKS)
+        .map(|i| {
+            let id = format!("BenchTrack{:012}", i);
+            let features = MockSpotifyApi::audio_features(&id);
+            TrackWithFeatures {
+                track: MockSpotifyApi::track(&id, &format!("Bench Track {}", i)),
+                embedding: Some(features.to_embedding()),
+                audio_features: Some(features),
+            }
+        })
+        .collect()
+}
+
+fn http(c: &mut Criterion) {
+    for metadata in [false, true] {
+        c.bench_function(&format!("http/map/metadata={}", metadata), |b| {
+            b.iter_batched(
+                tracks,
+                |tracks| tracks.into_iter().map(|t| TrackResponse::new(t, metadata)).collect::<Vec<_>>(),
+                BatchSize::SmallInput,
+            )
+        });
+        c.bench_function(&format!("http/map_and_serialize/metadata={}", metadata), |b| {
+            b.iter_batched(
+                tracks,
+                |tracks| {
+                    let response: Vec<_> = tracks.into_iter().map(|t| TrackResponse::new(t, metadata)).collect();
+                    serde_json::to_vec(&response).expect("serialize")
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+#[cfg(feature = "grpc")]
+fn grpc(c: &mut Criterion) {
+    use std::hint::black_box;
+
+    use prost::Message;
+    use spotify_search::proto::{featured_tracks_to_proto, GetTracksWithFeaturesResponse};
+
+    let tracks = tracks();
+    c.bench_function("grpc/map", |b| {
+        b.iter(|| featured_tracks_to_proto(black_box(&tracks), true).collect::<Vec<_>>())
+    });
+    c.bench_function("grpc/map_and_encode", |b| {
+        b.iter(|| {
+            let response = GetTracksWithFeaturesResponse {
+                tracks: featured_tracks_to_proto(black_box(&tracks), true).collect(),
+                ..Default::default()
+            };
+            response.encode_to_vec()
+        })
+    });
+}
+
+#[cfg(not(feature = "grpc"))]
+fn grpc(_: &mut Criterion) {}
+
+criterion_group!(benches, http, grpc);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+//! Latency of `search_tracks_with_features` for a 50-track page against a local fake
+//! Spotify, for several search chunk sizes. A chunk size of 50, the default, is one search
+//! followed by one features call, so the default adds no Spotify requests; the smaller
+//! sizes trade extra requests for latency. The fake answers after a fixed round trip plus a cost per track,
+//! as larger pages take Spotify longer to build and send; with no per-track cost,
+//! chunking can't help, since each chunk still searches before fetching features.
+//!
+//! ```text
+//! cargo bench --bench search_pipeline
+//! ```
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use axum::extract::Query;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use spotify_search::spotify::{MockSpotifyApi, SpotifyClient, StaticToken};
+
+/// Delay before the fake Spotify answers any request.
+const ROUND_TRIP: Duration = Duration::from_millis(20);
+/// Added delay per track searched or looked up.
+const PER_TRACK: Duration = Duration::from_millis(1);
+const ROUNDS: u32 = 20;
+
+async fn search(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+    let param = |name: &str| params.get(name).and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+    let (limit, offset) = (param("limit"), param("offset"));
+    tokio::time::sleep(ROUND_TRIP + PER_TRACK * limit).await;
+    let items: Vec<_> = (offset..offset + limit)
+        .map(|i| MockSpotifyApi::track(&format!("BenchTrack{:012}", i), &format!("Bench Track {}", i)))
+        .collect();
+    Json(json!({ "tracks": { "items": items, "total": 1000, "limit": limit, "offset": offset } }))
+}
+
+async fn audio_features(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+    let ids = params.get("ids").map(String::as_str).unwrap_or_default();
+    let features: Vec<_> = ids.split(',').map(MockSpotifyApi::audio_features).collect();
+    tokio::time::sleep(ROUND_TRIP + PER_TRACK * features.len() as u32).await;
+    Json(json!({ "audio_features": features }))
+}
+
+#[tokio::main]
+async fn main() {
+    let app = Router::new()
+        .route("/v1/search", get(search))
+        .route("/v1/audio-features", get(audio_features));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    println!(
+        "50-track search with features, upstream latency {:?} + {:?} per track, mean of {} rounds",
+        ROUND_TRIP, PER_TRACK, ROUNDS
+    );
+    for (chunk_size, concurrency) in [(50, 1), (25, 4), (10, 4), (10, 5)] {
+        let spotify = SpotifyClient::builder_with_token_provider(StaticToken("bench".into()))
+            .api_base(format!("http://{}/v1", addr))
+            .search_chunk_size(chunk_size)
+            .search_concurrency(concurrency)
+            .build()
+            .expect("client");
+        // Warm the connection pool and token so only the pipeline is measured.
+        spotify.search_tracks_with_features("bench", Some(50), None).await.expect("search");
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let result = spotify.search_tracks_with_features("bench", Some(50), None).await.expect("search");
+            assert_eq!(result.tracks.len(), 50);
+        }
+        let requests = 2 * 50_u32.div_ceil(chunk_size);
+        println!(
+            "chunk size {:>2}, concurrency {}  {:>8.1?}  ({} Spotify requests)",
+            chunk_size,
+            concurrency,
+            start.elapsed() / ROUNDS,
+            requests
+        );
+    }
+}
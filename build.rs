@@ -1,17 +1,70 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Prebuilt descriptor set for `proto/spotify.proto`, used when `protoc` isn't installed.
+/// Regenerate after editing the proto:
+///
+/// ```sh
+/// protoc --include_imports --include_source_info -I proto -o proto/spotify_descriptor.bin proto/spotify.proto
+/// ```
+const VENDORED_DESCRIPTOR: &str = "proto/spotify_descriptor.bin";
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let manifest = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
-    let local = manifest.join("proto/spotify.proto");
-    let monorepo = manifest.join("../../proto/spotify.proto");
-    let (path, inc) = if local.exists() {
-        (local, manifest.join("proto"))
-    } else if monorepo.exists() {
-        (manifest.join("../../proto/spotify.proto").canonicalize()?, manifest.join("../../proto").canonicalize()?)
+    // The proto is only needed for the gRPC server and client, and Kafka events.
+    let server = std::env::var_os("CARGO_FEATURE_GRPC").is_some();
+    let client = std::env::var_os("CARGO_FEATURE_GRPC_CLIENT").is_some();
+    let events = std::env::var_os("CARGO_FEATURE_KAFKA").is_some();
+    if server || client || events {
+        compile_proto(server, client)?;
+    }
+    // Embedded by `sqlx::migrate!`.
+    if std::env::var_os("CARGO_FEATURE_SQLITE").is_some() || std::env::var_os("CARGO_FEATURE_POSTGRES").is_some() {
+        println!("cargo:rerun-if-changed=migrations");
+    }
+
+    // VERGEN_GIT_SHA, VERGEN_BUILD_TIMESTAMP, VERGEN_CARGO_FEATURES for GET /version.
+    vergen::EmitBuilder::builder()
+        .build_timestamp()
+        .git_sha(false)
+        .cargo_features()
+        .emit()?;
+    Ok(())
+}
+
+/// Generate the proto code from the vendored `proto/spotify.proto` with `protoc` when it
+/// is available, otherwise from the prebuilt descriptor set next to it.
+fn compile_proto(server: bool, client: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let proto = manifest.join("proto/spotify.proto");
+    let vendored = manifest.join(VENDORED_DESCRIPTOR);
+    let descriptor = PathBuf::from(std::env::var("OUT_DIR")?).join("spotify_descriptor.bin");
+    println!("cargo:rerun-if-changed={}", vendored.display());
+    println!("cargo:rerun-if-env-changed=PROTOC");
+
+    let builder = tonic_build::configure()
+        .file_descriptor_set_path(&descriptor)
+        .build_server(server)
+        .build_client(client);
+    if protoc_available() {
+        builder.compile(&[&proto], &[manifest.join("proto")])?;
+        if std::fs::read(&descriptor)? != std::fs::read(&vendored)? {
+            println!(
+                "cargo:warning={} is out of date with proto/spotify.proto; regenerate it (see build.rs)",
+                VENDORED_DESCRIPTOR
+            );
+        }
     } else {
-        return Err("proto/spotify.proto not found (run from monorepo root or copy proto into spotify-search)".into());
-    };
-    tonic_build::configure()
-        .build_server(true)
-        .build_client(false)
-        .compile(&[path], &[inc])?;
+        std::fs::copy(&vendored, &descriptor)?;
+        builder.skip_protoc_run().compile(&[&proto], &[manifest.join("proto")])?;
+    }
     Ok(())
 }
+
+/// `PROTOC` is set, or `protoc` is on the `PATH`.
+fn protoc_available() -> bool {
+    std::env::var_os("PROTOC").is_some()
+        || Command::new("protoc")
+            .arg("--version")
+            .output()
+            .is_ok_and(|out| out.status.success())
+}
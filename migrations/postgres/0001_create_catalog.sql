@@ -0,0 +1,16 @@
+-- Spotify track objects as returned by the API.
+CREATE TABLE IF NOT EXISTS tracks (
+    id TEXT PRIMARY KEY,
+    data JSONB NOT NULL,
+    -- Unix seconds.
+    fetched_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS audio_features (
+    track_id TEXT PRIMARY KEY,
+    -- NULL when Spotify has no audio features for the track.
+    data JSONB,
+    -- See AudioFeatures::to_embedding.
+    embedding JSONB,
+    fetched_at BIGINT NOT NULL
+);
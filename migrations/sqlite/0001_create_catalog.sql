@@ -0,0 +1,16 @@
+-- Spotify track objects as returned by the API.
+CREATE TABLE IF NOT EXISTS tracks (
+    id TEXT PRIMARY KEY,
+    data TEXT NOT NULL,
+    -- Unix seconds.
+    fetched_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS audio_features (
+    track_id TEXT PRIMARY KEY,
+    -- NULL when Spotify has no audio features for the track.
+    data TEXT,
+    -- JSON array, see AudioFeatures::to_embedding.
+    embedding TEXT,
+    fetched_at INTEGER NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Oldest-first scans for the background refresh job.
+CREATE INDEX IF NOT EXISTS tracks_fetched_at ON tracks (fetched_at);
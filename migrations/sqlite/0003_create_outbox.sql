@@ -0,0 +1,13 @@
+-- Encoded track events waiting for the outbox relay, written in the same transaction
+-- as the tracks they describe.
+CREATE TABLE IF NOT EXISTS outbox (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- Event ID (UUID), sent along so consumers can drop redeliveries.
+    id TEXT NOT NULL UNIQUE,
+    -- Message key, the track ID.
+    key TEXT NOT NULL,
+    payload BLOB NOT NULL,
+    -- Unix seconds. A relay owns the event until then.
+    claimed_until INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL
+);
@@ -0,0 +1,16 @@
+-- Audit trail of requests sent to Spotify (`AUDIT_STORAGE`).
+CREATE TABLE IF NOT EXISTS upstream_calls (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- Unix milliseconds when the request was sent.
+    at_ms INTEGER NOT NULL,
+    tenant TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    method TEXT NOT NULL,
+    -- Hex SHA-256 of the request path and query.
+    params_hash TEXT NOT NULL,
+    -- NULL when no response arrived.
+    status INTEGER,
+    latency_ms INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS upstream_calls_at_ms ON upstream_calls (at_ms);
@@ -0,0 +1,153 @@
+//! One structured access-log event per HTTP request.
+//!
+//! Handlers and the Spotify client record into a task-local [`RequestStats`] scoped by
+//! the `access_log` middleware; recording is a no-op outside a request (and always
+//! when the client is used as a library without the `server` feature). The same
+//! task-local carries the request id so error bodies can echo it.
+
+use std::cell::RefCell;
+use std::time::Duration;
+#[cfg(feature = "server")]
+use std::time::Instant;
+
+#[cfg(feature = "server")]
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+#[cfg(feature = "server")]
+use crate::state::AppState;
+
+tokio::task_local! {
+    static STATS: RefCell<RequestStats>;
+}
+
+#[derive(Debug, Default)]
+struct RequestStats {
+    request_id: Option<String>,
+    /// Tenant whose credentials served the request; the default ones when unset.
+    tenant: Option<String>,
+    /// `sub` of the caller's verified JWT.
+    subject: Option<String>,
+    /// First identity of the caller's verified TLS client certificate.
+    client_cert: Option<String>,
+    query: Option<String>,
+    results: Option<usize>,
+    /// Sum of upstream call durations (concurrent calls overlap, so this can exceed wall time).
+    upstream: Duration,
+    upstream_calls: u32,
+    token_cache_hit: Option<bool>,
+    /// Answered from the local store instead of Spotify (offline mode or outage).
+    served_from_cache: bool,
+}
+
+fn with_stats(f: impl FnOnce(&mut RequestStats)) {
+    let _ = STATS.try_with(|s| f(&mut s.borrow_mut()));
+}
+
+/// The `x-request-id` of the request being handled, if any.
+pub fn current_request_id() -> Option<String> {
+    STATS.try_with(|s| s.borrow().request_id.clone()).ok().flatten()
+}
+
+/// Record the tenant this request runs as.
+pub fn record_tenant(tenant: &str) {
+    with_stats(|s| s.tenant = Some(tenant.to_owned()));
+}
+
+/// Record the authenticated subject of this request.
+pub fn record_subject(subject: Option<&str>) {
+    with_stats(|s| s.subject = subject.map(str::to_owned));
+}
+
+/// Record the identity of the client certificate this request came with.
+pub fn record_client_cert(name: &str) {
+    with_stats(|s| s.client_cert = Some(name.to_owned()));
+}
+
+/// Record the search query for this request.
+pub fn record_query(q: &str) {
+    with_stats(|s| s.query = Some(q.to_owned()));
+}
+
+/// Record the number of results returned.
+pub fn record_results(n: usize) {
+    with_stats(|s| s.results = Some(n));
+}
+
+/// Record one upstream Spotify call.
+pub fn record_upstream(elapsed: Duration) {
+    with_stats(|s| {
+        s.upstream += elapsed;
+        s.upstream_calls += 1;
+    });
+}
+
+/// Record whether the cached token was used; a miss wins over earlier hits.
+pub fn record_token_cache(hit: bool) {
+    with_stats(|s| s.token_cache_hit = Some(s.token_cache_hit.unwrap_or(true) && hit));
+}
+
+/// Record that (part of) the response came from the local store rather than Spotify.
+pub fn record_served_from_cache() {
+    with_stats(|s| s.served_from_cache = true);
+}
+
+/// Whether [`record_served_from_cache`] was called for the current request.
+pub fn served_from_cache() -> bool {
+    STATS.try_with(|s| s.borrow().served_from_cache).unwrap_or(false)
+}
+
+/// Middleware emitting the `access_log` event after the response is produced.
+#[cfg(feature = "server")]
+pub async fn access_log(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().clone();
+    let route = req.extensions().get::<MatchedPath>().map(|p| p.as_str().to_owned());
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+
+    let stats = RequestStats {
+        request_id,
+        ..Default::default()
+    };
+    let (response, stats) = STATS
+        .scope(RefCell::new(stats), async {
+            let response = next.run(req).await;
+            (response, STATS.with(|s| s.take()))
+        })
+        .await;
+
+    let query = if state.config.load().access_log_redact_query {
+        stats.query.as_ref().map(|q| format!("[redacted len={}]", q.chars().count()))
+    } else {
+        stats.query
+    };
+    let token_cache = stats.token_cache_hit.map(|hit| if hit { "hit" } else { "miss" });
+    let source = stats.served_from_cache.then_some("cache");
+
+    tracing::info!(
+        target: "access_log",
+        method = %method,
+        route = route.as_deref(),
+        status = response.status().as_u16(),
+        tenant = stats.tenant.as_deref(),
+        subject = stats.subject.as_deref(),
+        client_cert = stats.client_cert.as_deref(),
+        latency_ms = start.elapsed().as_millis() as u64,
+        upstream_ms = stats.upstream.as_millis() as u64,
+        upstream_calls = stats.upstream_calls,
+        query = query.as_deref(),
+        results = stats.results,
+        token_cache,
+        source,
+        "access"
+    );
+
+    response
+}
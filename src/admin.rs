@@ -0,0 +1,50 @@
+//! Authentication for the `/admin` endpoints.
+//!
+//! Every admin request must send `admin.token` as `Authorization: Bearer <token>`.
+//! Without a token configured the endpoints are disabled and answer 503, so a
+//! deployment that forgot to set one doesn't expose them. The token is reloadable, so
+//! it can be set or rotated without a restart.
+
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+
+use crate::error::AppError;
+use crate::state::AppState;
+
+/// Extractor that rejects with 401 unless the request carries the admin token, and with
+/// 503 if there is none configured.
+pub struct AdminAuth;
+
+#[async_trait]
+impl FromRequestParts<AppState> for AdminAuth {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let config = state.config.load();
+        let Some(expected) = config.admin_token.as_ref().map(|t| t.expose().as_str()) else {
+            return Err(AppError::Unavailable(
+                "the admin endpoints are disabled until ADMIN_TOKEN is set".into(),
+            ));
+        };
+        let presented = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim);
+        match presented {
+            Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(AdminAuth),
+            _ => Err(AppError::Unauthorized(
+                "a valid admin token is required in the Authorization header".into(),
+            )),
+        }
+    }
+}
+
+/// `a == b` in time independent of where they first differ.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
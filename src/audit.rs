@@ -0,0 +1,213 @@
+//! Audit trail of the requests sent to Spotify (`AUDIT_FILE`, `AUDIT_STORAGE`).
+//!
+//! Every Spotify client reports its upstream requests to one [`AuditLog`], which only
+//! queues them, so a slow disk or database never holds up a request. [`AuditWriter`]
+//! drains the queue in batches into a JSON-lines file, rotated by size, and into the
+//! store's `upstream_calls` table. Each line (and row) is an [`AuditRecord`]: when the
+//! request was sent, the tenant, endpoint and method, the response status and the
+//! latency.
+//!
+//! Search queries are not kept: `params_hash` is the hex HMAC-SHA256 of the request path
+//! and query, keyed with `AUDIT_HASH_KEY`, so that nobody without the key can recover a
+//! query by hashing guesses. When the queue is full, records are dropped and the writer
+//! logs how many.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::secret::Secret;
+use crate::spotify::{AuditRecord, UpstreamAudit, UpstreamCall};
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+use crate::storage::DynStorage;
+
+/// Records waiting for the writer before new ones are dropped.
+pub const QUEUE_CAPACITY: usize = 10_000;
+/// Most records written at once.
+const BATCH: usize = 256;
+
+/// Queues every upstream request for the [`AuditWriter`].
+pub struct AuditLog {
+    tx: mpsc::Sender<AuditRecord>,
+    dropped: Arc<AtomicU64>,
+    /// Keys `params_hash`.
+    hash_key: Secret,
+}
+
+impl AuditLog {
+    /// A log hashing request parameters with `hash_key`, and the writer draining it, which
+    /// has nowhere to write until given a file or a store.
+    pub fn new(hash_key: Secret) -> (Arc<Self>, AuditWriter) {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = AuditWriter {
+            rx,
+            dropped: dropped.clone(),
+            file: None,
+            #[cfg(any(feature = "sqlite", feature = "postgres"))]
+            store: None,
+        };
+        (Arc::new(Self { tx, dropped, hash_key }), writer)
+    }
+
+    /// Hex HMAC-SHA256 of `path_and_query`.
+    fn params_hash(&self, path_and_query: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.hash_key.expose().as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(path_and_query.as_bytes());
+        mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+impl UpstreamAudit for AuditLog {
+    fn record(&self, call: &UpstreamCall<'_>) {
+        let at_ms = call.at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64);
+        let params_hash = self.params_hash(call.path_and_query);
+        let record = AuditRecord {
+            at_ms,
+            tenant: call.tenant.to_string(),
+            endpoint: call.endpoint.to_string(),
+            method: call.method.to_string(),
+            params_hash,
+            status: call.status,
+            latency_ms: call.latency.as_millis() as i64,
+        };
+        if self.tx.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Writes the records queued by an [`AuditLog`].
+pub struct AuditWriter {
+    rx: mpsc::Receiver<AuditRecord>,
+    dropped: Arc<AtomicU64>,
+    file: Option<RotatingFile>,
+    #[cfg(any(feature = "sqlite", feature = "postgres"))]
+    store: Option<DynStorage>,
+}
+
+impl AuditWriter {
+    /// Append records to `path`, creating it if needed. Once it reaches `max_bytes` it is
+    /// renamed to `path.1` (older files move to `path.2`, ...) and a new file is started;
+    /// `max_files` rotated files are kept.
+    pub async fn file(mut self, path: impl Into<PathBuf>, max_bytes: u64, max_files: u32) -> std::io::Result<Self> {
+        self.file = Some(RotatingFile::open(path.into(), max_bytes, max_files).await?);
+        Ok(self)
+    }
+
+    /// Insert records into `store` as well.
+    #[cfg(any(feature = "sqlite", feature = "postgres"))]
+    pub fn store(mut self, store: DynStorage) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Write records as they arrive, until every [`AuditLog`] handle is dropped. Failed
+    /// writes are logged and their records lost.
+    pub async fn run(mut self) {
+        let mut batch = Vec::with_capacity(BATCH);
+        while self.rx.recv_many(&mut batch, BATCH).await > 0 {
+            if let Some(file) = &mut self.file {
+                if let Err(e) = file.write(&batch).await {
+                    tracing::warn!("writing {} audit records to {} failed: {}", batch.len(), file.path.display(), e);
+                }
+            }
+            #[cfg(any(feature = "sqlite", feature = "postgres"))]
+            if let Some(store) = &self.store {
+                if let Err(e) = store.insert_upstream_calls(&batch).await {
+                    tracing::warn!("storing {} audit records failed: {}", batch.len(), e);
+                }
+            }
+            let dropped = self.dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                tracing::warn!("audit queue full, dropped {} upstream call records", dropped);
+            }
+            batch.clear();
+        }
+    }
+}
+
+/// A JSON-lines file rotated by size.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    /// Bytes in the current file.
+    len: u64,
+    max_bytes: u64,
+    max_files: u32,
+}
+
+impl RotatingFile {
+    async fn open(path: PathBuf, max_bytes: u64, max_files: u32) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path).await?;
+        let len = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            file,
+            len,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    async fn write(&mut self, records: &[AuditRecord]) -> std::io::Result<()> {
+        let mut buf = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut buf, record)?;
+            buf.push(b'\n');
+        }
+        if self.len > 0 && self.len + buf.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+        }
+        self.file.write_all(&buf).await?;
+        self.file.flush().await?;
+        self.len += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `path.N` to `path.N+1`, dropping the oldest, and start a new `path`.
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.sync_all().await?;
+        let _ = fs::remove_file(numbered(&self.path, self.max_files)).await;
+        for n in (1..self.max_files).rev() {
+            let _ = fs::rename(numbered(&self.path, n), numbered(&self.path, n + 1)).await;
+        }
+        fs::rename(&self.path, numbered(&self.path, 1)).await?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// `path` with `.n` appended.
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_hash_depends_on_the_key() {
+        let (log, _writer) = AuditLog::new("key-one".into());
+        let (other, _writer) = AuditLog::new("key-two".into());
+        let path = "/v1/search?q=daft%20punk&type=track&limit=20&offset=0";
+        assert_eq!(log.params_hash(path), log.params_hash(path));
+        assert_ne!(log.params_hash(path), other.params_hash(path));
+        // Not the plain SHA-256 a dictionary of queries could be checked against.
+        let plain: String =
+            <Sha256 as sha2::Digest>::digest(path.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        assert_ne!(log.params_hash(path), plain);
+    }
+}
@@ -0,0 +1,338 @@
+//! Short-lived cache of search results, for hot queries.
+//!
+//! [`CachingSpotifyApi`] wraps the Spotify backend and keeps each search page for a TTL.
+//! When a page expires, one request recomputes it while the rest are protected from a
+//! stampede: within the stale window they get the expired page at once and a single
+//! background refresh replaces it; past that window, they wait for the one request
+//! that is already searching and share its result.
+//!
+//! Entries are per tenant: a tenant's searches go through its own Spotify client and
+//! count against its own quota and usage, so they are never answered from another
+//! tenant's results.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::spotify::{
+    AlbumDetail, AlbumTracksPage, ArtistAlbumsPage, ArtistDetail, AudioFeatures, ByteStream, DynSpotifyApi,
+    PlaylistTracksPage, RecommendationSeeds, ScoredTrack, SearchLimits, SearchTracksResponse,
+    SearchTracksWithFeaturesResponse, SpotifyApi, SpotifyError, TokenStatus, Track, TrackWithFeatures, UpstreamSnapshot,
+};
+use crate::tenants;
+
+/// Cached pages per kind of search; past this, unusable entries and then the oldest
+/// are dropped.
+const MAX_ENTRIES: usize = 10_000;
+
+/// Values by key, each recomputed by one request at a time.
+pub struct ResultCache<T> {
+    ttl: Duration,
+    stale: Duration,
+    entries: Mutex<HashMap<String, Slot<T>>>,
+}
+
+struct Slot<T> {
+    /// The latest value and when it was stored.
+    value: Option<(Arc<T>, Instant)>,
+    /// Held by the request recomputing the value.
+    refresh: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<T: Send + Sync + 'static> ResultCache<T> {
+    /// Values fresh for `ttl`, then served stale for up to `stale` while refreshed.
+    pub fn new(ttl: Duration, stale: Duration) -> Self {
+        Self {
+            ttl,
+            stale,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The value for `key`, from `compute` if there is no usable one. `compute` runs in
+    /// the background when a stale value is served. Errors are not cached.
+    pub async fn get<F, Fut, E>(self: &Arc<Self>, key: String, compute: F) -> Result<Arc<T>, E>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        E: Display + 'static,
+    {
+        let refresh = {
+            let mut entries = self.entries.lock().unwrap();
+            if !entries.contains_key(&key) {
+                self.make_room(&mut entries);
+            }
+            let slot = entries.entry(key.clone()).or_insert_with(|| Slot {
+                value: None,
+                refresh: Arc::default(),
+            });
+            match &slot.value {
+                Some((value, stored)) if stored.elapsed() < self.ttl => {
+                    record_search_cache("hit");
+                    return Ok(value.clone());
+                }
+                Some((value, stored)) if stored.elapsed() < self.ttl + self.stale => {
+                    // Only the first request past the TTL refreshes; later ones find
+                    // the lock taken.
+                    if let Ok(guard) = slot.refresh.clone().try_lock_owned() {
+                        let cache = self.clone();
+                        tokio::spawn(tenants::propagate(async move {
+                            let _guard = guard;
+                            match compute().await {
+                                Ok(value) => cache.store(key, Arc::new(value)),
+                                Err(e) => tracing::warn!("refreshing a cached search failed, serving it stale: {}", e),
+                            }
+                        }));
+                    }
+                    record_search_cache("stale");
+                    return Ok(value.clone());
+                }
+                _ => slot.refresh.clone(),
+            }
+        };
+
+        let _guard = refresh.lock().await;
+        // The request that held the lock may have just stored a value.
+        if let Some(value) = self.fresh(&key) {
+            record_search_cache("coalesced");
+            return Ok(value);
+        }
+        let value = Arc::new(compute().await?);
+        self.store(key, value.clone());
+        record_search_cache("miss");
+        Ok(value)
+    }
+
+    /// Recompute the value for `key` now and store it, after any request already
+    /// recomputing it. On error the old value, if any, is kept.
+    pub async fn refresh<F, Fut, E>(&self, key: String, compute: F) -> Result<(), E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let refresh = {
+            let mut entries = self.entries.lock().unwrap();
+            if !entries.contains_key(&key) {
+                self.make_room(&mut entries);
+            }
+            let slot = entries.entry(key.clone()).or_insert_with(|| Slot {
+                value: None,
+                refresh: Arc::default(),
+            });
+            slot.refresh.clone()
+        };
+        let _guard = refresh.lock().await;
+        let value = compute().await?;
+        self.store(key, Arc::new(value));
+        Ok(())
+    }
+
+    fn fresh(&self, key: &str) -> Option<Arc<T>> {
+        let entries = self.entries.lock().unwrap();
+        let (value, stored) = entries.get(key)?.value.as_ref()?;
+        (stored.elapsed() < self.ttl).then(|| value.clone())
+    }
+
+    fn store(&self, key: String, value: Arc<T>) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&key) {
+            self.make_room(&mut entries);
+        }
+        let slot = entries.entry(key).or_insert_with(|| Slot {
+            value: None,
+            refresh: Arc::default(),
+        });
+        slot.value = Some((value, Instant::now()));
+    }
+
+    fn make_room(&self, entries: &mut HashMap<String, Slot<T>>) {
+        if entries.len() < MAX_ENTRIES {
+            return;
+        }
+        let usable = self.ttl + self.stale;
+        entries.retain(|_, slot| {
+            let in_flight = slot.refresh.try_lock().is_err();
+            in_flight || slot.value.as_ref().is_some_and(|(_, stored)| stored.elapsed() < usable)
+        });
+        if entries.len() >= MAX_ENTRIES {
+            let oldest = entries
+                .iter()
+                .filter_map(|(key, slot)| Some((key, slot.value.as_ref()?.1)))
+                .min_by_key(|(_, stored)| *stored)
+                .map(|(key, _)| key.clone());
+            entries.remove(&oldest.unwrap_or_default());
+        }
+    }
+}
+
+/// [`SpotifyApi`] that serves searches from a [`ResultCache`] and passes everything
+/// else through.
+pub struct CachingSpotifyApi {
+    inner: DynSpotifyApi,
+    searches: Arc<ResultCache<SearchTracksResponse>>,
+    searches_with_features: Arc<ResultCache<SearchTracksWithFeaturesResponse>>,
+    /// How `inner` resolves a page, for the cache keys.
+    search_limits: SearchLimits,
+}
+
+impl CachingSpotifyApi {
+    /// Cache `inner`'s search pages for `ttl`, and serve them stale for up to `stale`
+    /// more while one request refreshes them.
+    pub fn new(inner: DynSpotifyApi, ttl: Duration, stale: Duration) -> Self {
+        Self {
+            inner,
+            searches: Arc::new(ResultCache::new(ttl, stale)),
+            searches_with_features: Arc::new(ResultCache::new(ttl, stale)),
+            search_limits: SearchLimits::default(),
+        }
+    }
+
+    /// The search limits `inner` was built with (default Spotify's), so that pages it
+    /// resolves the same share a cache entry.
+    pub fn search_limits(mut self, limits: SearchLimits) -> Self {
+        self.search_limits = limits;
+        self
+    }
+
+    /// Cache key for a search page: the current tenant, the query ignoring case and
+    /// extra whitespace, and the page as the client resolves it.
+    fn search_key(&self, q: &str, limit: Option<u32>, offset: Option<u32>) -> String {
+        let q = q.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        let (limit, offset) = self.search_limits.resolve(limit, offset);
+        let tenant = tenants::current().unwrap_or_default();
+        format!("{}\n{}\n{}\n{}", tenant, q, limit, offset)
+    }
+}
+
+#[async_trait]
+impl SpotifyApi for CachingSpotifyApi {
+    async fn has_token(&self) -> bool {
+        self.inner.has_token().await
+    }
+
+    fn upstream_status(&self) -> UpstreamSnapshot {
+        self.inner.upstream_status()
+    }
+
+    fn token_status(&self) -> TokenStatus {
+        self.inner.token_status()
+    }
+
+    async fn refresh_token(&self) -> Result<(), SpotifyError> {
+        self.inner.refresh_token().await
+    }
+
+    async fn search_tracks(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksResponse, SpotifyError> {
+        let (inner, query) = (self.inner.clone(), q.to_string());
+        let compute = move || async move { inner.search_tracks(&query, limit, offset).await };
+        let response = self.searches.get(self.search_key(q, limit, offset), compute).await?;
+        Ok(SearchTracksResponse::clone(&response))
+    }
+
+    async fn search_tracks_with_features(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksWithFeaturesResponse, SpotifyError> {
+        let (inner, query) = (self.inner.clone(), q.to_string());
+        let compute = move || async move { inner.search_tracks_with_features(&query, limit, offset).await };
+        let response = self.searches_with_features.get(self.search_key(q, limit, offset), compute).await?;
+        Ok(SearchTracksWithFeaturesResponse::clone(&response))
+    }
+
+    async fn search_tracks_raw(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ByteStream, SpotifyError> {
+        self.inner.search_tracks_raw(q, limit, offset).await
+    }
+
+    async fn get_tracks(&self, ids: &[String]) -> Result<Vec<Option<Track>>, SpotifyError> {
+        self.inner.get_tracks(ids).await
+    }
+
+    async fn get_tracks_raw(&self, ids: &[String]) -> Result<ByteStream, SpotifyError> {
+        self.inner.get_tracks_raw(ids).await
+    }
+
+    async fn get_audio_features(&self, ids: &[String]) -> Result<Vec<Option<AudioFeatures>>, SpotifyError> {
+        self.inner.get_audio_features(ids).await
+    }
+
+    async fn get_tracks_with_features(&self, ids: &[String]) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        self.inner.get_tracks_with_features(ids).await
+    }
+
+    async fn get_recommendations_with_features(
+        &self,
+        seeds: &RecommendationSeeds,
+        limit: Option<u32>,
+    ) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        self.inner.get_recommendations_with_features(seeds, limit).await
+    }
+
+    async fn get_similar_tracks(&self, id: &str, limit: Option<u32>) -> Result<Vec<ScoredTrack>, SpotifyError> {
+        self.inner.get_similar_tracks(id, limit).await
+    }
+
+    async fn get_playlist_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<PlaylistTracksPage, SpotifyError> {
+        self.inner.get_playlist_tracks(id, limit, offset).await
+    }
+
+    async fn get_artist_albums(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ArtistAlbumsPage, SpotifyError> {
+        self.inner.get_artist_albums(id, limit, offset).await
+    }
+
+    async fn get_album_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<AlbumTracksPage, SpotifyError> {
+        self.inner.get_album_tracks(id, limit, offset).await
+    }
+
+    async fn get_album(&self, id: &str) -> Result<AlbumDetail, SpotifyError> {
+        self.inner.get_album(id).await
+    }
+
+    async fn get_artist(&self, id: &str) -> Result<ArtistDetail, SpotifyError> {
+        self.inner.get_artist(id).await
+    }
+
+    async fn get_albums(&self, ids: &[String]) -> Result<Vec<Option<AlbumDetail>>, SpotifyError> {
+        self.inner.get_albums(ids).await
+    }
+
+    async fn get_artists(&self, ids: &[String]) -> Result<Vec<Option<ArtistDetail>>, SpotifyError> {
+        self.inner.get_artists(ids).await
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_search_cache(result: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("search_cache_requests_total", "result" => result).increment(1);
+}
@@ -0,0 +1,130 @@
+//! Command-line flags for the `spotify-search` binary. Flags override the
+//! config file and environment.
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use figment::providers::Serialized;
+
+use crate::config::Config;
+
+/// `--version` output: crate version, git SHA and build time.
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("VERGEN_GIT_SHA"),
+    ", built ",
+    env!("VERGEN_BUILD_TIMESTAMP"),
+    ")"
+);
+
+/// Spotify search and audio-feature service (HTTP + gRPC).
+///
+/// Settings come from the config file, then environment variables, then these flags.
+/// With a subcommand, it runs that one request, prints JSON and exits without serving.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "spotify-search", version = VERSION, about)]
+pub struct Cli {
+    /// Config file (.toml, .yaml or .yml); overrides CONFIG_FILE.
+    #[arg(short, long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// HTTP port.
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// gRPC port.
+    #[arg(long)]
+    pub grpc_port: Option<u16>,
+
+    /// Log output format.
+    #[arg(long, value_parser = ["pretty", "json"])]
+    pub log_format: Option<String>,
+
+    /// Serve the bundled fixture catalog instead of calling Spotify.
+    #[arg(long)]
+    pub mock: bool,
+
+    /// Replay a query corpus against the mock catalog through the HTTP stack, print the
+    /// p50/p95/p99 latencies and exit, without serving anything.
+    #[arg(long)]
+    pub self_test: bool,
+
+    /// Corpus for --self-test: one search query, or request path starting with /, per line
+    /// (default: the bundled corpus).
+    #[arg(long, value_name = "PATH", requires = "self_test")]
+    pub self_test_corpus: Option<PathBuf>,
+
+    /// Requests --self-test sends, cycling through the corpus.
+    #[arg(long, value_name = "N", default_value_t = 5000)]
+    pub self_test_requests: usize,
+
+    /// Requests --self-test keeps in flight.
+    #[arg(long, value_name = "N", default_value_t = 32)]
+    pub self_test_concurrency: usize,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// One-off requests through the configured Spotify backend. The JSON result goes to
+/// stdout and warnings to stderr. `tui` is interactive instead.
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Search tracks, as `GET /api/v1/search` does.
+    Query {
+        /// Search query.
+        q: String,
+
+        /// Add each track's audio-feature embedding.
+        #[arg(long)]
+        features: bool,
+
+        /// Results to return (1-50).
+        #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..=50))]
+        limit: u32,
+
+        /// Index of the first result.
+        #[arg(long, default_value_t = 0)]
+        offset: u32,
+    },
+    /// Fetch tracks by Spotify ID with their embeddings, as `GET /api/v1/tracks/with-features` does.
+    Tracks {
+        /// Spotify track IDs (up to 50).
+        #[arg(required = true, num_args = 1..=50)]
+        ids: Vec<String>,
+    },
+    /// Find the Spotify track for a title and artist, as one item of a match job.
+    Match {
+        /// Track title.
+        #[arg(long)]
+        title: String,
+
+        /// Artist name.
+        #[arg(long)]
+        artist: Option<String>,
+    },
+    /// Browse search results interactively, comparing audio features and embeddings.
+    #[cfg(feature = "tui")]
+    Tui,
+}
+
+impl Cli {
+    /// Resolve the configuration with these flags layered on top.
+    pub fn load_config(&self) -> anyhow::Result<Config> {
+        let mut figment = Config::figment(self.config.as_deref())?;
+        if let Some(port) = self.port {
+            figment = figment.merge(Serialized::default("port", port));
+        }
+        if let Some(port) = self.grpc_port {
+            figment = figment.merge(Serialized::default("grpc_port", port));
+        }
+        if let Some(format) = &self.log_format {
+            figment = figment.merge(Serialized::default("log.format", format));
+        }
+        if self.mock || self.self_test {
+            figment = figment.merge(Serialized::default("spotify.mock", true));
+        }
+        Config::from_figment(&figment)
+    }
+}
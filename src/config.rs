@@ -1,37 +1,1570 @@
+//! Layered configuration: defaults, then a TOML/YAML config file, then
+//! environment variables.
+//!
+//! Every environment variable also has a `{VAR}_FILE` form that reads the value
+//! from a file, for Docker and Kubernetes secrets.
+
+use std::collections::BTreeMap;
 use std::env;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use figment::providers::{Env, Format, Serialized, Toml, Yaml};
+use figment::Figment;
+use serde::{Deserialize, Deserializer};
+
+use crate::limits::{RequestLimits, SPOTIFY_MAX_BATCH_IDS};
+use crate::saved;
+use crate::secret::Secret;
+use crate::spotify::{
+    SearchLimits, DEFAULT_API_BASE, DEFAULT_SEARCH_CHUNK_SIZE, DEFAULT_SEARCH_CONCURRENCY, DEFAULT_TENANT,
+    DEFAULT_TOKEN_URL,
+};
 
-/// Application configuration from environment variables.
-#[derive(Debug, Clone)]
+/// Environment variables and the config keys they override.
+const ENV_KEYS: &[(&str, &str)] = &[
+    ("PORT", "port"),
+    ("GRPC_PORT", "grpc_port"),
+    ("BIND_ADDR", "bind_addr"),
+    ("GRPC_BIND_ADDR", "grpc_bind_addr"),
+    ("SINGLE_PORT", "single_port"),
+    ("HTTP_ENABLED", "http.enabled"),
+    ("HTTP_UNIX_SOCKET", "http.unix_socket"),
+    ("GRPC_UNIX_SOCKET", "grpc.unix_socket"),
+    ("HTTP_TLS_CERT", "http.tls.cert"),
+    ("HTTP_TLS_KEY", "http.tls.key"),
+    ("HTTP_TLS_RELOAD_INTERVAL_SECS", "http.tls.reload_interval_secs"),
+    ("HTTP_TLS_CLIENT_CA", "http.tls.client_ca"),
+    ("GRPC_ENABLED", "grpc.enabled"),
+    ("STARTUP_CHECK", "startup_check"),
+    ("LOG_FORMAT", "log.format"),
+    ("LOG_LEVEL", "log.level"),
+    ("ACCESS_LOG_REDACT_QUERY", "access_log.redact_query"),
+    ("SPOTIFY_CLIENT_ID", "spotify.client_id"),
+    ("SPOTIFY_CLIENT_SECRET", "spotify.client_secret"),
+    ("SPOTIFY_MOCK", "spotify.mock"),
+    ("SPOTIFY_TIMEOUT_SECS", "spotify.timeout_secs"),
+    ("SPOTIFY_CONNECT_TIMEOUT_SECS", "spotify.connect_timeout_secs"),
+    ("SPOTIFY_USER_AGENT", "spotify.user_agent"),
+    ("SPOTIFY_API_BASE", "spotify.api_base"),
+    ("SPOTIFY_TOKEN_URL", "spotify.token_url"),
+    ("SPOTIFY_POOL_MAX_IDLE_PER_HOST", "spotify.pool_max_idle_per_host"),
+    ("SPOTIFY_POOL_IDLE_TIMEOUT_SECS", "spotify.pool_idle_timeout_secs"),
+    ("SPOTIFY_TCP_KEEPALIVE_SECS", "spotify.tcp_keepalive_secs"),
+    ("SPOTIFY_HTTP2", "spotify.http2"),
+    ("SPOTIFY_PROXY", "spotify.proxy"),
+    ("EGRESS_PROXY", "egress.proxy"),
+    ("EGRESS_NO_PROXY", "egress.no_proxy"),
+    ("SPOTIFY_SEARCH_CHUNK_SIZE", "spotify.search_chunk_size"),
+    ("SPOTIFY_SEARCH_CONCURRENCY", "spotify.search_concurrency"),
+    ("SPOTIFY_MARKET", "spotify.market"),
+    ("SPOTIFY_TRANSLITERATE_QUERIES", "spotify.transliterate_queries"),
+    ("SPOTIFY_LENIENT_SCHEMA", "spotify.lenient_schema"),
+    ("SPOTIFY_SCHEMA_SAMPLES", "spotify.schema_samples"),
+    ("OTEL_EXPORTER_OTLP_ENDPOINT", "telemetry.otlp_endpoint"),
+    ("OTEL_SERVICE_NAME", "telemetry.service_name"),
+    ("GRPC_TLS_CERT", "grpc.tls.cert"),
+    ("GRPC_TLS_KEY", "grpc.tls.key"),
+    ("GRPC_TLS_CLIENT_CA", "grpc.tls.client_ca"),
+    ("GRPC_WEB_ALLOWED_ORIGINS", "grpc.web_allowed_origins"),
+    ("DATABASE_URL", "database.url"),
+    ("DATABASE_MAX_CONNECTIONS", "database.max_connections"),
+    ("OFFLINE_MODE", "offline_mode"),
+    ("REFRESH_MAX_AGE_SECS", "database.refresh.max_age_secs"),
+    ("REFRESH_INTERVAL_SECS", "database.refresh.interval_secs"),
+    ("REFRESH_PAUSE_MS", "database.refresh.pause_ms"),
+    ("KAFKA_BROKERS", "kafka.brokers"),
+    ("KAFKA_TOPIC", "kafka.topic"),
+    ("NATS_URL", "nats.url"),
+    ("NATS_EVENTS_SUBJECT", "nats.events_subject"),
+    ("JOB_CALLBACK_SECRET", "jobs.callback_secret"),
+    ("JOB_CALLBACK_ALLOWED_HOSTS", "jobs.callback_allowed_hosts"),
+    ("ADMIN_TOKEN", "admin.token"),
+    ("JWT_SECRET", "auth.jwt.secret"),
+    ("JWT_JWKS_URL", "auth.jwt.jwks_url"),
+    ("JWT_ISSUER", "auth.jwt.issuer"),
+    ("JWT_AUDIENCE", "auth.jwt.audience"),
+    ("JWT_TENANT_CLAIM", "auth.jwt.tenant_claim"),
+    ("SNAPSHOT_S3_BUCKET", "snapshots.bucket"),
+    ("SNAPSHOT_S3_PREFIX", "snapshots.prefix"),
+    ("SNAPSHOT_S3_ENDPOINT", "snapshots.endpoint"),
+    ("SNAPSHOT_S3_REGION", "snapshots.region"),
+    ("SNAPSHOT_S3_ACCESS_KEY_ID", "snapshots.access_key_id"),
+    ("SNAPSHOT_S3_SECRET_ACCESS_KEY", "snapshots.secret_access_key"),
+    ("SNAPSHOT_HOUR_UTC", "snapshots.hour_utc"),
+    ("AUDIT_FILE", "audit.file"),
+    ("AUDIT_MAX_FILE_BYTES", "audit.max_file_bytes"),
+    ("AUDIT_MAX_FILES", "audit.max_files"),
+    ("AUDIT_STORAGE", "audit.storage"),
+    ("AUDIT_HASH_KEY", "audit.hash_key"),
+    ("LOCAL_SEARCH", "local_search.enabled"),
+    ("LOCAL_SEARCH_SYNC_INTERVAL_SECS", "local_search.sync_interval_secs"),
+    ("SUGGEST_CACHE_TTL_SECS", "suggest.cache_ttl_secs"),
+    ("SUGGEST_DEBOUNCE_MS", "suggest.debounce_ms"),
+    ("SEARCH_DEFAULT_LIMIT", "search.default_limit"),
+    ("SEARCH_MAX_LIMIT", "search.max_limit"),
+    ("SEARCH_MAX_OFFSET", "search.max_offset"),
+    ("SEARCH_STRICT_LIMITS", "search.strict_limits"),
+    ("LIMITS_MAX_BODY_BYTES", "limits.max_body_bytes"),
+    ("LIMITS_MAX_BATCH_IDS", "limits.max_batch_ids"),
+    ("LIMITS_MAX_QUERY_STRING_BYTES", "limits.max_query_string_bytes"),
+    ("SEARCH_CACHE_TTL_SECS", "search_cache.ttl_secs"),
+    ("SEARCH_CACHE_STALE_SECS", "search_cache.stale_secs"),
+    ("CACHE_WARM_FILE", "cache_warm.file"),
+    ("CACHE_WARM_PAUSE_MS", "cache_warm.pause_ms"),
+    ("RUNTIME_WORKER_THREADS", "runtime.worker_threads"),
+    ("RUNTIME_MAX_BLOCKING_THREADS", "runtime.max_blocking_threads"),
+    ("RUNTIME_BLOCKING_KEEP_ALIVE_SECS", "runtime.blocking_keep_alive_secs"),
+];
+
+/// Resolved application configuration. Credentials are [`Secret`]s, and `Debug` hides the
+/// passwords in URLs.
+#[derive(Clone)]
 pub struct Config {
     pub port: u16,
     pub grpc_port: u16,
+    /// HTTP listen address; `::` listens on IPv6 and IPv4.
+    pub bind_addr: IpAddr,
+    /// gRPC listen address.
+    pub grpc_bind_addr: IpAddr,
+    /// Serve HTTP on this Unix socket instead of `bind_addr:port`.
+    pub http_unix_socket: Option<PathBuf>,
+    /// Serve gRPC on this Unix socket instead of `grpc_bind_addr:grpc_port`.
+    pub grpc_unix_socket: Option<PathBuf>,
+    /// Run the HTTP server.
+    pub http_enabled: bool,
+    /// Run the gRPC server; requires the `grpc` feature.
+    pub grpc_enabled: bool,
+    /// Empty in mock mode.
     pub spotify_client_id: String,
-    pub spotify_client_secret: String,
+    pub spotify_client_secret: Secret,
+    /// Serve the bundled fixture catalog instead of calling Spotify.
+    pub spotify_mock: bool,
+    /// Total timeout per Spotify request; `None` waits indefinitely.
+    pub spotify_timeout: Option<Duration>,
+    /// TCP/TLS connect timeout for Spotify.
+    pub spotify_connect_timeout: Option<Duration>,
+    /// User-Agent sent to Spotify; the client's default when unset.
+    pub spotify_user_agent: Option<String>,
+    /// Web API base URL, e.g. an API gateway mock or a caching reverse proxy in staging.
+    pub spotify_api_base: String,
+    /// Client Credentials token endpoint.
+    pub spotify_token_url: String,
+    /// Most idle connections kept open to each Spotify host.
+    pub spotify_pool_max_idle_per_host: usize,
+    /// How long an idle connection to Spotify is kept open.
+    pub spotify_pool_idle_timeout: Duration,
+    /// TCP keepalive interval on connections to Spotify; off when unset.
+    pub spotify_tcp_keepalive: Option<Duration>,
+    /// Use HTTP/2 to Spotify where TLS negotiates it; HTTP/1.1 only when false.
+    pub spotify_http2: bool,
+    /// HTTP(S) proxy for all outbound Spotify traffic; `egress_proxy` when unset.
+    pub spotify_proxy: Option<String>,
+    /// HTTP(S) proxy for all outbound HTTP: Spotify, JWKS, job callbacks and S3. The
+    /// standard `HTTPS_PROXY` / `NO_PROXY` variables apply when unset.
+    pub egress_proxy: Option<String>,
+    /// Hosts, domains (`.example.com`) and CIDR ranges reached without the proxy.
+    pub egress_no_proxy: Vec<String>,
+    /// Tracks per search request when searching with features (1-50).
+    pub spotify_search_chunk_size: u32,
+    /// Search chunks in flight at once when searching with features.
+    pub spotify_search_concurrency: usize,
+    /// Market (uppercase ISO 3166-1 alpha-2) Spotify relinks tracks for; no relinking when
+    /// unset.
+    pub spotify_market: Option<String>,
+    /// Spell search queries in ASCII before sending them to Spotify.
+    pub spotify_transliterate_queries: bool,
+    /// Count unknown fields and skip invalid array elements in Spotify responses instead
+    /// of failing the request.
+    pub spotify_lenient_schema: bool,
+    /// Log the offending JSON with schema drift.
+    pub spotify_schema_samples: bool,
+    /// Products with their own Spotify credentials, by name.
+    pub tenants: Vec<TenantConfig>,
+    /// OTLP/gRPC collector endpoint; trace export is disabled when unset.
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` resource attribute on exported spans.
+    pub service_name: String,
+    /// Credential check performed before listeners start.
+    pub startup_check: StartupCheck,
+    /// Log output format.
+    pub log_format: LogFormat,
+    /// Default `tracing` filter; `RUST_LOG` takes precedence.
+    pub log_level: String,
+    /// Replace search queries in the access log with their length.
+    pub access_log_redact_query: bool,
+    /// HTTPS for the HTTP server; plaintext when unset.
+    pub http_tls: Option<TlsConfig>,
+    /// How often to check the HTTP certificate files for rotation; never when unset.
+    pub http_tls_reload_interval: Option<Duration>,
+    /// TLS for the gRPC server; plaintext when unset.
+    pub grpc_tls: Option<TlsConfig>,
+    /// Browser origins allowed to call the gRPC-Web API; `*` allows any. Empty sends no CORS headers.
+    pub grpc_web_allowed_origins: Vec<String>,
+    /// Serve HTTP and gRPC together on `port`; `grpc_port` is unused.
+    pub single_port: bool,
+    /// Persist fetched tracks here: `sqlite://...` (`sqlite` feature) or
+    /// `postgres://...` (`postgres` feature).
+    pub database_url: Option<String>,
+    /// Connection pool size for `database_url`.
+    pub database_max_connections: u32,
+    /// Never call Spotify: serve track lookups and similarity from the local store.
+    pub offline_mode: bool,
+    /// Re-fetch stored tracks older than this in the background; never when unset.
+    pub refresh_max_age: Option<Duration>,
+    /// How often the refresh job looks for stale tracks.
+    pub refresh_interval: Duration,
+    /// Pause between refresh batches, to leave Spotify quota for live traffic.
+    pub refresh_pause: Duration,
+    /// Kafka bootstrap servers for track events (`kafka` feature); no events when empty.
+    pub kafka_brokers: Vec<String>,
+    /// Topic for track events.
+    pub kafka_topic: String,
+    /// NATS server to answer requests from (`nats` feature); off when unset.
+    pub nats_url: Option<String>,
+    /// Subject to publish track events to over `nats_url`; off when unset.
+    pub nats_events_subject: Option<String>,
+    /// Key job callbacks are signed with; callbacks are refused when unset.
+    pub job_callback_secret: Option<Secret>,
+    /// Hosts job callbacks may go to, private ones included; when empty, any host that
+    /// resolves to public addresses only.
+    pub job_callback_allowed_hosts: Vec<String>,
+    /// Bearer token required on `/admin` endpoints; they answer 503 when unset.
+    pub admin_token: Option<Secret>,
+    /// JWT validation for the API (`jwt` feature); the API is open when unset.
+    pub jwt: Option<JwtConfig>,
+    /// Daily snapshots of the local store to S3 (`s3` feature); off when unset.
+    pub snapshots: Option<SnapshotConfig>,
+    /// Audit trail of upstream Spotify requests; off when unset.
+    pub audit: Option<AuditConfig>,
+    /// Index the local store for `/api/v1/local-search` (`local-search` feature).
+    pub local_search: bool,
+    /// How often the local search index picks up newly stored tracks.
+    pub local_search_sync_interval: Duration,
+    /// How long `/api/v1/suggest` reuses the suggestions for a query.
+    pub suggest_cache_ttl: Duration,
+    /// How long a suggest request with a session waits for a newer one.
+    pub suggest_debounce: Duration,
+    /// Default and maximum search page size, maximum search offset, and whether pages
+    /// past it are rejected.
+    pub search_limits: SearchLimits,
+    /// Largest request body and query string, and most IDs per batch.
+    pub request_limits: RequestLimits,
+    /// How long search results are cached; off when unset.
+    pub search_cache_ttl: Option<Duration>,
+    /// How long past `search_cache_ttl` results are served while one request refreshes them.
+    pub search_cache_stale: Duration,
+    /// Named searches served at `/api/v1/saved/:alias`, each with its own cache.
+    pub saved_queries: Vec<SavedQueryConfig>,
+    /// Seed file of searches and track IDs prefetched at startup and on `/admin/cache/warm`.
+    pub cache_warm_file: Option<PathBuf>,
+    /// Pause between the Spotify calls of a cache warm-up.
+    pub cache_warm_pause: Duration,
+    /// Tokio worker threads; one per CPU core when unset.
+    pub runtime_worker_threads: Option<usize>,
+    /// Most threads in Tokio's blocking pool (file I/O, index and export work).
+    pub runtime_max_blocking_threads: usize,
+    /// How long an idle blocking-pool thread is kept.
+    pub runtime_blocking_keep_alive: Duration,
+}
+
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("port", &self.port)
+            .field("grpc_port", &self.grpc_port)
+            .field("bind_addr", &self.bind_addr)
+            .field("grpc_bind_addr", &self.grpc_bind_addr)
+            .field("http_unix_socket", &self.http_unix_socket)
+            .field("grpc_unix_socket", &self.grpc_unix_socket)
+            .field("http_enabled", &self.http_enabled)
+            .field("grpc_enabled", &self.grpc_enabled)
+            .field("spotify_client_id", &self.spotify_client_id)
+            .field("spotify_client_secret", &self.spotify_client_secret)
+            .field("spotify_mock", &self.spotify_mock)
+            .field("spotify_timeout", &self.spotify_timeout)
+            .field("spotify_connect_timeout", &self.spotify_connect_timeout)
+            .field("spotify_user_agent", &self.spotify_user_agent)
+            .field("spotify_api_base", &self.spotify_api_base)
+            .field("spotify_token_url", &self.spotify_token_url)
+            .field("spotify_pool_max_idle_per_host", &self.spotify_pool_max_idle_per_host)
+            .field("spotify_pool_idle_timeout", &self.spotify_pool_idle_timeout)
+            .field("spotify_tcp_keepalive", &self.spotify_tcp_keepalive)
+            .field("spotify_http2", &self.spotify_http2)
+            .field("spotify_proxy", &self.spotify_proxy.as_deref().map(redact_password))
+            .field("egress_proxy", &self.egress_proxy.as_deref().map(redact_password))
+            .field("egress_no_proxy", &self.egress_no_proxy)
+            .field("spotify_search_chunk_size", &self.spotify_search_chunk_size)
+            .field("spotify_search_concurrency", &self.spotify_search_concurrency)
+            .field("spotify_market", &self.spotify_market)
+            .field("spotify_transliterate_queries", &self.spotify_transliterate_queries)
+            .field("spotify_lenient_schema", &self.spotify_lenient_schema)
+            .field("spotify_schema_samples", &self.spotify_schema_samples)
+            .field("tenants", &self.tenants)
+            .field("otlp_endpoint", &self.otlp_endpoint)
+            .field("service_name", &self.service_name)
+            .field("startup_check", &self.startup_check)
+            .field("log_format", &self.log_format)
+            .field("log_level", &self.log_level)
+            .field("access_log_redact_query", &self.access_log_redact_query)
+            .field("http_tls", &self.http_tls)
+            .field("http_tls_reload_interval", &self.http_tls_reload_interval)
+            .field("grpc_tls", &self.grpc_tls)
+            .field("grpc_web_allowed_origins", &self.grpc_web_allowed_origins)
+            .field("single_port", &self.single_port)
+            .field("database_url", &self.database_url.as_deref().map(redact_password))
+            .field("database_max_connections", &self.database_max_connections)
+            .field("offline_mode", &self.offline_mode)
+            .field("refresh_max_age", &self.refresh_max_age)
+            .field("refresh_interval", &self.refresh_interval)
+            .field("refresh_pause", &self.refresh_pause)
+            .field("kafka_brokers", &self.kafka_brokers)
+            .field("kafka_topic", &self.kafka_topic)
+            .field("nats_url", &self.nats_url.as_deref().map(redact_password))
+            .field("nats_events_subject", &self.nats_events_subject)
+            .field("job_callback_secret", &self.job_callback_secret)
+            .field("job_callback_allowed_hosts", &self.job_callback_allowed_hosts)
+            .field("admin_token", &self.admin_token)
+            .field("jwt", &self.jwt)
+            .field("snapshots", &self.snapshots)
+            .field("audit", &self.audit)
+            .field("local_search", &self.local_search)
+            .field("local_search_sync_interval", &self.local_search_sync_interval)
+            .field("suggest_cache_ttl", &self.suggest_cache_ttl)
+            .field("suggest_debounce", &self.suggest_debounce)
+            .field("search_limits", &self.search_limits)
+            .field("request_limits", &self.request_limits)
+            .field("search_cache_ttl", &self.search_cache_ttl)
+            .field("search_cache_stale", &self.search_cache_stale)
+            .field("saved_queries", &self.saved_queries)
+            .field("cache_warm_file", &self.cache_warm_file)
+            .field("cache_warm_pause", &self.cache_warm_pause)
+            .field("runtime_worker_threads", &self.runtime_worker_threads)
+            .field("runtime_max_blocking_threads", &self.runtime_max_blocking_threads)
+            .field("runtime_blocking_keep_alive", &self.runtime_blocking_keep_alive)
+            .finish()
+    }
+}
+
+/// Bucket and schedule for daily store snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotConfig {
+    pub bucket: String,
+    /// Key prefix inside the bucket.
+    pub prefix: String,
+    /// S3-compatible endpoint (MinIO, R2, ...); AWS when unset.
+    pub endpoint: Option<String>,
+    /// Defaults to `AWS_REGION`, then `us-east-1`.
+    pub region: Option<String>,
+    /// Static credentials; when unset the usual AWS environment variables, web
+    /// identity or instance credentials are used.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<Secret>,
+    /// Hour of the day (UTC) snapshots are taken at.
+    pub hour_utc: u8,
+}
+
+/// Where upstream Spotify requests are audited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditConfig {
+    /// JSON-lines file, rotated by size.
+    pub file: Option<PathBuf>,
+    /// Size at which `file` is rotated.
+    pub max_file_bytes: u64,
+    /// Rotated files kept next to `file`.
+    pub max_files: u32,
+    /// Also insert records into the `DATABASE_URL` store.
+    pub storage: bool,
+    /// Keys the HMAC of each request's path and query; `None` for a random key per process.
+    pub hash_key: Option<Secret>,
+}
+
+/// A product with its own Spotify credentials.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TenantConfig {
+    /// Sent as `X-Tenant` and used as the `tenant` metric label.
+    pub name: String,
+    /// Empty in mock mode.
+    pub client_id: String,
+    pub client_secret: Secret,
+    /// `X-Api-Key` values that select this tenant. When there are any, `X-Tenant` alone
+    /// does not.
+    pub api_keys: Vec<Secret>,
+    /// Client certificate identities (SAN DNS name, URI or email, or subject CN) that
+    /// select this tenant.
+    pub client_certs: Vec<String>,
+}
+
+/// A named search, served from its own cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedQueryConfig {
+    /// The `:alias` in `/api/v1/saved/:alias`.
+    pub alias: String,
+    /// `/api/v1/search` query string, e.g. `q=jazz&new_releases_only=true`.
+    pub query: String,
+    /// How long a response is served from the cache.
+    pub ttl: Duration,
+    /// Refresh the response this often in the background; only on request when unset.
+    pub prewarm_interval: Option<Duration>,
+}
+
+/// How API bearer tokens are validated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JwtConfig {
+    pub key: JwtKey,
+    /// Required `iss`; any when unset.
+    pub issuer: Option<String>,
+    /// Required `aud`; tokens with an `aud` are rejected when unset.
+    pub audience: Option<String>,
+    /// Claim naming the caller's tenant.
+    pub tenant_claim: String,
+}
+
+/// Key JWT signatures are checked with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JwtKey {
+    /// HMAC secret (HS256, HS384, HS512).
+    Secret(Secret),
+    /// JWKS document with the public keys (RSA, EC, EdDSA), selected by `kid`.
+    JwksUrl(String),
+}
+
+/// PEM files for a TLS listener.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// CA bundle for verifying client certificates; when set, clients must present one.
+    pub client_ca_path: Option<PathBuf>,
+}
+
+/// Log output format for the fmt layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable, colored output.
+    Pretty,
+    /// One JSON object per line (ELK-friendly).
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pretty" | "text" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            other => Err(anyhow::anyhow!("log format must be json or pretty (got '{}')", other)),
+        }
+    }
+}
+
+/// What to do when the boot-time Spotify credential check fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupCheck {
+    /// Skip the check.
+    Off,
+    /// Log the failure and keep starting.
+    Warn,
+    /// Exit non-zero.
+    Fail,
+}
+
+impl std::str::FromStr for StartupCheck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" | "false" | "0" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "fail" | "true" | "1" => Ok(Self::Fail),
+            other => Err(anyhow::anyhow!("startup check must be off, warn or fail (got '{}')", other)),
+        }
+    }
+}
+
+/// Raw layered settings; the shape of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Settings {
+    port: u16,
+    grpc_port: u16,
+    bind_addr: IpAddr,
+    /// Defaults to `bind_addr`.
+    grpc_bind_addr: Option<IpAddr>,
+    single_port: bool,
+    offline_mode: bool,
+    #[serde(deserialize_with = "from_scalar")]
+    startup_check: StartupCheck,
+    http: HttpSettings,
+    log: LogSettings,
+    access_log: AccessLogSettings,
+    spotify: SpotifySettings,
+    telemetry: TelemetrySettings,
+    egress: EgressSettings,
+    grpc: GrpcSettings,
+    database: DatabaseSettings,
+    kafka: KafkaSettings,
+    nats: NatsSettings,
+    jobs: JobsSettings,
+    admin: AdminSettings,
+    auth: AuthSettings,
+    snapshots: SnapshotSettings,
+    audit: AuditSettings,
+    local_search: LocalSearchSettings,
+    suggest: SuggestSettings,
+    search: SearchSettings,
+    limits: LimitsSettings,
+    search_cache: SearchCacheSettings,
+    cache_warm: CacheWarmSettings,
+    runtime: RuntimeSettings,
+    tenants: BTreeMap<String, TenantSettings>,
+    saved_queries: BTreeMap<String, SavedQuerySettings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            port: 8081,
+            grpc_port: 50051,
+            bind_addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            grpc_bind_addr: None,
+            single_port: false,
+            offline_mode: false,
+            startup_check: StartupCheck::Warn,
+            http: HttpSettings::default(),
+            log: LogSettings::default(),
+            access_log: AccessLogSettings::default(),
+            spotify: SpotifySettings::default(),
+            telemetry: TelemetrySettings::default(),
+            egress: EgressSettings::default(),
+            grpc: GrpcSettings::default(),
+            database: DatabaseSettings::default(),
+            kafka: KafkaSettings::default(),
+            nats: NatsSettings::default(),
+            jobs: JobsSettings::default(),
+            admin: AdminSettings::default(),
+            auth: AuthSettings::default(),
+            snapshots: SnapshotSettings::default(),
+            audit: AuditSettings::default(),
+            local_search: LocalSearchSettings::default(),
+            suggest: SuggestSettings::default(),
+            search: SearchSettings::default(),
+            limits: LimitsSettings::default(),
+            search_cache: SearchCacheSettings::default(),
+            cache_warm: CacheWarmSettings::default(),
+            runtime: RuntimeSettings::default(),
+            tenants: BTreeMap::new(),
+            saved_queries: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HttpSettings {
+    enabled: bool,
+    unix_socket: Option<PathBuf>,
+    tls: HttpTlsSettings,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            unix_socket: None,
+            tls: HttpTlsSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HttpTlsSettings {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    reload_interval_secs: Option<u64>,
+    client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LogSettings {
+    #[serde(deserialize_with = "from_scalar")]
+    format: LogFormat,
+    level: String,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Pretty,
+            level: "info".into(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AccessLogSettings {
+    redact_query: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SpotifySettings {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    mock: bool,
+    timeout_secs: Option<u64>,
+    connect_timeout_secs: Option<u64>,
+    user_agent: Option<String>,
+    api_base: String,
+    token_url: String,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout_secs: u64,
+    /// 0 turns keepalive off.
+    tcp_keepalive_secs: u64,
+    http2: bool,
+    proxy: Option<String>,
+    search_chunk_size: u32,
+    search_concurrency: usize,
+    market: Option<String>,
+    transliterate_queries: bool,
+    lenient_schema: bool,
+    schema_samples: bool,
+}
+
+impl Default for SpotifySettings {
+    fn default() -> Self {
+        Self {
+            client_id: None,
+            client_secret: None,
+            mock: false,
+            timeout_secs: None,
+            connect_timeout_secs: None,
+            user_agent: None,
+            api_base: DEFAULT_API_BASE.into(),
+            token_url: DEFAULT_TOKEN_URL.into(),
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout_secs: 90,
+            tcp_keepalive_secs: 60,
+            http2: true,
+            proxy: None,
+            search_chunk_size: DEFAULT_SEARCH_CHUNK_SIZE,
+            search_concurrency: DEFAULT_SEARCH_CONCURRENCY,
+            market: None,
+            transliterate_queries: false,
+            lenient_schema: false,
+            schema_samples: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TenantSettings {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    #[serde(deserialize_with = "string_list")]
+    api_keys: Vec<String>,
+    #[serde(deserialize_with = "string_list")]
+    client_certs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SavedQuerySettings {
+    query: Option<String>,
+    ttl_secs: u64,
+    prewarm_secs: Option<u64>,
+}
+
+impl Default for SavedQuerySettings {
+    fn default() -> Self {
+        Self {
+            query: None,
+            ttl_secs: saved::DEFAULT_TTL_SECS,
+            prewarm_secs: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct EgressSettings {
+    proxy: Option<String>,
+    #[serde(deserialize_with = "string_list")]
+    no_proxy: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TelemetrySettings {
+    otlp_endpoint: Option<String>,
+    service_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DatabaseSettings {
+    url: Option<String>,
+    max_connections: u32,
+    refresh: RefreshSettings,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        Self {
+            url: None,
+            max_connections: 10,
+            refresh: RefreshSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RefreshSettings {
+    max_age_secs: Option<u64>,
+    interval_secs: u64,
+    pause_ms: u64,
+}
+
+impl Default for RefreshSettings {
+    fn default() -> Self {
+        Self {
+            max_age_secs: None,
+            interval_secs: 3600,
+            pause_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct KafkaSettings {
+    #[serde(deserialize_with = "string_list")]
+    brokers: Vec<String>,
+    topic: String,
+}
+
+impl Default for KafkaSettings {
+    fn default() -> Self {
+        Self {
+            brokers: Vec::new(),
+            topic: "spotify.tracks.resolved".into(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct NatsSettings {
+    url: Option<String>,
+    events_subject: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct JobsSettings {
+    callback_secret: Option<String>,
+    #[serde(deserialize_with = "string_list")]
+    callback_allowed_hosts: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AdminSettings {
+    token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSettings {
+    jwt: JwtSettings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct JwtSettings {
+    secret: Option<String>,
+    jwks_url: Option<String>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    tenant_claim: String,
+}
+
+impl Default for JwtSettings {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            jwks_url: None,
+            issuer: None,
+            audience: None,
+            tenant_claim: "tenant".into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SnapshotSettings {
+    bucket: Option<String>,
+    prefix: String,
+    endpoint: Option<String>,
+    region: Option<String>,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    hour_utc: u8,
+}
+
+impl Default for SnapshotSettings {
+    fn default() -> Self {
+        Self {
+            bucket: None,
+            prefix: "snapshots".into(),
+            endpoint: None,
+            region: None,
+            access_key_id: None,
+            secret_access_key: None,
+            hour_utc: 3,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuditSettings {
+    file: Option<PathBuf>,
+    max_file_bytes: u64,
+    max_files: u32,
+    storage: bool,
+    hash_key: Option<String>,
+}
+
+impl Default for AuditSettings {
+    fn default() -> Self {
+        Self {
+            file: None,
+            max_file_bytes: 100 * 1024 * 1024,
+            max_files: 5,
+            storage: false,
+            hash_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LocalSearchSettings {
+    enabled: bool,
+    sync_interval_secs: u64,
+}
+
+impl Default for LocalSearchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sync_interval_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SuggestSettings {
+    cache_ttl_secs: u64,
+    debounce_ms: u64,
+}
+
+impl Default for SuggestSettings {
+    fn default() -> Self {
+        Self {
+            cache_ttl_secs: 600,
+            debounce_ms: 150,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SearchSettings {
+    default_limit: u32,
+    max_limit: u32,
+    max_offset: u32,
+    strict_limits: bool,
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        let limits = SearchLimits::default();
+        Self {
+            default_limit: limits.default_limit,
+            max_limit: limits.max_limit,
+            max_offset: limits.max_offset,
+            strict_limits: limits.strict,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsSettings {
+    max_body_bytes: usize,
+    max_batch_ids: usize,
+    max_query_string_bytes: usize,
+}
+
+impl Default for LimitsSettings {
+    fn default() -> Self {
+        let limits = RequestLimits::default();
+        Self {
+            max_body_bytes: limits.max_body_bytes,
+            max_batch_ids: limits.max_batch_ids,
+            max_query_string_bytes: limits.max_query_string_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SearchCacheSettings {
+    /// 0 turns the cache off.
+    ttl_secs: u64,
+    stale_secs: u64,
+}
+
+impl Default for SearchCacheSettings {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 60,
+            stale_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CacheWarmSettings {
+    file: Option<PathBuf>,
+    pause_ms: u64,
+}
+
+impl Default for CacheWarmSettings {
+    fn default() -> Self {
+        Self {
+            file: None,
+            pause_ms: 200,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RuntimeSettings {
+    worker_threads: Option<usize>,
+    max_blocking_threads: usize,
+    blocking_keep_alive_secs: u64,
+}
+
+impl Default for RuntimeSettings {
+    fn default() -> Self {
+        Self {
+            worker_threads: None,
+            max_blocking_threads: 512,
+            blocking_keep_alive_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GrpcSettings {
+    enabled: bool,
+    unix_socket: Option<PathBuf>,
+    tls: TlsSettings,
+    #[serde(deserialize_with = "string_list")]
+    web_allowed_origins: Vec<String>,
+}
+
+#[cfg_attr(not(feature = "grpc"), allow(clippy::derivable_impls))]
+impl Default for GrpcSettings {
+    fn default() -> Self {
+        Self {
+            // Off by default in builds without the gRPC server.
+            enabled: cfg!(feature = "grpc"),
+            unix_socket: None,
+            tls: TlsSettings::default(),
+            web_allowed_origins: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSettings {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    client_ca: Option<PathBuf>,
 }
 
 impl Config {
-    pub fn from_env() -> anyhow::Result<Self> {
-        let port = env::var("PORT")
-            .ok()
-            .and_then(|p| p.parse().ok())
-            .unwrap_or(8081);
+    /// Load from the config file (if any) and environment.
+    pub fn load() -> anyhow::Result<Self> {
+        Self::from_figment(&Self::figment(None)?)
+    }
 
-        let grpc_port = env::var("GRPC_PORT")
-            .ok()
-            .and_then(|p| p.parse().ok())
-            .unwrap_or(50051);
+    /// Defaults, then the config file, then environment variables. Merge more
+    /// providers on top (e.g. CLI flags) before [`from_figment`](Self::from_figment).
+    ///
+    /// The file is `config_file`, or `CONFIG_FILE` if that is unset. Without
+    /// either no file is read, whatever is in the working directory.
+    pub fn figment(config_file: Option<&Path>) -> anyhow::Result<Figment> {
+        let file = config_file
+            .map(|p| p.as_os_str().to_owned())
+            .or_else(|| env::var_os("CONFIG_FILE"))
+            .filter(|v| !v.is_empty());
+        let mut figment = Figment::new();
+        if let Some(path) = file {
+            let path = PathBuf::from(path);
+            anyhow::ensure!(path.is_file(), "config file {} not found", path.display());
+            figment = figment.merge(file_provider(&path)?);
+        }
+        Ok(figment.merge(env_provider()).merge(env_file_provider()?))
+    }
+
+    /// Extract and validate a layered configuration.
+    pub fn from_figment(figment: &Figment) -> anyhow::Result<Self> {
+        let settings: Settings = figment.extract_lossy()?;
+
+        let spotify_mock = settings.spotify.mock;
+        let required = |value: Option<String>, key: &str, var: &str| match value {
+            Some(v) => Ok(v),
+            None if spotify_mock => Ok(String::new()),
+            None => Err(anyhow::anyhow!("{} ({}) is required (or set SPOTIFY_MOCK=1)", key, var)),
+        };
+        let spotify_client_id = required(settings.spotify.client_id, "spotify.client_id", "SPOTIFY_CLIENT_ID")?;
+        let spotify_client_secret =
+            required(settings.spotify.client_secret, "spotify.client_secret", "SPOTIFY_CLIENT_SECRET")?;
+        let spotify_client_secret = Secret::new(spotify_client_secret);
+
+        let spotify_user_agent = settings.spotify.user_agent.filter(|s| !s.trim().is_empty());
+        let http_url = |value: String, var: &str| -> anyhow::Result<String> {
+            let url =
+                reqwest::Url::parse(value.trim()).with_context(|| format!("{} is not a valid URL: {}", var, value))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                anyhow::bail!("{} must be an http:// or https:// URL", var);
+            }
+            Ok(value.trim().trim_end_matches('/').to_string())
+        };
+        let spotify_api_base = http_url(settings.spotify.api_base, "SPOTIFY_API_BASE")?;
+        let spotify_token_url = http_url(settings.spotify.token_url, "SPOTIFY_TOKEN_URL")?;
+        if settings.spotify.pool_idle_timeout_secs == 0 {
+            anyhow::bail!("SPOTIFY_POOL_IDLE_TIMEOUT_SECS must be at least 1");
+        }
+        if settings.runtime.worker_threads == Some(0) {
+            anyhow::bail!("RUNTIME_WORKER_THREADS must be at least 1");
+        }
+        if settings.runtime.max_blocking_threads == 0 {
+            anyhow::bail!("RUNTIME_MAX_BLOCKING_THREADS must be at least 1");
+        }
+        if !(1..=50).contains(&settings.spotify.search_chunk_size) {
+            anyhow::bail!("SPOTIFY_SEARCH_CHUNK_SIZE must be between 1 and 50");
+        }
+        if settings.spotify.search_concurrency == 0 {
+            anyhow::bail!("SPOTIFY_SEARCH_CONCURRENCY must be at least 1");
+        }
+        let proxy = |value: Option<String>, var: &str| -> anyhow::Result<Option<String>> {
+            let Some(proxy) = value.filter(|s| !s.trim().is_empty()) else {
+                return Ok(None);
+            };
+            let url = reqwest::Url::parse(&proxy)
+                .with_context(|| format!("{} is not a valid URL: {}", var, redact_password(&proxy)))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                anyhow::bail!("{} must be an http:// or https:// URL", var);
+            }
+            Ok(Some(proxy))
+        };
+        let spotify_proxy = proxy(settings.spotify.proxy, "SPOTIFY_PROXY")?;
+        let egress_proxy = proxy(settings.egress.proxy, "EGRESS_PROXY")?;
+        let egress_no_proxy: Vec<String> =
+            settings.egress.no_proxy.into_iter().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+
+        let spotify_market = settings.spotify.market.filter(|s| !s.trim().is_empty());
+        if let Some(market) = &spotify_market {
+            if market.len() != 2 || !market.chars().all(|c| c.is_ascii_alphabetic()) {
+                anyhow::bail!("SPOTIFY_MARKET must be a two-letter country code, e.g. US");
+            }
+        }
+        let spotify_market = spotify_market.map(|m| m.to_ascii_uppercase());
+
+        let mut tenants = Vec::new();
+        let mut api_keys = std::collections::HashSet::new();
+        let mut client_certs = std::collections::HashSet::new();
+        for (name, tenant) in settings.tenants {
+            let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if name.is_empty() || !name.chars().all(valid_name) {
+                anyhow::bail!("tenant name '{}' may only contain letters, digits, '-' and '_'", name);
+            }
+            if name == DEFAULT_TENANT {
+                anyhow::bail!("tenant name '{}' is reserved for the SPOTIFY_CLIENT_ID credentials", name);
+            }
+            let credential = |value: Option<String>, field: &str| match value.filter(|s| !s.trim().is_empty()) {
+                Some(v) => Ok(v),
+                None if spotify_mock => Ok(String::new()),
+                None => Err(anyhow::anyhow!("tenants.{}.{} is required", name, field)),
+            };
+            let client_id = credential(tenant.client_id, "client_id")?;
+            let client_secret = credential(tenant.client_secret, "client_secret").map(Secret::new)?;
+            if !tenant.api_keys.iter().all(|k| api_keys.insert(k.clone())) {
+                anyhow::bail!("tenants.{}.api_keys repeats an API key already given to a tenant", name);
+            }
+            if let Some(cert) = tenant.client_certs.iter().find(|c| !client_certs.insert(c.to_string())) {
+                anyhow::bail!("tenants.{}.client_certs repeats '{}', already given to a tenant", name, cert);
+            }
+            tenants.push(TenantConfig {
+                name,
+                client_id,
+                client_secret,
+                api_keys: tenant.api_keys.into_iter().map(Secret::new).collect(),
+                client_certs: tenant.client_certs,
+            });
+        }
 
-        let spotify_client_id = env::var("SPOTIFY_CLIENT_ID")
-            .map_err(|_| anyhow::anyhow!("SPOTIFY_CLIENT_ID is required"))?;
+        let mut saved_queries = Vec::new();
+        for (alias, saved) in settings.saved_queries {
+            let valid_alias = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if alias.is_empty() || !alias.chars().all(valid_alias) {
+                anyhow::bail!("saved query alias '{}' may only contain letters, digits, '-' and '_'", alias);
+            }
+            let Some(query) = saved.query.filter(|q| !q.trim().is_empty()) else {
+                anyhow::bail!("saved_queries.{}.query is required", alias);
+            };
+            if saved.ttl_secs == 0 {
+                anyhow::bail!("saved_queries.{}.ttl_secs must be at least 1", alias);
+            }
+            if saved.prewarm_secs.is_some_and(|secs| secs < saved::MIN_PREWARM_SECS) {
+                anyhow::bail!("saved_queries.{}.prewarm_secs must be at least {}", alias, saved::MIN_PREWARM_SECS);
+            }
+            saved_queries.push(SavedQueryConfig {
+                alias,
+                query,
+                ttl: Duration::from_secs(saved.ttl_secs),
+                prewarm_interval: saved.prewarm_secs.map(Duration::from_secs),
+            });
+        }
 
-        let spotify_client_secret = env::var("SPOTIFY_CLIENT_SECRET")
-            .map_err(|_| anyhow::anyhow!("SPOTIFY_CLIENT_SECRET is required"))?;
+        let tls = settings.grpc.tls;
+        let grpc_tls = match (tls.cert, tls.key) {
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                cert_path,
+                key_path,
+                client_ca_path: tls.client_ca,
+            }),
+            (None, None) => None,
+            _ => anyhow::bail!("grpc.tls.cert (GRPC_TLS_CERT) and grpc.tls.key (GRPC_TLS_KEY) must be set together"),
+        };
+
+        let http_tls = match (settings.http.tls.cert, settings.http.tls.key) {
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                cert_path,
+                key_path,
+                client_ca_path: settings.http.tls.client_ca,
+            }),
+            (None, None) if settings.http.tls.client_ca.is_some() => {
+                anyhow::bail!("HTTP_TLS_CLIENT_CA needs HTTP_TLS_CERT and HTTP_TLS_KEY")
+            }
+            (None, None) => None,
+            _ => anyhow::bail!("http.tls.cert (HTTP_TLS_CERT) and http.tls.key (HTTP_TLS_KEY) must be set together"),
+        };
+        let verifies_clients = |tls: &Option<TlsConfig>| tls.as_ref().is_some_and(|t| t.client_ca_path.is_some());
+        if !client_certs.is_empty() && !verifies_clients(&http_tls) && !verifies_clients(&grpc_tls) {
+            anyhow::bail!("tenants with client_certs need HTTP_TLS_CLIENT_CA or GRPC_TLS_CLIENT_CA");
+        }
+        let http_tls_reload_interval = settings
+            .http
+            .tls
+            .reload_interval_secs
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs);
+
+        let (http_enabled, grpc_enabled) = (settings.http.enabled, settings.grpc.enabled);
+        if !http_enabled && !grpc_enabled {
+            anyhow::bail!("HTTP_ENABLED and GRPC_ENABLED are both false; nothing to serve");
+        }
+        if grpc_enabled && cfg!(not(feature = "grpc")) {
+            anyhow::bail!("GRPC_ENABLED is set but this build has no gRPC server (enable the `grpc` feature)");
+        }
+        if settings.single_port && !(http_enabled && grpc_enabled) {
+            anyhow::bail!("SINGLE_PORT needs both the HTTP and gRPC servers enabled");
+        }
+        let (http_unix_socket, grpc_unix_socket) = (settings.http.unix_socket, settings.grpc.unix_socket);
+        if cfg!(not(unix)) && (http_unix_socket.is_some() || grpc_unix_socket.is_some()) {
+            anyhow::bail!("Unix socket listeners are only supported on Unix");
+        }
+        if settings.single_port && (http_unix_socket.is_some() || grpc_unix_socket.is_some()) {
+            anyhow::bail!("SINGLE_PORT cannot be combined with HTTP_UNIX_SOCKET or GRPC_UNIX_SOCKET");
+        }
+        if settings.single_port && (grpc_tls.is_some() || http_tls.is_some()) {
+            anyhow::bail!("SINGLE_PORT cannot be combined with GRPC_TLS or HTTP_TLS; terminate TLS at the ingress instead");
+        }
+        let database_url = settings.database.url.filter(|s| !s.trim().is_empty());
+        if let Some(url) = &database_url {
+            let (feature, enabled) = match url.split(':').next() {
+                Some("sqlite") => ("sqlite", cfg!(feature = "sqlite")),
+                Some("postgres" | "postgresql") => ("postgres", cfg!(feature = "postgres")),
+                _ => anyhow::bail!("DATABASE_URL must be a sqlite: or postgres: URL"),
+            };
+            if !enabled {
+                anyhow::bail!("DATABASE_URL needs the `{}` feature, which this build doesn't have", feature);
+            }
+        }
+        if settings.database.max_connections == 0 {
+            anyhow::bail!("DATABASE_MAX_CONNECTIONS must be at least 1");
+        }
+        if settings.offline_mode && database_url.is_none() {
+            anyhow::bail!("OFFLINE_MODE serves from the local store and needs DATABASE_URL");
+        }
+        let refresh = settings.database.refresh;
+        let refresh_max_age = refresh.max_age_secs.filter(|&secs| secs > 0).map(Duration::from_secs);
+        if refresh_max_age.is_some() && (database_url.is_none() || settings.offline_mode) {
+            anyhow::bail!("REFRESH_MAX_AGE_SECS needs DATABASE_URL and can't be combined with OFFLINE_MODE");
+        }
+        if refresh.interval_secs == 0 {
+            anyhow::bail!("REFRESH_INTERVAL_SECS must be at least 1");
+        }
+        let kafka = settings.kafka;
+        if !kafka.brokers.is_empty() && cfg!(not(feature = "kafka")) {
+            anyhow::bail!("KAFKA_BROKERS is set but this build has no Kafka producer (enable the `kafka` feature)");
+        }
+        if kafka.topic.trim().is_empty() {
+            anyhow::bail!("KAFKA_TOPIC cannot be empty");
+        }
+        let nats_url = settings.nats.url.filter(|s| !s.trim().is_empty());
+        if nats_url.is_some() && cfg!(not(feature = "nats")) {
+            anyhow::bail!("NATS_URL is set but this build has no NATS interface (enable the `nats` feature)");
+        }
+        let nats_events_subject = settings.nats.events_subject.filter(|s| !s.trim().is_empty());
+        if nats_events_subject.is_some() && nats_url.is_none() {
+            anyhow::bail!("NATS_EVENTS_SUBJECT needs NATS_URL");
+        }
+        let jwt = settings.auth.jwt;
+        let nonempty = |s: Option<String>| s.filter(|s| !s.trim().is_empty());
+        let jwt_key = match (nonempty(jwt.secret), nonempty(jwt.jwks_url)) {
+            (Some(secret), None) => Some(JwtKey::Secret(Secret::new(secret))),
+            (None, Some(url)) => {
+                let parsed =
+                    reqwest::Url::parse(&url).with_context(|| format!("JWT_JWKS_URL is not a valid URL: {}", url))?;
+                if !matches!(parsed.scheme(), "http" | "https") {
+                    anyhow::bail!("JWT_JWKS_URL must be an http:// or https:// URL");
+                }
+                Some(JwtKey::JwksUrl(url))
+            }
+            (None, None) => None,
+            (Some(_), Some(_)) => anyhow::bail!("set one of JWT_SECRET and JWT_JWKS_URL, not both"),
+        };
+        if jwt_key.is_some() && cfg!(not(feature = "jwt")) {
+            anyhow::bail!("a JWT key is set but this build has no JWT support (enable the `jwt` feature)");
+        }
+        if jwt.tenant_claim.trim().is_empty() {
+            anyhow::bail!("JWT_TENANT_CLAIM cannot be empty");
+        }
+        let jwt = jwt_key.map(|key| JwtConfig {
+            key,
+            issuer: nonempty(jwt.issuer),
+            audience: nonempty(jwt.audience),
+            tenant_claim: jwt.tenant_claim,
+        });
+        let snapshots = settings.snapshots;
+        let snapshots = match snapshots.bucket.filter(|s| !s.trim().is_empty()) {
+            Some(bucket) => {
+                if cfg!(not(feature = "s3")) {
+                    anyhow::bail!(
+                        "SNAPSHOT_S3_BUCKET is set but this build has no S3 snapshots (enable the `s3` feature)"
+                    );
+                }
+                if database_url.is_none() {
+                    anyhow::bail!("SNAPSHOT_S3_BUCKET snapshots the local store and needs DATABASE_URL");
+                }
+                if snapshots.hour_utc > 23 {
+                    anyhow::bail!("SNAPSHOT_HOUR_UTC must be between 0 and 23");
+                }
+                let nonempty = |s: Option<String>| s.filter(|s| !s.trim().is_empty());
+                let (access_key_id, secret_access_key) =
+                    (nonempty(snapshots.access_key_id), nonempty(snapshots.secret_access_key));
+                if access_key_id.is_some() != secret_access_key.is_some() {
+                    anyhow::bail!("SNAPSHOT_S3_ACCESS_KEY_ID and SNAPSHOT_S3_SECRET_ACCESS_KEY must be set together");
+                }
+                Some(SnapshotConfig {
+                    bucket,
+                    prefix: snapshots.prefix,
+                    endpoint: nonempty(snapshots.endpoint),
+                    region: nonempty(snapshots.region),
+                    access_key_id,
+                    secret_access_key: secret_access_key.map(Secret::new),
+                    hour_utc: snapshots.hour_utc,
+                })
+            }
+            None => None,
+        };
+        let audit = settings.audit;
+        let audit_file = audit.file.filter(|p| !p.as_os_str().is_empty());
+        if audit.max_file_bytes < 4096 {
+            anyhow::bail!("AUDIT_MAX_FILE_BYTES must be at least 4096");
+        }
+        if audit.max_files == 0 {
+            anyhow::bail!("AUDIT_MAX_FILES must be at least 1");
+        }
+        if audit.storage && database_url.is_none() {
+            anyhow::bail!("AUDIT_STORAGE writes to the local store and needs DATABASE_URL");
+        }
+        let audit = (audit_file.is_some() || audit.storage).then_some(AuditConfig {
+            file: audit_file,
+            max_file_bytes: audit.max_file_bytes,
+            max_files: audit.max_files,
+            storage: audit.storage,
+            hash_key: audit.hash_key.filter(|k| !k.is_empty()).map(Secret::new),
+        });
+        let local_search = settings.local_search;
+        if local_search.enabled {
+            if cfg!(not(feature = "local-search")) {
+                anyhow::bail!(
+                    "LOCAL_SEARCH is set but this build has no local search (enable the `local-search` feature)"
+                );
+            }
+            if database_url.is_none() {
+                anyhow::bail!("LOCAL_SEARCH indexes the local store and needs DATABASE_URL");
+            }
+        }
+        if local_search.sync_interval_secs == 0 {
+            anyhow::bail!("LOCAL_SEARCH_SYNC_INTERVAL_SECS must be at least 1");
+        }
+        let search = settings.search;
+        if search.max_limit == 0 {
+            anyhow::bail!("SEARCH_MAX_LIMIT must be at least 1");
+        }
+        if !(1..=search.max_limit).contains(&search.default_limit) {
+            anyhow::bail!("SEARCH_DEFAULT_LIMIT must be between 1 and SEARCH_MAX_LIMIT ({})", search.max_limit);
+        }
+        let limits = settings.limits;
+        if limits.max_body_bytes < 1024 {
+            anyhow::bail!("LIMITS_MAX_BODY_BYTES must be at least 1024");
+        }
+        if !(1..=SPOTIFY_MAX_BATCH_IDS).contains(&limits.max_batch_ids) {
+            anyhow::bail!("LIMITS_MAX_BATCH_IDS must be between 1 and {}", SPOTIFY_MAX_BATCH_IDS);
+        }
+        if limits.max_query_string_bytes < 256 {
+            anyhow::bail!("LIMITS_MAX_QUERY_STRING_BYTES must be at least 256");
+        }
+        let request_limits = RequestLimits {
+            max_body_bytes: limits.max_body_bytes,
+            max_batch_ids: limits.max_batch_ids,
+            max_query_string_bytes: limits.max_query_string_bytes,
+        };
 
         Ok(Self {
-            port,
-            grpc_port,
+            port: settings.port,
+            grpc_port: settings.grpc_port,
+            bind_addr: settings.bind_addr,
+            grpc_bind_addr: settings.grpc_bind_addr.unwrap_or(settings.bind_addr),
+            http_unix_socket,
+            grpc_unix_socket,
+            http_enabled,
+            grpc_enabled,
             spotify_client_id,
             spotify_client_secret,
+            spotify_mock,
+            spotify_timeout: settings.spotify.timeout_secs.map(Duration::from_secs),
+            spotify_connect_timeout: settings.spotify.connect_timeout_secs.map(Duration::from_secs),
+            spotify_user_agent,
+            spotify_api_base,
+            spotify_token_url,
+            spotify_pool_max_idle_per_host: settings.spotify.pool_max_idle_per_host,
+            spotify_pool_idle_timeout: Duration::from_secs(settings.spotify.pool_idle_timeout_secs),
+            spotify_tcp_keepalive: Some(settings.spotify.tcp_keepalive_secs)
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            spotify_http2: settings.spotify.http2,
+            spotify_proxy,
+            egress_proxy,
+            egress_no_proxy,
+            spotify_search_chunk_size: settings.spotify.search_chunk_size,
+            spotify_search_concurrency: settings.spotify.search_concurrency,
+            spotify_market,
+            spotify_transliterate_queries: settings.spotify.transliterate_queries,
+            spotify_lenient_schema: settings.spotify.lenient_schema,
+            spotify_schema_samples: settings.spotify.schema_samples,
+            tenants,
+            otlp_endpoint: settings.telemetry.otlp_endpoint.filter(|s| !s.trim().is_empty()),
+            service_name: settings.telemetry.service_name.unwrap_or_else(|| "spotify-search".into()),
+            startup_check: settings.startup_check,
+            log_format: settings.log.format,
+            log_level: settings.log.level,
+            access_log_redact_query: settings.access_log.redact_query,
+            http_tls,
+            http_tls_reload_interval,
+            grpc_tls,
+            grpc_web_allowed_origins: settings.grpc.web_allowed_origins,
+            single_port: settings.single_port,
+            database_url,
+            database_max_connections: settings.database.max_connections,
+            offline_mode: settings.offline_mode,
+            refresh_max_age,
+            refresh_interval: Duration::from_secs(refresh.interval_secs),
+            refresh_pause: Duration::from_millis(refresh.pause_ms),
+            kafka_brokers: kafka.brokers,
+            kafka_topic: kafka.topic,
+            nats_url,
+            nats_events_subject,
+            job_callback_secret: settings.jobs.callback_secret.filter(|s| !s.is_empty()).map(Secret::new),
+            job_callback_allowed_hosts: settings.jobs.callback_allowed_hosts,
+            admin_token: settings.admin.token.filter(|s| !s.is_empty()).map(Secret::new),
+            jwt,
+            snapshots,
+            audit,
+            local_search: local_search.enabled,
+            local_search_sync_interval: Duration::from_secs(local_search.sync_interval_secs),
+            suggest_cache_ttl: Duration::from_secs(settings.suggest.cache_ttl_secs),
+            suggest_debounce: Duration::from_millis(settings.suggest.debounce_ms),
+            search_limits: SearchLimits {
+                default_limit: search.default_limit,
+                max_limit: search.max_limit,
+                max_offset: search.max_offset,
+                strict: search.strict_limits,
+            },
+            request_limits,
+            search_cache_ttl: Some(settings.search_cache.ttl_secs)
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            search_cache_stale: Duration::from_secs(settings.search_cache.stale_secs),
+            saved_queries,
+            cache_warm_file: settings.cache_warm.file.filter(|file| !file.as_os_str().is_empty()),
+            cache_warm_pause: Duration::from_millis(settings.cache_warm.pause_ms),
+            runtime_worker_threads: settings.runtime.worker_threads,
+            runtime_max_blocking_threads: settings.runtime.max_blocking_threads,
+            runtime_blocking_keep_alive: Duration::from_secs(settings.runtime.blocking_keep_alive_secs),
         })
     }
 }
+
+/// `url` with any password in its userinfo replaced, for logs.
+pub fn redact_password(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let authority_end = rest.find('/').unwrap_or(rest.len());
+    match rest[..authority_end].rsplit_once('@') {
+        Some((userinfo, host)) if userinfo.contains(':') => {
+            let user = userinfo.split(':').next().unwrap_or_default();
+            format!("{}://{}:[redacted]@{}{}", scheme, user, host, &rest[authority_end..])
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// TOML or YAML, by extension.
+fn file_provider(path: &Path) -> anyhow::Result<Figment> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => Ok(Figment::from(Toml::file_exact(path))),
+        Some("yaml" | "yml") => Ok(Figment::from(Yaml::file_exact(path))),
+        _ => anyhow::bail!("config file {} must end in .toml, .yaml or .yml", path.display()),
+    }
+}
+
+/// [`ENV_KEYS`] variables that are set and non-empty, mapped to their config keys.
+fn env_provider() -> Env {
+    let set: Vec<&str> = ENV_KEYS
+        .iter()
+        .map(|(var, _)| *var)
+        .filter(|var| env::var(var).is_ok_and(|v| !v.trim().is_empty()))
+        .collect();
+    Env::raw().only(&set).map(|var| {
+        ENV_KEYS
+            .iter()
+            .find(|(name, _)| var == *name)
+            .map_or(var.as_str(), |(_, key)| key)
+            .into()
+    })
+}
+
+/// `{VAR}_FILE` for any of [`ENV_KEYS`]: the trimmed contents of that file.
+fn env_file_provider() -> anyhow::Result<Figment> {
+    let mut figment = Figment::new();
+    for (var, key) in ENV_KEYS {
+        let file_var = format!("{}_FILE", var);
+        let Some(path) = env::var_os(&file_var).filter(|p| !p.is_empty()) else {
+            continue;
+        };
+        if env::var(var).is_ok_and(|v| !v.trim().is_empty()) {
+            anyhow::bail!("{} and {} are both set", var, file_var);
+        }
+        let path = PathBuf::from(path);
+        let value = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {} ({})", file_var, path.display()))?;
+        figment = figment.merge(Serialized::default(key, value.trim()));
+    }
+    Ok(figment)
+}
+
+/// Parse a `FromStr` value that environment variables may hand over as a bare
+/// boolean or number.
+fn from_scalar<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    struct Scalar;
+
+    impl serde::de::Visitor<'_> for Scalar {
+        type Value = String;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a string")
+        }
+
+        fn visit_str<E>(self, v: &str) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+
+        fn visit_bool<E>(self, v: bool) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+
+        fn visit_i64<E>(self, v: i64) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+
+        fn visit_u64<E>(self, v: u64) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+    }
+
+    deserializer
+        .deserialize_any(Scalar)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+/// A list, or a comma-separated string as environment variables provide it.
+fn string_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum List {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let items = match List::deserialize(deserializer)? {
+        List::One(s) => s.split(',').map(str::to_string).collect(),
+        List::Many(items) => items,
+    };
+    Ok(items
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect())
+}
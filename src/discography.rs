@@ -0,0 +1,73 @@
+//! Artist discography sync, run as a [`jobs`](crate::jobs) job.
+//!
+//! The job walks the artist's albums, singles and compilations, then each album's
+//! tracks, and fetches full metadata and audio features for every track. As with
+//! [playlist ingests](crate::ingest), the tracks, features and embeddings are
+//! persisted by [`StoredSpotifyApi`](crate::storage::StoredSpotifyApi), so the sync
+//! needs `DATABASE_URL`.
+
+use std::collections::HashSet;
+
+use crate::jobs::{retry_rate_limited, JobHandle};
+use crate::spotify::{DynSpotifyApi, MAX_ALBUM_TRACKS_PAGE, MAX_ARTIST_ALBUMS_PAGE};
+
+/// Job kind for artist discography syncs.
+pub const KIND: &str = "artist_discography_sync";
+
+/// Tracks per `get_tracks_with_features` call, Spotify's `/tracks` limit.
+const TRACKS_CHUNK: usize = 50;
+
+/// Walk artist `artist_id`'s albums and their tracks, fetching each album's tracks
+/// with features. Progress counts albums; the stored track IDs are the job's results.
+/// Failing to read an album or track page fails the job; failing to fetch a chunk's
+/// tracks is recorded as an error and skipped.
+pub async fn sync_artist(spotify: DynSpotifyApi, artist_id: String, job: JobHandle) -> anyhow::Result<()> {
+    let mut seen = HashSet::new();
+    let mut offset = 0;
+    loop {
+        let page = retry_rate_limited(|| {
+            spotify.get_artist_albums(&artist_id, Some(MAX_ARTIST_ALBUMS_PAGE), Some(offset))
+        })
+        .await?;
+        job.set_total(page.total);
+
+        for album in &page.albums {
+            let mut ids = Vec::new();
+            let mut track_offset = 0;
+            loop {
+                let tracks = retry_rate_limited(|| {
+                    spotify.get_album_tracks(&album.id, Some(MAX_ALBUM_TRACKS_PAGE), Some(track_offset))
+                })
+                .await?;
+                let items = tracks.tracks.len() as u32;
+                ids.extend(tracks.tracks.into_iter().map(|t| t.id).filter(|id| seen.insert(id.clone())));
+                track_offset += items;
+                if items == 0 || track_offset >= tracks.total {
+                    break;
+                }
+            }
+
+            for chunk in ids.chunks(TRACKS_CHUNK) {
+                match retry_rate_limited(|| spotify.get_tracks_with_features(chunk)).await {
+                    Ok(tracks) => {
+                        job.count("tracks", tracks.len() as u32);
+                        job.count(
+                            "without_features",
+                            tracks.iter().filter(|t| t.audio_features.is_none()).count() as u32,
+                        );
+                        job.push_results(tracks.into_iter().map(|t| serde_json::Value::String(t.track.id)));
+                    }
+                    Err(e) => job.error(format!("tracks of album {}: {}", album.id, e)),
+                }
+            }
+            job.count("albums", 1);
+            job.advance(1);
+        }
+
+        let items = page.albums.len() as u32;
+        offset += items;
+        if items == 0 || offset >= page.total {
+            return Ok(());
+        }
+    }
+}
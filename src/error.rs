@@ -1,29 +1,126 @@
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde_json::json;
 
+use crate::spotify::SpotifyError;
+use crate::validation::FieldError;
+
 /// Application error type.
 #[derive(Debug)]
 pub enum AppError {
-    Spotify(String),
+    Spotify(SpotifyError),
     BadRequest(String),
+    NotFound(String),
+    /// A feature this request needs is not configured.
+    Unavailable(String),
+    /// An `Idempotency-Key` was reused with a different request.
+    IdempotencyKeyReused,
+    /// One or more invalid request parameters.
+    Validation(Vec<FieldError>),
+    /// The client accepts no media type the endpoint can produce.
+    NotAcceptable(String),
+    /// Missing or wrong admin token or API key.
+    Unauthorized(String),
+    /// The request body is over the configured size limit.
+    PayloadTooLarge(String),
     Internal(String),
 }
 
+impl AppError {
+    /// Stable machine-readable error category.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Spotify(SpotifyError::NotFound(_)) => "not_found",
+            AppError::Spotify(SpotifyError::RateLimited { .. }) => "rate_limited",
+            AppError::Spotify(SpotifyError::Auth(_)) => "upstream_auth_failed",
+            AppError::Spotify(SpotifyError::Network { .. }) => "upstream_unavailable",
+            AppError::Spotify(SpotifyError::Decode { .. }) => "upstream_decode_failed",
+            AppError::Spotify(SpotifyError::Upstream { .. }) => "upstream_error",
+            AppError::Spotify(SpotifyError::Unavailable(_)) => "upstream_unavailable",
+            AppError::Spotify(SpotifyError::Cassette(_)) => "cassette_miss",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::NotFound(_) => "not_found",
+            AppError::Unavailable(_) => "unavailable",
+            AppError::IdempotencyKeyReused => "idempotency_key_reused",
+            AppError::Validation(_) => "validation_failed",
+            AppError::NotAcceptable(_) => "not_acceptable",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::Internal(_) => "internal",
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let (status, message) = match &self {
-            AppError::Spotify(msg) => (StatusCode::BAD_GATEWAY, msg.clone()),
+            AppError::Spotify(err @ SpotifyError::NotFound(_)) => (StatusCode::NOT_FOUND, err.to_string()),
+            AppError::Spotify(err @ SpotifyError::RateLimited { .. }) => (StatusCode::TOO_MANY_REQUESTS, err.to_string()),
+            AppError::Spotify(SpotifyError::Auth(err)) => {
+                // Our credentials are the problem, not the caller's request.
+                tracing::error!("Spotify auth failure: {}", err);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Spotify authentication failed; the service credentials need attention".to_string(),
+                )
+            }
+            AppError::Spotify(err @ SpotifyError::Unavailable(_)) => (StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
+            AppError::Spotify(err) => (StatusCode::BAD_GATEWAY, err.to_string()),
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
+            AppError::Unavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg.clone()),
+            AppError::IdempotencyKeyReused => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used for a different request".to_string(),
+            ),
+            AppError::Validation(fields) => (
+                StatusCode::BAD_REQUEST,
+                format!("{} invalid parameter(s)", fields.len()),
+            ),
+            AppError::NotAcceptable(msg) => (StatusCode::NOT_ACCEPTABLE, msg.clone()),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg.clone()),
+            AppError::PayloadTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg.clone()),
             AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
         };
-        (
+        let unauthorized = matches!(self, AppError::Unauthorized(_));
+        let retry_after = match self {
+            AppError::Spotify(SpotifyError::RateLimited { retry_after }) => retry_after,
+            _ => None,
+        };
+
+        let mut error = json!({ "code": self.code(), "message": message });
+        if let Some(secs) = retry_after {
+            error["retry_after"] = json!(secs);
+        }
+        if let AppError::Validation(fields) = &self {
+            error["fields"] = json!(fields);
+        }
+        if let Some(id) = crate::access_log::current_request_id() {
+            error["request_id"] = json!(id);
+        }
+
+        let mut response = (
             status,
-            Json(json!({ "error": message })),
+            Json(json!({ "error": error })),
         )
-            .into_response()
+            .into_response();
+        if let Some(secs) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        if unauthorized {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+impl From<SpotifyError> for AppError {
+    fn from(err: SpotifyError) -> Self {
+        AppError::Spotify(err)
     }
 }
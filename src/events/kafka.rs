@@ -0,0 +1,109 @@
+//! [`EventSink`] producing to a Kafka topic.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use prost::Message;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+
+use super::{DeliveryError, EventSink};
+use crate::proto::TrackResolved;
+
+/// `type` header on every record, naming the protobuf message in the value.
+const EVENT_TYPE: &str = "spotify.TrackResolved";
+/// How long [`EventSink::deliver`] waits for room in the producer queue.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Produces [`TrackResolved`] events keyed by track ID, so every event for a track
+/// lands on the same partition.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaSink {
+    /// Producer for `brokers` (`host:port` bootstrap servers) publishing to `topic`.
+    /// Brokers are contacted in the background; this only fails on invalid settings.
+    pub fn new(brokers: &[String], topic: &str, client_id: &str) -> Result<Self, rdkafka::error::KafkaError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers.join(","))
+            .set("client.id", client_id)
+            .set("compression.type", "lz4")
+            // Producer retries don't duplicate records.
+            .set("enable.idempotence", "true")
+            .create()?;
+        Ok(Self {
+            producer,
+            topic: topic.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaSink {
+    fn publish(&self, event: TrackResolved) {
+        let key = event.track.as_ref().map(|t| t.id.clone()).unwrap_or_default();
+        let payload = event.encode_to_vec();
+        let record = FutureRecord::to(&self.topic).key(&key).payload(&payload).headers(headers());
+        match self.producer.send_result(record) {
+            Ok(delivery) => {
+                tokio::spawn(async move {
+                    match delivery.await {
+                        Ok(Ok(_)) => record_event("delivered"),
+                        Ok(Err((e, _))) => {
+                            tracing::warn!(track_id = %key, "kafka delivery failed: {}", e);
+                            record_event("failed");
+                        }
+                        // The producer was dropped with the event still queued.
+                        Err(_) => record_event("failed"),
+                    }
+                });
+            }
+            Err((e, _)) => {
+                tracing::warn!(track_id = %key, "dropping track event: {}", e);
+                record_event("dropped");
+            }
+        }
+    }
+
+    /// Adds an `event-id` header with `id`.
+    async fn deliver(&self, id: &str, event: &TrackResolved) -> Result<(), DeliveryError> {
+        let key = event.track.as_ref().map(|t| t.id.as_str()).unwrap_or_default();
+        let payload = event.encode_to_vec();
+        let headers = headers().insert(Header {
+            key: "event-id",
+            value: Some(id),
+        });
+        let record = FutureRecord::to(&self.topic).key(key).payload(&payload).headers(headers);
+        match self.producer.send(record, QUEUE_TIMEOUT).await {
+            Ok(_) => {
+                record_event("delivered");
+                Ok(())
+            }
+            Err((e, _)) => {
+                record_event("failed");
+                Err(DeliveryError(e.to_string()))
+            }
+        }
+    }
+}
+
+fn headers() -> OwnedHeaders {
+    OwnedHeaders::new()
+        .insert(Header {
+            key: "content-type",
+            value: Some("application/x-protobuf"),
+        })
+        .insert(Header {
+            key: "type",
+            value: Some(EVENT_TYPE),
+        })
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_event(result: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("kafka_events_total", "result" => result).increment(1);
+}
@@ -0,0 +1,241 @@
+//! Events for tracks resolved by the API, for downstream consumers that would
+//! otherwise poll.
+//!
+//! [`PublishingSpotifyApi`] wraps the Spotify backend and hands a
+//! [`TrackResolved`](crate::proto::TrackResolved) event to an [`EventSink`] for every
+//! track an endpoint returns with audio features: searches with features, track
+//! lookups, recommendations and similar tracks. Publishing never fails or slows the
+//! request; events that can't be queued are dropped and counted.
+//!
+//! With a local store, events go through its outbox instead: [`TrackEvents`] has
+//! [`StoredSpotifyApi`](crate::storage::StoredSpotifyApi) write them in the same
+//! transaction as the tracks, and [`OutboxRelay`] delivers them to the sink in the
+//! background, so none are lost to a crash or a broker outage.
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "nats")]
+mod nats;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+mod outbox;
+
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaSink;
+#[cfg(feature = "nats")]
+pub use nats::NatsSink;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub use outbox::{OutboxRelay, RelayError, TrackEvents};
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::clock::unix_millis;
+use crate::proto::{self, TrackResolved, REASON_NO_AUDIO_FEATURES};
+use crate::spotify::{
+    AlbumDetail, AlbumTracksPage, ArtistAlbumsPage, ArtistDetail, AudioFeatures, ByteStream, DynSpotifyApi,
+    PlaylistTracksPage, RecommendationSeeds, ScoredTrack, SearchTracksResponse, SearchTracksWithFeaturesResponse,
+    SpotifyApi, SpotifyError, TokenStatus, Track, TrackWithFeatures, UpstreamSnapshot,
+};
+
+/// An event the destination didn't acknowledge.
+#[derive(Debug, thiserror::Error)]
+#[error("event delivery failed: {0}")]
+pub struct DeliveryError(pub String);
+
+/// Where [`TrackResolved`] events go.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// Queue `event` for delivery. Must not block; delivery failures are the sink's to
+    /// log and count.
+    fn publish(&self, event: TrackResolved);
+
+    /// Send `event` tagged with its unique `id` and wait until the destination has
+    /// acknowledged it. Used by the [outbox relay](OutboxRelay), which retries failures.
+    async fn deliver(&self, id: &str, event: &TrackResolved) -> Result<(), DeliveryError>;
+}
+
+/// Shared handle to an [`EventSink`].
+pub type DynEventSink = Arc<dyn EventSink>;
+
+/// Sends every event to each of several sinks.
+pub struct FanoutSink(pub Vec<DynEventSink>);
+
+#[async_trait]
+impl EventSink for FanoutSink {
+    fn publish(&self, event: TrackResolved) {
+        for sink in &self.0 {
+            sink.publish(event.clone());
+        }
+    }
+
+    /// Fails if any sink fails; a retry sends the event to all of them again.
+    async fn deliver(&self, id: &str, event: &TrackResolved) -> Result<(), DeliveryError> {
+        for sink in &self.0 {
+            sink.deliver(id, event).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Events for `tracks` resolved by the `source` operation.
+pub fn track_resolved<'a>(source: &str, tracks: impl IntoIterator<Item = &'a TrackWithFeatures>) -> Vec<TrackResolved> {
+    let resolved_at_ms = unix_millis() as i64;
+    tracks
+        .into_iter()
+        .map(|t| {
+            let mut track = proto::TrackWithFeatures::from(t);
+            if t.embedding.is_none() {
+                track.missing_embedding_reason = REASON_NO_AUDIO_FEATURES.into();
+            }
+            TrackResolved {
+                track: Some(track),
+                resolved_at_ms,
+                source: source.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// [`SpotifyApi`] that publishes an event for every track it resolves with features.
+pub struct PublishingSpotifyApi {
+    inner: DynSpotifyApi,
+    sink: DynEventSink,
+}
+
+impl PublishingSpotifyApi {
+    pub fn new(inner: DynSpotifyApi, sink: DynEventSink) -> Self {
+        Self { inner, sink }
+    }
+
+    fn publish<'a>(&self, source: &str, tracks: impl IntoIterator<Item = &'a TrackWithFeatures>) {
+        for event in track_resolved(source, tracks) {
+            self.sink.publish(event);
+        }
+    }
+}
+
+#[async_trait]
+impl SpotifyApi for PublishingSpotifyApi {
+    async fn has_token(&self) -> bool {
+        self.inner.has_token().await
+    }
+
+    fn upstream_status(&self) -> UpstreamSnapshot {
+        self.inner.upstream_status()
+    }
+
+    fn token_status(&self) -> TokenStatus {
+        self.inner.token_status()
+    }
+
+    async fn refresh_token(&self) -> Result<(), SpotifyError> {
+        self.inner.refresh_token().await
+    }
+
+    async fn search_tracks(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksResponse, SpotifyError> {
+        self.inner.search_tracks(q, limit, offset).await
+    }
+
+    async fn search_tracks_with_features(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksWithFeaturesResponse, SpotifyError> {
+        let response = self.inner.search_tracks_with_features(q, limit, offset).await?;
+        self.publish("search", &response.tracks);
+        Ok(response)
+    }
+
+    async fn search_tracks_raw(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ByteStream, SpotifyError> {
+        self.inner.search_tracks_raw(q, limit, offset).await
+    }
+
+    async fn get_tracks(&self, ids: &[String]) -> Result<Vec<Option<Track>>, SpotifyError> {
+        self.inner.get_tracks(ids).await
+    }
+
+    async fn get_tracks_raw(&self, ids: &[String]) -> Result<ByteStream, SpotifyError> {
+        self.inner.get_tracks_raw(ids).await
+    }
+
+    async fn get_audio_features(&self, ids: &[String]) -> Result<Vec<Option<AudioFeatures>>, SpotifyError> {
+        self.inner.get_audio_features(ids).await
+    }
+
+    async fn get_tracks_with_features(&self, ids: &[String]) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        let tracks = self.inner.get_tracks_with_features(ids).await?;
+        self.publish("tracks_with_features", &tracks);
+        Ok(tracks)
+    }
+
+    async fn get_recommendations_with_features(
+        &self,
+        seeds: &RecommendationSeeds,
+        limit: Option<u32>,
+    ) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        let tracks = self.inner.get_recommendations_with_features(seeds, limit).await?;
+        self.publish("recommendations", &tracks);
+        Ok(tracks)
+    }
+
+    async fn get_similar_tracks(&self, id: &str, limit: Option<u32>) -> Result<Vec<ScoredTrack>, SpotifyError> {
+        let tracks = self.inner.get_similar_tracks(id, limit).await?;
+        self.publish("similar", tracks.iter().map(|t| &t.track));
+        Ok(tracks)
+    }
+
+    async fn get_playlist_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<PlaylistTracksPage, SpotifyError> {
+        self.inner.get_playlist_tracks(id, limit, offset).await
+    }
+
+    async fn get_artist_albums(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ArtistAlbumsPage, SpotifyError> {
+        self.inner.get_artist_albums(id, limit, offset).await
+    }
+
+    async fn get_album_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<AlbumTracksPage, SpotifyError> {
+        self.inner.get_album_tracks(id, limit, offset).await
+    }
+
+    async fn get_album(&self, id: &str) -> Result<AlbumDetail, SpotifyError> {
+        self.inner.get_album(id).await
+    }
+
+    async fn get_artist(&self, id: &str) -> Result<ArtistDetail, SpotifyError> {
+        self.inner.get_artist(id).await
+    }
+
+    async fn get_albums(&self, ids: &[String]) -> Result<Vec<Option<AlbumDetail>>, SpotifyError> {
+        self.inner.get_albums(ids).await
+    }
+
+    async fn get_artists(&self, ids: &[String]) -> Result<Vec<Option<ArtistDetail>>, SpotifyError> {
+        self.inner.get_artists(ids).await
+    }
+}
@@ -0,0 +1,74 @@
+//! [`EventSink`] publishing to a NATS subject.
+
+use async_nats::{Client, HeaderMap};
+use async_trait::async_trait;
+use prost::Message;
+
+use super::{DeliveryError, EventSink};
+use crate::proto::TrackResolved;
+
+/// Publishes [`TrackResolved`] events with the same `content-type` and `type` headers
+/// as the Kafka records. Core NATS keeps no messages; capture the subject in a
+/// JetStream stream to retain them.
+pub struct NatsSink {
+    client: Client,
+    subject: String,
+}
+
+impl NatsSink {
+    pub fn new(client: Client, subject: &str) -> Self {
+        Self {
+            client,
+            subject: subject.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for NatsSink {
+    fn publish(&self, event: TrackResolved) {
+        let client = self.client.clone();
+        let subject = self.subject.clone();
+        tokio::spawn(async move {
+            match send(&client, subject, &event, None).await {
+                Ok(()) => record_event("delivered"),
+                Err(e) => {
+                    tracing::warn!("dropping track event: {}", e);
+                    record_event("dropped");
+                }
+            }
+        });
+    }
+
+    /// Sends `id` as `Nats-Msg-Id`, which JetStream uses to drop duplicates, and waits
+    /// until the server has the message.
+    async fn deliver(&self, id: &str, event: &TrackResolved) -> Result<(), DeliveryError> {
+        let result = match send(&self.client, self.subject.clone(), event, Some(id)).await {
+            Ok(()) => self.client.flush().await.map_err(|e| DeliveryError(e.to_string())),
+            Err(e) => Err(DeliveryError(e.to_string())),
+        };
+        record_event(if result.is_ok() { "delivered" } else { "failed" });
+        result
+    }
+}
+
+async fn send(
+    client: &Client,
+    subject: String,
+    event: &TrackResolved,
+    id: Option<&str>,
+) -> Result<(), async_nats::PublishError> {
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", "application/x-protobuf");
+    headers.insert("type", "spotify.TrackResolved");
+    if let Some(id) = id {
+        headers.insert("Nats-Msg-Id", id);
+    }
+    client.publish_with_headers(subject, headers, event.encode_to_vec().into()).await
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_event(result: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("nats_events_total", "result" => result).increment(1);
+}
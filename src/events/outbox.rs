@@ -0,0 +1,127 @@
+//! Event delivery through the local store's outbox.
+//!
+//! [`TrackEvents`] encodes the events that
+//! [`StoredSpotifyApi`](crate::storage::StoredSpotifyApi) writes to the outbox along
+//! with the tracks they describe. [`OutboxRelay`] claims pending events, delivers them
+//! to an [`EventSink`](super::EventSink) and deletes them once acknowledged. An event
+//! is only deleted after its delivery, so a crash at any point means a redelivery
+//! rather than a lost event; redeliveries carry the same event ID for consumers to drop.
+
+use std::time::Duration;
+
+use prost::Message;
+
+use super::{track_resolved, DeliveryError, DynEventSink};
+use crate::clock::unix_now;
+use crate::proto::TrackResolved;
+use crate::spotify::TrackWithFeatures;
+use crate::storage::{DynStorage, OutboxEncoder, OutboxEvent, StorageError};
+
+/// Events claimed per batch.
+const BATCH_SIZE: u32 = 100;
+/// How long a claimed batch belongs to this relay before another may take it over.
+const CLAIM_SECS: i64 = 60;
+/// Wait when the outbox is empty.
+const IDLE_POLL: Duration = Duration::from_secs(1);
+/// First wait after a failure; doubles up to [`MAX_BACKOFF`].
+const BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// [`OutboxEncoder`] for [`TrackResolved`] events keyed by track ID.
+pub struct TrackEvents;
+
+impl OutboxEncoder for TrackEvents {
+    fn encode(&self, source: &str, tracks: &[TrackWithFeatures]) -> Vec<OutboxEvent> {
+        track_resolved(source, tracks)
+            .into_iter()
+            .map(|event| OutboxEvent {
+                id: uuid::Uuid::new_v4().to_string(),
+                key: event.track.as_ref().map(|t| t.id.clone()).unwrap_or_default(),
+                payload: event.encode_to_vec(),
+            })
+            .collect()
+    }
+}
+
+/// Background delivery of outbox events to a sink.
+pub struct OutboxRelay {
+    store: DynStorage,
+    sink: DynEventSink,
+}
+
+impl OutboxRelay {
+    pub fn new(store: DynStorage, sink: DynEventSink) -> Self {
+        Self { store, sink }
+    }
+
+    /// Deliver pending events as they appear, forever. Failures are logged and retried
+    /// with backoff.
+    pub async fn run(self) {
+        let mut backoff = BACKOFF;
+        loop {
+            match self.relay_batch().await {
+                Ok(0) => tokio::time::sleep(IDLE_POLL).await,
+                Ok(_) => backoff = BACKOFF,
+                Err(e) => {
+                    tracing::warn!("outbox relay failed, retrying in {:?}: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Claim one batch, deliver it in order and delete what was delivered. Returns the
+    /// number of events taken out of the outbox. Stops at the first failed delivery;
+    /// the rest of the batch is claimed again once its claim expires.
+    pub async fn relay_batch(&self) -> Result<usize, RelayError> {
+        let events = self.store.claim_outbox(BATCH_SIZE, unix_now() as i64 + CLAIM_SECS).await?;
+        let mut done = Vec::with_capacity(events.len());
+        let mut failure = None;
+        for event in events {
+            let decoded = match TrackResolved::decode(event.payload.as_slice()) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    // Retrying can't fix it; drop it rather than block the outbox.
+                    tracing::error!(event_id = %event.id, "dropping undecodable outbox event: {}", e);
+                    record_outbox("dropped");
+                    done.push(event.id);
+                    continue;
+                }
+            };
+            match self.sink.deliver(&event.id, &decoded).await {
+                Ok(()) => {
+                    record_outbox("delivered");
+                    done.push(event.id);
+                }
+                Err(e) => {
+                    record_outbox("failed");
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+        if !done.is_empty() {
+            self.store.delete_outbox(&done).await?;
+        }
+        match failure {
+            Some(e) => Err(RelayError::Delivery(e)),
+            None => Ok(done.len()),
+        }
+    }
+}
+
+/// Why an outbox batch stopped early.
+#[derive(Debug, thiserror::Error)]
+pub enum RelayError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Delivery(#[from] DeliveryError),
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_outbox(result: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("outbox_events_total", "result" => result).increment(1);
+}
@@ -0,0 +1,410 @@
+//! Streaming export of the stored tracks and embeddings, for ML pipelines.
+//!
+//! [`export_stream`] pages through the store in ID order, [`PAGE_SIZE`] tracks at a
+//! time, and encodes each page as soon as it is read, so an export of any size runs in
+//! constant memory. NDJSON has one JSON object per track; Parquet (`parquet` feature)
+//! has the same columns, one row group per page.
+//!
+//! Over HTTP the response has started by the time a page fails to read, so the failure
+//! can't be a status code. [`response_stream`] ends an NDJSON export with an
+//! `{"error": ...}` line instead. A Parquet file can't carry one, so its body is aborted
+//! and the client sees a truncated transfer rather than a short file.
+
+use std::ops::Range;
+
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+
+use crate::storage::{DynStorage, StorageError, StoredTrack};
+
+/// Tracks read from the store per page.
+pub const PAGE_SIZE: u32 = 5000;
+
+/// Output format of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Ndjson,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Failure producing an export.
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("encoding export: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "parquet")]
+    #[error("encoding export: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    /// The format needs a feature this build doesn't have.
+    #[error("this build has no {0} export (enable the `{0}` feature)")]
+    Unsupported(&'static str),
+}
+
+/// One exported track, as an NDJSON line.
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    id: &'a str,
+    name: &'a str,
+    artists: Vec<&'a str>,
+    album: &'a str,
+    duration_ms: u32,
+    popularity: u32,
+    explicit: bool,
+    /// Unix seconds.
+    fetched_at: i64,
+    /// `null` for tracks Spotify has no audio features for.
+    embedding: Option<&'a [f32]>,
+}
+
+impl<'a> From<&'a StoredTrack> for ExportRow<'a> {
+    fn from(stored: &'a StoredTrack) -> Self {
+        let track = &stored.track.track;
+        Self {
+            id: &track.id,
+            name: &track.name,
+            artists: track.artists.iter().map(|a| a.name.as_str()).collect(),
+            album: &track.album.name,
+            duration_ms: track.duration_ms,
+            popularity: track.popularity,
+            explicit: track.explicit,
+            fetched_at: stored.fetched_at,
+            embedding: stored.track.embedding.as_deref(),
+        }
+    }
+}
+
+/// Stream every stored track fetched within `fetched` (Unix seconds) as `format`.
+/// Fails up front if the format isn't available; an error while streaming is the
+/// stream's last item.
+pub fn export_stream(
+    store: DynStorage,
+    format: ExportFormat,
+    fetched: Range<i64>,
+) -> Result<impl Stream<Item = Result<Bytes, ExportError>>, ExportError> {
+    struct State {
+        store: DynStorage,
+        fetched: Range<i64>,
+        after: Option<String>,
+        encoder: Option<Encoder>,
+        rows: usize,
+    }
+
+    let state = State {
+        store,
+        fetched,
+        after: None,
+        encoder: Some(Encoder::new(format)?),
+        rows: 0,
+    };
+    Ok(futures::stream::try_unfold(state, move |mut state| async move {
+        let Some(mut encoder) = state.encoder.take() else {
+            return Ok(None);
+        };
+        let page = state
+            .store
+            .export_tracks(state.after.as_deref(), state.fetched.clone(), PAGE_SIZE)
+            .await
+            .inspect_err(|e| tracing::warn!(rows = state.rows, format = format.extension(), "export failed: {}", e))?;
+        state.rows += page.len();
+        let mut chunk = encoder.page(&page)?;
+        match page.last() {
+            Some(last) if page.len() == PAGE_SIZE as usize => {
+                state.after = Some(last.track.track.id.clone());
+                state.encoder = Some(encoder);
+            }
+            _ => {
+                chunk.extend(encoder.finish()?);
+                tracing::info!(rows = state.rows, format = format.extension(), "export finished");
+            }
+        }
+        Ok(Some((Bytes::from(chunk), state)))
+    }))
+}
+
+/// An [`export_stream`] as an HTTP response body. A failed NDJSON export ends with an
+/// `{"error": {"code": "export_failed", "message": ...}}` line; a failed Parquet export
+/// keeps its error, which aborts the body.
+pub fn response_stream(
+    export: impl Stream<Item = Result<Bytes, ExportError>>,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Bytes, ExportError>> {
+    export.map(move |chunk| match (chunk, format) {
+        (Err(e), ExportFormat::Ndjson) => {
+            let error = serde_json::json!({ "error": { "code": "export_failed", "message": e.to_string() } });
+            Ok(Bytes::from(format!("{}\n", error)))
+        }
+        (chunk, _) => chunk,
+    })
+}
+
+/// Encodes pages of tracks into chunks of the output file.
+enum Encoder {
+    Ndjson,
+    #[cfg(feature = "parquet")]
+    Parquet(Box<parquet_encoder::ParquetEncoder>),
+}
+
+impl Encoder {
+    fn new(format: ExportFormat) -> Result<Self, ExportError> {
+        match format {
+            ExportFormat::Ndjson => Ok(Encoder::Ndjson),
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => Ok(Encoder::Parquet(Box::new(parquet_encoder::ParquetEncoder::new()?))),
+            #[cfg(not(feature = "parquet"))]
+            ExportFormat::Parquet => Err(ExportError::Unsupported("parquet")),
+        }
+    }
+
+    /// The bytes for `rows`.
+    fn page(&mut self, rows: &[StoredTrack]) -> Result<Vec<u8>, ExportError> {
+        match self {
+            Encoder::Ndjson => {
+                let mut out = Vec::new();
+                for row in rows {
+                    serde_json::to_writer(&mut out, &ExportRow::from(row))?;
+                    out.push(b'\n');
+                }
+                Ok(out)
+            }
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(encoder) => encoder.page(rows),
+        }
+    }
+
+    /// The bytes after the last page.
+    fn finish(self) -> Result<Vec<u8>, ExportError> {
+        match self {
+            Encoder::Ndjson => Ok(Vec::new()),
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(encoder) => encoder.finish(),
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_encoder {
+    use std::sync::Arc;
+
+    use arrow_array::builder::{Float32Builder, ListBuilder, StringBuilder};
+    use arrow_array::{ArrayRef, BooleanArray, RecordBatch, StringArray, TimestampSecondArray, UInt32Array};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+    use parquet::arrow::ArrowWriter;
+    use parquet::basic::Compression;
+    use parquet::file::properties::WriterProperties;
+
+    use super::ExportError;
+    use crate::storage::StoredTrack;
+
+    /// Writes a Parquet file with one row group per page, handing out the bytes as
+    /// each row group is flushed.
+    pub(super) struct ParquetEncoder {
+        writer: ArrowWriter<Vec<u8>>,
+        schema: SchemaRef,
+    }
+
+    impl ParquetEncoder {
+        pub(super) fn new() -> Result<Self, ExportError> {
+            let schema = Arc::new(schema());
+            let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+            let writer = ArrowWriter::try_new(Vec::new(), schema.clone(), Some(props))?;
+            Ok(Self { writer, schema })
+        }
+
+        pub(super) fn page(&mut self, rows: &[StoredTrack]) -> Result<Vec<u8>, ExportError> {
+            if !rows.is_empty() {
+                self.writer.write(&self.batch(rows)?)?;
+                self.writer.flush()?;
+            }
+            // The writer counts bytes itself, so what it has written so far can be taken.
+            Ok(std::mem::take(self.writer.inner_mut()))
+        }
+
+        pub(super) fn finish(self) -> Result<Vec<u8>, ExportError> {
+            Ok(self.writer.into_inner()?)
+        }
+
+        fn batch(&self, rows: &[StoredTrack]) -> Result<RecordBatch, ExportError> {
+            let tracks = || rows.iter().map(|r| &r.track.track);
+            let mut artists = ListBuilder::new(StringBuilder::new());
+            let mut embeddings = ListBuilder::new(Float32Builder::new());
+            for row in rows {
+                for artist in &row.track.track.artists {
+                    artists.values().append_value(&artist.name);
+                }
+                artists.append(true);
+                match &row.track.embedding {
+                    Some(embedding) => {
+                        embeddings.values().append_slice(embedding);
+                        embeddings.append(true);
+                    }
+                    None => embeddings.append(false),
+                }
+            }
+            let fetched_at = TimestampSecondArray::from_iter_values(rows.iter().map(|r| r.fetched_at));
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from_iter_values(tracks().map(|t| &t.id))),
+                Arc::new(StringArray::from_iter_values(tracks().map(|t| &t.name))),
+                Arc::new(artists.finish()),
+                Arc::new(StringArray::from_iter_values(tracks().map(|t| &t.album.name))),
+                Arc::new(UInt32Array::from_iter_values(tracks().map(|t| t.duration_ms))),
+                Arc::new(UInt32Array::from_iter_values(tracks().map(|t| t.popularity))),
+                Arc::new(BooleanArray::from_iter(tracks().map(|t| Some(t.explicit)))),
+                Arc::new(fetched_at.with_timezone("UTC")),
+                Arc::new(embeddings.finish()),
+            ];
+            Ok(RecordBatch::try_new(self.schema.clone(), columns).map_err(parquet::errors::ParquetError::from)?)
+        }
+    }
+
+    /// Same columns as the NDJSON rows; `fetched_at` is a UTC timestamp.
+    fn schema() -> Schema {
+        let item = |data_type| Arc::new(Field::new("item", data_type, true));
+        Schema::new(vec![
+            Field::new("id", DataType::Utf8, false),
+            Field::new("name", DataType::Utf8, false),
+            Field::new("artists", DataType::List(item(DataType::Utf8)), false),
+            Field::new("album", DataType::Utf8, false),
+            Field::new("duration_ms", DataType::UInt32, false),
+            Field::new("popularity", DataType::UInt32, false),
+            Field::new("explicit", DataType::Boolean, false),
+            Field::new("fetched_at", DataType::Timestamp(TimeUnit::Second, Some("UTC".into())), false),
+            Field::new("embedding", DataType::List(item(DataType::Float32)), true),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::spotify::{AuditRecord, AudioFeatures, Track, TrackWithFeatures};
+    use crate::storage::{OutboxEvent, Storage};
+
+    /// A store whose first export page is full and whose second fails.
+    #[derive(Default)]
+    struct SecondPageFails {
+        pages: AtomicU32,
+    }
+
+    #[async_trait]
+    impl Storage for SecondPageFails {
+        fn backend(&self) -> &'static str {
+            "test"
+        }
+
+        async fn export_tracks(
+            &self,
+            _after: Option<&str>,
+            _fetched: Range<i64>,
+            limit: u32,
+        ) -> Result<Vec<StoredTrack>, StorageError> {
+            if self.pages.fetch_add(1, Ordering::SeqCst) > 0 {
+                return Err(StorageError::Database(sqlx::Error::PoolTimedOut));
+            }
+            Ok((0..limit).map(stored).collect())
+        }
+
+        async fn tracks(&self, _: &[String]) -> Result<HashMap<String, Track>, StorageError> {
+            unimplemented!()
+        }
+        async fn audio_features(&self, _: &[String]) -> Result<HashMap<String, Option<AudioFeatures>>, StorageError> {
+            unimplemented!()
+        }
+        async fn upsert_tracks(&self, _: &[&Track]) -> Result<(), StorageError> {
+            unimplemented!()
+        }
+        async fn upsert_audio_features(&self, _: &[(&str, Option<&AudioFeatures>)]) -> Result<(), StorageError> {
+            unimplemented!()
+        }
+        async fn recent_tracks_with_features(&self, _: u32) -> Result<Vec<TrackWithFeatures>, StorageError> {
+            unimplemented!()
+        }
+        async fn stale_track_ids(&self, _: i64, _: u32) -> Result<Vec<String>, StorageError> {
+            unimplemented!()
+        }
+        async fn touch_tracks(&self, _: &[String]) -> Result<(), StorageError> {
+            unimplemented!()
+        }
+        async fn upsert_with_events(
+            &self,
+            _: &[&Track],
+            _: &[(&str, Option<&AudioFeatures>)],
+            _: &[OutboxEvent],
+        ) -> Result<(), StorageError> {
+            unimplemented!()
+        }
+        async fn claim_outbox(&self, _: u32, _: i64) -> Result<Vec<OutboxEvent>, StorageError> {
+            unimplemented!()
+        }
+        async fn delete_outbox(&self, _: &[String]) -> Result<(), StorageError> {
+            unimplemented!()
+        }
+        async fn insert_upstream_calls(&self, _: &[AuditRecord]) -> Result<(), StorageError> {
+            unimplemented!()
+        }
+    }
+
+    fn stored(n: u32) -> StoredTrack {
+        let track = serde_json::json!({ "id": format!("track{:05}", n), "name": "Track", "uri": "spotify:track:x" });
+        StoredTrack {
+            track: TrackWithFeatures {
+                track: serde_json::from_value(track).unwrap(),
+                audio_features: None,
+                embedding: None,
+            },
+            fetched_at: 0,
+        }
+    }
+
+    async fn export(format: ExportFormat) -> Vec<Result<Bytes, ExportError>> {
+        let store = Arc::new(SecondPageFails::default());
+        response_stream(export_stream(store, format, 0..i64::MAX).unwrap(), format).collect().await
+    }
+
+    #[tokio::test]
+    async fn ndjson_export_ends_with_an_error_line_when_a_page_fails() {
+        let chunks = export(ExportFormat::Ndjson).await;
+        assert_eq!(chunks.len(), 2);
+        let body: Vec<u8> = chunks.into_iter().flat_map(|c| c.unwrap()).collect();
+        let lines: Vec<serde_json::Value> =
+            body.split(|&b| b == b'\n').filter(|l| !l.is_empty()).map(|l| serde_json::from_slice(l).unwrap()).collect();
+        assert_eq!(lines.len(), PAGE_SIZE as usize + 1);
+        assert_eq!(lines[0]["id"], "track00000");
+        let last = lines.last().unwrap();
+        assert_eq!(last["error"]["code"], "export_failed");
+        assert!(last["error"]["message"].as_str().unwrap().contains("database error"));
+    }
+
+    #[cfg(feature = "parquet")]
+    #[tokio::test]
+    async fn parquet_export_ends_with_an_error_when_a_page_fails() {
+        let chunks = export(ExportFormat::Parquet).await;
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[0].is_ok());
+        assert!(matches!(chunks[1], Err(ExportError::Storage(_))));
+    }
+}
@@ -1,71 +1,418 @@
 //! gRPC server for Spotify search service.
 
+use std::future::Future;
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::codec::CompressionEncoding;
+use tonic::codegen::http::{HeaderName, HeaderValue, Method};
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
 use tonic::{Request, Response, Status};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tower_http_04::cors::{AllowOrigin, CorsLayer};
+use tracing::Instrument;
 
-use crate::spotify::SpotifyClient;
+use crate::config::TlsConfig;
+use crate::limits::RequestLimits;
+use crate::spotify::{self, DynSpotifyApi, RecommendationSeeds, SearchLimits, SpotifyError};
+use crate::tenants;
+use crate::validation::is_spotify_id;
 
-// Include generated proto code
-pub mod spotify_proto {
-    tonic::include_proto!("spotify");
-}
+pub use crate::proto as spotify_proto;
 
 use spotify_proto::spotify_search_server::{SpotifySearch, SpotifySearchServer};
-use spotify_proto::{GetTracksWithFeaturesRequest, GetTracksWithFeaturesResponse, TrackWithFeatures};
+use spotify_proto::{
+    featured_tracks_to_proto, GetRecommendationsRequest, GetRecommendationsResponse, GetSimilarTracksRequest,
+    GetSimilarTracksResponse, GetTracksWithFeaturesRequest, GetTracksWithFeaturesResponse, SearchTracksRequest,
+    SearchTracksResponse, SimilarTrack, TrackWithFeatures,
+};
+
+impl From<SpotifyError> for Status {
+    fn from(err: SpotifyError) -> Self {
+        let message = err.to_string();
+        match err {
+            SpotifyError::NotFound(_) => Status::not_found(message),
+            SpotifyError::RateLimited { .. } => Status::resource_exhausted(message),
+            SpotifyError::Auth(_) | SpotifyError::Network { .. } | SpotifyError::Unavailable(_) => {
+                Status::unavailable(message)
+            }
+            SpotifyError::Upstream { .. } | SpotifyError::Decode { .. } | SpotifyError::Cassette(_) => {
+                Status::internal(message)
+            }
+        }
+    }
+}
+
+/// Track IDs per upstream batch call when streaming.
+const STREAM_CHUNK_SIZE: usize = 50;
+/// Upstream batch calls in flight per stream.
+const STREAM_CONCURRENCY: usize = 4;
+
+/// Headroom kept from the caller's deadline so DEADLINE_EXCEEDED reaches them in time.
+const DEADLINE_MARGIN: Duration = Duration::from_millis(50);
+
+/// Upstream budget from the request's `grpc-timeout`, minus `DEADLINE_MARGIN`.
+/// `None` when the caller set no deadline; DEADLINE_EXCEEDED if it is already too short.
+#[allow(clippy::result_large_err)] // tonic::Status is large; this mirrors handler signatures.
+fn deadline<T>(request: &Request<T>) -> Result<Option<Instant>, Status> {
+    let Some(timeout) = request
+        .metadata()
+        .get("grpc-timeout")
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_grpc_timeout)
+    else {
+        return Ok(None);
+    };
+    match timeout.checked_sub(DEADLINE_MARGIN) {
+        Some(budget) if !budget.is_zero() => Ok(Some(Instant::now() + budget)),
+        _ => Err(Status::deadline_exceeded(format!(
+            "deadline of {:?} too short to call Spotify",
+            timeout
+        ))),
+    }
+}
+
+/// Parse a `grpc-timeout` value: up to 8 digits followed by a unit (H, M, S, m, u, n).
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    let n: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(n * 3600),
+        "M" => Duration::from_secs(n * 60),
+        "S" => Duration::from_secs(n),
+        "m" => Duration::from_millis(n),
+        "u" => Duration::from_micros(n),
+        "n" => Duration::from_nanos(n),
+        _ => return None,
+    })
+}
+
+/// Run an upstream call within the caller's deadline, dropping in-flight Spotify
+/// requests and returning DEADLINE_EXCEEDED once it passes.
+async fn within_deadline<T>(
+    deadline: Option<Instant>,
+    fut: impl Future<Output = Result<T, SpotifyError>>,
+) -> Result<T, Status> {
+    match deadline {
+        Some(at) => tokio::time::timeout_at(at, fut)
+            .await
+            .map_err(|_| Status::deadline_exceeded("deadline exceeded waiting for Spotify"))?
+            .map_err(Status::from),
+        None => fut.await.map_err(Status::from),
+    }
+}
+
+/// Load the server certificate, key and optional client CA for the gRPC listener.
+pub fn tls_config(tls: &TlsConfig) -> anyhow::Result<ServerTlsConfig> {
+    let read = |path: &std::path::Path| {
+        std::fs::read(path).map_err(|e| anyhow::anyhow!("reading {}: {}", path.display(), e))
+    };
+    let mut config = ServerTlsConfig::new().identity(Identity::from_pem(read(&tls.cert_path)?, read(&tls.key_path)?));
+    if let Some(ref ca) = tls.client_ca_path {
+        config = config.client_ca_root(Certificate::from_pem(read(ca)?));
+    }
+    Ok(config)
+}
+
+/// CORS for gRPC-Web browser clients: preflight and the headers grpc-web needs to
+/// send and read. No origins means no CORS headers (same-origin only).
+pub fn grpc_web_cors(allowed_origins: &[String]) -> anyhow::Result<CorsLayer> {
+    let origin = if allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = allowed_origins
+            .iter()
+            .map(|o| o.parse().map_err(|_| anyhow::anyhow!("invalid GRPC_WEB_ALLOWED_ORIGINS entry '{}'", o)))
+            .collect::<anyhow::Result<Vec<HeaderValue>>>()?;
+        AllowOrigin::list(origins)
+    };
+    Ok(CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods([Method::POST])
+        .allow_headers(
+            ["content-type", "x-grpc-web", "x-user-agent", "grpc-timeout", "x-request-id", "traceparent", "tracestate"]
+                .map(HeaderName::from_static),
+        )
+        .expose_headers(
+            ["grpc-status", "grpc-message", "grpc-status-details-bin", "x-request-id"].map(HeaderName::from_static),
+        )
+        .max_age(CORS_MAX_AGE))
+}
+
+/// How long browsers may cache a gRPC-Web preflight.
+const CORS_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often the health reporter re-checks readiness.
+const HEALTH_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Keep `grpc.health.v1.Health` in sync with readiness: SERVING once a Spotify token
+/// has been obtained and refreshes are succeeding, NOT_SERVING otherwise. Reports both
+/// the overall ("") status and `spotify.SpotifySearch`.
+pub async fn report_health(mut reporter: HealthReporter, spotify: DynSpotifyApi) {
+    let mut last = None;
+    let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let status = if spotify.has_token().await {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        if last != Some(status) {
+            tracing::info!("gRPC health: {:?}", status);
+            reporter.set_service_status("", status).await;
+            match status {
+                ServingStatus::Serving => reporter.set_serving::<SpotifySearchServer<SpotifySearchService>>().await,
+                _ => reporter.set_not_serving::<SpotifySearchServer<SpotifySearchService>>().await,
+            }
+            last = Some(status);
+        }
+    }
+}
 
 /// gRPC service implementation.
 pub struct SpotifySearchService {
-    spotify: SpotifyClient,
+    spotify: DynSpotifyApi,
+    search_limits: SearchLimits,
+    request_limits: RequestLimits,
 }
 
 impl SpotifySearchService {
-    pub fn new(spotify: SpotifyClient) -> Self {
-        Self { spotify }
+    pub fn new(spotify: DynSpotifyApi) -> Self {
+        Self {
+            spotify,
+            search_limits: SearchLimits::default(),
+            request_limits: RequestLimits::default(),
+        }
+    }
+
+    /// Bounds for `SearchTracks` requests' `limit` and `offset` (default Spotify's), and
+    /// whether pages past the last result are rejected.
+    pub fn search_limits(mut self, limits: SearchLimits) -> Self {
+        self.search_limits = limits;
+        self
+    }
+
+    /// Largest request message, and most `track_ids` in a `GetTracksWithFeatures` call
+    /// (default 2 MiB and 50).
+    pub fn request_limits(mut self, limits: RequestLimits) -> Self {
+        self.request_limits = limits;
+        self
     }
 
+    /// Wrap in the generated server. Accepts gzip requests and gzips responses
+    /// for clients that advertise it in `grpc-accept-encoding`, and rejects messages
+    /// over the request limit with `RESOURCE_EXHAUSTED`.
     pub fn into_router(self) -> SpotifySearchServer<SpotifySearchService> {
+        let max_message_bytes = self.request_limits.max_body_bytes;
         SpotifySearchServer::new(self)
+            .max_decoding_message_size(max_message_bytes)
+            .accept_compressed(CompressionEncoding::Gzip)
+            .send_compressed(CompressionEncoding::Gzip)
     }
 }
 
 #[tonic::async_trait]
 impl SpotifySearch for SpotifySearchService {
+    type StreamTracksWithFeaturesStream = ReceiverStream<Result<TrackWithFeatures, Status>>;
+
     async fn get_tracks_with_features(
         &self,
         request: Request<GetTracksWithFeaturesRequest>,
     ) -> Result<Response<GetTracksWithFeaturesResponse>, Status> {
-        let ids = request.into_inner().track_ids;
-        if ids.is_empty() {
-            return Ok(Response::new(GetTracksWithFeaturesResponse { tracks: vec![] }));
+        let deadline = deadline(&request)?;
+        let req = request.into_inner();
+        let include_missing = req.include_missing_embeddings.unwrap_or(true);
+        if req.track_ids.is_empty() {
+            return Ok(Response::new(GetTracksWithFeaturesResponse::default()));
+        }
+        let max_ids = self.request_limits.max_batch_ids;
+        if req.track_ids.len() > max_ids {
+            return Err(Status::invalid_argument(format!(
+                "at most {} track_ids allowed (got {}); use StreamTracksWithFeatures for more",
+                max_ids,
+                req.track_ids.len()
+            )));
         }
 
-        let tracks: Vec<TrackWithFeatures> = self
-            .spotify
-            .get_tracks_with_features(&ids)
-            .await
-            .map_err(|e| Status::internal(e))?
-            .into_iter()
-            .filter_map(|t| {
-                t.embedding.as_ref().map(|emb| {
-                    let mut metadata = std::collections::HashMap::new();
-                    metadata.insert("spotify_id".into(), t.track.id.clone());
-                    metadata.insert("title".into(), t.track.name.clone());
-                    metadata.insert(
-                        "artist".into(),
-                        t.track.artists.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", "),
-                    );
-                    metadata.insert("album".into(), t.track.album.name.clone());
-                    if let Some(ref url) = t.track.external_urls.spotify {
-                        metadata.insert("spotify_url".into(), url.clone());
-                    }
-                    TrackWithFeatures {
-                        id: t.track.id.clone(),
-                        embedding: emb.clone(),
-                        metadata,
+        let tracks = within_deadline(deadline, self.spotify.get_tracks_with_features(&req.track_ids)).await?;
+        let tracks: Vec<TrackWithFeatures> = featured_tracks_to_proto(&tracks, include_missing).collect();
+
+        Ok(Response::new(GetTracksWithFeaturesResponse {
+            tracks,
+            ..Default::default()
+        }))
+    }
+
+    async fn search_tracks(
+        &self,
+        request: Request<SearchTracksRequest>,
+    ) -> Result<Response<SearchTracksResponse>, Status> {
+        let deadline = deadline(&request)?;
+        let req = request.into_inner();
+        if req.query.trim().is_empty() {
+            return Err(Status::invalid_argument("query is required and cannot be empty"));
+        }
+        let SearchLimits { max_limit, max_offset, .. } = self.search_limits;
+        if req.limit > max_limit {
+            return Err(Status::invalid_argument(format!(
+                "limit must be between 1 and {} (got {})",
+                max_limit, req.limit
+            )));
+        }
+        if req.offset > max_offset {
+            return Err(Status::invalid_argument(format!(
+                "offset must be between 0 and {} (got {})",
+                max_offset, req.offset
+            )));
+        }
+        let limit = (req.limit > 0).then_some(req.limit);
+        let page_limit = limit.unwrap_or(self.search_limits.default_limit);
+        if let Some(end) = self.search_limits.overrun(page_limit, req.offset).filter(|_| self.search_limits.strict) {
+            return Err(Status::invalid_argument(format!(
+                "with limit {} the page would end at {}, past the last result at {}",
+                page_limit, end, max_offset
+            )));
+        }
+
+        let response = if req.include_features {
+            let result = within_deadline(
+                deadline,
+                self.spotify.search_tracks_with_features(&req.query, limit, Some(req.offset)),
+            )
+            .await?;
+            SearchTracksResponse {
+                tracks: featured_tracks_to_proto(&result.tracks, true).collect(),
+                total: result.total,
+                limit: result.limit,
+                offset: result.offset,
+            }
+        } else {
+            let result =
+                within_deadline(deadline, self.spotify.search_tracks(&req.query, limit, Some(req.offset))).await?;
+            SearchTracksResponse {
+                tracks: result
+                    .tracks
+                    .into_iter()
+                    .map(|track| {
+                        TrackWithFeatures::from(&spotify::TrackWithFeatures {
+                            track,
+                            audio_features: None,
+                            embedding: None,
+                        })
+                    })
+                    .collect(),
+                total: result.total,
+                limit: result.limit,
+                offset: result.offset,
+            }
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn stream_tracks_with_features(
+        &self,
+        request: Request<GetTracksWithFeaturesRequest>,
+    ) -> Result<Response<Self::StreamTracksWithFeaturesStream>, Status> {
+        let deadline = deadline(&request)?;
+        let req = request.into_inner();
+        let ids = req.track_ids;
+        let include_missing = req.include_missing_embeddings.unwrap_or(true);
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_CHUNK_SIZE);
+        let spotify = self.spotify.clone();
+
+        tokio::spawn(tenants::propagate(
+            async move {
+                let chunks: Vec<Vec<String>> = ids.chunks(STREAM_CHUNK_SIZE).map(<[String]>::to_vec).collect();
+                let mut results = futures::stream::iter(chunks)
+                    .map(|chunk| {
+                        let spotify = spotify.clone();
+                        async move { within_deadline(deadline, spotify.get_tracks_with_features(&chunk)).await }
+                    })
+                    .buffered(STREAM_CONCURRENCY);
+
+                while let Some(result) = results.next().await {
+                    match result {
+                        Ok(tracks) => {
+                            for t in featured_tracks_to_proto(&tracks, include_missing) {
+                                if tx.send(Ok(t)).await.is_err() {
+                                    return; // client went away
+                                }
+                            }
+                        }
+                        Err(status) => {
+                            let _ = tx.send(Err(status)).await;
+                            return;
+                        }
                     }
-                })
-            })
-            .collect();
+                }
+            }
+            .instrument(tracing::Span::current()),
+        ));
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_recommendations(
+        &self,
+        request: Request<GetRecommendationsRequest>,
+    ) -> Result<Response<GetRecommendationsResponse>, Status> {
+        let deadline = deadline(&request)?;
+        let req = request.into_inner();
+        let seeds = RecommendationSeeds {
+            tracks: req.seed_track_ids,
+            artists: req.seed_artist_ids,
+            genres: req.seed_genres,
+        };
+        let count = seeds.tracks.len() + seeds.artists.len() + seeds.genres.len();
+        if !(1..=5).contains(&count) {
+            return Err(Status::invalid_argument(format!("between 1 and 5 seeds required (got {})", count)));
+        }
+        if let Some(id) = seeds.tracks.iter().chain(&seeds.artists).find(|id| !is_spotify_id(id)) {
+            return Err(Status::invalid_argument(format!("'{}' is not a valid Spotify ID", id)));
+        }
+        if req.limit > 100 {
+            return Err(Status::invalid_argument(format!("limit must be between 1 and 100 (got {})", req.limit)));
+        }
+        let limit = (req.limit > 0).then_some(req.limit);
 
-        Ok(Response::new(GetTracksWithFeaturesResponse { tracks }))
+        let tracks = within_deadline(deadline, self.spotify.get_recommendations_with_features(&seeds, limit)).await?;
+        Ok(Response::new(GetRecommendationsResponse {
+            tracks: featured_tracks_to_proto(&tracks, true).collect(),
+        }))
+    }
+
+    async fn get_similar_tracks(
+        &self,
+        request: Request<GetSimilarTracksRequest>,
+    ) -> Result<Response<GetSimilarTracksResponse>, Status> {
+        let deadline = deadline(&request)?;
+        let req = request.into_inner();
+        if !is_spotify_id(&req.track_id) {
+            return Err(Status::invalid_argument(format!("'{}' is not a valid Spotify ID", req.track_id)));
+        }
+        if req.limit > 50 {
+            return Err(Status::invalid_argument(format!("limit must be between 1 and 50 (got {})", req.limit)));
+        }
+        let limit = (req.limit > 0).then_some(req.limit);
+
+        let tracks = within_deadline(deadline, self.spotify.get_similar_tracks(&req.track_id, limit)).await?;
+        Ok(Response::new(GetSimilarTracksResponse {
+            tracks: tracks
+                .iter()
+                .map(|t| SimilarTrack {
+                    track: Some(TrackWithFeatures::from(&t.track)),
+                    score: t.score,
+                })
+                .collect(),
+        }))
     }
 }
+
@@ -1,38 +1,655 @@
 //! HTTP handlers for the Spotify search API.
 
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::get,
+    extract::{rejection::JsonRejection, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
     Json, Router,
 };
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::access_log;
+use crate::admin::AdminAuth;
+use crate::config::SavedQueryConfig;
 use crate::error::AppError;
-use crate::spotify::{SpotifyClient, Track, TrackWithFeatures};
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+use crate::export::{self, ExportError, ExportFormat};
+use crate::discography;
+use crate::ingest;
+use crate::jobs::{Callback, Chunks, Job, JobHandle, Jobs};
+use crate::limits::{RequestLimits, SPOTIFY_MAX_BATCH_IDS};
+#[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+use crate::local_search::LocalSearchError;
+use crate::matching::{self, MatchQuery};
+#[cfg(feature = "grpc")]
+use crate::negotiate::protobuf;
+use crate::negotiate::{Accept, Format};
+#[cfg(feature = "grpc")]
+use crate::proto::{self, featured_tracks_to_proto};
+use crate::spotify::{
+    self, ByteStream, DynSpotifyApi, EmbeddingVersion, RecommendationSeeds, ScoredTrack, SearchFilters, SearchLimits,
+    SpotifyError, TrackWithFeatures, YearRange,
+};
+use crate::saved::{self, SavedQueryStatus};
+use crate::state::AppState;
+use crate::suggest::{Suggestion, Suggestions, MAX_SUGGESTIONS};
+use crate::usage::UsageReport;
+use crate::validation::{is_spotify_id, FieldErrors, FromRawQuery, Validated};
+use crate::warm::WarmStatus;
+
+/// Max seeds (tracks, artists and genres combined) per recommendations request.
+const MAX_SEEDS: usize = 5;
+/// IDs per Spotify call when a batch has a `timeout_ms`, so a slow call holds back
+/// only its own IDs.
+const PARTIAL_CHUNK_SIZE: usize = 10;
+/// Allowed `timeout_ms` on batch lookups.
+const MIN_BATCH_TIMEOUT_MS: u32 = 100;
+const MAX_BATCH_TIMEOUT_MS: u32 = 60_000;
+/// Job kind saving the finished chunks of batch lookups left unfinished.
+const BATCH_KIND: &str = "batch_lookup";
+/// Header carrying the resume token of a batch lookup left unfinished.
+const RESUME_TOKEN_HEADER: &str = "resume-token";
+/// Max `Idempotency-Key` length.
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
 
 /// Query parameters for search endpoint.
-#[derive(Debug, Deserialize)]
+#[derive(Debug)]
 pub struct SearchQuery {
-    /// Search query (required).
+    /// Search query (required unless a filter is given).
     pub q: String,
-    /// Max results (1-50, default 20).
-    #[serde(default)]
+    /// Max results (1-`SEARCH_MAX_LIMIT`, default `SEARCH_DEFAULT_LIMIT`).
     pub limit: Option<u32>,
-    /// Pagination offset (0-1000).
-    #[serde(default)]
+    /// Pagination offset (0-`SEARCH_MAX_OFFSET`).
     pub offset: Option<u32>,
     /// Include audio features and embeddings in response (for Go import).
-    #[serde(default)]
     pub include_features: Option<bool>,
+    /// Collapse duplicate releases to the most popular one.
+    pub dedupe: Option<bool>,
+    /// Return Spotify's response body untouched.
+    pub raw: Option<bool>,
+    /// Add the string `metadata` map to each track.
+    pub include_metadata: Option<bool>,
+    /// Caller's market (uppercase country code), for `playable_in`.
+    pub market: Option<String>,
+    /// Drop tracks not available in `market`.
+    pub playable_only: Option<bool>,
+    /// Embedding version (`v1` default, `v3` adds artist genres).
+    pub embedding: Option<EmbeddingVersion>,
+    /// Release year or range of years, e.g. `1990-1999`.
+    pub year: Option<YearRange>,
+    /// Only tracks on albums released in the past two weeks.
+    pub new_releases_only: Option<bool>,
+    /// Drop tracks below this popularity (0-100).
+    pub min_popularity: Option<u32>,
+    /// Weight of popularity when re-ranking (0-1; 0 keeps Spotify's order).
+    pub popularity_boost: Option<f32>,
+    /// Add how each track was ranked and which filters ran.
+    pub explain: Option<bool>,
+    /// Reject a page reaching past `SEARCH_MAX_OFFSET` (default `SEARCH_STRICT_LIMITS`).
+    pub strict: Option<bool>,
+}
+
+impl SearchQuery {
+    /// `q` with the filters in Spotify's query syntax.
+    pub fn spotify_query(&self) -> String {
+        SearchFilters {
+            year: self.year,
+            new_releases_only: self.new_releases_only.unwrap_or(false),
+        }
+        .apply(&self.q)
+    }
 }
 
-/// Query parameters for GET tracks with features (called by Go saga).
 #[derive(Debug, Deserialize)]
+pub struct RawSearchQuery {
+    q: Option<String>,
+    limit: Option<String>,
+    offset: Option<String>,
+    include_features: Option<String>,
+    dedupe: Option<String>,
+    raw: Option<String>,
+    include_metadata: Option<String>,
+    market: Option<String>,
+    playable_only: Option<String>,
+    embedding: Option<String>,
+    year: Option<String>,
+    new_releases_only: Option<String>,
+    min_popularity: Option<String>,
+    popularity_boost: Option<String>,
+    explain: Option<String>,
+    strict: Option<String>,
+}
+
+impl FromRawQuery for SearchQuery {
+    type Raw = RawSearchQuery;
+
+    fn validate(raw: RawSearchQuery) -> Result<Self, AppError> {
+        Self::validate_with(raw, &SearchLimits::default())
+    }
+
+    fn validate_with(raw: RawSearchQuery, limits: &SearchLimits) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let year = raw.year.as_deref().and_then(|year| match year.parse::<YearRange>() {
+            Ok(year) => Some(year),
+            Err(message) => {
+                errors.add("year", message);
+                None
+            }
+        });
+        let new_releases_only = errors.bool("new_releases_only", raw.new_releases_only.as_deref());
+        let q = raw.q.unwrap_or_default();
+        if q.trim().is_empty() && raw.year.is_none() && new_releases_only != Some(true) {
+            errors.add("q", "is required and cannot be empty unless year or new_releases_only is set");
+        }
+        let limit = errors.u32_in_range("limit", raw.limit.as_deref(), 1, limits.max_limit);
+        let offset = errors.u32_in_range("offset", raw.offset.as_deref(), 0, limits.max_offset);
+        let strict = errors.bool("strict", raw.strict.as_deref());
+        let page_valid = limit.is_some() == raw.limit.is_some() && offset.is_some() == raw.offset.is_some();
+        if strict.unwrap_or(limits.strict) && page_valid {
+            check_page_end(&mut errors, limits, limit, offset);
+        }
+        let include_features = errors.bool("include_features", raw.include_features.as_deref());
+        let dedupe = errors.bool("dedupe", raw.dedupe.as_deref());
+        let include_metadata = errors.bool("include_metadata", raw.include_metadata.as_deref());
+        let market = errors.market("market", raw.market.as_deref());
+        let playable_only = errors.bool("playable_only", raw.playable_only.as_deref());
+        let embedding = parse_embedding(&mut errors, raw.embedding.as_deref());
+        let min_popularity = errors.u32_in_range("min_popularity", raw.min_popularity.as_deref(), 0, 100);
+        let popularity_boost = errors.f32_in_range("popularity_boost", raw.popularity_boost.as_deref(), 0.0, 1.0);
+        let explain = errors.bool("explain", raw.explain.as_deref());
+        let raw = errors.bool("raw", raw.raw.as_deref());
+        let reshapes = [include_features, dedupe, include_metadata, playable_only, explain].contains(&Some(true))
+            || min_popularity.is_some()
+            || popularity_boost.is_some();
+        if raw == Some(true) && reshapes {
+            errors.add(
+                "raw",
+                "cannot be combined with include_features, dedupe, include_metadata, playable_only, min_popularity, \
+                 popularity_boost or explain",
+            );
+        }
+        if playable_only == Some(true) && market.is_none() {
+            errors.add("playable_only", "requires market");
+        }
+        if embedding == Some(EmbeddingVersion::V3) && include_features != Some(true) {
+            errors.add("embedding", "v3 requires include_features=true");
+        }
+        errors.finish(SearchQuery {
+            q,
+            limit,
+            offset,
+            include_features,
+            dedupe,
+            raw,
+            include_metadata,
+            market,
+            playable_only,
+            embedding,
+            year,
+            new_releases_only,
+            min_popularity,
+            popularity_boost,
+            explain,
+            strict,
+        })
+    }
+}
+
+/// Query parameters for GET /api/v1/search/artists.
+#[derive(Debug)]
+pub struct ArtistSearchQuery {
+    /// Search query (required).
+    pub q: String,
+    /// Tracks to search and group (1-`max_limit`, default 50).
+    pub limit: Option<u32>,
+    /// Pagination offset into the tracks (0-`max_offset`).
+    pub offset: Option<u32>,
+    /// Top tracks listed per artist (1-10, default 3).
+    pub top_tracks: Option<u32>,
+    /// Add the string `metadata` map to each track.
+    pub include_metadata: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawArtistSearchQuery {
+    q: Option<String>,
+    limit: Option<String>,
+    offset: Option<String>,
+    top_tracks: Option<String>,
+    include_metadata: Option<String>,
+}
+
+impl FromRawQuery for ArtistSearchQuery {
+    type Raw = RawArtistSearchQuery;
+
+    fn validate(raw: RawArtistSearchQuery) -> Result<Self, AppError> {
+        Self::validate_with(raw, &SearchLimits::default())
+    }
+
+    fn validate_with(raw: RawArtistSearchQuery, limits: &SearchLimits) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let q = raw.q.unwrap_or_default();
+        if q.trim().is_empty() {
+            errors.add("q", "is required and cannot be empty");
+        }
+        let limit = errors.u32_in_range("limit", raw.limit.as_deref(), 1, limits.max_limit);
+        let offset = errors.u32_in_range("offset", raw.offset.as_deref(), 0, limits.max_offset);
+        let top_tracks = errors.u32_in_range("top_tracks", raw.top_tracks.as_deref(), 1, 10);
+        let include_metadata = errors.bool("include_metadata", raw.include_metadata.as_deref());
+        errors.finish(ArtistSearchQuery {
+            q,
+            limit,
+            offset,
+            top_tracks,
+            include_metadata,
+        })
+    }
+}
+
+/// Query parameters for GET /api/v1/suggest.
+#[derive(Debug)]
+pub struct SuggestQuery {
+    /// What the user has typed so far (required, at most 100 characters).
+    pub q: String,
+    /// Max suggestions (1-10, default 10).
+    pub limit: Option<u32>,
+    /// Opaque ID of the typing session (e.g. one per search box); enables debouncing.
+    pub session: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawSuggestQuery {
+    q: Option<String>,
+    limit: Option<String>,
+    session: Option<String>,
+}
+
+impl FromRawQuery for SuggestQuery {
+    type Raw = RawSuggestQuery;
+
+    fn validate(raw: RawSuggestQuery) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let q = raw.q.unwrap_or_default();
+        if q.trim().is_empty() {
+            errors.add("q", "is required and cannot be empty");
+        } else if q.chars().count() > 100 {
+            errors.add("q", "must be at most 100 characters");
+        }
+        let limit = errors.u32_in_range("limit", raw.limit.as_deref(), 1, MAX_SUGGESTIONS);
+        let session = raw.session.filter(|s| !s.is_empty());
+        if let Some(session) = &session {
+            if session.len() > 128 || !session.bytes().all(|b| b.is_ascii_graphic()) {
+                errors.add("session", "must be at most 128 printable ASCII characters");
+            }
+        }
+        errors.finish(SuggestQuery { q, limit, session })
+    }
+}
+
+/// Query parameters for GET tracks with features (called by Go saga).
+#[derive(Debug)]
 pub struct TracksWithFeaturesQuery {
-    /// Comma-separated Spotify track IDs (max 50).
-    pub ids: String,
+    /// Spotify track IDs, from the comma-separated `ids` parameter (max 50).
+    pub ids: Vec<String>,
+    /// Return Spotify's `/tracks` response body untouched, without features.
+    pub raw: Option<bool>,
+    /// Add the string `metadata` map to each track.
+    pub include_metadata: Option<bool>,
+    /// Embedding version (`v1` default, `v3` adds artist genres).
+    pub embedding: Option<EmbeddingVersion>,
+    /// Return what resolved within this long, and the IDs that didn't.
+    pub timeout: Option<Duration>,
+    /// Skip the chunks an earlier, unfinished lookup of the same IDs finished.
+    pub resume_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawTracksWithFeaturesQuery {
+    ids: Option<String>,
+    raw: Option<String>,
+    include_metadata: Option<String>,
+    embedding: Option<String>,
+    timeout_ms: Option<String>,
+    resume_token: Option<String>,
+}
+
+impl FromRawQuery for TracksWithFeaturesQuery {
+    type Raw = RawTracksWithFeaturesQuery;
+
+    fn validate(raw: RawTracksWithFeaturesQuery) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let ids = parse_ids(&mut errors, "ids", raw.ids.as_deref());
+        let include_metadata = errors.bool("include_metadata", raw.include_metadata.as_deref());
+        let embedding = parse_embedding(&mut errors, raw.embedding.as_deref());
+        let timeout = parse_batch_timeout(&mut errors, raw.timeout_ms.as_deref());
+        let resume_token = parse_resume_token(&mut errors, raw.resume_token, timeout);
+        let raw = errors.bool("raw", raw.raw.as_deref());
+        if raw == Some(true) && (include_metadata == Some(true) || embedding.is_some() || timeout.is_some()) {
+            errors.add("raw", "cannot be combined with include_metadata, embedding or timeout_ms");
+        }
+        errors.finish(TracksWithFeaturesQuery {
+            ids,
+            raw,
+            include_metadata,
+            embedding,
+            timeout,
+            resume_token,
+        })
+    }
+}
+
+/// Query parameters for the album and artist batch lookups.
+#[derive(Debug)]
+pub struct IdsQuery {
+    /// Spotify IDs, from the comma-separated `ids` parameter (max 50).
+    pub ids: Vec<String>,
+    /// Return what resolved within this long, and the IDs that didn't.
+    pub timeout: Option<Duration>,
+    /// Skip the chunks an earlier, unfinished lookup of the same IDs finished.
+    pub resume_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawIdsQuery {
+    ids: Option<String>,
+    timeout_ms: Option<String>,
+    resume_token: Option<String>,
+}
+
+impl FromRawQuery for IdsQuery {
+    type Raw = RawIdsQuery;
+
+    fn validate(raw: RawIdsQuery) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let ids = parse_ids(&mut errors, "ids", raw.ids.as_deref());
+        let timeout = parse_batch_timeout(&mut errors, raw.timeout_ms.as_deref());
+        let resume_token = parse_resume_token(&mut errors, raw.resume_token, timeout);
+        errors.finish(IdsQuery {
+            ids,
+            timeout,
+            resume_token,
+        })
+    }
+}
+
+/// Query parameters for GET recommendations.
+#[derive(Debug)]
+pub struct RecommendationsQuery {
+    /// From comma-separated `seed_tracks`, `seed_artists` and `seed_genres` (1-5 in total).
+    pub seeds: RecommendationSeeds,
+    /// Max results (1-100, default 20).
+    pub limit: Option<u32>,
+    /// Add the string `metadata` map to each track.
+    pub include_metadata: Option<bool>,
+    /// Embedding version (`v1` default, `v3` adds artist genres).
+    pub embedding: Option<EmbeddingVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawRecommendationsQuery {
+    seed_tracks: Option<String>,
+    seed_artists: Option<String>,
+    seed_genres: Option<String>,
+    limit: Option<String>,
+    include_metadata: Option<String>,
+    embedding: Option<String>,
+}
+
+impl FromRawQuery for RecommendationsQuery {
+    type Raw = RawRecommendationsQuery;
+
+    fn validate(raw: RawRecommendationsQuery) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let seeds = RecommendationSeeds {
+            tracks: split_list(raw.seed_tracks.as_deref()),
+            artists: split_list(raw.seed_artists.as_deref()),
+            genres: split_list(raw.seed_genres.as_deref()),
+        };
+        check_ids(&mut errors, "seed_tracks", &seeds.tracks);
+        check_ids(&mut errors, "seed_artists", &seeds.artists);
+        let count = seeds.tracks.len() + seeds.artists.len() + seeds.genres.len();
+        if count == 0 {
+            errors.add("seed_tracks", "at least one of seed_tracks, seed_artists or seed_genres required");
+        }
+        if count > MAX_SEEDS {
+            errors.add("seed_tracks", format!("at most {} seeds allowed in total (got {})", MAX_SEEDS, count));
+        }
+        let limit = errors.u32_in_range("limit", raw.limit.as_deref(), 1, 100);
+        let include_metadata = errors.bool("include_metadata", raw.include_metadata.as_deref());
+        let embedding = parse_embedding(&mut errors, raw.embedding.as_deref());
+        errors.finish(RecommendationsQuery {
+            seeds,
+            limit,
+            include_metadata,
+            embedding,
+        })
+    }
+}
+
+/// Query parameters for GET similar tracks.
+#[derive(Debug)]
+pub struct SimilarTracksQuery {
+    /// Max results (1-50, default 20).
+    pub limit: Option<u32>,
+    /// Add the string `metadata` map to each track.
+    pub include_metadata: Option<bool>,
+    /// Embedding version to rank by (`v1` default, `v3` adds artist genres).
+    pub embedding: Option<EmbeddingVersion>,
+    /// Add how each track was ranked.
+    pub explain: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawSimilarTracksQuery {
+    limit: Option<String>,
+    include_metadata: Option<String>,
+    embedding: Option<String>,
+    explain: Option<String>,
+}
+
+impl FromRawQuery for SimilarTracksQuery {
+    type Raw = RawSimilarTracksQuery;
+
+    fn validate(raw: RawSimilarTracksQuery) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let limit = errors.u32_in_range("limit", raw.limit.as_deref(), 1, 50);
+        let include_metadata = errors.bool("include_metadata", raw.include_metadata.as_deref());
+        let embedding = parse_embedding(&mut errors, raw.embedding.as_deref());
+        let explain = errors.bool("explain", raw.explain.as_deref());
+        errors.finish(SimilarTracksQuery {
+            limit,
+            include_metadata,
+            embedding,
+            explain,
+        })
+    }
+}
+
+/// Body of POST /api/v1/jobs/match.
+#[derive(Debug, Deserialize)]
+pub struct MatchRequest {
+    pub queries: Vec<MatchQuery>,
+    /// POST the final job status here.
+    #[serde(default)]
+    pub callback_url: Option<String>,
+}
+
+/// Query parameters for POST /api/v1/ingest/playlist/:id and /api/v1/ingest/artist/:id.
+#[derive(Debug)]
+pub struct IngestQuery {
+    /// POST the final job status here.
+    pub callback_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawIngestQuery {
+    callback_url: Option<String>,
+}
+
+impl FromRawQuery for IngestQuery {
+    type Raw = RawIngestQuery;
+
+    fn validate(raw: RawIngestQuery) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let callback_url = errors.http_url("callback_url", raw.callback_url.as_deref());
+        errors.finish(IngestQuery { callback_url })
+    }
+}
+
+/// Query parameters for GET /api/v1/export.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+#[derive(Debug)]
+pub struct ExportQuery {
+    /// `ndjson` (default) or `parquet`.
+    pub format: ExportFormat,
+    /// Only tracks fetched at or after this Unix time.
+    pub fetched_after: Option<u32>,
+    /// Only tracks fetched before this Unix time.
+    pub fetched_before: Option<u32>,
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+#[derive(Debug, Deserialize)]
+pub struct RawExportQuery {
+    format: Option<String>,
+    fetched_after: Option<String>,
+    fetched_before: Option<String>,
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+impl FromRawQuery for ExportQuery {
+    type Raw = RawExportQuery;
+
+    fn validate(raw: RawExportQuery) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let format = match raw.format.as_deref().map(str::trim) {
+            None | Some("ndjson") => ExportFormat::Ndjson,
+            Some("parquet") => ExportFormat::Parquet,
+            Some(other) => {
+                errors.add("format", format!("must be ndjson or parquet (got '{}')", other));
+                ExportFormat::Ndjson
+            }
+        };
+        let fetched_after = errors.u32_in_range("fetched_after", raw.fetched_after.as_deref(), 0, u32::MAX);
+        let fetched_before = errors.u32_in_range("fetched_before", raw.fetched_before.as_deref(), 0, u32::MAX);
+        if let (Some(after), Some(before)) = (fetched_after, fetched_before) {
+            if before <= after {
+                errors.add("fetched_before", "must be later than fetched_after");
+            }
+        }
+        errors.finish(ExportQuery {
+            format,
+            fetched_after,
+            fetched_before,
+        })
+    }
+}
+
+/// Query parameters for GET job results.
+#[derive(Debug)]
+pub struct JobResultsQuery {
+    /// First item to return (default 0).
+    pub offset: Option<u32>,
+    /// Max items (1-1000, default 100).
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawJobResultsQuery {
+    offset: Option<String>,
+    limit: Option<String>,
+}
+
+impl FromRawQuery for JobResultsQuery {
+    type Raw = RawJobResultsQuery;
+
+    fn validate(raw: RawJobResultsQuery) -> Result<Self, AppError> {
+        let mut errors = FieldErrors::default();
+        let offset = errors.u32_in_range("offset", raw.offset.as_deref(), 0, u32::MAX);
+        let limit = errors.u32_in_range("limit", raw.limit.as_deref(), 1, 1000);
+        errors.finish(JobResultsQuery { offset, limit })
+    }
+}
+
+/// Split a comma-separated list, dropping empty entries.
+fn split_list(raw: Option<&str>) -> Vec<String> {
+    raw.unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Split a comma-separated ID list, recording missing, malformed and excess IDs.
+fn parse_ids(errors: &mut FieldErrors, field: &str, raw: Option<&str>) -> Vec<String> {
+    let ids = split_list(raw);
+    if ids.is_empty() {
+        errors.add(field, "at least one id required (comma-separated)");
+    }
+    if ids.len() > SPOTIFY_MAX_BATCH_IDS {
+        errors.add(field, format!("at most {} ids allowed (got {})", SPOTIFY_MAX_BATCH_IDS, ids.len()));
+    }
+    check_ids(errors, field, &ids);
+    ids
+}
+
+/// Parse the `embedding` version parameter.
+fn parse_embedding(errors: &mut FieldErrors, raw: Option<&str>) -> Option<EmbeddingVersion> {
+    match raw?.parse() {
+        Ok(version) => Some(version),
+        Err(message) => {
+            errors.add("embedding", message);
+            None
+        }
+    }
+}
+
+fn parse_batch_timeout(errors: &mut FieldErrors, raw: Option<&str>) -> Option<Duration> {
+    errors
+        .u32_in_range("timeout_ms", raw, MIN_BATCH_TIMEOUT_MS, MAX_BATCH_TIMEOUT_MS)
+        .map(|ms| Duration::from_millis(ms.into()))
+}
+
+/// A `resume_token`, which must be a job ID and only goes with `timeout_ms`.
+fn parse_resume_token(errors: &mut FieldErrors, raw: Option<String>, timeout: Option<Duration>) -> Option<String> {
+    let token = raw?.trim().to_string();
+    if uuid::Uuid::parse_str(&token).is_err() {
+        errors.add("resume_token", format!("'{}' is not a resume token", token));
+        return None;
+    }
+    if timeout.is_none() {
+        errors.add("resume_token", "requires timeout_ms");
+    }
+    Some(token)
+}
+
+/// Record a page that would reach past `max_offset` and so come back short.
+fn check_page_end(errors: &mut FieldErrors, limits: &SearchLimits, limit: Option<u32>, offset: Option<u32>) {
+    let limit = limit.unwrap_or(limits.default_limit);
+    if let Some(end) = limits.overrun(limit, offset.unwrap_or(0)) {
+        errors.add(
+            "offset",
+            format!(
+                "with limit {} the page would end at {}, past the last result at {}; lower offset or limit",
+                limit, end, limits.max_offset
+            ),
+        );
+    }
+}
+
+/// Record every entry that is not a Spotify ID.
+fn check_ids(errors: &mut FieldErrors, field: &str, ids: &[String]) {
+    for (i, id) in ids.iter().enumerate() {
+        if !is_spotify_id(id) {
+            errors.add(format!("{}[{}]", field, i), format!("'{}' is not a valid Spotify ID", id));
+        }
+    }
 }
 
 /// API response for track search.
@@ -42,6 +659,212 @@ pub struct SearchResponse {
     pub total: u32,
     pub limit: u32,
     pub offset: u32,
+    /// `cache` when served from the local store because Spotify is unavailable, `local`
+    /// for local search.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<&'static str>,
+    /// How the page was filtered and ranked, with `explain=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explain: Option<Explanation>,
+    /// Set when a batch's `timeout_ms` passed before every ID resolved.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+    /// IDs of the batch not looked up before `timeout_ms` passed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remaining_ids: Vec<String>,
+    /// Pass back with the same IDs to look up only `remaining_ids`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+}
+
+/// How a page of tracks was filtered and ranked (`explain=true`).
+#[derive(Debug, Serialize)]
+pub struct Explanation {
+    /// The search sent to Spotify, with `year` and `new_releases_only` written into it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// Filters in the order they ran.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<FilterExplanation>,
+    /// Weight of popularity in the ranking, when boosted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub popularity_boost: Option<f32>,
+    /// Embedding version similar tracks were ranked by.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<&'static str>,
+}
+
+/// One filter applied to a page.
+#[derive(Debug, Serialize)]
+pub struct FilterExplanation {
+    /// The parameter, e.g. `min_popularity`.
+    pub filter: &'static str,
+    pub value: String,
+    /// Tracks it removed from the page; absent for filters Spotify applies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed: Option<usize>,
+}
+
+/// How one track was ranked (`explain=true`).
+#[derive(Clone, Debug, Serialize)]
+pub struct TrackExplanation {
+    /// Position in Spotify's results (the index's for local search, the similarity
+    /// ranking for similar tracks), counting from 1 at offset 0.
+    pub rank: u32,
+    pub popularity: u32,
+    /// Score the popularity boost sorted by, from `rank` and `popularity`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boosted_score: Option<f32>,
+    /// Cosine similarity to the seed track.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<f32>,
+}
+
+/// Response of GET /api/v1/search/artists.
+#[derive(Debug, Serialize)]
+pub struct ArtistSearchResponse {
+    pub artists: Vec<ArtistSummary>,
+    /// Tracks matching the query on Spotify; `limit` and `offset` page through these.
+    pub total: u32,
+    pub limit: u32,
+    pub offset: u32,
+    /// `cache` when served from the local store because Spotify is unavailable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<&'static str>,
+}
+
+/// One artist among the search results.
+#[derive(Debug, Serialize)]
+pub struct ArtistSummary {
+    pub id: Option<String>,
+    pub name: String,
+    pub spotify_url: Option<String>,
+    /// The artist's tracks among the searched page.
+    pub track_count: u32,
+    /// Highest popularity (0-100) of those tracks.
+    pub popularity: u32,
+    /// Mean of the tracks' 12-dim embeddings; `null` if none has audio features.
+    pub embedding: Option<Vec<f32>>,
+    /// Most popular first, with embeddings.
+    pub top_tracks: Vec<TrackResponse>,
+}
+
+/// Response of GET /api/v1/albums/:id.
+#[derive(Debug, Serialize)]
+pub struct AlbumDetailResponse {
+    pub id: String,
+    pub name: String,
+    /// `album`, `single` or `compilation`.
+    pub album_type: String,
+    pub artists: Vec<ArtistResponse>,
+    pub label: Option<String>,
+    pub release_date: String,
+    /// `year`, `month` or `day`: how much of `release_date` is known.
+    pub release_date_precision: String,
+    pub genres: Vec<String>,
+    pub copyrights: Vec<spotify::Copyright>,
+    pub total_tracks: u32,
+    /// Largest first.
+    pub images: Vec<spotify::Image>,
+    pub popularity: u32,
+    pub upc: Option<String>,
+    pub spotify_url: Option<String>,
+}
+
+impl From<spotify::AlbumDetail> for AlbumDetailResponse {
+    fn from(album: spotify::AlbumDetail) -> Self {
+        Self {
+            id: album.id,
+            name: album.name,
+            album_type: album.album_type,
+            artists: album
+                .artists
+                .into_iter()
+                .map(|a| ArtistResponse { id: a.id, name: a.name })
+                .collect(),
+            label: album.label,
+            release_date: album.release_date,
+            release_date_precision: album.release_date_precision,
+            genres: album.genres,
+            copyrights: album.copyrights,
+            total_tracks: album.total_tracks,
+            images: album.images,
+            popularity: album.popularity,
+            upc: album.external_ids.upc,
+            spotify_url: album.external_urls.spotify,
+        }
+    }
+}
+
+/// Response of GET /api/v1/albums.
+#[derive(Debug, Serialize)]
+pub struct AlbumsResponse {
+    /// In the order requested; unknown IDs are left out.
+    pub albums: Vec<AlbumDetailResponse>,
+    /// Set when `timeout_ms` passed before every ID resolved.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+    /// IDs not looked up before `timeout_ms` passed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remaining_ids: Vec<String>,
+    /// Pass back with the same IDs to look up only `remaining_ids`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+}
+
+/// Response of GET /api/v1/artists.
+#[derive(Debug, Serialize)]
+pub struct ArtistsResponse {
+    /// In the order requested; unknown IDs are left out.
+    pub artists: Vec<ArtistDetailResponse>,
+    /// Set when `timeout_ms` passed before every ID resolved.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+    /// IDs not looked up before `timeout_ms` passed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remaining_ids: Vec<String>,
+    /// Pass back with the same IDs to look up only `remaining_ids`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+}
+
+/// Response of GET /api/v1/artists/:id, and one artist in GET /api/v1/artists.
+#[derive(Debug, Serialize)]
+pub struct ArtistDetailResponse {
+    pub id: String,
+    pub name: String,
+    pub genres: Vec<String>,
+    /// 0-100.
+    pub popularity: u32,
+    pub followers: u32,
+    /// Largest first.
+    pub images: Vec<spotify::Image>,
+    pub spotify_url: Option<String>,
+}
+
+impl From<spotify::ArtistDetail> for ArtistDetailResponse {
+    fn from(artist: spotify::ArtistDetail) -> Self {
+        Self {
+            id: artist.id,
+            name: artist.name,
+            genres: artist.genres,
+            popularity: artist.popularity,
+            followers: artist.followers.total,
+            images: artist.images,
+            spotify_url: artist.external_urls.spotify,
+        }
+    }
+}
+
+/// Response of GET /api/v1/suggest.
+#[derive(Debug, Serialize)]
+pub struct SuggestResponse {
+    pub query: String,
+    pub suggestions: Vec<Suggestion>,
+    /// The same session sent a newer query while this one was debounced; `suggestions`
+    /// is empty and the response can be dropped.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub superseded: bool,
 }
 
 /// Single track in API response.
@@ -55,12 +878,32 @@ pub struct TrackResponse {
     pub artists: Vec<ArtistResponse>,
     pub album: AlbumResponse,
     pub spotify_url: Option<String>,
+    /// The ID that was asked for, when Spotify relinked it to `id`, the version playable
+    /// in `SPOTIFY_MARKET`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_id: Option<String>,
+    /// Whether the track is playable in `SPOTIFY_MARKET`; absent without a market.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_playable: Option<bool>,
+    /// Number of markets the track is available in, when Spotify listed them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_markets: Option<usize>,
+    /// Whether the track is available in the requested `market`, when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub playable_in: Option<bool>,
     /// 12-dim embedding from Spotify audio features (when include_features=true).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub embedding: Option<Vec<f32>>,
-    /// Metadata for Go import (spotify_id, title, artist, album).
+    /// Metadata for Go import (spotify_id, title, artist, album, spotify_url), when
+    /// include_metadata=true.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub metadata: Option<std::collections::HashMap<String, String>>,
+    pub metadata: Option<std::collections::HashMap<&'static str, String>>,
+    /// Cosine similarity to the seed track (similar-tracks endpoint only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
+    /// How the track was ranked, with `explain=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explain: Option<TrackExplanation>,
 }
 
 #[derive(Debug, Serialize)]
@@ -76,121 +919,1235 @@ pub struct AlbumResponse {
     pub image_url: Option<String>,
 }
 
-fn track_to_response(t: &Track) -> TrackResponse {
-    track_with_features_to_response(&TrackWithFeatures {
-        track: t.clone(),
-        audio_features: None,
-        embedding: None,
+/// `source` for a response: set only when the local store stood in for Spotify.
+fn response_source() -> Option<&'static str> {
+    access_log::served_from_cache().then_some("cache")
+}
+
+/// A Spotify response body, streamed through as it arrives.
+fn raw_json(body: ByteStream) -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], axum::body::Body::from_stream(body)).into_response()
+}
+
+/// Raw responses are Spotify's JSON, whatever else the client asked for.
+fn require_json_for_raw(format: Format) -> Result<(), AppError> {
+    if format != Format::Json {
+        return Err(AppError::NotAcceptable("raw=true responses are always application/json".into()));
+    }
+    Ok(())
+}
+
+impl TrackResponse {
+    /// The response for `t`, taking its strings rather than copying them. `metadata` adds
+    /// the string map, whose values are the only copies made.
+    pub fn new(t: TrackWithFeatures, metadata: bool) -> Self {
+        let track = t.track;
+        let metadata = metadata.then(|| {
+            let mut metadata = std::collections::HashMap::with_capacity(5);
+            metadata.insert("spotify_id", track.id.clone());
+            metadata.insert("title", track.name.clone());
+            metadata.insert("artist", track.artists.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", "));
+            metadata.insert("album", track.album.name.clone());
+            if let Some(url) = &track.external_urls.spotify {
+                metadata.insert("spotify_url", url.clone());
+            }
+            metadata
+        });
+
+        TrackResponse {
+            id: track.id,
+            name: track.name,
+            uri: track.uri,
+            duration_ms: track.duration_ms,
+            explicit: track.explicit,
+            artists: track
+                .artists
+                .into_iter()
+                .map(|a| ArtistResponse { id: a.id, name: a.name })
+                .collect(),
+            album: AlbumResponse {
+                id: track.album.id,
+                name: track.album.name,
+                image_url: track.album.images.into_iter().next().and_then(|i| i.url),
+            },
+            spotify_url: track.external_urls.spotify,
+            requested_id: track.linked_from.map(|l| l.id),
+            is_playable: track.is_playable,
+            available_markets: (!track.available_markets.is_empty()).then_some(track.available_markets.len()),
+            playable_in: None,
+            embedding: t.embedding,
+            metadata,
+            score: None,
+            explain: None,
+        }
+    }
+
+    /// Like [`new`](Self::new), with the similarity score.
+    pub fn scored(t: ScoredTrack, metadata: bool) -> Self {
+        TrackResponse {
+            score: Some(t.score),
+            ..TrackResponse::new(t.track, metadata)
+        }
+    }
+
+    /// Like [`new`](Self::new), with whether the track is available in `market`.
+    pub fn in_market(t: TrackWithFeatures, metadata: bool, market: Option<&str>) -> Self {
+        TrackResponse {
+            playable_in: market.and_then(|m| t.track.playable_in(m)),
+            ..TrackResponse::new(t, metadata)
+        }
+    }
+}
+
+/// Drop tracks known to be unavailable in `market`; tracks whose markets Spotify didn't
+/// list are kept.
+fn retain_playable(tracks: &mut Vec<TrackWithFeatures>, market: &str) {
+    tracks.retain(|t| t.track.playable_in(market) != Some(false));
+}
+
+/// Outcome of [`resolve_within`].
+struct Resolved<T> {
+    /// Results of the chunks that finished, in order.
+    items: Vec<T>,
+    /// IDs of the chunks that didn't.
+    remaining: Vec<String>,
+    /// The job saving the finished chunks, while some are left.
+    resume_token: Option<String>,
+}
+
+impl<T> Resolved<T> {
+    fn whole(items: Vec<T>) -> Self {
+        Resolved {
+            items,
+            remaining: Vec::new(),
+            resume_token: None,
+        }
+    }
+}
+
+/// A batch lookup that failed, with the resume token of the chunks it did finish.
+struct BatchFailure {
+    error: SpotifyError,
+    resume_token: Option<String>,
+}
+
+impl IntoResponse for BatchFailure {
+    fn into_response(self) -> Response {
+        with_resume_token(AppError::Spotify(self.error).into_response(), self.resume_token.as_deref())
+    }
+}
+
+/// The chunks already finished according to `resume_token`, which must come from an
+/// earlier lookup of the same `ids` on `endpoint`.
+fn resume_batch(
+    jobs: &Jobs,
+    endpoint: &str,
+    ids: &[String],
+    resume_token: Option<&str>,
+) -> Result<Option<(String, Chunks)>, AppError> {
+    let Some(token) = resume_token else { return Ok(None) };
+    let mut errors = FieldErrors::default();
+    match jobs.progress(token, BATCH_KIND) {
+        Some((params, chunks)) if params == batch_params(endpoint, ids) => return Ok(Some((token.to_string(), chunks))),
+        Some(_) => errors.add("resume_token", "was issued for a different batch"),
+        None => errors.add("resume_token", "is unknown or has expired"),
+    }
+    errors.finish(None)
+}
+
+/// Params of the job saving a batch lookup's progress.
+fn batch_params(endpoint: &str, ids: &[String]) -> serde_json::Value {
+    serde_json::json!({ "endpoint": endpoint, "ids": ids })
+}
+
+/// Look `ids` up with `fetch`, [`PARTIAL_CHUNK_SIZE`] at a time and concurrently,
+/// until `timeout` passes, skipping the chunks finished in `resume`. Chunks that time
+/// out, whether at `timeout` or on a Spotify request timeout, are left for a retry;
+/// any other error fails the whole batch once the other chunks are done. Either way,
+/// the finished chunks are saved as a [`BATCH_KIND`] job whose ID is the resume token.
+async fn resolve_within<T, F, Fut>(
+    jobs: &Jobs,
+    endpoint: &'static str,
+    ids: &[String],
+    timeout: Duration,
+    resume: Option<(String, Chunks)>,
+    fetch: F,
+) -> Result<Resolved<T>, BatchFailure>
+where
+    T: Serialize + DeserializeOwned,
+    F: Fn(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, SpotifyError>>,
+{
+    let deadline = tokio::time::Instant::now() + timeout;
+    let chunks: Vec<&[String]> = ids.chunks(PARTIAL_CHUNK_SIZE).collect();
+    let (token, mut done) = match resume {
+        Some((token, done)) => (Some(token), done),
+        None => (None, Chunks::new()),
+    };
+    let pending: Vec<usize> = (0..chunks.len()).filter(|i| !done.contains_key(i)).collect();
+    let results = futures::future::join_all(
+        pending
+            .iter()
+            .map(|&i| tokio::time::timeout_at(deadline, fetch(chunks[i].to_vec()))),
+    )
+    .await;
+    let (mut remaining, mut error) = (Vec::new(), None);
+    for (i, result) in pending.into_iter().zip(results) {
+        match result {
+            Ok(Ok(items)) => {
+                let items = items.iter().map(|item| serde_json::to_value(item).expect("batch items serialize"));
+                done.insert(i, items.collect());
+            }
+            Ok(Err(e)) if !e.is_timeout() => {
+                remaining.extend_from_slice(chunks[i]);
+                error.get_or_insert(e);
+            }
+            Ok(Err(_)) | Err(_) => remaining.extend_from_slice(chunks[i]),
+        }
+    }
+
+    let resume_token = if token.is_some() || (!done.is_empty() && !remaining.is_empty()) {
+        let outcome = match &error {
+            Some(e) => Some(e.to_string()),
+            None if !remaining.is_empty() => Some(format!("{} IDs timed out", remaining.len())),
+            None => None,
+        };
+        let params = batch_params(endpoint, ids);
+        let id = jobs.save_progress(token.as_deref(), BATCH_KIND, params, chunks.len(), done.clone(), outcome);
+        (!remaining.is_empty()).then_some(id)
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        return Err(BatchFailure { error, resume_token });
+    }
+    if !remaining.is_empty() {
+        tracing::warn!(
+            resolved = ids.len() - remaining.len(),
+            remaining = remaining.len(),
+            "batch lookup timed out, returning it incomplete"
+        );
+    }
+    let items = done
+        .into_values()
+        .flatten()
+        .map(|item| serde_json::from_value(item).expect("saved batch items deserialize"))
+        .collect();
+    Ok(Resolved {
+        items,
+        remaining,
+        resume_token,
     })
 }
 
-fn track_with_features_to_response(t: &TrackWithFeatures) -> TrackResponse {
-    let mut metadata = std::collections::HashMap::new();
-    metadata.insert("spotify_id".into(), t.track.id.clone());
-    metadata.insert("title".into(), t.track.name.clone());
-    metadata.insert("artist".into(), t.track.artists.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", "));
-    metadata.insert("album".into(), t.track.album.name.clone());
-    if let Some(ref url) = t.track.external_urls.spotify {
-        metadata.insert("spotify_url".into(), url.clone());
-    }
-
-    TrackResponse {
-        id: t.track.id.clone(),
-        name: t.track.name.clone(),
-        uri: t.track.uri.clone(),
-        duration_ms: t.track.duration_ms,
-        explicit: t.track.explicit,
-        artists: t.track.artists.iter().map(|a| ArtistResponse {
-            id: a.id.clone(),
-            name: a.name.clone(),
-        }).collect(),
-        album: AlbumResponse {
-            id: t.track.album.id.clone(),
-            name: t.track.album.name.clone(),
-            image_url: t.track.album.images.first().and_then(|i| i.url.clone()),
-        },
-        spotify_url: t.track.external_urls.spotify.clone(),
-        embedding: t.embedding.clone(),
-        metadata: Some(metadata),
-    }
-}
-
-/// GET /health - Health check.
+/// `response` with `resume_token`, if any, in [`RESUME_TOKEN_HEADER`].
+fn with_resume_token(mut response: Response, resume_token: Option<&str>) -> Response {
+    if let Some(value) = resume_token.and_then(|t| header::HeaderValue::from_str(t).ok()) {
+        response.headers_mut().insert(RESUME_TOKEN_HEADER, value);
+    }
+    response
+}
+
+/// A search page after [`refine_page`].
+struct RefinedPage {
+    tracks: Vec<TrackWithFeatures>,
+    /// What was done to the page, with `explain`.
+    explanation: Option<Explanation>,
+    /// Per-track explanations by ID, with `explain`.
+    ranked: HashMap<String, TrackExplanation>,
+}
+
+/// Filter and re-rank a search page as `params` asks: `playable_only`,
+/// `min_popularity`, `dedupe`, then `popularity_boost`. `offset` is where the page
+/// starts.
+fn refine_page(mut tracks: Vec<TrackWithFeatures>, params: &SearchQuery, offset: u32) -> RefinedPage {
+    let explain = params.explain.unwrap_or(false);
+    let mut ranked: HashMap<String, TrackExplanation> = HashMap::new();
+    if explain {
+        for (i, t) in tracks.iter().enumerate() {
+            ranked.entry(t.track.id.clone()).or_insert(TrackExplanation {
+                rank: offset + i as u32 + 1,
+                popularity: t.track.popularity,
+                boosted_score: None,
+                similarity: None,
+            });
+        }
+    }
+    let mut filters = Vec::new();
+    if let Some(year) = params.year {
+        filters.push(FilterExplanation {
+            filter: "year",
+            value: year.to_string(),
+            removed: None,
+        });
+    }
+    if params.new_releases_only == Some(true) {
+        filters.push(FilterExplanation {
+            filter: "new_releases_only",
+            value: "true".into(),
+            removed: None,
+        });
+    }
+    let mut record = |filter, value: String, removed: usize| {
+        filters.push(FilterExplanation {
+            filter,
+            value,
+            removed: Some(removed),
+        });
+    };
+
+    if let (Some(true), Some(market)) = (params.playable_only, params.market.as_deref()) {
+        let before = tracks.len();
+        retain_playable(&mut tracks, market);
+        record("playable_only", market.to_string(), before - tracks.len());
+    }
+    if let Some(min) = params.min_popularity {
+        let before = tracks.len();
+        tracks.retain(|t| t.track.popularity >= min);
+        record("min_popularity", min.to_string(), before - tracks.len());
+    }
+    if params.dedupe.unwrap_or(false) {
+        let before = tracks.len();
+        tracks = spotify::dedupe_by(tracks, |t| &t.track);
+        record("dedupe", "true".into(), before - tracks.len());
+    }
+    if let Some(weight) = params.popularity_boost {
+        if explain {
+            let scores = spotify::popularity_boost_scores(&tracks, weight, |t| &t.track);
+            for (t, score) in tracks.iter().zip(scores) {
+                if let Some(e) = ranked.get_mut(&t.track.id) {
+                    e.boosted_score = Some(score);
+                }
+            }
+        }
+        tracks = spotify::boost_popularity_by(tracks, weight, |t| &t.track);
+    }
+
+    RefinedPage {
+        tracks,
+        explanation: explain.then_some(Explanation {
+            query: None,
+            filters,
+            popularity_boost: params.popularity_boost,
+            embedding: None,
+        }),
+        ranked,
+    }
+}
+
+/// GET /health, /health/live - Liveness: the process is up and serving HTTP.
 pub async fn health() -> impl IntoResponse {
     Json(serde_json::json!({ "status": "ok" }))
 }
 
+/// GET /version - Crate version, git SHA, build timestamp and enabled cargo features.
+pub async fn version() -> impl IntoResponse {
+    let features: Vec<&str> = env!("VERGEN_CARGO_FEATURES")
+        .split(',')
+        .filter(|f| !f.is_empty())
+        .collect();
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("VERGEN_GIT_SHA"),
+        "build_timestamp": env!("VERGEN_BUILD_TIMESTAMP"),
+        "features": features,
+    }))
+}
+
+/// GET /health/ready - Readiness: a Spotify token has been obtained and is refreshing successfully.
+pub async fn ready(State(spotify): State<DynSpotifyApi>) -> impl IntoResponse {
+    let token = spotify.has_token().await;
+    let status = if token { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (
+        status,
+        Json(serde_json::json!({
+            "status": if token { "ready" } else { "not_ready" },
+            "checks": { "spotify_token": token },
+        })),
+    )
+}
+
 /// GET /api/v1/search - Search Spotify for tracks.
 pub async fn search(
-    State(spotify): State<SpotifyClient>,
-    Query(params): Query<SearchQuery>,
-) -> Result<impl IntoResponse, AppError> {
-    if params.q.trim().is_empty() {
-        return Err(AppError::BadRequest("query 'q' is required and cannot be empty".into()));
+    State(spotify): State<DynSpotifyApi>,
+    Accept(format): Accept,
+    Validated(params): Validated<SearchQuery>,
+) -> Result<Response, AppError> {
+    let q = params.spotify_query();
+    access_log::record_query(&q);
+    if params.raw.unwrap_or(false) {
+        require_json_for_raw(format)?;
+        let body = spotify
+            .search_tracks_raw(&q, params.limit, params.offset)
+            .await
+            .map_err(AppError::Spotify)?;
+        return Ok(raw_json(body));
     }
 
-    let response = if params.include_features.unwrap_or(false) {
+    let include_features = params.include_features.unwrap_or(false);
+    let metadata = params.include_metadata.unwrap_or(false);
+    let (tracks, total, limit, offset) = if include_features {
         let result = spotify
-            .search_tracks_with_features(&params.q, params.limit, params.offset)
+            .search_tracks_with_features(&q, params.limit, params.offset)
             .await
-            .map_err(|e| AppError::Spotify(e))?;
-
-        SearchResponse {
-            tracks: result.tracks.iter().map(track_with_features_to_response).collect(),
-            total: result.total,
-            limit: result.limit,
-            offset: result.offset,
-        }
+            .map_err(AppError::Spotify)?;
+        (result.tracks, result.total, result.limit, result.offset)
     } else {
         let result = spotify
-            .search_tracks(&params.q, params.limit, params.offset)
+            .search_tracks(&q, params.limit, params.offset)
             .await
-            .map_err(|e| AppError::Spotify(e))?;
+            .map_err(AppError::Spotify)?;
+        let tracks = result
+            .tracks
+            .into_iter()
+            .map(|track| TrackWithFeatures {
+                track,
+                audio_features: None,
+                embedding: None,
+            })
+            .collect();
+        (tracks, result.total, result.limit, result.offset)
+    };
+    let market = params.market.as_deref();
+    let RefinedPage {
+        mut tracks,
+        explanation,
+        ranked,
+    } = refine_page(tracks, &params, offset);
+    if params.embedding == Some(EmbeddingVersion::V3) {
+        spotify::add_genres(spotify.as_ref(), &mut tracks).await;
+    }
+    access_log::record_results(tracks.len());
+
+    #[cfg(feature = "grpc")]
+    if format == Format::Protobuf {
+        return Ok(protobuf(&proto::SearchTracksResponse {
+            tracks: if include_features {
+                featured_tracks_to_proto(&tracks, true).collect()
+            } else {
+                tracks.iter().map(Into::into).collect()
+            },
+            total,
+            limit,
+            offset,
+        }));
+    }
+    Ok(format.respond(&SearchResponse {
+        tracks: tracks
+            .into_iter()
+            .map(|t| TrackResponse {
+                explain: ranked.get(&t.track.id).cloned(),
+                ..TrackResponse::in_market(t, metadata, market)
+            })
+            .collect(),
+        total,
+        limit,
+        offset,
+        source: response_source(),
+        explain: explanation.map(|e| Explanation { query: Some(q), ..e }),
+        incomplete: false,
+        remaining_ids: Vec::new(),
+        resume_token: None,
+    }))
+}
+
+/// GET /api/v1/search/artists - Search Spotify for tracks and group them by primary artist.
+pub async fn search_artists(
+    State(spotify): State<DynSpotifyApi>,
+    Accept(format): Accept,
+    Validated(params): Validated<ArtistSearchQuery>,
+) -> Result<Response, AppError> {
+    access_log::record_query(&params.q);
+    let result = spotify
+        .search_tracks_with_features(&params.q, Some(params.limit.unwrap_or(50)), params.offset)
+        .await
+        .map_err(AppError::Spotify)?;
+
+    let top_tracks = params.top_tracks.unwrap_or(3) as usize;
+    let metadata = params.include_metadata.unwrap_or(false);
+    let artists = spotify::group_by_artist(result.tracks)
+        .into_iter()
+        .map(|group| ArtistSummary {
+            id: group.artist.id,
+            name: group.artist.name,
+            spotify_url: group.artist.external_urls.spotify,
+            track_count: group.tracks.len() as u32,
+            popularity: group.tracks.first().map_or(0, |t| t.track.popularity),
+            embedding: group.embedding,
+            top_tracks: group
+                .tracks
+                .into_iter()
+                .take(top_tracks)
+                .map(|t| TrackResponse::new(t, metadata))
+                .collect(),
+        })
+        .collect();
+    let response = ArtistSearchResponse {
+        artists,
+        total: result.total,
+        limit: result.limit,
+        offset: result.offset,
+        source: response_source(),
+    };
+    access_log::record_results(response.artists.len());
+
+    Ok(format.respond(&response))
+}
 
-        SearchResponse {
-            tracks: result.tracks.iter().map(track_to_response).collect(),
+/// GET /api/v1/local-search - Search the tracks in the local store, without calling Spotify.
+#[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+pub async fn local_search(
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    Validated(params): Validated<SearchQuery>,
+) -> Result<Response, AppError> {
+    access_log::record_query(&params.q);
+    let mut errors = FieldErrors::default();
+    if params.raw == Some(true) {
+        errors.add("raw", "is not supported by local search");
+    }
+    if params.embedding == Some(EmbeddingVersion::V3) {
+        errors.add("embedding", "v3 is not supported by local search");
+    }
+    if params.year.is_some() {
+        errors.add("year", "is not supported by local search");
+    }
+    if params.new_releases_only == Some(true) {
+        errors.add("new_releases_only", "is not supported by local search");
+    }
+    errors.finish(())?;
+    let Some(index) = &state.local_index else {
+        return Err(AppError::Unavailable("local search is off; set LOCAL_SEARCH=true".into()));
+    };
+    let (limit, offset) = state.search_limits.resolve(params.limit, params.offset);
+    let mut result = index.search(&params.q, limit, offset).map_err(|e| match e {
+        LocalSearchError::NotReady => AppError::Unavailable(e.to_string()),
+        e => AppError::Internal(e.to_string()),
+    })?;
+    let market = params.market.as_deref();
+    let page = refine_page(result.tracks, &params, offset);
+    result.tracks = page.tracks;
+    let include_features = params.include_features.unwrap_or(false);
+    let metadata = params.include_metadata.unwrap_or(false);
+    if !include_features {
+        for track in &mut result.tracks {
+            track.audio_features = None;
+            track.embedding = None;
+        }
+    }
+    access_log::record_results(result.tracks.len());
+
+    #[cfg(feature = "grpc")]
+    if format == Format::Protobuf {
+        return Ok(protobuf(&proto::SearchTracksResponse {
+            tracks: if include_features {
+                featured_tracks_to_proto(&result.tracks, true).collect()
+            } else {
+                result.tracks.iter().map(Into::into).collect()
+            },
             total: result.total,
-            limit: result.limit,
-            offset: result.offset,
+            limit,
+            offset,
+        }));
+    }
+    Ok(format.respond(&SearchResponse {
+        tracks: result
+            .tracks
+            .into_iter()
+            .map(|t| TrackResponse {
+                explain: page.ranked.get(&t.track.id).cloned(),
+                ..TrackResponse::in_market(t, metadata, market)
+            })
+            .collect(),
+        total: result.total,
+        limit,
+        offset,
+        source: Some("local"),
+        explain: page.explanation,
+        incomplete: false,
+        remaining_ids: Vec::new(),
+        resume_token: None,
+    }))
+}
+
+/// GET /api/v1/suggest - Lightweight typeahead suggestions (title and primary artist).
+pub async fn suggest(
+    State(state): State<AppState>,
+    Validated(params): Validated<SuggestQuery>,
+) -> Result<Response, AppError> {
+    access_log::record_query(&params.q);
+    let result = state
+        .suggester
+        .suggest(&params.q, params.limit.unwrap_or(MAX_SUGGESTIONS), params.session.as_deref())
+        .await
+        .map_err(AppError::Spotify)?;
+    let (suggestions, superseded, cache, cache_control) = match result {
+        Suggestions::Found { suggestions, cached } => {
+            let max_age = state.config.load().suggest_cache_ttl.as_secs();
+            let cache = if cached { "hit" } else { "miss" };
+            (suggestions.to_vec(), false, cache, format!("public, max-age={}", max_age))
         }
+        Suggestions::Superseded => (Vec::new(), true, "superseded", "no-store".to_string()),
     };
-
-    Ok((StatusCode::OK, Json(response)))
+    access_log::record_results(suggestions.len());
+    let headers = [
+        (header::CACHE_CONTROL, cache_control),
+        (header::HeaderName::from_static("x-cache"), cache.to_string()),
+    ];
+    let response = SuggestResponse {
+        query: params.q,
+        suggestions,
+        superseded,
+    };
+    Ok((headers, Json(response)).into_response())
 }
 
 /// GET /api/v1/tracks/with-features - Fetch tracks by IDs with metadata + embeddings (for Go saga).
 pub async fn tracks_with_features(
-    State(spotify): State<SpotifyClient>,
-    Query(params): Query<TracksWithFeaturesQuery>,
-) -> Result<impl IntoResponse, AppError> {
-    if params.ids.trim().is_empty() {
-        return Err(AppError::BadRequest("ids is required (comma-separated track IDs)".into()));
+    State(spotify): State<DynSpotifyApi>,
+    State(jobs): State<Jobs>,
+    State(limits): State<RequestLimits>,
+    Accept(format): Accept,
+    Validated(params): Validated<TracksWithFeaturesQuery>,
+) -> Result<Response, AppError> {
+    limits.check_batch("ids", params.ids.len())?;
+    if params.raw.unwrap_or(false) {
+        require_json_for_raw(format)?;
+        let body = spotify.get_tracks_raw(&params.ids).await.map_err(AppError::Spotify)?;
+        return Ok(raw_json(body));
+    }
+    let metadata = params.include_metadata.unwrap_or(false);
+    let resume = resume_batch(&jobs, "tracks", &params.ids, params.resume_token.as_deref())?;
+    let resolved = match params.timeout {
+        Some(timeout) => {
+            let resolved = resolve_within(&jobs, "tracks", &params.ids, timeout, resume, |chunk| {
+                let spotify = spotify.clone();
+                async move { spotify.get_tracks_with_features(&chunk).await }
+            })
+            .await;
+            match resolved {
+                Ok(resolved) => resolved,
+                Err(failure) => return Ok(failure.into_response()),
+            }
+        }
+        None => Resolved::whole(spotify.get_tracks_with_features(&params.ids).await.map_err(AppError::Spotify)?),
+    };
+    let Resolved {
+        items: mut tracks,
+        remaining: remaining_ids,
+        resume_token,
+    } = resolved;
+    if params.embedding == Some(EmbeddingVersion::V3) {
+        spotify::add_genres(spotify.as_ref(), &mut tracks).await;
     }
 
-    let ids: Vec<String> = params.ids.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
-    if ids.is_empty() {
-        return Err(AppError::BadRequest("at least one track id required".into()));
+    #[cfg(feature = "grpc")]
+    if format == Format::Protobuf {
+        access_log::record_results(tracks.len());
+        let response = protobuf(&proto::GetTracksWithFeaturesResponse {
+            tracks: featured_tracks_to_proto(&tracks, true).collect(),
+            remaining_ids,
+            resume_token: resume_token.clone().unwrap_or_default(),
+        });
+        return Ok(with_resume_token(response, resume_token.as_deref()));
     }
+    let count = tracks.len() as u32;
+    let response = SearchResponse {
+        tracks: tracks.into_iter().map(|t| TrackResponse::new(t, metadata)).collect(),
+        total: count,
+        limit: count,
+        offset: 0,
+        source: response_source(),
+        explain: None,
+        incomplete: !remaining_ids.is_empty(),
+        remaining_ids,
+        resume_token,
+    };
+    access_log::record_results(response.tracks.len());
 
-    let tracks = spotify
-        .get_tracks_with_features(&ids)
+    Ok(with_resume_token(format.respond(&response), response.resume_token.as_deref()))
+}
+
+/// GET /api/v1/recommendations - Spotify recommendations for the given seeds, with embeddings.
+pub async fn recommendations(
+    State(spotify): State<DynSpotifyApi>,
+    Accept(format): Accept,
+    Validated(params): Validated<RecommendationsQuery>,
+) -> Result<Response, AppError> {
+    let metadata = params.include_metadata.unwrap_or(false);
+    let mut tracks = spotify
+        .get_recommendations_with_features(&params.seeds, params.limit)
         .await
-        .map_err(|e| AppError::Spotify(e))?;
+        .map_err(AppError::Spotify)?;
+    if params.embedding == Some(EmbeddingVersion::V3) {
+        spotify::add_genres(spotify.as_ref(), &mut tracks).await;
+    }
+
+    #[cfg(feature = "grpc")]
+    if format == Format::Protobuf {
+        access_log::record_results(tracks.len());
+        return Ok(protobuf(&proto::GetRecommendationsResponse {
+            tracks: featured_tracks_to_proto(&tracks, true).collect(),
+        }));
+    }
+    let count = tracks.len() as u32;
+    let response = SearchResponse {
+        tracks: tracks.into_iter().map(|t| TrackResponse::new(t, metadata)).collect(),
+        total: count,
+        limit: count,
+        offset: 0,
+        source: response_source(),
+        explain: None,
+        incomplete: false,
+        remaining_ids: Vec::new(),
+        resume_token: None,
+    };
+    access_log::record_results(response.tracks.len());
+
+    Ok(format.respond(&response))
+}
+
+/// GET /api/v1/tracks/:id/similar - Tracks ranked by embedding similarity to `id`.
+pub async fn similar_tracks(
+    State(spotify): State<DynSpotifyApi>,
+    Path(id): Path<String>,
+    Accept(format): Accept,
+    Validated(params): Validated<SimilarTracksQuery>,
+) -> Result<Response, AppError> {
+    let mut errors = FieldErrors::default();
+    if !is_spotify_id(&id) {
+        errors.add("id", format!("'{}' is not a valid Spotify ID", id));
+    }
+    errors.finish(())?;
+
+    let metadata = params.include_metadata.unwrap_or(false);
+    let tracks = match params.embedding.unwrap_or_default() {
+        EmbeddingVersion::V1 => spotify.get_similar_tracks(&id, params.limit).await,
+        EmbeddingVersion::V3 => spotify::similar_tracks_v3(spotify.as_ref(), &id, params.limit).await,
+    }
+    .map_err(AppError::Spotify)?;
 
+    #[cfg(feature = "grpc")]
+    if format == Format::Protobuf {
+        access_log::record_results(tracks.len());
+        return Ok(protobuf(&proto::GetSimilarTracksResponse {
+            tracks: tracks
+                .iter()
+                .map(|t| proto::SimilarTrack {
+                    track: Some((&t.track).into()),
+                    score: t.score,
+                })
+                .collect(),
+        }));
+    }
+    let explain = params.explain.unwrap_or(false);
+    let count = tracks.len() as u32;
     let response = SearchResponse {
-        tracks: tracks.iter().map(track_with_features_to_response).collect(),
-        total: tracks.len() as u32,
-        limit: tracks.len() as u32,
+        tracks: tracks
+            .into_iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let explain = explain.then(|| TrackExplanation {
+                    rank: i as u32 + 1,
+                    popularity: t.track.track.popularity,
+                    boosted_score: None,
+                    similarity: Some(t.score),
+                });
+                TrackResponse {
+                    explain,
+                    ..TrackResponse::scored(t, metadata)
+                }
+            })
+            .collect(),
+        total: count,
+        limit: count,
         offset: 0,
+        source: response_source(),
+        explain: explain.then(|| Explanation {
+            query: None,
+            filters: Vec::new(),
+            popularity_boost: None,
+            embedding: Some(params.embedding.unwrap_or_default().as_str()),
+        }),
+        incomplete: false,
+        remaining_ids: Vec::new(),
+        resume_token: None,
     };
+    access_log::record_results(response.tracks.len());
+
+    Ok(format.respond(&response))
+}
+
+/// GET /api/v1/albums/:id - Album metadata: label, release date, genres, copyrights and images.
+pub async fn album(
+    State(spotify): State<DynSpotifyApi>,
+    Path(id): Path<String>,
+    Accept(format): Accept,
+) -> Result<Response, AppError> {
+    let mut errors = FieldErrors::default();
+    if !is_spotify_id(&id) {
+        errors.add("id", format!("'{}' is not a valid Spotify ID", id));
+    }
+    errors.finish(())?;
+
+    let album = spotify.get_album(&id).await.map_err(AppError::Spotify)?;
+    Ok(format.respond(&AlbumDetailResponse::from(album)))
+}
+
+/// GET /api/v1/artists/:id - Artist genres, popularity, follower count and images.
+pub async fn artist(
+    State(spotify): State<DynSpotifyApi>,
+    Path(id): Path<String>,
+    Accept(format): Accept,
+) -> Result<Response, AppError> {
+    let mut errors = FieldErrors::default();
+    if !is_spotify_id(&id) {
+        errors.add("id", format!("'{}' is not a valid Spotify ID", id));
+    }
+    errors.finish(())?;
+
+    let artist = spotify.get_artist(&id).await.map_err(AppError::Spotify)?;
+    Ok(format.respond(&ArtistDetailResponse::from(artist)))
+}
+
+/// GET /api/v1/albums - Album metadata for up to `LIMITS_MAX_BATCH_IDS` IDs.
+pub async fn albums(
+    State(spotify): State<DynSpotifyApi>,
+    State(jobs): State<Jobs>,
+    State(limits): State<RequestLimits>,
+    Accept(format): Accept,
+    Validated(params): Validated<IdsQuery>,
+) -> Result<Response, AppError> {
+    limits.check_batch("ids", params.ids.len())?;
+    let resume = resume_batch(&jobs, "albums", &params.ids, params.resume_token.as_deref())?;
+    let resolved = match params.timeout {
+        Some(timeout) => {
+            let resolved = resolve_within(&jobs, "albums", &params.ids, timeout, resume, |chunk| {
+                let spotify = spotify.clone();
+                async move { spotify.get_albums(&chunk).await }
+            })
+            .await;
+            match resolved {
+                Ok(resolved) => resolved,
+                Err(failure) => return Ok(failure.into_response()),
+            }
+        }
+        None => Resolved::whole(spotify.get_albums(&params.ids).await.map_err(AppError::Spotify)?),
+    };
+    let albums: Vec<AlbumDetailResponse> = resolved.items.into_iter().flatten().map(Into::into).collect();
+    access_log::record_results(albums.len());
+    let response = AlbumsResponse {
+        albums,
+        incomplete: !resolved.remaining.is_empty(),
+        remaining_ids: resolved.remaining,
+        resume_token: resolved.resume_token,
+    };
+    Ok(with_resume_token(format.respond(&response), response.resume_token.as_deref()))
+}
+
+/// GET /api/v1/artists - Artist genres, popularity, followers and images for up to `LIMITS_MAX_BATCH_IDS` IDs.
+pub async fn artists(
+    State(spotify): State<DynSpotifyApi>,
+    State(jobs): State<Jobs>,
+    State(limits): State<RequestLimits>,
+    Accept(format): Accept,
+    Validated(params): Validated<IdsQuery>,
+) -> Result<Response, AppError> {
+    limits.check_batch("ids", params.ids.len())?;
+    let resume = resume_batch(&jobs, "artists", &params.ids, params.resume_token.as_deref())?;
+    let resolved = match params.timeout {
+        Some(timeout) => {
+            let resolved = resolve_within(&jobs, "artists", &params.ids, timeout, resume, |chunk| {
+                let spotify = spotify.clone();
+                async move { spotify.get_artists(&chunk).await }
+            })
+            .await;
+            match resolved {
+                Ok(resolved) => resolved,
+                Err(failure) => return Ok(failure.into_response()),
+            }
+        }
+        None => Resolved::whole(spotify.get_artists(&params.ids).await.map_err(AppError::Spotify)?),
+    };
+    let artists: Vec<ArtistDetailResponse> = resolved.items.into_iter().flatten().map(Into::into).collect();
+    access_log::record_results(artists.len());
+    let response = ArtistsResponse {
+        artists,
+        incomplete: !resolved.remaining.is_empty(),
+        remaining_ids: resolved.remaining,
+        resume_token: resolved.resume_token,
+    };
+    Ok(with_resume_token(format.respond(&response), response.resume_token.as_deref()))
+}
+
+/// POST /api/v1/ingest/playlist/:id - Start a job storing the playlist's tracks, audio
+/// features and embeddings.
+pub async fn ingest_playlist(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Validated(query): Validated<IngestQuery>,
+) -> Result<Response, AppError> {
+    let mut errors = FieldErrors::default();
+    if !is_spotify_id(&id) {
+        errors.add("id", format!("'{}' is not a valid Spotify ID", id));
+    }
+    let key = idempotency_key(&headers, &mut errors);
+    errors.finish(())?;
+    if state.config.load().database_url.is_none() {
+        return Err(AppError::Unavailable(
+            "playlist ingest stores tracks in the local store; set DATABASE_URL".into(),
+        ));
+    }
+    let callback = job_callback(&state, query.callback_url).await?;
+
+    let spotify = state.spotify.clone();
+    let params = serde_json::json!({ "playlist_id": id });
+    submit_job(&state, ingest::KIND, key, &id.clone(), params, callback, move |job| {
+        ingest::ingest_playlist(spotify, id, job)
+    })
+}
+
+/// POST /api/v1/ingest/artist/:id - Start a job storing the tracks, audio features and
+/// embeddings of the artist's albums, singles and compilations.
+pub async fn sync_artist_discography(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Validated(query): Validated<IngestQuery>,
+) -> Result<Response, AppError> {
+    let mut errors = FieldErrors::default();
+    if !is_spotify_id(&id) {
+        errors.add("id", format!("'{}' is not a valid Spotify ID", id));
+    }
+    let key = idempotency_key(&headers, &mut errors);
+    errors.finish(())?;
+    if state.config.load().database_url.is_none() {
+        return Err(AppError::Unavailable(
+            "discography sync stores tracks in the local store; set DATABASE_URL".into(),
+        ));
+    }
+    let callback = job_callback(&state, query.callback_url).await?;
+
+    let spotify = state.spotify.clone();
+    let params = serde_json::json!({ "artist_id": id });
+    submit_job(&state, discography::KIND, key, &id.clone(), params, callback, move |job| {
+        discography::sync_artist(spotify, id, job)
+    })
+}
+
+/// GET /api/v1/export - Stream the stored tracks and embeddings as NDJSON or Parquet,
+/// optionally only those fetched within a time range.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub async fn export_tracks(
+    State(state): State<AppState>,
+    Validated(query): Validated<ExportQuery>,
+) -> Result<Response, AppError> {
+    let Some(store) = state.store.clone() else {
+        return Err(AppError::Unavailable("export reads the local store; set DATABASE_URL".into()));
+    };
+    let fetched = query.fetched_after.map_or(0, i64::from)..query.fetched_before.map_or(i64::MAX, i64::from);
+    let stream = export::export_stream(store, query.format, fetched).map_err(|e| match e {
+        ExportError::Unsupported(_) => AppError::Unavailable(e.to_string()),
+        e => AppError::Internal(e.to_string()),
+    })?;
+    let disposition = format!("attachment; filename=\"tracks.{}\"", query.format.extension());
+    let headers = [
+        (header::CONTENT_TYPE, query.format.content_type().to_string()),
+        (header::CONTENT_DISPOSITION, disposition),
+    ];
+    let body = axum::body::Body::from_stream(export::response_stream(stream, query.format));
+    Ok((headers, body).into_response())
+}
+
+/// POST /api/v1/jobs/match - Start a job matching `{title, artist}` pairs to Spotify tracks.
+pub async fn submit_match(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Result<Json<MatchRequest>, JsonRejection>,
+) -> Result<Response, AppError> {
+    let Json(request) = body.map_err(|e| match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => AppError::PayloadTooLarge(e.body_text()),
+        _ => AppError::BadRequest(e.body_text()),
+    })?;
+    let mut errors = FieldErrors::default();
+    if request.queries.is_empty() || request.queries.len() > matching::MAX_QUERIES {
+        errors.add(
+            "queries",
+            format!("between 1 and {} queries required (got {})", matching::MAX_QUERIES, request.queries.len()),
+        );
+    }
+    for (i, query) in request.queries.iter().enumerate() {
+        if query.title.trim().is_empty() {
+            errors.add(format!("queries[{}].title", i), "is required and cannot be empty");
+        }
+    }
+    let callback_url = errors.http_url("callback_url", request.callback_url.as_deref());
+    let key = idempotency_key(&headers, &mut errors);
+    errors.finish(())?;
+    let callback = job_callback(&state, callback_url).await?;
+
+    let spotify = state.spotify.clone();
+    let params = serde_json::json!({ "queries": request.queries.len() });
+    submit_job(&state, matching::KIND, key, &request.queries.clone(), params, callback, move |job| {
+        matching::match_tracks(spotify, request.queries, job)
+    })
+}
+
+/// GET /api/v1/jobs/:id (and /api/v1/ingest/jobs/:id) - Status and progress of a job.
+pub async fn job_status(State(state): State<AppState>, Path(id): Path<String>) -> Result<impl IntoResponse, AppError> {
+    let job = state
+        .jobs
+        .get(&id)
+        .ok_or_else(|| AppError::NotFound(format!("no job {}", id)))?;
+    Ok(Json(job))
+}
+
+/// GET /api/v1/jobs/:id/results - A page of a job's result items.
+pub async fn job_results(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Validated(params): Validated<JobResultsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let page = state
+        .jobs
+        .results(&id, params.offset.unwrap_or(0) as usize, params.limit.unwrap_or(100) as usize)
+        .ok_or_else(|| AppError::NotFound(format!("no job {}", id)))?;
+    Ok(Json(page))
+}
+
+/// The `Idempotency-Key` header, if sent: 1-255 visible ASCII characters.
+fn idempotency_key(headers: &HeaderMap, errors: &mut FieldErrors) -> Option<String> {
+    let value = headers.get("idempotency-key")?;
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN && key.bytes().all(|b| b.is_ascii_graphic()) => {
+            Some(key.to_string())
+        }
+        _ => {
+            errors.add(
+                "Idempotency-Key",
+                format!("must be 1-{} visible ASCII characters", MAX_IDEMPOTENCY_KEY_LEN),
+            );
+            None
+        }
+    }
+}
+
+/// The callback for a validated `callback_url`, signed with `JOB_CALLBACK_SECRET`. URLs
+/// that could reach internal services are refused ([`Jobs::check_callback`]).
+async fn job_callback(state: &AppState, url: Option<String>) -> Result<Option<Callback>, AppError> {
+    let Some(url) = url else {
+        return Ok(None);
+    };
+    let secret = state.config.load().job_callback_secret.clone().ok_or_else(|| {
+        AppError::Unavailable("job callbacks are signed with JOB_CALLBACK_SECRET; set it to use callback_url".into())
+    })?;
+    if let Err(reason) = state.jobs.check_callback(&url).await {
+        let mut errors = FieldErrors::default();
+        errors.add("callback_url", reason);
+        errors.finish(())?;
+    }
+    Ok(Some(Callback { url, secret }))
+}
+
+/// Submit a job, or with an idempotency `key` return the job an earlier submission with
+/// the same key and `request` started. New jobs answer `202`, replays `200` with
+/// `Idempotent-Replayed: true`.
+fn submit_job<F, Fut>(
+    state: &AppState,
+    kind: &'static str,
+    key: Option<String>,
+    request: &impl std::hash::Hash,
+    params: serde_json::Value,
+    callback: Option<Callback>,
+    task: F,
+) -> Result<Response, AppError>
+where
+    F: FnOnce(JobHandle) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let Some(key) = key else {
+        return Ok(accepted(state.jobs.submit(kind, params, callback, task)));
+    };
+    let submitted = state
+        .jobs
+        .submit_idempotent(kind, &key, request, params, callback, task)
+        .map_err(|_| AppError::IdempotencyKeyReused)?;
+    if !submitted.replayed {
+        return Ok(accepted(submitted.job));
+    }
+    let location = format!("/api/v1/jobs/{}", submitted.job.id);
+    Ok((
+        StatusCode::OK,
+        [
+            (header::LOCATION, location),
+            (header::HeaderName::from_static("idempotent-replayed"), "true".to_string()),
+        ],
+        Json(submitted.job),
+    )
+        .into_response())
+}
+
+/// `202 Accepted` with the job and its status URL in `Location`.
+fn accepted(job: Job) -> Response {
+    let location = format!("/api/v1/jobs/{}", job.id);
+    (StatusCode::ACCEPTED, [(header::LOCATION, location)], Json(job)).into_response()
+}
+
+/// GET /admin/upstream - Spotify upstream status and rate-limit headers per endpoint.
+pub async fn upstream_status(_: AdminAuth, State(spotify): State<DynSpotifyApi>) -> impl IntoResponse {
+    Json(spotify.upstream_status())
+}
+
+/// GET /admin/token - whether a Spotify token is cached, when it is next refreshed and
+/// how the last refresh went.
+pub async fn token_status(_: AdminAuth, State(spotify): State<DynSpotifyApi>) -> impl IntoResponse {
+    Json(spotify.token_status())
+}
+
+/// POST /admin/token/refresh - fetch a new Spotify token now, replacing the cached one.
+pub async fn refresh_token(_: AdminAuth, State(spotify): State<DynSpotifyApi>) -> Result<impl IntoResponse, AppError> {
+    spotify.refresh_token().await.map_err(AppError::Spotify)?;
+    tracing::info!("Spotify token refreshed via /admin/token/refresh");
+    Ok(Json(spotify.token_status()))
+}
+
+/// POST /admin/reload - re-read the configuration and apply reloadable settings.
+pub async fn reload_config(_: AdminAuth, State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let report = state
+        .reloader
+        .reload()
+        .await
+        .map_err(|e| AppError::Internal(format!("configuration reload failed: {:#}", e)))?;
+    tracing::info!(
+        changed = ?report.changed,
+        requires_restart = ?report.requires_restart,
+        "configuration reloaded via /admin/reload"
+    );
+    Ok(Json(report))
+}
+
+/// GET /api/v1/saved/:alias - The saved search `alias`, from its own cache.
+pub async fn saved_search(
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    Path(alias): Path<String>,
+) -> Result<Response, AppError> {
+    access_log::record_query(&alias);
+    state.saved.search(&alias, format).await
+}
+
+/// Body of `PUT /admin/saved/:alias`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SavedQueryRequest {
+    /// `/api/v1/search` query string.
+    pub query: String,
+    pub ttl_secs: Option<u64>,
+    pub prewarm_secs: Option<u64>,
+}
+
+/// GET /admin/saved - The saved searches.
+pub async fn list_saved(_: AdminAuth, State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.saved.list())
+}
+
+/// PUT /admin/saved/:alias - Save a search as `alias`, replacing any saved as `alias`
+/// and its cached responses. Not written to the config file.
+pub async fn put_saved(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    Path(alias): Path<String>,
+    body: Result<Json<SavedQueryRequest>, JsonRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    let Json(request) = body.map_err(|e| AppError::BadRequest(e.body_text()))?;
+    let saved = SavedQueryConfig {
+        alias,
+        query: request.query,
+        ttl: Duration::from_secs(request.ttl_secs.unwrap_or(saved::DEFAULT_TTL_SECS)),
+        prewarm_interval: request.prewarm_secs.map(Duration::from_secs),
+    };
+    let status = SavedQueryStatus::from(&saved);
+    state.saved.put(saved).map_err(AppError::Validation)?;
+    Ok(Json(status))
+}
+
+/// DELETE /admin/saved/:alias - Remove a saved search.
+pub async fn delete_saved(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    Path(alias): Path<String>,
+) -> Result<StatusCode, AppError> {
+    if !state.saved.remove(&alias) {
+        return Err(AppError::NotFound(format!("no saved search '{}'", alias)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /admin/cache/warm - Progress of the latest cache warm-up.
+pub async fn warm_status(_: AdminAuth, State(state): State<AppState>) -> Json<WarmStatus> {
+    Json(state.warmer.status())
+}
+
+/// POST /admin/cache/warm - Re-read the seed file and warm its entries in the background,
+/// unless a warm-up is already running.
+pub async fn warm_cache(_: AdminAuth, State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let Some(file) = state.warmer.file() else {
+        return Err(AppError::Unavailable("no cache seed file configured (CACHE_WARM_FILE)".into()));
+    };
+    let status = state
+        .warmer
+        .start()
+        .await
+        .map_err(|e| AppError::Internal(format!("reading {}: {}", file.display(), e)))?;
+    tracing::info!(searches = status.searches, tracks = status.tracks, "cache warm-up via /admin/cache/warm");
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
 
-    Ok((StatusCode::OK, Json(response)))
+/// GET /admin/usage - Spotify requests by route and tenant since startup.
+pub async fn usage_report(_: AdminAuth, State(state): State<AppState>) -> Json<UsageReport> {
+    Json(state.usage.report())
 }
 
 /// Build the API router.
-pub fn router() -> Router<SpotifyClient> {
-    Router::new()
+pub fn router() -> Router<AppState> {
+    let router = Router::new()
         .route("/health", get(health))
+        .route("/health/live", get(health))
+        .route("/health/ready", get(ready))
+        .route("/version", get(version))
         .route("/api/v1/search", get(search))
+        .route("/api/v1/search/artists", get(search_artists))
+        .route("/api/v1/suggest", get(suggest))
         .route("/api/v1/tracks/with-features", get(tracks_with_features))
+        .route("/api/v1/tracks/:id/similar", get(similar_tracks))
+        .route("/api/v1/recommendations", get(recommendations))
+        .route("/api/v1/albums", get(albums))
+        .route("/api/v1/albums/:id", get(album))
+        .route("/api/v1/artists", get(artists))
+        .route("/api/v1/artists/:id", get(artist))
+        .route("/api/v1/ingest/playlist/:id", post(ingest_playlist))
+        .route("/api/v1/ingest/artist/:id", post(sync_artist_discography))
+        .route("/api/v1/ingest/jobs/:id", get(job_status))
+        .route("/api/v1/jobs/match", post(submit_match))
+        .route("/api/v1/jobs/:id", get(job_status))
+        .route("/api/v1/jobs/:id/results", get(job_results))
+        .route("/api/v1/saved/:alias", get(saved_search))
+        .route("/admin/upstream", get(upstream_status))
+        .route("/admin/token", get(token_status))
+        .route("/admin/token/refresh", post(refresh_token))
+        .route("/admin/reload", post(reload_config))
+        .route("/admin/saved", get(list_saved))
+        .route("/admin/saved/:alias", put(put_saved).delete(delete_saved))
+        .route("/admin/cache/warm", get(warm_status).post(warm_cache))
+        .route("/admin/usage", get(usage_report));
+    #[cfg(any(feature = "sqlite", feature = "postgres"))]
+    let router = router.route("/api/v1/export", get(export_tracks));
+    #[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+    let router = router.route("/api/v1/local-search", get(local_search));
+    #[cfg(feature = "prometheus")]
+    let router = router
+        .route("/metrics", get(crate::metrics::render))
+        .route_layer(axum::middleware::from_fn(crate::metrics::track_http));
+    router
 }
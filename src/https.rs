@@ -0,0 +1,131 @@
+//! HTTPS for the HTTP server: rustls termination, optionally picking up rotated
+//! certificates (e.g. from cert-manager) without a restart, and optionally requiring
+//! client certificates (see [`mtls`](crate::mtls)).
+
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwap;
+use axum::{Extension, Router};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+use crate::listener::{accept, serve_connection, Accept};
+use crate::mtls::{self, ClientCert};
+
+/// Connections that don't finish the TLS handshake within this window are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve `app` over TLS on `listener`. With `reload_interval`, the certificate and
+/// key files are checked for changes on that interval and swapped in for new
+/// connections; a bad rotation is logged and the previous certificate kept. With a
+/// client CA, each connection's [`ClientCert`] is added to its requests' extensions.
+pub fn serve(
+    listener: impl Accept,
+    app: Router,
+    tls: &TlsConfig,
+    reload_interval: Option<Duration>,
+) -> anyhow::Result<impl Future<Output = anyhow::Result<()>>> {
+    let resolver = Arc::new(CertResolver {
+        current: ArcSwap::from_pointee(load_certified_key(tls)?),
+    });
+    let builder = ServerConfig::builder();
+    let builder = match &tls.client_ca_path {
+        Some(ca) => builder.with_client_cert_verifier(mtls::client_verifier(ca)?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(resolver.clone());
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    if let Some(interval) = reload_interval {
+        tokio::spawn(watch_certificate(resolver, tls.clone(), interval));
+    }
+
+    Ok(async move {
+        loop {
+            let (stream, peer) = accept(&listener).await;
+            let acceptor = acceptor.clone();
+            let app = app.clone();
+            tokio::spawn(async move {
+                let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => return tracing::debug!(%peer, "TLS handshake failed: {}", e),
+                    Err(_) => return tracing::debug!(%peer, "TLS handshake timed out"),
+                };
+                let cert = stream.get_ref().1.peer_certificates().and_then(|certs| certs.first());
+                let app = match cert.and_then(|cert| ClientCert::from_der(cert)) {
+                    Some(cert) => app.layer(Extension(cert)),
+                    None => app,
+                };
+                serve_connection(stream, peer, app).await;
+            });
+        }
+    })
+}
+
+/// Hands out the most recently loaded certificate.
+#[derive(Debug)]
+struct CertResolver {
+    current: ArcSwap<CertifiedKey>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.load_full())
+    }
+}
+
+/// Reload the certificate whenever either PEM file's modification time changes.
+async fn watch_certificate(resolver: Arc<CertResolver>, tls: TlsConfig, interval: Duration) {
+    let mtimes = |tls: &TlsConfig| (modified(&tls.cert_path), modified(&tls.key_path));
+    let mut last = mtimes(&tls);
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let now = mtimes(&tls);
+        if now == last {
+            continue;
+        }
+        match load_certified_key(&tls) {
+            Ok(key) => {
+                resolver.current.store(Arc::new(key));
+                last = now;
+                tracing::info!(cert = %tls.cert_path.display(), "reloaded HTTP TLS certificate");
+            }
+            // Cert and key may be mid-rotation; retry on the next tick.
+            Err(e) => tracing::warn!("failed to reload HTTP TLS certificate, keeping the current one: {:#}", e),
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Certificate chain and private key from PEM files.
+fn load_certified_key(tls: &TlsConfig) -> anyhow::Result<CertifiedKey> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| anyhow::anyhow!("reading {}: {}", path.display(), e))
+    };
+    let certs = rustls_pemfile::certs(&mut open(&tls.cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("parsing {}: {}", tls.cert_path.display(), e))?;
+    anyhow::ensure!(!certs.is_empty(), "no certificates in {}", tls.cert_path.display());
+    let key = rustls_pemfile::private_key(&mut open(&tls.key_path)?)
+        .map_err(|e| anyhow::anyhow!("parsing {}: {}", tls.key_path.display(), e))?
+        .ok_or_else(|| anyhow::anyhow!("no private key in {}", tls.key_path.display()))?;
+    let key = tokio_rustls::rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| anyhow::anyhow!("unsupported private key in {}: {}", tls.key_path.display(), e))?;
+    Ok(CertifiedKey::new(certs, key))
+}
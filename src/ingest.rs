@@ -0,0 +1,44 @@
+//! Bulk playlist ingestion, run as a [`jobs`](crate::jobs) job.
+//!
+//! The job walks the playlist page by page and fetches audio features for every
+//! track. The tracks, features and embeddings are persisted by
+//! [`StoredSpotifyApi`](crate::storage::StoredSpotifyApi), so ingestion needs
+//! `DATABASE_URL`.
+
+use crate::jobs::{retry_rate_limited, JobHandle};
+use crate::spotify::{DynSpotifyApi, MAX_PLAYLIST_PAGE};
+
+/// Job kind for playlist ingests.
+pub const KIND: &str = "playlist_ingest";
+
+/// Walk playlist `playlist_id` and fetch features page by page. Progress counts
+/// playlist items; the stored track IDs are the job's results. Failing to read a page
+/// fails the job; failing to fetch a page's features is counted and skipped.
+pub async fn ingest_playlist(spotify: DynSpotifyApi, playlist_id: String, job: JobHandle) -> anyhow::Result<()> {
+    let mut offset = 0;
+    loop {
+        let page = retry_rate_limited(|| spotify.get_playlist_tracks(&playlist_id, Some(MAX_PLAYLIST_PAGE), Some(offset)))
+            .await?;
+        let ids: Vec<String> = page.tracks.iter().map(|t| t.id.clone()).collect();
+        if !ids.is_empty() {
+            match retry_rate_limited(|| spotify.get_audio_features(&ids)).await {
+                Ok(features) => {
+                    job.count("without_features", features.iter().filter(|f| f.is_none()).count() as u32)
+                }
+                Err(e) => job.error(format!("audio features at offset {}: {}", offset, e)),
+            }
+        }
+
+        let items = page.tracks.len() as u32 + page.skipped;
+        job.set_total(page.total);
+        job.advance(items);
+        job.count("tracks", ids.len() as u32);
+        job.count("skipped", page.skipped);
+        job.push_results(ids.into_iter().map(serde_json::Value::String));
+
+        offset += items;
+        if items == 0 || offset >= page.total {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,744 @@
+//! Long-running operations as background jobs.
+//!
+//! A submitting endpoint answers `202` with a [`Job`] right away. The work runs in a
+//! spawned task that reports progress and appends result items through a
+//! [`JobHandle`]. Clients poll `GET /api/v1/jobs/:id` and page through
+//! `GET /api/v1/jobs/:id/results`. Jobs live in memory and are lost on restart.
+//!
+//! Submissions can carry an idempotency key ([`Jobs::submit_idempotent`]): a retry
+//! with the same key gets the job the first attempt started, for as long as that job
+//! is retained.
+//!
+//! A job submitted with a [`Callback`] has its final [`Job`] POSTed to the callback URL
+//! once it completes or fails, signed with HMAC-SHA256 in [`SIGNATURE_HEADER`] over the
+//! [`TIMESTAMP_HEADER`] value, a `.` and the body. Deliveries are retried with backoff on
+//! network errors, `408`, `429` and `5xx`. Callback URLs must resolve to public addresses
+//! only, unless their host is allowed by name ([`Jobs::callback_hosts`]); this is checked
+//! on submission, and deliveries only connect to addresses that pass it
+//! ([`CallbackResolver`]), so a host can't be re-pointed at an internal address in
+//! between. Redirects are not followed.
+//!
+//! Work that runs within a request can record its progress here too
+//! ([`Jobs::save_progress`]): a chunked batch lookup left unfinished saves the chunks it
+//! did finish as a job, and a retry naming that job skips them.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::sync::Semaphore;
+
+use crate::clock::unix_now;
+use crate::secret::Secret;
+use crate::spotify::SpotifyError;
+use crate::tenants;
+
+/// Jobs running at once, across kinds; later submissions wait as `queued`.
+const MAX_RUNNING: usize = 2;
+/// Finished jobs kept for polling; the oldest are dropped beyond this.
+const MAX_FINISHED: usize = 100;
+/// Attempts per Spotify call while Spotify is rate limiting.
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+/// Wait on 429 without `Retry-After`.
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(30);
+/// Attempts per callback delivery.
+const MAX_CALLBACK_ATTEMPTS: u32 = 5;
+/// Wait before the first callback retry; doubles after each attempt.
+const CALLBACK_BACKOFF: Duration = Duration::from_secs(1);
+/// Per-attempt timeout for callback deliveries.
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of "<timestamp>.<body>">` on callback deliveries.
+pub const SIGNATURE_HEADER: &str = "x-signature-256";
+/// Header carrying the Unix time a callback delivery was signed at.
+pub const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+
+/// Lifecycle of a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Status of one job, as returned by the jobs endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: String,
+    /// What the job does, e.g. `playlist_ingest`.
+    pub kind: &'static str,
+    /// The submitted parameters.
+    pub params: serde_json::Value,
+    pub status: JobStatus,
+    /// Units of work, when known.
+    pub total: Option<u32>,
+    /// Units of work done so far.
+    pub processed: u32,
+    /// Failures the job skipped past.
+    pub errors: u32,
+    /// Latest skipped failure, or why the job failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Kind-specific counters.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub counters: BTreeMap<&'static str, u32>,
+    /// Result items so far; page through them with `GET /api/v1/jobs/:id/results`.
+    pub results: usize,
+    /// Where the final status is POSTed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+    /// Unix seconds.
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+}
+
+/// A page of a job's result items.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultsPage {
+    pub items: Vec<serde_json::Value>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// Result of [`Jobs::submit_idempotent`].
+#[derive(Debug, Clone)]
+pub struct Submitted {
+    pub job: Job,
+    /// The key was seen before; `job` is the one the first submission started.
+    pub replayed: bool,
+}
+
+/// The idempotency key was already used for a different request.
+#[derive(Debug, thiserror::Error)]
+#[error("Idempotency-Key was already used for a different request")]
+pub struct IdempotencyConflict;
+
+/// Items of the finished chunks of a chunked batch, by chunk index.
+pub type Chunks = BTreeMap<usize, Vec<serde_json::Value>>;
+
+/// Where to POST a job's final status, and the key to sign it with.
+#[derive(Clone)]
+pub struct Callback {
+    pub url: String,
+    pub secret: Secret,
+}
+
+struct Entry {
+    job: Job,
+    results: Vec<serde_json::Value>,
+    /// Idempotency key and a hash of the request that started the job.
+    idempotency: Option<(String, u64)>,
+    callback: Option<Callback>,
+    /// Finished chunks, for progress saved with [`Jobs::save_progress`].
+    chunks: Chunks,
+}
+
+/// In-memory job registry. Cheap to clone.
+#[derive(Clone)]
+pub struct Jobs {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    running: Arc<Semaphore>,
+    http: reqwest::Client,
+    /// Hosts callbacks may go to whatever they resolve to; any public host when empty.
+    callback_hosts: Arc<[String]>,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self {
+            entries: Arc::default(),
+            running: Arc::new(Semaphore::new(MAX_RUNNING)),
+            http: callback_client_builder(Vec::new()).build().expect("default HTTP client"),
+            callback_hosts: Arc::new([]),
+        }
+    }
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver callbacks with `http`, e.g. one that goes through the egress proxy. Build it
+    /// from [`callback_client_builder`] so that it only connects to vetted addresses.
+    pub fn http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Only send callbacks to these hosts, which may then be private. Without any, a
+    /// callback may go to any host that resolves to public addresses only. Replaces the
+    /// client from [`http_client`](Self::http_client) with a direct one, so call it first.
+    pub fn callback_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.callback_hosts = hosts.iter().map(|h| h.to_ascii_lowercase()).collect();
+        self.http = callback_client_builder(hosts).build().expect("default HTTP client");
+        self
+    }
+
+    /// Why callbacks to `url` are refused, if they are: its host isn't allowed, or it
+    /// resolves to a loopback, private, link-local or otherwise internal address.
+    pub async fn check_callback(&self, url: &str) -> Result<(), String> {
+        check_callback_url(url, &self.callback_hosts).await
+    }
+
+    /// Queue `task` as a job of `kind` and return it. The task runs once a slot is
+    /// free; an `Err` marks the job failed with the error as `last_error`. The final
+    /// status is delivered to `callback`, if any.
+    pub fn submit<F, Fut>(
+        &self,
+        kind: &'static str,
+        params: serde_json::Value,
+        callback: Option<Callback>,
+        task: F,
+    ) -> Job
+    where
+        F: FnOnce(JobHandle) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        self.start(&mut entries, kind, params, None, callback, task)
+    }
+
+    /// Like [`submit`](Self::submit), unless a retained job of the same `kind` was
+    /// submitted with `key`: then that job is returned instead of starting another,
+    /// provided `request` and the callback URL are the same as the first time.
+    pub fn submit_idempotent<F, Fut>(
+        &self,
+        kind: &'static str,
+        key: &str,
+        request: &impl Hash,
+        params: serde_json::Value,
+        callback: Option<Callback>,
+        task: F,
+    ) -> Result<Submitted, IdempotencyConflict>
+    where
+        F: FnOnce(JobHandle) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let mut hasher = DefaultHasher::new();
+        request.hash(&mut hasher);
+        callback.as_ref().map(|c| &c.url).hash(&mut hasher);
+        let fingerprint = hasher.finish();
+
+        let mut entries = self.entries.lock().unwrap();
+        let existing = entries
+            .values()
+            .find(|e| e.job.kind == kind && e.idempotency.as_ref().is_some_and(|(k, _)| k == key));
+        if let Some(entry) = existing {
+            return match entry.idempotency {
+                Some((_, seen)) if seen == fingerprint => Ok(Submitted {
+                    job: entry.job.clone(),
+                    replayed: true,
+                }),
+                _ => Err(IdempotencyConflict),
+            };
+        }
+        let job = self.start(
+            &mut entries,
+            kind,
+            params,
+            Some((key.to_string(), fingerprint)),
+            callback,
+            task,
+        );
+        Ok(Submitted { job, replayed: false })
+    }
+
+    /// Register a new job in `entries` and spawn its task.
+    fn start<F, Fut>(
+        &self,
+        entries: &mut HashMap<String, Entry>,
+        kind: &'static str,
+        params: serde_json::Value,
+        idempotency: Option<(String, u64)>,
+        callback: Option<Callback>,
+        task: F,
+    ) -> Job
+    where
+        F: FnOnce(JobHandle) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let job = new_job(kind, params, callback.as_ref().map(|c| c.url.clone()));
+        entries.insert(
+            job.id.clone(),
+            Entry {
+                job: job.clone(),
+                results: Vec::new(),
+                idempotency,
+                callback,
+                chunks: Chunks::new(),
+            },
+        );
+        let handle = JobHandle {
+            jobs: self.clone(),
+            id: job.id.clone(),
+        };
+        tokio::spawn(tenants::propagate(self.clone().run(handle, task)));
+        job
+    }
+
+    /// Record the progress of a chunked batch that runs within a request rather than as a
+    /// spawned task: `chunks` of `total` are done, and `error` says why the rest aren't.
+    /// Updates job `id`, or registers a new job of `kind` when `id` is `None` or no longer
+    /// retained. The job is `failed` while chunks are missing and `completed` once they
+    /// are all done. Returns the job's ID.
+    pub fn save_progress(
+        &self,
+        id: Option<&str>,
+        kind: &'static str,
+        params: serde_json::Value,
+        total: usize,
+        chunks: Chunks,
+        error: Option<String>,
+    ) -> String {
+        let mut entries = self.entries.lock().unwrap();
+        let id = match id.filter(|id| entries.contains_key(*id)) {
+            Some(id) => id.to_string(),
+            None => {
+                let job = new_job(kind, params, None);
+                let id = job.id.clone();
+                entries.insert(
+                    id.clone(),
+                    Entry {
+                        job,
+                        results: Vec::new(),
+                        idempotency: None,
+                        callback: None,
+                        chunks: Chunks::new(),
+                    },
+                );
+                id
+            }
+        };
+        let entry = entries.get_mut(&id).expect("entry was just looked up or inserted");
+        entry.job.status = if error.is_some() { JobStatus::Failed } else { JobStatus::Completed };
+        entry.job.total = Some(total as u32);
+        entry.job.processed = chunks.len() as u32;
+        entry.job.last_error = error;
+        entry.job.finished_at = Some(unix_now());
+        entry.chunks = chunks;
+        drop(entries);
+        self.prune();
+        id
+    }
+
+    /// The params and finished chunks of job `id` of `kind`, as saved with
+    /// [`save_progress`](Self::save_progress).
+    pub fn progress(&self, id: &str, kind: &str) -> Option<(serde_json::Value, Chunks)> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(id).filter(|e| e.job.kind == kind)?;
+        Some((entry.job.params.clone(), entry.chunks.clone()))
+    }
+
+    /// Current status of job `id`.
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.entries.lock().unwrap().get(id).map(|e| e.job.clone())
+    }
+
+    /// Up to `limit` result items of job `id` from `offset`.
+    pub fn results(&self, id: &str, offset: usize, limit: usize) -> Option<ResultsPage> {
+        let entries = self.entries.lock().unwrap();
+        let results = &entries.get(id)?.results;
+        Some(ResultsPage {
+            items: results.iter().skip(offset).take(limit).cloned().collect(),
+            total: results.len(),
+            offset,
+            limit,
+        })
+    }
+
+    async fn run<F, Fut>(self, handle: JobHandle, task: F)
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let _permit = self.running.acquire().await.expect("jobs semaphore is never closed");
+        let id = handle.id.clone();
+        let mut kind = "";
+        self.update(&id, |e| {
+            e.job.status = JobStatus::Running;
+            kind = e.job.kind;
+        });
+        tracing::info!(job_id = %id, kind, "job started");
+
+        let result = task(handle).await;
+        let mut delivery = None;
+        self.update(&id, |e| {
+            e.job.status = if result.is_ok() { JobStatus::Completed } else { JobStatus::Failed };
+            e.job.finished_at = Some(unix_now());
+            if let Err(err) = &result {
+                e.job.last_error = Some(format!("{:#}", err));
+            }
+            delivery = e.callback.take().map(|callback| (callback, e.job.clone()));
+        });
+        if let Some((callback, job)) = delivery {
+            tokio::spawn(deliver(self.http.clone(), self.callback_hosts.clone(), callback, job));
+        }
+        match &result {
+            Ok(()) => tracing::info!(job_id = %id, kind, "job completed"),
+            Err(e) => tracing::warn!(job_id = %id, kind, "job failed: {:#}", e),
+        }
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "jobs_total",
+            "kind" => kind,
+            "result" => if result.is_ok() { "completed" } else { "failed" }
+        )
+        .increment(1);
+        self.prune();
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut Entry)) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(id) {
+            f(entry);
+        }
+    }
+
+    /// Drop the oldest finished jobs beyond [`MAX_FINISHED`].
+    fn prune(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        let mut finished: Vec<(u64, String)> = entries
+            .values()
+            .filter(|e| e.job.finished_at.is_some())
+            .map(|e| (e.job.created_at, e.job.id.clone()))
+            .collect();
+        if finished.len() > MAX_FINISHED {
+            finished.sort();
+            for (_, id) in &finished[..finished.len() - MAX_FINISHED] {
+                entries.remove(id);
+            }
+        }
+    }
+}
+
+/// A running job's view of its own status.
+#[derive(Clone)]
+pub struct JobHandle {
+    jobs: Jobs,
+    id: String,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Set the number of units of work.
+    pub fn set_total(&self, total: u32) {
+        self.jobs.update(&self.id, |e| e.job.total = Some(total));
+    }
+
+    /// Mark `n` more units of work done.
+    pub fn advance(&self, n: u32) {
+        self.jobs.update(&self.id, |e| e.job.processed += n);
+    }
+
+    /// Add `n` to a kind-specific counter.
+    pub fn count(&self, counter: &'static str, n: u32) {
+        self.jobs.update(&self.id, |e| *e.job.counters.entry(counter).or_default() += n);
+    }
+
+    /// Record a failure the job skips past.
+    pub fn error(&self, message: impl Into<String>) {
+        let message = message.into();
+        self.jobs.update(&self.id, |e| {
+            e.job.errors += 1;
+            e.job.last_error = Some(message);
+        });
+    }
+
+    /// Append result items.
+    pub fn push_results(&self, items: impl IntoIterator<Item = serde_json::Value>) {
+        self.jobs.update(&self.id, |e| {
+            e.results.extend(items);
+            e.job.results = e.results.len();
+        });
+    }
+}
+
+/// POST `job` to the callback URL, retrying failed deliveries with backoff. Each attempt
+/// is signed with its own timestamp, so receivers can refuse stale replays.
+async fn deliver(http: reqwest::Client, allowed_hosts: Arc<[String]>, callback: Callback, job: Job) {
+    let body = serde_json::to_vec(&job).expect("job serializes to JSON");
+    let mut backoff = CALLBACK_BACKOFF;
+    let mut attempt = 1;
+    let outcome = loop {
+        // The host may resolve differently than when the job was submitted.
+        if let Err(reason) = check_callback_url(&callback.url, &allowed_hosts).await {
+            tracing::warn!(job_id = %job.id, "job callback blocked: {}", reason);
+            break "blocked";
+        }
+        let timestamp = unix_now();
+        let signature = format!("sha256={}", sign(callback.secret.expose(), timestamp, &body));
+        let response = http
+            .post(&callback.url)
+            .timeout(CALLBACK_TIMEOUT)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .body(body.clone())
+            .send()
+            .await;
+        let error = match response {
+            Ok(r) if r.status().is_success() => break "delivered",
+            Ok(r) if !is_retryable(r.status()) => {
+                tracing::warn!(job_id = %job.id, "job callback rejected with {}", r.status());
+                break "rejected";
+            }
+            Ok(r) => format!("status {}", r.status()),
+            Err(e) => e.to_string(),
+        };
+        if attempt == MAX_CALLBACK_ATTEMPTS {
+            tracing::warn!(job_id = %job.id, "job callback failed after {} attempts: {}", attempt, error);
+            break "failed";
+        }
+        tracing::debug!(job_id = %job.id, attempt, "job callback failed, retrying: {}", error);
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    };
+    record_callback(job.kind, outcome);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_callback(kind: &'static str, outcome: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("job_callbacks_total", "kind" => kind, "result" => outcome).increment(1);
+}
+
+fn is_retryable(status: reqwest::StatusCode) -> bool {
+    status.is_server_error()
+        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Hex HMAC-SHA256 of `<timestamp>.<body>` under `secret`.
+fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// A client builder for callback deliveries: resolves hosts with a [`CallbackResolver`]
+/// allowing `allowed_hosts`, and doesn't follow redirects, which could lead a delivery
+/// to a host it was not checked against.
+pub fn callback_client_builder(allowed_hosts: Vec<String>) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(Arc::new(CallbackResolver::new(allowed_hosts)))
+}
+
+/// Resolves hosts for callback deliveries, refusing those with a non-public address
+/// unless they are allowed by name. The connection then uses the addresses vetted here,
+/// rather than a second lookup that could answer differently. IP literals in URLs are
+/// not resolved; [`Jobs::check_callback`] vets them.
+pub struct CallbackResolver {
+    allowed_hosts: Arc<[String]>,
+}
+
+impl CallbackResolver {
+    pub fn new(allowed_hosts: Vec<String>) -> Self {
+        Self {
+            allowed_hosts: allowed_hosts.iter().map(|h| h.to_ascii_lowercase()).collect(),
+        }
+    }
+}
+
+impl reqwest::dns::Resolve for CallbackResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let allowed_hosts = self.allowed_hosts.clone();
+        Box::pin(async move {
+            let host = name.as_str().to_ascii_lowercase();
+            let addrs = match allowed_hosts.contains(&host) {
+                true => lookup(&host, 0).await?,
+                false => resolve_public(&host, 0).await?,
+            };
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Why callbacks to `url` are refused, if they are. Hosts in `allowed_hosts` are trusted
+/// as named; without any, every address the host resolves to must be public.
+async fn check_callback_url(url: &str, allowed_hosts: &[String]) -> Result<(), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("is not a valid URL: {}", e))?;
+    let host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+    let host = host.to_ascii_lowercase();
+    if !allowed_hosts.is_empty() {
+        return match allowed_hosts.contains(&host) {
+            true => Ok(()),
+            false => Err(format!("host '{}' is not in JOB_CALLBACK_ALLOWED_HOSTS", host)),
+        };
+    }
+    resolve_public(&host, url.port_or_known_default().unwrap_or(443)).await?;
+    Ok(())
+}
+
+/// The addresses of `host`, provided they are all public.
+async fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs = lookup(host, port).await?;
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(format!("host '{}' resolves to the non-public address {}", host, addr.ip()));
+    }
+    Ok(addrs)
+}
+
+/// The addresses of `host`, at least one.
+async fn lookup(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("host '{}' could not be resolved: {}", host, e))?
+        .collect();
+    match addrs.is_empty() {
+        true => Err(format!("host '{}' resolves to no address", host)),
+        false => Ok(addrs),
+    }
+}
+
+/// Whether `ip` is a globally routable unicast address: not loopback, private, shared,
+/// link-local, multicast, documentation or otherwise reserved. IPv6 addresses that
+/// embed an IPv4 address (mapped, NAT64, 6to4) are judged by that address.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && ip.octets()[2] == 0)
+                || (a == 198 && (b == 18 || b == 19))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let embedded = |high: u16, low: u16| {
+                let [a, b] = high.to_be_bytes();
+                let [c, d] = low.to_be_bytes();
+                IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+            };
+            match segments {
+                // IPv4-mapped, ::ffff:a.b.c.d
+                [0, 0, 0, 0, 0, 0xffff, high, low] => return is_public(embedded(high, low)),
+                // NAT64, 64:ff9b::a.b.c.d
+                [0x64, 0xff9b, 0, 0, 0, 0, high, low] => return is_public(embedded(high, low)),
+                // 6to4, 2002:aabb:ccdd::/48
+                [0x2002, high, low, ..] => return is_public(embedded(high, low)),
+                _ => {}
+            }
+            let [first, second, ..] = segments;
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                // Local-use NAT64 (64:ff9b:1::/48), Teredo (2001::/32), documentation
+                || (first == 0x64 && second == 0xff9b)
+                || (first == 0x2001 && (second == 0 || second == 0x0db8))
+                // Deprecated IPv4-compatible, ::a.b.c.d
+                || segments[..6] == [0; 6])
+        }
+    }
+}
+
+/// Run a Spotify call, waiting out up to [`MAX_RATE_LIMIT_RETRIES`] rate limits.
+/// Jobs aren't latency sensitive, so they wait rather than fail.
+pub async fn retry_rate_limited<T, F, Fut>(mut call: F) -> Result<T, SpotifyError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SpotifyError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match call().await {
+            Err(SpotifyError::RateLimited { retry_after }) if attempt < MAX_RATE_LIMIT_RETRIES => {
+                attempt += 1;
+                tokio::time::sleep(retry_after.map(Duration::from_secs).unwrap_or(RATE_LIMIT_BACKOFF)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A new `queued` job.
+fn new_job(kind: &'static str, params: serde_json::Value, callback_url: Option<String>) -> Job {
+    Job {
+        id: uuid::Uuid::new_v4().to_string(),
+        kind,
+        params,
+        status: JobStatus::Queued,
+        total: None,
+        processed: 0,
+        errors: 0,
+        last_error: None,
+        counters: BTreeMap::new(),
+        results: 0,
+        callback_url,
+        created_at: unix_now(),
+        finished_at: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::dns::Resolve;
+
+    use super::*;
+
+    #[test]
+    fn is_public_sees_through_embedded_ipv4() {
+        let cases = [
+            ("93.184.216.34", true),
+            ("2606:2800:220:1::1", true),
+            ("127.0.0.1", false),
+            ("10.1.2.3", false),
+            ("100.64.0.1", false),
+            ("169.254.169.254", false),
+            ("::1", false),
+            ("fd00::1", false),
+            ("fe80::1", false),
+            ("::ffff:10.0.0.1", false),
+            ("::ffff:93.184.216.34", true),
+            ("64:ff9b::a00:1", false),
+            ("64:ff9b::5db8:d822", true),
+            ("64:ff9b:1::1", false),
+            ("2002:a9fe:a9fe::1", false),
+            ("2002:5db8:d822::1", true),
+            ("2001:0:4136:e378::1", false),
+            ("::10.0.0.1", false),
+        ];
+        for (ip, public) in cases {
+            assert_eq!(is_public(ip.parse().unwrap()), public, "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn callback_resolver_refuses_internal_hosts_unless_allowed() {
+        let name = |host: &str| host.parse::<reqwest::dns::Name>().unwrap();
+        let refused = CallbackResolver::new(Vec::new()).resolve(name("localhost")).await;
+        assert!(refused.err().unwrap().to_string().contains("non-public address"));
+        let allowed = CallbackResolver::new(vec!["LocalHost".into()]).resolve(name("localhost")).await;
+        assert!(allowed.unwrap().all(|addr| addr.ip().is_loopback()));
+    }
+}
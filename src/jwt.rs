@@ -0,0 +1,322 @@
+//! JWT bearer authentication for the API.
+//!
+//! With `JWT_SECRET` or `JWT_JWKS_URL` set, every `/api` HTTP request and every
+//! `spotify.SpotifySearch` gRPC call must carry `Authorization: Bearer <jwt>`. The token
+//! is checked against the HMAC secret, or against the JWKS key its `kid` names, and must
+//! not be expired; `iss` and `aud` are checked when configured. Health, version, metrics
+//! and admin endpoints are left to their own protection.
+//!
+//! The verified `sub` and tenant claim become the request's [`Principal`], in the request
+//! extensions. The access log records both, and the request runs as that tenant (see
+//! [`tenants`](crate::tenants)), taking precedence over `X-Api-Key` and `X-Tenant`.
+//!
+//! JWKS documents are fetched on first use, re-fetched hourly, and re-fetched early when
+//! a token names an unknown `kid` (at most every [`JWKS_MIN_REFETCH`]), so rotated keys
+//! are picked up without a restart.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwapOption;
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::{Map, Value};
+
+use crate::config::{JwtConfig, JwtKey};
+use crate::error::AppError;
+
+/// How long a fetched JWKS document is used before it is fetched again.
+pub const JWKS_TTL: Duration = Duration::from_secs(3600);
+/// Shortest time between two JWKS fetches, however many unknown `kid`s arrive.
+pub const JWKS_MIN_REFETCH: Duration = Duration::from_secs(30);
+/// Timeout of a JWKS fetch.
+const JWKS_TIMEOUT: Duration = Duration::from_secs(10);
+
+const HMAC: &[Algorithm] = &[Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+const ASYMMETRIC: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// The caller a verified token identifies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// The `sub` claim.
+    pub subject: Option<String>,
+    /// The configured tenant claim, when it is a string.
+    pub tenant: Option<String>,
+}
+
+/// Why a request was not authenticated.
+#[derive(Debug, thiserror::Error)]
+pub enum JwtError {
+    #[error("a bearer token is required in the Authorization header")]
+    Missing,
+    #[error("invalid bearer token: {0}")]
+    Invalid(String),
+    /// The JWKS document could not be fetched, so no token can be checked.
+    #[error("signing keys unavailable: {0}")]
+    Keys(String),
+}
+
+impl From<JwtError> for AppError {
+    fn from(e: JwtError) -> Self {
+        match e {
+            JwtError::Keys(_) => AppError::Unavailable(e.to_string()),
+            _ => AppError::Unauthorized(e.to_string()),
+        }
+    }
+}
+
+/// Checks bearer tokens against the configured key.
+pub struct JwtValidator {
+    config: JwtConfig,
+    jwks: Option<Jwks>,
+}
+
+impl JwtValidator {
+    /// A validator for `config`.
+    pub fn new(config: JwtConfig) -> Self {
+        let jwks = match &config.key {
+            JwtKey::JwksUrl(url) => Some(Jwks {
+                url: url.clone(),
+                http: reqwest::Client::new(),
+                keys: ArcSwapOption::empty(),
+                last_fetch: tokio::sync::Mutex::new(None),
+                min_refetch: JWKS_MIN_REFETCH,
+            }),
+            JwtKey::Secret(_) => None,
+        };
+        Self { config, jwks }
+    }
+
+    /// Fetch JWKS documents with `http`, e.g. one that goes through the egress proxy.
+    pub fn http_client(mut self, http: reqwest::Client) -> Self {
+        if let Some(jwks) = &mut self.jwks {
+            jwks.http = http;
+        }
+        self
+    }
+
+    /// Shortest time between two JWKS fetches (default [`JWKS_MIN_REFETCH`]).
+    pub fn min_refetch(mut self, interval: Duration) -> Self {
+        if let Some(jwks) = &mut self.jwks {
+            jwks.min_refetch = interval;
+        }
+        self
+    }
+
+    /// The principal of the `Authorization` header value `authorization`.
+    pub async fn authenticate(&self, authorization: Option<&str>) -> Result<Principal, JwtError> {
+        let token = authorization
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .ok_or(JwtError::Missing)?;
+        let invalid = |e: jsonwebtoken::errors::Error| JwtError::Invalid(e.to_string());
+        let header = jsonwebtoken::decode_header(token).map_err(invalid)?;
+        let (key, algorithms) = match (&self.config.key, &self.jwks) {
+            (JwtKey::Secret(secret), _) => (DecodingKey::from_secret(secret.expose().as_bytes()), HMAC),
+            (JwtKey::JwksUrl(_), Some(jwks)) => (jwks.key(header.kid.as_deref()).await?, ASYMMETRIC),
+            (JwtKey::JwksUrl(_), None) => unreachable!("a JWKS URL always has a key cache"),
+        };
+        // Only the token's own algorithm, so its key decides the family; an HMAC token
+        // can't be checked against a public key.
+        if !algorithms.contains(&header.alg) {
+            return Err(JwtError::Invalid(format!("algorithm {:?} is not accepted", header.alg)));
+        }
+        let mut validation = Validation::new(header.alg);
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let claims = jsonwebtoken::decode::<Map<String, Value>>(token, &key, &validation)
+            .map_err(invalid)?
+            .claims;
+        let string = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_string);
+        Ok(Principal {
+            subject: string("sub"),
+            tenant: string(&self.config.tenant_claim),
+        })
+    }
+}
+
+/// Cached JWKS document.
+struct Jwks {
+    url: String,
+    http: reqwest::Client,
+    /// The keys and when they were fetched.
+    keys: ArcSwapOption<(JwkSet, Instant)>,
+    /// When the last fetch was attempted; held while fetching, so only one runs.
+    last_fetch: tokio::sync::Mutex<Option<Instant>>,
+    min_refetch: Duration,
+}
+
+impl Jwks {
+    /// The key `kid` names, or the only key when the token names none.
+    async fn key(&self, kid: Option<&str>) -> Result<DecodingKey, JwtError> {
+        let cached = self.keys.load_full();
+        if let Some((set, fetched)) = cached.as_deref() {
+            if fetched.elapsed() < JWKS_TTL {
+                if let Some(key) = find(set, kid) {
+                    return key;
+                }
+            }
+        }
+
+        let mut last_fetch = self.last_fetch.lock().await;
+        if last_fetch.is_none_or(|at| at.elapsed() >= self.min_refetch) {
+            *last_fetch = Some(Instant::now());
+            match self.fetch().await {
+                Ok(set) => self.keys.store(Some(Arc::new((set, Instant::now())))),
+                // Stale keys are better than none while the issuer is unreachable.
+                Err(e) if self.keys.load().is_some() => {
+                    tracing::warn!("refreshing JWKS from {} failed, keeping the old keys: {}", self.url, e);
+                }
+                Err(e) => return Err(JwtError::Keys(e)),
+            }
+        }
+        drop(last_fetch);
+        match self.keys.load_full().as_deref() {
+            Some((set, _)) => find(set, kid).unwrap_or_else(|| {
+                Err(JwtError::Invalid(format!("no signing key with kid {}", kid.unwrap_or("(none)"))))
+            }),
+            None => Err(JwtError::Keys(format!("JWKS from {} not fetched yet", self.url))),
+        }
+    }
+
+    async fn fetch(&self) -> Result<JwkSet, String> {
+        let response = self
+            .http
+            .get(&self.url)
+            .timeout(JWKS_TIMEOUT)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| e.to_string())?;
+        response.json::<JwkSet>().await.map_err(|e| e.to_string())
+    }
+}
+
+/// The decoding key for `kid` in `set`; `None` if there is no such key.
+fn find(set: &JwkSet, kid: Option<&str>) -> Option<Result<DecodingKey, JwtError>> {
+    let jwk = match kid {
+        Some(kid) => set.find(kid)?,
+        None if set.keys.len() == 1 => &set.keys[0],
+        None => return None,
+    };
+    Some(DecodingKey::from_jwk(jwk).map_err(|e| JwtError::Keys(format!("unusable JWK: {}", e))))
+}
+
+/// Middleware requiring a valid JWT on `/api` requests, and storing its [`Principal`]
+/// in the request extensions.
+pub async fn authenticate(State(validator): State<Arc<JwtValidator>>, mut req: Request, next: Next) -> Response {
+    if !req.uri().path().starts_with("/api/") {
+        return next.run(req).await;
+    }
+    let authorization = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+    match validator.authenticate(authorization).await {
+        Ok(principal) => {
+            crate::access_log::record_subject(principal.subject.as_deref());
+            req.extensions_mut().insert(principal);
+            next.run(req).await
+        }
+        Err(e) => AppError::from(e).into_response(),
+    }
+}
+
+#[cfg(feature = "grpc")]
+pub use grpc::GrpcJwtLayer;
+
+#[cfg(feature = "grpc")]
+mod grpc {
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+
+    use tonic::{
+        body::BoxBody,
+        codegen::{http, BoxFuture},
+        Status,
+    };
+    use tower::{Layer, Service};
+
+    use super::{JwtError, JwtValidator};
+    use crate::tenants;
+
+    /// Requires a valid JWT on `spotify.SpotifySearch` calls, and runs each call as the
+    /// token's tenant. Health and reflection stay open; without a validator, so does
+    /// everything.
+    #[derive(Clone)]
+    pub struct GrpcJwtLayer(pub Option<Arc<JwtValidator>>);
+
+    impl<S> Layer<S> for GrpcJwtLayer {
+        type Service = GrpcJwt<S>;
+
+        fn layer(&self, inner: S) -> Self::Service {
+            GrpcJwt {
+                validator: self.0.clone(),
+                inner,
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct GrpcJwt<S> {
+        validator: Option<Arc<JwtValidator>>,
+        inner: S,
+    }
+
+    impl<S, ReqBody> Service<http::Request<ReqBody>> for GrpcJwt<S>
+    where
+        S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        ReqBody: Send + 'static,
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
+            // The clone may not be ready; take the one that is and leave the clone.
+            let clone = self.inner.clone();
+            let mut inner = std::mem::replace(&mut self.inner, clone);
+            let validator = match &self.validator {
+                Some(validator) if req.uri().path().starts_with("/spotify.SpotifySearch/") => validator.clone(),
+                _ => return Box::pin(inner.call(req)),
+            };
+            Box::pin(async move {
+                let authorization = req.headers().get("authorization").and_then(|v| v.to_str().ok());
+                match validator.authenticate(authorization).await {
+                    Ok(principal) => {
+                        // Without a tenant claim, keep the tenant of the client certificate.
+                        let tenant = principal.tenant.as_deref().map(Into::into).or_else(tenants::current);
+                        req.extensions_mut().insert(principal);
+                        tenants::run_as(tenant, inner.call(req)).await
+                    }
+                    Err(e @ JwtError::Keys(_)) => Ok(Status::unavailable(e.to_string()).to_http()),
+                    Err(e) => Ok(Status::unauthenticated(e.to_string()).to_http()),
+                }
+            })
+        }
+    }
+}
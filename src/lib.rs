@@ -0,0 +1,118 @@
+//! Spotify search service.
+//!
+//! The crate is usable as a library: [`spotify::SpotifyClient`] (Client Credentials auth,
+//! search, tracks, audio features, embeddings) needs no server dependencies. Build with
+//! `default-features = false` to leave out the server; the default features are
+//!
+//! - `server`: the HTTP API, required by the `spotify-search` binary;
+//! - `grpc`: the gRPC server, which compiles the proto and needs `protoc`;
+//! - `prometheus`: the Prometheus recorder and `GET /metrics`.
+//!
+//! The optional `jwt` feature adds JWT bearer authentication for the HTTP and gRPC APIs.
+//!
+//! The `metrics` feature alone emits client instrumentation through the `metrics`
+//! facade for whatever recorder the embedding application installs.
+//!
+//! With the `grpc-client` feature, [`SpotifySearchClient`] is a ready-made typed
+//! client for the `spotify.SpotifySearch` gRPC service:
+//!
+//! ```ignore
+//! use spotify_search::{proto::SearchTracksRequest, SpotifySearchClient};
+//!
+//! let mut client = SpotifySearchClient::connect("http://spotify-search:50051").await?;
+//! let tracks = client
+//!     .search_tracks(SearchTracksRequest { query: "daft punk".into(), ..Default::default() })
+//!     .await?
+//!     .into_inner()
+//!     .tracks;
+//! ```
+
+pub mod access_log;
+pub mod clock;
+pub mod secret;
+pub mod spotify;
+
+#[cfg(feature = "server")]
+pub mod admin;
+#[cfg(feature = "server")]
+pub mod audit;
+#[cfg(feature = "server")]
+pub mod cache;
+#[cfg(feature = "server")]
+pub mod cli;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod egress;
+#[cfg(feature = "server")]
+pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "server")]
+pub mod discography;
+#[cfg(any(feature = "kafka", feature = "nats"))]
+pub mod events;
+#[cfg(all(feature = "server", any(feature = "sqlite", feature = "postgres")))]
+pub mod export;
+#[cfg(feature = "server")]
+pub mod handlers;
+#[cfg(feature = "server")]
+pub mod https;
+#[cfg(feature = "server")]
+pub mod ingest;
+#[cfg(feature = "server")]
+pub mod jobs;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+#[cfg(feature = "server")]
+pub mod limits;
+#[cfg(feature = "server")]
+pub mod listener;
+#[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+pub mod local_search;
+#[cfg(feature = "server")]
+pub mod matching;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+#[cfg(feature = "server")]
+pub mod mtls;
+#[cfg(feature = "grpc")]
+pub mod mux;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "server")]
+pub mod negotiate;
+#[cfg(feature = "server")]
+pub mod panic;
+#[cfg(feature = "server")]
+pub mod reload;
+#[cfg(feature = "server")]
+pub mod saved;
+#[cfg(feature = "server")]
+pub mod selftest;
+#[cfg(all(feature = "s3", any(feature = "sqlite", feature = "postgres")))]
+pub mod snapshot;
+#[cfg(feature = "server")]
+pub mod state;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub mod storage;
+#[cfg(feature = "server")]
+pub mod suggest;
+#[cfg(feature = "server")]
+pub mod telemetry;
+#[cfg(feature = "server")]
+pub mod tenants;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "server")]
+pub mod usage;
+#[cfg(feature = "server")]
+pub mod validation;
+#[cfg(feature = "server")]
+pub mod warm;
+
+#[cfg(any(feature = "grpc", feature = "grpc-client", feature = "kafka"))]
+pub mod proto;
+
+#[cfg(feature = "grpc-client")]
+pub use proto::spotify_search_client::SpotifySearchClient;
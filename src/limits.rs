@@ -0,0 +1,80 @@
+//! Request size limits, so one oversized bulk request can't tie up the service.
+//!
+//! [`enforce`] turns away HTTP requests whose query string or declared body is too big
+//! before any handler runs; bodies without a `Content-Length` are cut off at the same
+//! size while read. Batch endpoints check their ID count against
+//! [`RequestLimits::max_batch_ids`], and the gRPC server applies the same limits to
+//! its messages and `track_ids`.
+
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::validation::FieldErrors;
+
+/// Most IDs Spotify accepts in one batch call; `max_batch_ids` can only lower it.
+pub const SPOTIFY_MAX_BATCH_IDS: usize = 50;
+
+/// Size limits on incoming requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Largest HTTP request body or gRPC message, in bytes.
+    pub max_body_bytes: usize,
+    /// Most IDs in one batch request.
+    pub max_batch_ids: usize,
+    /// Longest HTTP query string, in bytes.
+    pub max_query_string_bytes: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 2 * 1024 * 1024,
+            max_batch_ids: SPOTIFY_MAX_BATCH_IDS,
+            max_query_string_bytes: 8 * 1024,
+        }
+    }
+}
+
+impl RequestLimits {
+    /// Reject a batch of `count` IDs in `field` if it is over [`max_batch_ids`](Self::max_batch_ids).
+    pub fn check_batch(&self, field: &str, count: usize) -> Result<(), AppError> {
+        let mut errors = FieldErrors::default();
+        if count > self.max_batch_ids {
+            errors.add(field, format!("at most {} ids allowed (got {})", self.max_batch_ids, count));
+        }
+        errors.finish(())
+    }
+}
+
+/// Middleware rejecting query strings over `max_query_string_bytes` with 400, and
+/// bodies declared bigger than `max_body_bytes` with 413.
+pub async fn enforce(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let limits = state.request_limits;
+    let query = req.uri().query().map_or(0, str::len);
+    if query > limits.max_query_string_bytes {
+        return AppError::BadRequest(format!(
+            "query string is {} bytes; at most {} allowed",
+            query, limits.max_query_string_bytes
+        ))
+        .into_response();
+    }
+    let length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let Some(length) = length.filter(|&length| length > limits.max_body_bytes as u64) {
+        return AppError::PayloadTooLarge(format!(
+            "request body is {} bytes; at most {} allowed",
+            length, limits.max_body_bytes
+        ))
+        .into_response();
+    }
+    next.run(req).await
+}
@@ -0,0 +1,159 @@
+//! Listening sockets for the HTTP and gRPC servers.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Pending-connection backlog, as `tokio::net::TcpListener::bind` uses.
+const BACKLOG: i32 = 1024;
+/// Pause after a failed accept that isn't about one connection (e.g. out of file
+/// descriptors), as `axum::serve` does.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Bind a TCP listener. IPv6 addresses are dual-stack (`::` also accepts IPv4)
+/// regardless of the host's `bindv6only` default.
+pub fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to bind {}: {}", addr, e)))?;
+    socket.listen(BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Bind a Unix domain socket, replacing a stale socket file left by a previous run.
+#[cfg(unix)]
+pub fn bind_unix(path: &std::path::Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    tokio::net::UnixListener::bind(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to bind {}: {}", path.display(), e)))
+}
+
+/// A listener the HTTP servers can accept connections from.
+pub trait Accept: Send + 'static {
+    type Io: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Next connection and a peer description for logs.
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Io, String)>> + Send;
+}
+
+impl Accept for TcpListener {
+    type Io = TcpStream;
+
+    async fn accept(&self) -> io::Result<(TcpStream, String)> {
+        let (stream, peer) = TcpListener::accept(self).await?;
+        Ok((stream, peer.to_string()))
+    }
+}
+
+#[cfg(unix)]
+impl Accept for tokio::net::UnixListener {
+    type Io = tokio::net::UnixStream;
+
+    async fn accept(&self) -> io::Result<(tokio::net::UnixStream, String)> {
+        let (stream, _) = tokio::net::UnixListener::accept(self).await?;
+        Ok((stream, "unix".into()))
+    }
+}
+
+/// Plain HTTP (HTTP/1, h2c) on any [`Accept`]or; `axum::serve` only takes TCP.
+pub async fn serve_http(listener: impl Accept, app: Router) -> anyhow::Result<()> {
+    loop {
+        let (stream, peer) = accept(&listener).await;
+        tokio::spawn(serve_connection(stream, peer, app.clone()));
+    }
+}
+
+/// Next connection from `listener`. Accept errors don't stop the server: errors about
+/// one connection are skipped, others (such as running out of file descriptors) are
+/// logged and retried after [`ACCEPT_BACKOFF`].
+pub(crate) async fn accept<L: Accept>(listener: &L) -> (L::Io, String) {
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => return accepted,
+            Err(e) if is_connection_error(&e) => {}
+            Err(e) => {
+                tracing::error!("accepting a connection failed: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Serve `app` on one established connection.
+pub(crate) async fn serve_connection<I>(stream: I, peer: String, app: Router)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = TowerToHyperService::new(app);
+    if let Err(e) = auto::Builder::new(TokioExecutor::new())
+        .serve_connection_with_upgrades(TokioIo::new(stream), service)
+        .await
+    {
+        tracing::debug!(%peer, "HTTP connection error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    /// Fails with the queued errors, then hands out a connection.
+    struct Failing(Mutex<Vec<io::Error>>);
+
+    impl Accept for Failing {
+        type Io = DuplexStream;
+
+        async fn accept(&self) -> io::Result<(DuplexStream, String)> {
+            match self.0.lock().unwrap().pop() {
+                Some(e) => Err(e),
+                None => Ok((tokio::io::duplex(64).0, "peer".into())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn accept_outlives_failed_accepts() {
+        let errors = vec![
+            io::Error::other("Too many open files"),
+            io::Error::from(io::ErrorKind::ConnectionAborted),
+        ];
+        let listener = Failing(Mutex::new(errors));
+        let started = std::time::Instant::now();
+        let (_, peer) = accept(&listener).await;
+        assert_eq!(peer, "peer");
+        // Only the error that wasn't about one connection waited.
+        assert!(started.elapsed() >= ACCEPT_BACKOFF);
+        assert!(started.elapsed() < ACCEPT_BACKOFF * 2);
+    }
+}
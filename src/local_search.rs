@@ -0,0 +1,273 @@
+//! Full-text search over the local store (`local-search` feature).
+//!
+//! [`LocalIndex`] is an in-memory tantivy index of the stored tracks' names, artists
+//! and albums. Each document also keeps the whole track with its features, so a query
+//! is answered from memory without touching the store or Spotify. [`IndexSync`] builds
+//! the index from the store at startup and then re-indexes recently fetched tracks
+//! every interval, which picks up tracks stored by other replicas as well.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::QueryParser;
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value as _, STORED, STRING,
+};
+use tantivy::tokenizer::{AsciiFoldingFilter, LowerCaser, RemoveLongFilter, SimpleTokenizer, TextAnalyzer};
+use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+
+use crate::clock::unix_now;
+use crate::spotify::{AudioFeatures, Track, TrackWithFeatures};
+use crate::storage::{DynStorage, StorageError, StoredTrack};
+
+/// Tracks read from the store per sync page.
+const PAGE_SIZE: u32 = 5000;
+/// Heap for the index writer.
+const WRITER_HEAP_BYTES: usize = 50_000_000;
+/// Incremental syncs also re-read tracks fetched this long before the previous sync
+/// started, to cover clock skew between replicas and transactions committing late.
+const SYNC_OVERLAP_SECS: i64 = 60;
+
+/// Failure indexing or searching.
+#[derive(Debug, thiserror::Error)]
+pub enum LocalSearchError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("local index: {0}")]
+    Index(#[from] tantivy::TantivyError),
+    #[error("indexed track could not be decoded: {0}")]
+    Serde(#[from] serde_json::Error),
+    /// The first sync from the store hasn't finished.
+    #[error("the local index is still being built")]
+    NotReady,
+}
+
+/// One page of local search results.
+#[derive(Debug)]
+pub struct LocalSearchResults {
+    pub tracks: Vec<TrackWithFeatures>,
+    /// All matches, not just this page.
+    pub total: u32,
+}
+
+struct Fields {
+    id: Field,
+    name: Field,
+    artists: Field,
+    album: Field,
+    track: Field,
+}
+
+/// In-memory full-text index of stored tracks.
+pub struct LocalIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+    ready: AtomicBool,
+}
+
+impl LocalIndex {
+    /// An empty index. Matching ignores case and accents (`beyonce` finds `Beyoncé`).
+    pub fn new() -> Result<Self, LocalSearchError> {
+        let text = TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer("track")
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+        );
+        let mut schema = Schema::builder();
+        let fields = Fields {
+            id: schema.add_text_field("id", STRING),
+            name: schema.add_text_field("name", text.clone()),
+            artists: schema.add_text_field("artists", text.clone()),
+            album: schema.add_text_field("album", text),
+            track: schema.add_bytes_field("track", STORED),
+        };
+        let index = Index::create_in_ram(schema.build());
+        let analyzer = TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(RemoveLongFilter::limit(40))
+            .filter(LowerCaser)
+            .filter(AsciiFoldingFilter)
+            .build();
+        index.tokenizers().register("track", analyzer);
+        let reader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        let writer = index.writer(WRITER_HEAP_BYTES)?;
+        Ok(Self {
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            fields,
+            ready: AtomicBool::new(false),
+        })
+    }
+
+    /// Whether the first sync from the store has finished.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    fn set_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+
+    /// Number of indexed tracks.
+    pub fn len(&self) -> u64 {
+        self.reader.searcher().num_docs()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add `tracks`, replacing earlier versions, and make them searchable. Blocks.
+    pub fn upsert(&self, tracks: &[StoredTrack]) -> Result<(), LocalSearchError> {
+        let f = &self.fields;
+        let mut writer = self.writer.lock().unwrap();
+        for stored in tracks {
+            let track = &stored.track.track;
+            writer.delete_term(Term::from_field_text(f.id, &track.id));
+            let mut document = doc!(
+                f.id => track.id.as_str(),
+                f.name => track.name.as_str(),
+                f.album => track.album.name.as_str(),
+                f.track => serde_json::to_vec(&(track, &stored.track.audio_features))?,
+            );
+            for artist in &track.artists {
+                document.add_text(f.artists, &artist.name);
+            }
+            writer.add_document(document)?;
+        }
+        writer.commit()?;
+        self.reader.reload()?;
+        Ok(())
+    }
+
+    /// Tracks matching `query`, best first. All words must match, in the name, an artist
+    /// or the album; quotes search for a phrase. Blocks, briefly.
+    pub fn search(&self, query: &str, limit: u32, offset: u32) -> Result<LocalSearchResults, LocalSearchError> {
+        if !self.is_ready() {
+            return Err(LocalSearchError::NotReady);
+        }
+        let f = &self.fields;
+        let mut parser = QueryParser::for_index(&self.index, vec![f.name, f.artists, f.album]);
+        parser.set_conjunction_by_default();
+        parser.set_field_boost(f.name, 2.0);
+        parser.set_field_boost(f.artists, 1.5);
+        // Unbalanced quotes and the like are searched as plain words rather than rejected.
+        let (query, _) = parser.parse_query_lenient(query);
+
+        let searcher = self.reader.searcher();
+        let top = TopDocs::with_limit(limit.max(1) as usize).and_offset(offset as usize);
+        let (hits, total) = searcher.search(&query, &(top, Count))?;
+        let mut tracks = Vec::with_capacity(hits.len());
+        for (_, address) in hits {
+            let document: TantivyDocument = searcher.doc(address)?;
+            let bytes = document.get_first(f.track).and_then(|v| v.as_bytes()).unwrap_or_default();
+            let (track, audio_features): (Track, Option<AudioFeatures>) = serde_json::from_slice(bytes)?;
+            tracks.push(TrackWithFeatures {
+                track,
+                embedding: audio_features.as_ref().map(AudioFeatures::to_embedding),
+                audio_features,
+            });
+        }
+        Ok(LocalSearchResults {
+            tracks,
+            total: total as u32,
+        })
+    }
+}
+
+/// Keeps a [`LocalIndex`] in step with the store.
+pub struct IndexSync {
+    store: DynStorage,
+    index: Arc<LocalIndex>,
+    interval: Duration,
+    /// `fetched_at` of the tracks indexed within the overlap window, so reading the
+    /// window again doesn't re-index tracks that haven't changed.
+    recent: HashMap<String, i64>,
+}
+
+impl IndexSync {
+    pub fn new(store: DynStorage, index: Arc<LocalIndex>) -> Self {
+        Self {
+            store,
+            index,
+            interval: Duration::from_secs(10),
+            recent: HashMap::new(),
+        }
+    }
+
+    /// How often to look for newly fetched tracks (default 10s).
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Index the whole store, then newly fetched tracks every interval, forever.
+    /// Failures are logged and retried at the next interval.
+    pub async fn run(mut self) {
+        let mut since = 0;
+        loop {
+            let next_since = unix_now() as i64 - SYNC_OVERLAP_SECS;
+            let start = Instant::now();
+            match self.sync(since).await {
+                Ok(indexed) => {
+                    if !self.index.is_ready() {
+                        self.index.set_ready();
+                        tracing::info!("indexed {} stored tracks for local search in {:?}", indexed, start.elapsed());
+                    } else if indexed > 0 {
+                        tracing::debug!("re-indexed {} recently fetched tracks", indexed);
+                    }
+                    record_index_size(self.index.len());
+                    since = next_since;
+                    self.recent.retain(|_, fetched_at| *fetched_at >= since);
+                }
+                Err(e) => tracing::warn!("local search index sync failed, retrying in {:?}: {}", self.interval, e),
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Index the stored tracks fetched at or after `since` (Unix seconds), except those
+    /// already indexed as they are. Returns how many were indexed.
+    pub async fn sync(&mut self, since: i64) -> Result<usize, LocalSearchError> {
+        let window = unix_now() as i64 - SYNC_OVERLAP_SECS;
+        let mut after: Option<String> = None;
+        let mut indexed = 0;
+        loop {
+            let mut page = self.store.export_tracks(after.as_deref(), since..i64::MAX, PAGE_SIZE).await?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some(last.track.track.id.clone());
+            let full = page.len() == PAGE_SIZE as usize;
+            page.retain(|t| self.recent.get(&t.track.track.id) != Some(&t.fetched_at));
+            if !page.is_empty() {
+                indexed += page.len();
+                let in_window: Vec<_> = page
+                    .iter()
+                    .filter(|t| t.fetched_at >= window)
+                    .map(|t| (t.track.track.id.clone(), t.fetched_at))
+                    .collect();
+                let index = self.index.clone();
+                tokio::task::spawn_blocking(move || index.upsert(&page))
+                    .await
+                    .expect("index upsert panicked")?;
+                self.recent.extend(in_window);
+            }
+            if !full {
+                break;
+            }
+        }
+        Ok(indexed)
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_index_size(tracks: u64) {
+    #[cfg(feature = "metrics")]
+    metrics::gauge!("local_search_indexed_tracks").set(tracks as f64);
+}
@@ -1,55 +1,817 @@
-mod config;
-mod error;
-mod grpc;
-mod handlers;
-mod spotify;
-
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
+use axum::{extract::DefaultBodyLimit, middleware, Router};
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use clap::Parser;
+use futures::future::BoxFuture;
+#[cfg(feature = "grpc")]
+use futures::stream::BoxStream;
+#[cfg(feature = "prometheus")]
+use metrics_exporter_prometheus::PrometheusHandle;
+#[cfg(all(feature = "s3", any(feature = "sqlite", feature = "postgres")))]
+use object_store::{aws::AmazonS3Builder, ClientOptions, ObjectStore};
+#[cfg(feature = "grpc")]
+use tokio::net::TcpStream;
+#[cfg(all(feature = "grpc", unix))]
+use tokio_stream::wrappers::UnixListenerStream;
+#[cfg(feature = "grpc")]
+use tokio_stream::wrappers::TcpListenerStream;
+use tower::ServiceBuilder;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-use crate::config::Config;
-use crate::grpc::SpotifySearchService;
-use crate::handlers::router;
-use crate::spotify::SpotifyClient;
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+use tracing_subscriber::EnvFilter;
 
-    let config = Config::from_env()?;
-    let spotify = SpotifyClient::new(config.spotify_client_id.clone(), config.spotify_client_secret.clone());
+use spotify_search::audit::{AuditLog, AuditWriter};
+use spotify_search::cache::CachingSpotifyApi;
+use spotify_search::cli::{Cli, Command};
+#[cfg(any(feature = "sqlite", feature = "postgres", feature = "nats"))]
+use spotify_search::config::redact_password;
+use spotify_search::config::{Config, StartupCheck};
+#[cfg(feature = "kafka")]
+use spotify_search::events::KafkaSink;
+#[cfg(feature = "nats")]
+use spotify_search::events::NatsSink;
+#[cfg(all(any(feature = "kafka", feature = "nats"), any(feature = "sqlite", feature = "postgres")))]
+use spotify_search::events::{OutboxRelay, TrackEvents};
+#[cfg(any(feature = "kafka", feature = "nats"))]
+use spotify_search::events::{DynEventSink, FanoutSink, PublishingSpotifyApi};
+#[cfg(feature = "grpc")]
+use spotify_search::grpc::{self, SpotifySearchService};
+#[cfg(feature = "grpc")]
+use spotify_search::mtls::GrpcClientCertLayer;
+use spotify_search::handlers::{router, TrackResponse};
+use spotify_search::jobs::Jobs;
+use spotify_search::matching::{self, MatchQuery};
+#[cfg(feature = "jwt")]
+use spotify_search::jwt::{self, JwtValidator};
+#[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+use spotify_search::local_search::{IndexSync, LocalIndex};
+#[cfg(all(feature = "grpc", feature = "prometheus"))]
+use spotify_search::metrics::GrpcMetricsLayer;
+#[cfg(feature = "grpc")]
+use spotify_search::panic::GrpcCatchPanicLayer;
+use spotify_search::reload::{LogFilterHandle, Reloader, SharedConfig};
+use spotify_search::saved::SavedQueries;
+use spotify_search::secret::Secret;
+use spotify_search::spotify::{
+    DynSpotifyApi, MockSpotifyApi, SpotifyClient, SpotifyError, TrackWithFeatures, UpstreamAudit, DEFAULT_TENANT,
+};
+use spotify_search::state::AppState;
+use spotify_search::suggest::Suggester;
+use spotify_search::tenants::{self, TenantSpotifyApi};
+#[cfg(feature = "grpc")]
+use spotify_search::usage::GrpcUsageLayer;
+use spotify_search::usage::{self, Usage};
+use spotify_search::warm::CacheWarmer;
+#[cfg(all(feature = "s3", any(feature = "sqlite", feature = "postgres")))]
+use spotify_search::config::SnapshotConfig;
+#[cfg(all(feature = "s3", any(feature = "sqlite", feature = "postgres")))]
+use spotify_search::snapshot::Snapshotter;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+use spotify_search::storage::{self, DynStorage, Refresher, StoredSpotifyApi};
+#[cfg(feature = "grpc")]
+use spotify_search::telemetry::GrpcRequestIdLayer;
+#[cfg(feature = "grpc")]
+use spotify_search::mux;
+#[cfg(feature = "nats")]
+use spotify_search::nats;
+use spotify_search::{access_log, egress, https, limits, listener, panic, selftest, telemetry, validation};
 
-    let grpc_svc = SpotifySearchService::new(spotify.clone());
-    let grpc_router = grpc_svc.into_router();
+type ServerFuture = BoxFuture<'static, anyhow::Result<()>>;
 
-    let app = router()
-        .layer(TraceLayer::new_for_http())
-        .with_state(spotify);
+const STARTUP_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() -> anyhow::Result<()> {
+    let mut cli = Cli::parse();
+    let config = cli.load_config()?;
+    let runtime = runtime(&config).context("starting the Tokio runtime")?;
+    if let Some(command) = cli.command.take() {
+        return runtime.block_on(one_shot(command, config));
+    }
+    runtime.block_on(run(cli, config))
+}
+
+/// Multi-threaded runtime sized by the `runtime` settings.
+fn runtime(config: &Config) -> std::io::Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder
+        .enable_all()
+        .max_blocking_threads(config.runtime_max_blocking_threads)
+        .thread_keep_alive(config.runtime_blocking_keep_alive);
+    if let Some(threads) = config.runtime_worker_threads {
+        builder.worker_threads(threads);
+    }
+    builder.build()
+}
+
+async fn run(cli: Cli, config: Config) -> anyhow::Result<()> {
+    let log_filter = telemetry::init(&config)?;
+    tracing::info!(
+        "spotify-search {} ({}, built {}), {} worker threads",
+        env!("CARGO_PKG_VERSION"),
+        env!("VERGEN_GIT_SHA"),
+        env!("VERGEN_BUILD_TIMESTAMP"),
+        tokio::runtime::Handle::current().metrics().num_workers(),
+    );
+    #[cfg(feature = "prometheus")]
+    let metrics = spotify_search::metrics::install_recorder()?;
+    if cli.self_test {
+        let result = self_test(
+            cli,
+            config,
+            log_filter,
+            #[cfg(feature = "prometheus")]
+            metrics,
+        )
+        .await;
+        telemetry::shutdown();
+        return result;
+    }
+    let audit = audit_log(&config).await?;
+    let usage = Arc::new(Usage::new());
+    let mut audits: Vec<Arc<dyn UpstreamAudit>> = vec![usage.clone()];
+    if let Some((log, _)) = &audit {
+        audits.push(log.clone());
+    }
+    let spotify = spotify_backend(&config, &audits).await?;
+    #[cfg(any(feature = "sqlite", feature = "postgres"))]
+    let (spotify, store) = with_storage(&config, spotify).await?;
+    if let Some((_, writer)) = audit {
+        #[cfg(any(feature = "sqlite", feature = "postgres"))]
+        let writer = match (&store, config.audit.as_ref().is_some_and(|a| a.storage)) {
+            (Some(store), true) => writer.store(store.clone()),
+            _ => writer,
+        };
+        tokio::spawn(writer.run());
+    }
+    #[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+    let local_index = local_index(&config, store.as_ref())?;
+    #[cfg(any(feature = "kafka", feature = "nats"))]
+    let spotify = with_events(&config, spotify).await?;
+    // Outermost, so cached results are neither stored nor published again.
+    let spotify: DynSpotifyApi = match config.search_cache_ttl {
+        Some(ttl) => Arc::new(
+            CachingSpotifyApi::new(spotify, ttl, config.search_cache_stale).search_limits(config.search_limits),
+        ),
+        None => spotify,
+    };
+    #[cfg(feature = "jwt")]
+    let http = egress::http_client(&config)?;
+    #[cfg(feature = "jwt")]
+    let jwt = config.jwt.clone().map(|c| Arc::new(JwtValidator::new(c).http_client(http.clone())));
+    #[cfg(feature = "grpc")]
+    let serve_grpc = grpc_server(
+        &config,
+        spotify.clone(),
+        #[cfg(feature = "jwt")]
+        jwt.clone(),
+    )?;
+    #[cfg(feature = "nats")]
+    let serve_nats = nats_server(&config, spotify.clone()).await?;
+
+    let http_addr = SocketAddr::new(config.bind_addr, config.port);
 
-    let http_addr = SocketAddr::from(([0, 0, 0, 0], config.port));
-    let grpc_addr = SocketAddr::from(([0, 0, 0, 0], config.grpc_port));
+    let config = Arc::new(config);
+    let shared_config: SharedConfig = Arc::new(ArcSwap::new(config.clone()));
+    let reloader = Reloader::new(cli, shared_config.clone(), log_filter);
+    #[cfg(unix)]
+    tokio::spawn(reloader.clone().reload_on_sighup());
+    let saved = saved_queries(&config, spotify.clone())?;
+    let warmer = CacheWarmer::new(spotify.clone(), config.cache_warm_file.clone())
+        .pause(config.cache_warm_pause)
+        .searches(config.search_cache_ttl.is_some());
+    #[cfg(any(feature = "sqlite", feature = "postgres"))]
+    let warmer = warmer.tracks(store.is_some());
+    #[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+    let warmer = warmer.tracks(false);
+    let warmer = Arc::new(warmer);
+    if let Some(file) = &config.cache_warm_file {
+        let status = warmer
+            .start()
+            .await
+            .with_context(|| format!("reading the cache seed file {}", file.display()))?;
+        tracing::info!(searches = status.searches, tracks = status.tracks, "warming the cache from {}", file.display());
+    }
+    let suggester = Suggester::new(spotify.clone())
+        .ttl(config.suggest_cache_ttl)
+        .debounce(config.suggest_debounce);
+    let state = AppState {
+        config: shared_config,
+        reloader,
+        spotify,
+        jobs: Jobs::new()
+            .callback_hosts(config.job_callback_allowed_hosts.clone())
+            .http_client(egress::callback_client(&config)?),
+        search_limits: config.search_limits,
+        request_limits: config.request_limits,
+        suggester: Arc::new(suggester),
+        saved: Arc::new(saved),
+        warmer,
+        usage,
+        #[cfg(any(feature = "sqlite", feature = "postgres"))]
+        store,
+        #[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+        local_index,
+        #[cfg(feature = "prometheus")]
+        metrics,
+    };
+    let app = http_app(
+        &config,
+        state,
+        #[cfg(feature = "jwt")]
+        jwt,
+    );
 
-    tracing::info!("HTTP listening on {}", http_addr);
-    tracing::info!("gRPC listening on {}", grpc_addr);
+    let mut servers: Vec<(&'static str, ServerFuture)> = Vec::new();
+    if config.single_port {
+        // Validation only allows SINGLE_PORT with the gRPC server enabled.
+        #[cfg(feature = "grpc")]
+        {
+            tracing::info!("HTTP and gRPC listening on {}", http_addr);
+            let (accept, grpc_incoming) = mux::serve(listener::bind_tcp(http_addr)?, app);
+            servers.push(("HTTP", Box::pin(accept)));
+            servers.push(("gRPC", serve_grpc(GrpcIncoming::Mux(Box::pin(grpc_incoming)))));
+        }
+    } else {
+        if config.http_enabled {
+            servers.push(("HTTP", http_server(&config, http_addr, app)?));
+        }
+        #[cfg(feature = "grpc")]
+        if config.grpc_enabled {
+            let tls_note = if config.grpc_tls.is_some() { " (TLS)" } else { "" };
+            let incoming = match &config.grpc_unix_socket {
+                #[cfg(unix)]
+                Some(path) => {
+                    tracing::info!("gRPC listening on {}{}", path.display(), tls_note);
+                    GrpcIncoming::Unix(UnixListenerStream::new(listener::bind_unix(path)?))
+                }
+                _ => {
+                    let grpc_addr = SocketAddr::new(config.grpc_bind_addr, config.grpc_port);
+                    tracing::info!("gRPC listening on {}{}", grpc_addr, tls_note);
+                    GrpcIncoming::Tcp(TcpListenerStream::new(listener::bind_tcp(grpc_addr)?))
+                }
+            };
+            servers.push(("gRPC", serve_grpc(incoming)));
+        }
+    }
+    #[cfg(feature = "nats")]
+    servers.extend(serve_nats.map(|server| ("NATS", server)));
+    let result = supervise(servers).await;
 
-    let grpc_server = tonic::transport::Server::builder()
-        .add_service(grpc_router)
-        .serve(grpc_addr);
+    telemetry::shutdown();
+    result
+}
 
-    tokio::select! {
-        r = axum::serve(
-            tokio::net::TcpListener::bind(http_addr).await?,
-            app.into_make_service(),
-        ) => r?,
-        r = grpc_server => r?,
+/// The HTTP router with the tenant, auth, limit, logging and tracing layers.
+fn http_app(
+    config: &Config,
+    state: AppState,
+    #[cfg(feature = "jwt")] jwt: Option<Arc<JwtValidator>>,
+) -> Router {
+    let app = router()
+        .layer(DefaultBodyLimit::max(config.request_limits.max_body_bytes))
+        .layer(middleware::from_fn_with_state(state.clone(), tenants::scope))
+        .layer(middleware::from_fn(usage::scope));
+    // Outside the tenant scope, which reads the token's tenant claim.
+    #[cfg(feature = "jwt")]
+    let app = match jwt {
+        Some(validator) => app.layer(middleware::from_fn_with_state(validator, jwt::authenticate)),
+        None => app,
+    };
+    app.layer(CatchPanicLayer::custom(panic::handle_http_panic))
+        .layer(middleware::from_fn_with_state(state.clone(), limits::enforce))
+        .layer(middleware::from_fn_with_state(state.clone(), access_log::access_log))
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(
+                    TraceLayer::new_for_http().make_span_with(telemetry::make_http_span),
+                ),
+        )
+        .with_state(state)
+}
+
+/// `--self-test`: replay the corpus through the HTTP app, backed by the mock catalog, and
+/// print the latencies. Fails if any request did.
+async fn self_test(
+    cli: Cli,
+    config: Config,
+    log_filter: LogFilterHandle,
+    #[cfg(feature = "prometheus")] metrics: PrometheusHandle,
+) -> anyhow::Result<()> {
+    let corpus = match &cli.self_test_corpus {
+        Some(path) => std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?,
+        None => selftest::BUNDLED_CORPUS.to_string(),
+    };
+    let paths = selftest::parse_corpus(&corpus);
+    anyhow::ensure!(!paths.is_empty(), "the self-test corpus has no requests");
+    // A request log line each would swamp the report and slow the run.
+    log_filter.reload(EnvFilter::new("warn"))?;
+
+    let spotify: DynSpotifyApi = Arc::new(MockSpotifyApi::bundled());
+    let (requests, concurrency) = (cli.self_test_requests, cli.self_test_concurrency);
+    let shared_config: SharedConfig = Arc::new(ArcSwap::from_pointee(config.clone()));
+    let state = AppState {
+        config: shared_config.clone(),
+        reloader: Reloader::new(cli, shared_config, log_filter),
+        spotify: spotify.clone(),
+        jobs: Jobs::new(),
+        search_limits: config.search_limits,
+        request_limits: config.request_limits,
+        suggester: Arc::new(
+            Suggester::new(spotify.clone())
+                .ttl(config.suggest_cache_ttl)
+                .debounce(config.suggest_debounce),
+        ),
+        saved: Arc::new(SavedQueries::new(
+            spotify.clone(),
+            config.search_limits,
+            config.search_cache_stale,
+        )),
+        warmer: Arc::new(CacheWarmer::new(spotify, None)),
+        usage: Arc::default(),
+        #[cfg(any(feature = "sqlite", feature = "postgres"))]
+        store: None,
+        #[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+        local_index: None,
+        #[cfg(feature = "prometheus")]
+        metrics,
+    };
+    let app = http_app(
+        &config,
+        state,
+        #[cfg(feature = "jwt")]
+        None,
+    );
+    println!("self-test: {} corpus requests against the mock catalog", paths.len());
+    let report = selftest::run(app, &paths, requests, concurrency).await;
+    println!("{}", report);
+    for ((path, status), count) in &report.failures {
+        println!("  {} x {} {}", count, status, path);
     }
+    anyhow::ensure!(report.errors == 0, "{} of {} self-test requests failed", report.errors, report.requests);
+    Ok(())
+}
 
+/// A subcommand: run its one request against the configured Spotify backend and print
+/// the result as JSON, or the TUI, without serving anything.
+async fn one_shot(command: Command, config: Config) -> anyhow::Result<()> {
+    #[cfg(feature = "tui")]
+    if let Command::Tui = command {
+        // The terminal is the UI, and a log line would tear it, so nothing is logged.
+        let spotify = spotify_backend(&config, &[]).await?;
+        return Ok(spotify_search::tui::run(spotify).await?);
+    }
+    // stdout is for the result, so only warnings are logged, to stderr.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::new("warn"))
+        .init();
+    let spotify = spotify_backend(&config, &[]).await?;
+    let output = match command {
+        Command::Query { q, features, limit, offset } => {
+            anyhow::ensure!(!q.trim().is_empty(), "the query cannot be empty");
+            let page = if features {
+                spotify.search_tracks_with_features(&q, Some(limit), Some(offset)).await?
+            } else {
+                let page = spotify.search_tracks(&q, Some(limit), Some(offset)).await?;
+                let tracks = page.tracks.into_iter().map(|track| TrackWithFeatures {
+                    track,
+                    audio_features: None,
+                    embedding: None,
+                });
+                spotify_search::spotify::SearchTracksWithFeaturesResponse {
+                    tracks: tracks.collect(),
+                    total: page.total,
+                    limit: page.limit,
+                    offset: page.offset,
+                }
+            };
+            let tracks: Vec<_> = page.tracks.into_iter().map(|t| TrackResponse::new(t, false)).collect();
+            serde_json::json!({ "tracks": tracks, "total": page.total, "limit": page.limit, "offset": page.offset })
+        }
+        Command::Tracks { ids } => {
+            if let Some(id) = ids.iter().find(|id| !validation::is_spotify_id(id)) {
+                anyhow::bail!("{:?} is not a Spotify track ID (22 base62 characters)", id);
+            }
+            let tracks = spotify.get_tracks_with_features(&ids).await?;
+            let tracks: Vec<_> = tracks.into_iter().map(|t| TrackResponse::new(t, false)).collect();
+            serde_json::json!({ "tracks": tracks })
+        }
+        Command::Match { title, artist } => {
+            anyhow::ensure!(!title.trim().is_empty(), "the title cannot be empty");
+            let query = MatchQuery { title, artist };
+            let track = matching::match_one(&spotify, &query).await?;
+            serde_json::json!({
+                "title": query.title,
+                "artist": query.artist,
+                "track": track.as_ref().map(matching::track_summary),
+            })
+        }
+        #[cfg(feature = "tui")]
+        Command::Tui => unreachable!("handled above"),
+    };
+    println!("{}", serde_json::to_string_pretty(&output)?);
     Ok(())
 }
+
+/// The `saved_queries` from the config, registered and prewarming.
+fn saved_queries(config: &Config, spotify: DynSpotifyApi) -> anyhow::Result<SavedQueries> {
+    let saved = SavedQueries::new(spotify, config.search_limits, config.search_cache_stale);
+    for query in &config.saved_queries {
+        if let Err(errors) = saved.put(query.clone()) {
+            let errors: Vec<_> = errors.iter().map(|e| format!("{} {}", e.field, e.message)).collect();
+            anyhow::bail!("saved_queries.{}: {}", query.alias, errors.join("; "));
+        }
+    }
+    Ok(saved)
+}
+
+/// Answer `SearchTracks` and `GetTracksWithFeatures` requests over NATS when `NATS_URL` is set.
+#[cfg(feature = "nats")]
+async fn nats_server(config: &Config, spotify: DynSpotifyApi) -> anyhow::Result<Option<ServerFuture>> {
+    let Some(url) = &config.nats_url else {
+        return Ok(None);
+    };
+    let client = nats::connect(url, &config.service_name)
+        .await
+        .with_context(|| format!("connecting to NATS_URL {}", redact_password(url)))?;
+    tracing::info!(
+        "NATS answering {} and {} on {}",
+        nats::SEARCH_SUBJECT,
+        nats::TRACKS_WITH_FEATURES_SUBJECT,
+        redact_password(url)
+    );
+    Ok(Some(Box::pin(nats::serve(client, SpotifySearchService::new(spotify).search_limits(config.search_limits)))))
+}
+
+/// Where the gRPC server accepts connections.
+#[cfg(feature = "grpc")]
+enum GrpcIncoming {
+    Tcp(TcpListenerStream),
+    #[cfg(unix)]
+    Unix(UnixListenerStream),
+    /// HTTP/2 connections handed over by the single-port listener.
+    Mux(BoxStream<'static, std::io::Result<TcpStream>>),
+}
+
+/// The gRPC server with health, reflection, gRPC-Web and the common layers; the
+/// returned function starts it on an incoming connection stream.
+#[cfg(feature = "grpc")]
+fn grpc_server(
+    config: &Config,
+    spotify: DynSpotifyApi,
+    #[cfg(feature = "jwt")] jwt: Option<Arc<JwtValidator>>,
+) -> anyhow::Result<impl FnOnce(GrpcIncoming) -> ServerFuture> {
+    let grpc_router = SpotifySearchService::new(spotify.clone())
+        .search_limits(config.search_limits)
+        .request_limits(config.request_limits)
+        .into_router();
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    tokio::spawn(grpc::report_health(health_reporter, spotify));
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(grpc::spotify_proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()?;
+    let grpc_tls = config.grpc_tls.as_ref().map(grpc::tls_config).transpose()?;
+    let grpc_web_cors = grpc::grpc_web_cors(&config.grpc_web_allowed_origins)?;
+
+    // HTTP/1.1 is accepted for gRPC-Web browser clients; native gRPC still uses HTTP/2.
+    let mut grpc_builder = tonic::transport::Server::builder().accept_http1(true);
+    if let Some(tls) = grpc_tls {
+        grpc_builder = grpc_builder.tls_config(tls)?;
+    }
+    let layers = ServiceBuilder::new()
+        .layer(grpc_web_cors)
+        .layer(tonic_web::GrpcWebLayer::new())
+        .layer(GrpcRequestIdLayer);
+    #[cfg(feature = "prometheus")]
+    let layers = layers.layer(GrpcMetricsLayer);
+    let layers = layers.layer(GrpcUsageLayer);
+    // Outside the JWT layer, so a token's tenant claim overrides the certificate's.
+    let layers = layers.layer(GrpcClientCertLayer(config.tenants.clone().into()));
+    #[cfg(feature = "jwt")]
+    let layers = layers.layer(jwt::GrpcJwtLayer(jwt));
+    let server = grpc_builder
+        .trace_fn(telemetry::make_grpc_span)
+        .layer(layers.layer(GrpcCatchPanicLayer))
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(grpc_router);
+
+    Ok(move |incoming: GrpcIncoming| -> ServerFuture {
+        match incoming {
+            GrpcIncoming::Tcp(incoming) => Box::pin(async move { Ok(server.serve_with_incoming(incoming).await?) }),
+            #[cfg(unix)]
+            GrpcIncoming::Unix(incoming) => Box::pin(async move { Ok(server.serve_with_incoming(incoming).await?) }),
+            GrpcIncoming::Mux(incoming) => Box::pin(async move { Ok(server.serve_with_incoming(incoming).await?) }),
+        }
+    })
+}
+
+/// The HTTP server on its TCP address or Unix socket, over TLS if configured.
+fn http_server(config: &Config, addr: SocketAddr, app: Router) -> anyhow::Result<ServerFuture> {
+    let tls = config.http_tls.as_ref();
+    let scheme = if tls.is_some() { "HTTPS" } else { "HTTP" };
+    #[cfg(unix)]
+    if let Some(path) = &config.http_unix_socket {
+        let listener = listener::bind_unix(path)?;
+        tracing::info!("{} listening on {}", scheme, path.display());
+        return Ok(match tls {
+            Some(tls) => Box::pin(https::serve(listener, app, tls, config.http_tls_reload_interval)?),
+            None => Box::pin(listener::serve_http(listener, app)),
+        });
+    }
+    let listener = listener::bind_tcp(addr)?;
+    tracing::info!("{} listening on {}", scheme, addr);
+    Ok(match tls {
+        Some(tls) => Box::pin(https::serve(listener, app, tls, config.http_tls_reload_interval)?),
+        None => Box::pin(async move { Ok(axum::serve(listener, app.into_make_service()).await?) }),
+    })
+}
+
+/// Run the servers until the first one stops, and return its result.
+async fn supervise(servers: Vec<(&'static str, ServerFuture)>) -> anyhow::Result<()> {
+    let (names, servers): (Vec<_>, Vec<_>) = servers.into_iter().unzip();
+    let (result, index, _) = futures::future::select_all(servers).await;
+    match &result {
+        Ok(()) => tracing::warn!("{} server stopped", names[index]),
+        Err(e) => tracing::error!("{} server failed: {:#}", names[index], e),
+    }
+    result.with_context(|| format!("{} server", names[index]))
+}
+
+/// Wrap `spotify` so fetched tracks and features are persisted to `DATABASE_URL`, and
+/// return the store too.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+async fn with_storage(
+    config: &Config,
+    spotify: DynSpotifyApi,
+) -> anyhow::Result<(DynSpotifyApi, Option<DynStorage>)> {
+    let Some(url) = &config.database_url else {
+        return Ok((spotify, None));
+    };
+    let store = storage::connect(url, config.database_max_connections)
+        .await
+        .with_context(|| format!("opening DATABASE_URL {}", redact_password(url)))?;
+    tracing::info!("persisting fetched tracks to {} ({})", redact_password(url), store.backend());
+    if let Some(max_age) = config.refresh_max_age {
+        tracing::info!("refreshing stored tracks older than {:?} every {:?}", max_age, config.refresh_interval);
+        let refresher = Refresher::new(spotify.clone(), store.clone(), max_age)
+            .interval(config.refresh_interval)
+            .pause(config.refresh_pause);
+        tokio::spawn(refresher.run());
+    }
+    #[cfg(feature = "s3")]
+    if let Some(snapshots) = &config.snapshots {
+        tracing::info!(
+            "snapshotting the store to s3://{}/{} daily at {:02}:00 UTC",
+            snapshots.bucket,
+            snapshots.prefix,
+            snapshots.hour_utc
+        );
+        let snapshotter = Snapshotter::new(store.clone(), snapshot_bucket(config, snapshots)?)
+            .prefix(&snapshots.prefix)
+            .hour_utc(snapshots.hour_utc);
+        tokio::spawn(snapshotter.run());
+    }
+    let stored = StoredSpotifyApi::new(spotify, store.clone()).offline(config.offline_mode);
+    #[cfg(any(feature = "kafka", feature = "nats"))]
+    let stored = match event_sink(config).await? {
+        Some(sink) => {
+            tracing::info!("delivering track events through the {} outbox", store.backend());
+            tokio::spawn(OutboxRelay::new(store.clone(), sink).run());
+            stored.outbox(Arc::new(TrackEvents))
+        }
+        None => stored,
+    };
+    Ok((Arc::new(stored), Some(store)))
+}
+
+/// With `LOCAL_SEARCH`, an index of `store` kept up to date in the background.
+#[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+fn local_index(config: &Config, store: Option<&DynStorage>) -> anyhow::Result<Option<Arc<LocalIndex>>> {
+    let (true, Some(store)) = (config.local_search, store) else {
+        return Ok(None);
+    };
+    let index = Arc::new(LocalIndex::new().context("creating the local search index")?);
+    tracing::info!("indexing stored tracks for local search, syncing every {:?}", config.local_search_sync_interval);
+    let sync = IndexSync::new(store.clone(), index.clone()).interval(config.local_search_sync_interval);
+    tokio::spawn(sync.run());
+    Ok(Some(index))
+}
+
+/// The `SNAPSHOT_S3_*` bucket, reached through `EGRESS_PROXY`. Settings left unset fall
+/// back to the standard `AWS_*` environment variables.
+#[cfg(all(feature = "s3", any(feature = "sqlite", feature = "postgres")))]
+fn snapshot_bucket(config: &Config, snapshots: &SnapshotConfig) -> anyhow::Result<Arc<dyn ObjectStore>> {
+    let mut builder = AmazonS3Builder::from_env().with_bucket_name(&snapshots.bucket);
+    if let Some(endpoint) = &snapshots.endpoint {
+        builder = builder.with_endpoint(endpoint).with_allow_http(endpoint.starts_with("http://"));
+    }
+    if let Some(region) = &snapshots.region {
+        builder = builder.with_region(region);
+    }
+    if let (Some(id), Some(secret)) = (&snapshots.access_key_id, &snapshots.secret_access_key) {
+        builder = builder.with_access_key_id(id).with_secret_access_key(secret.expose());
+    }
+    if let Some(proxy) = &config.egress_proxy {
+        let options = ClientOptions::new()
+            .with_proxy_url(proxy)
+            .with_proxy_excludes(config.egress_no_proxy.join(","));
+        builder = builder.with_client_options(options);
+    }
+    let bucket = builder.build().with_context(|| format!("configuring S3 bucket {}", snapshots.bucket))?;
+    Ok(Arc::new(bucket))
+}
+
+/// Wrap `spotify` so resolved tracks are published to the event sinks. With
+/// `DATABASE_URL` they go through the store's outbox instead (see `with_storage`).
+#[cfg(any(feature = "kafka", feature = "nats"))]
+async fn with_events(config: &Config, spotify: DynSpotifyApi) -> anyhow::Result<DynSpotifyApi> {
+    if config.database_url.is_some() {
+        return Ok(spotify);
+    }
+    Ok(match event_sink(config).await? {
+        Some(sink) => Arc::new(PublishingSpotifyApi::new(spotify, sink)),
+        None => spotify,
+    })
+}
+
+/// Sink for track events: `KAFKA_BROKERS` and/or `NATS_EVENTS_SUBJECT`, if set.
+#[cfg(any(feature = "kafka", feature = "nats"))]
+async fn event_sink(config: &Config) -> anyhow::Result<Option<DynEventSink>> {
+    let mut sinks: Vec<DynEventSink> = Vec::new();
+    #[cfg(feature = "kafka")]
+    if !config.kafka_brokers.is_empty() {
+        let sink = KafkaSink::new(&config.kafka_brokers, &config.kafka_topic, &config.service_name)
+            .context("creating Kafka producer")?;
+        tracing::info!(
+            "publishing track events to Kafka topic {} on {}",
+            config.kafka_topic,
+            config.kafka_brokers.join(",")
+        );
+        sinks.push(Arc::new(sink));
+    }
+    #[cfg(feature = "nats")]
+    if let (Some(url), Some(subject)) = (&config.nats_url, &config.nats_events_subject) {
+        let client = nats::connect(url, &config.service_name)
+            .await
+            .with_context(|| format!("connecting to NATS_URL {}", redact_password(url)))?;
+        tracing::info!("publishing track events to NATS subject {} on {}", subject, redact_password(url));
+        sinks.push(Arc::new(NatsSink::new(client, subject)));
+    }
+    Ok(match sinks.len() {
+        0 => None,
+        1 => sinks.pop(),
+        _ => Some(Arc::new(FanoutSink(sinks))),
+    })
+}
+
+/// With `AUDIT_FILE` or `AUDIT_STORAGE`, the log the Spotify clients report their requests
+/// to, and its writer with the file open.
+async fn audit_log(config: &Config) -> anyhow::Result<Option<(Arc<AuditLog>, AuditWriter)>> {
+    let Some(audit) = &config.audit else {
+        return Ok(None);
+    };
+    let hash_key = audit.hash_key.clone().unwrap_or_else(|| {
+        tracing::warn!("AUDIT_HASH_KEY is not set; audit params_hash values only match within this process");
+        Secret::new(uuid::Uuid::new_v4().simple().to_string())
+    });
+    let (log, mut writer) = AuditLog::new(hash_key);
+    if let Some(path) = &audit.file {
+        writer = writer
+            .file(path, audit.max_file_bytes, audit.max_files)
+            .await
+            .with_context(|| format!("opening AUDIT_FILE {}", path.display()))?;
+        tracing::info!("auditing Spotify requests to {}", path.display());
+    }
+    if audit.storage {
+        tracing::info!("auditing Spotify requests to the local store");
+    }
+    Ok(Some((log, writer)))
+}
+
+/// The bundled mock catalog with `SPOTIFY_MOCK`, otherwise the `SPOTIFY_CLIENT_ID` client
+/// and, with tenants configured, each tenant's own client behind a router. Every client
+/// reports its requests to `audits`.
+async fn spotify_backend(config: &Config, audits: &[Arc<dyn UpstreamAudit>]) -> anyhow::Result<DynSpotifyApi> {
+    if config.spotify_mock {
+        tracing::warn!("SPOTIFY_MOCK is set: serving bundled fixture data, Spotify is never called");
+        return Ok(Arc::new(MockSpotifyApi::bundled()));
+    }
+
+    let credentials = "SPOTIFY_CLIENT_ID / SPOTIFY_CLIENT_SECRET";
+    let (id, secret) = (&config.spotify_client_id, &config.spotify_client_secret);
+    let default = spotify_client(config, DEFAULT_TENANT, id, secret, credentials, audits).await?;
+    if config.tenants.is_empty() {
+        return Ok(default);
+    }
+    let mut tenants = HashMap::new();
+    for tenant in &config.tenants {
+        let credentials = format!("tenants.{}", tenant.name);
+        let (id, secret) = (&tenant.client_id, &tenant.client_secret);
+        let client = spotify_client(config, &tenant.name, id, secret, &credentials, audits).await?;
+        tenants.insert(tenant.name.clone(), client);
+    }
+    let names: Vec<&str> = config.tenants.iter().map(|t| t.name.as_str()).collect();
+    tracing::info!("tenants {} use their own Spotify credentials", names.join(", "));
+    Ok(Arc::new(TenantSpotifyApi::new(default, tenants)))
+}
+
+/// A Spotify client for `tenant`'s credentials that has passed the startup credential
+/// check and keeps fetching its first token in the background. `credentials` names the
+/// settings they come from, for errors.
+async fn spotify_client(
+    config: &Config,
+    tenant: &str,
+    client_id: &str,
+    client_secret: &Secret,
+    credentials: &str,
+    audits: &[Arc<dyn UpstreamAudit>],
+) -> anyhow::Result<DynSpotifyApi> {
+    let mut builder = SpotifyClient::builder(client_id, client_secret.expose())
+        .tenant(tenant)
+        .api_base(&config.spotify_api_base)
+        .token_url(&config.spotify_token_url);
+    for audit in audits {
+        builder = builder.audit(audit.clone());
+    }
+    if let Some(timeout) = config.spotify_timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(timeout) = config.spotify_connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(user_agent) = &config.spotify_user_agent {
+        builder = builder.user_agent(user_agent);
+    }
+    builder = builder
+        .pool_max_idle_per_host(config.spotify_pool_max_idle_per_host)
+        .pool_idle_timeout(config.spotify_pool_idle_timeout)
+        .search_chunk_size(config.spotify_search_chunk_size)
+        .search_concurrency(config.spotify_search_concurrency)
+        .transliterate_queries(config.spotify_transliterate_queries)
+        .lenient_schema(config.spotify_lenient_schema)
+        .schema_samples(config.spotify_schema_samples)
+        .search_limits(config.search_limits);
+    if let Some(interval) = config.spotify_tcp_keepalive {
+        builder = builder.tcp_keepalive(interval);
+    }
+    if !config.spotify_http2 {
+        builder = builder.http1_only();
+    }
+    if let Some(proxy) = egress::spotify_proxy(config)? {
+        builder = builder.proxy(proxy);
+    }
+    if let Some(market) = &config.spotify_market {
+        builder = builder.market(market);
+    }
+    let spotify = builder.build()?;
+    if config.offline_mode {
+        tracing::warn!("OFFLINE_MODE is set: serving from the local store, Spotify is never called");
+        return Ok(Arc::new(spotify));
+    }
+
+    if config.startup_check != StartupCheck::Off {
+        let result = match tokio::time::timeout(STARTUP_CHECK_TIMEOUT, spotify.validate_credentials()).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e @ SpotifyError::Auth(_))) => {
+                Err(format!("Spotify rejected the credentials (check {}): {}", credentials, e))
+            }
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("timed out after {:?}", STARTUP_CHECK_TIMEOUT)),
+        };
+        match result {
+            Ok(()) => tracing::info!(tenant, "Spotify credentials validated"),
+            Err(e) if config.startup_check == StartupCheck::Fail => {
+                anyhow::bail!("startup credential check failed for tenant {}: {}", tenant, e);
+            }
+            Err(e) => {
+                tracing::error!(tenant, "startup credential check failed (continuing, STARTUP_CHECK=warn): {}", e)
+            }
+        }
+    }
+
+    // Readiness flips once the first token fetch succeeds; retry with backoff until then.
+    tokio::spawn({
+        let (spotify, tenant) = (spotify.clone(), tenant.to_string());
+        async move {
+            let mut delay = Duration::from_secs(1);
+            while let Err(e) = spotify.warm_up().await {
+                tracing::warn!(tenant, "initial Spotify token fetch failed, retrying in {:?}: {}", delay, e);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(30));
+            }
+            tracing::info!(tenant, "Spotify token acquired");
+        }
+    });
+
+    Ok(Arc::new(spotify))
+}
@@ -0,0 +1,78 @@
+//! Batch matching of `{title, artist}` pairs to Spotify tracks, run as a
+//! [`jobs`](crate::jobs) job.
+//!
+//! Each pair becomes a field-filtered search (`track:"..." artist:"..."`) and the top
+//! hit, if any, is the match. Results keep the submitted order and carry the
+//! submitted `index`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::jobs::{retry_rate_limited, JobHandle};
+use crate::spotify::{normalize_query, DynSpotifyApi, SpotifyError, Track};
+
+/// Job kind for batch matches.
+pub const KIND: &str = "batch_match";
+/// Max pairs per batch.
+pub const MAX_QUERIES: usize = 5000;
+
+/// A track to find on Spotify.
+#[derive(Debug, Clone, Hash, Deserialize, Serialize)]
+pub struct MatchQuery {
+    pub title: String,
+    #[serde(default)]
+    pub artist: Option<String>,
+}
+
+impl MatchQuery {
+    /// Spotify search string with field filters. Quotes in the input would end the
+    /// filter early, so they are dropped, typographic ones included.
+    fn search(&self) -> String {
+        let quoted = |s: &str| normalize_query(s, false).replace('"', "");
+        let mut q = format!("track:\"{}\"", quoted(&self.title));
+        if let Some(artist) = self.artist.as_deref().filter(|a| !a.trim().is_empty()) {
+            q.push_str(&format!(" artist:\"{}\"", quoted(artist)));
+        }
+        q
+    }
+}
+
+/// The best match for `query`, if Spotify has one.
+pub async fn match_one(spotify: &DynSpotifyApi, query: &MatchQuery) -> Result<Option<Track>, SpotifyError> {
+    let search = query.search();
+    let found = retry_rate_limited(|| spotify.search_tracks(&search, Some(1), None)).await?;
+    Ok(found.tracks.into_iter().next())
+}
+
+/// Match each query in turn. A failed search is recorded on its result item and
+/// counted as a job error; the rest of the batch continues.
+pub async fn match_tracks(spotify: DynSpotifyApi, queries: Vec<MatchQuery>, job: JobHandle) -> anyhow::Result<()> {
+    job.set_total(queries.len() as u32);
+    for (index, query) in queries.into_iter().enumerate() {
+        let mut item = json!({ "index": index, "title": query.title, "artist": query.artist });
+        match match_one(&spotify, &query).await {
+            Ok(track) => {
+                job.count(if track.is_some() { "matched" } else { "unmatched" }, 1);
+                item["track"] = track.as_ref().map(track_summary).unwrap_or_default();
+            }
+            Err(e) => {
+                job.error(format!("query {}: {}", index, e));
+                item["error"] = json!(e.to_string());
+            }
+        }
+        job.push_results([item]);
+        job.advance(1);
+    }
+    Ok(())
+}
+
+/// The fields of a matched track a result item carries.
+pub fn track_summary(t: &Track) -> serde_json::Value {
+    json!({
+        "id": t.id,
+        "name": t.name,
+        "uri": t.uri,
+        "artists": t.artists.iter().map(|a| a.name.as_str()).collect::<Vec<_>>(),
+        "album": t.album.name,
+    })
+}
@@ -0,0 +1,119 @@
+//! Prometheus metrics: recorder setup, HTTP middleware and gRPC layer.
+
+#[cfg(feature = "grpc")]
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+#[cfg(feature = "grpc")]
+use tonic::codegen::{http, BoxFuture};
+#[cfg(feature = "grpc")]
+use tower::{Layer, Service};
+
+use crate::state::AppState;
+
+/// Latency buckets (seconds) shared by all `*_duration_seconds` histograms.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Install the global Prometheus recorder and return a handle for rendering.
+pub fn install_recorder() -> anyhow::Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("duration_seconds".into()), LATENCY_BUCKETS)?
+        .install_recorder()?;
+    Ok(handle)
+}
+
+/// GET /metrics - Prometheus text exposition.
+pub async fn render(State(state): State<AppState>) -> impl IntoResponse {
+    state.metrics.render()
+}
+
+/// Axum middleware recording request count and latency per route and status.
+pub async fn track_http(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".into());
+
+    let response = next.run(req).await;
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels).record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Tower layer recording gRPC request count and latency per method and status code.
+#[cfg(feature = "grpc")]
+#[derive(Clone, Default)]
+pub struct GrpcMetricsLayer;
+
+#[cfg(feature = "grpc")]
+impl<S> Layer<S> for GrpcMetricsLayer {
+    type Service = GrpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcMetrics { inner }
+    }
+}
+
+#[cfg(feature = "grpc")]
+#[derive(Clone)]
+pub struct GrpcMetrics<S> {
+    inner: S,
+}
+
+#[cfg(feature = "grpc")]
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for GrpcMetrics<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        let start = Instant::now();
+        let method = req.uri().path().to_owned();
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+            // Errors are sent as trailers-only responses, so grpc-status is in the headers;
+            // successful responses carry it in trailers (0 = OK).
+            let status = match &result {
+                Ok(res) => res
+                    .headers()
+                    .get("grpc-status")
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("0")
+                    .to_owned(),
+                Err(_) => "transport_error".to_owned(),
+            };
+            let labels = [("method", method), ("status", status)];
+            metrics::counter!("grpc_requests_total", &labels).increment(1);
+            metrics::histogram!("grpc_request_duration_seconds", &labels).record(start.elapsed().as_secs_f64());
+            result
+        })
+    }
+}
@@ -0,0 +1,148 @@
+//! Client certificate (mTLS) authentication.
+//!
+//! With `HTTP_TLS_CLIENT_CA` or `GRPC_TLS_CLIENT_CA` set, that server only completes
+//! handshakes with clients presenting a certificate the CA bundle signed. The verified
+//! certificate's identities, its SAN DNS names, URIs and emails and its subject CN, form
+//! the connection's [`ClientCert`], in the request extensions of every request on it. A
+//! tenant whose `client_certs` lists one of them is the one the request runs as (see
+//! [`tenants`](crate::tenants)), for callers that can't send bearer tokens or API keys.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::server::{danger::ClientCertVerifier, WebPkiClientVerifier};
+use tokio_rustls::rustls::RootCertStore;
+use x509_parser::extensions::GeneralName;
+
+/// Identities of a verified client certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCert {
+    /// SAN DNS names, URIs (e.g. SPIFFE IDs) and emails, then the subject CN.
+    pub names: Vec<String>,
+}
+
+impl ClientCert {
+    /// Identities of the DER certificate `der`; `None` if it can't be parsed.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        let mut names = Vec::new();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(s) | GeneralName::URI(s) | GeneralName::RFC822Name(s) => {
+                        names.push(s.to_string())
+                    }
+                    _ => {}
+                }
+            }
+        }
+        names.extend(cert.subject().iter_common_name().filter_map(|cn| cn.as_str().ok()).map(str::to_string));
+        Some(Self { names })
+    }
+
+    /// The identity to log: the first one.
+    pub fn name(&self) -> Option<&str> {
+        self.names.first().map(String::as_str)
+    }
+}
+
+/// Verifier requiring client certificates signed by the CAs in the PEM bundle `ca_path`.
+pub fn client_verifier(ca_path: &Path) -> anyhow::Result<Arc<dyn ClientCertVerifier>> {
+    let file = File::open(ca_path).map_err(|e| anyhow::anyhow!("reading {}: {}", ca_path.display(), e))?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(file)) {
+        let cert = cert.map_err(|e| anyhow::anyhow!("parsing {}: {}", ca_path.display(), e))?;
+        roots
+            .add(cert)
+            .map_err(|e| anyhow::anyhow!("invalid CA certificate in {}: {}", ca_path.display(), e))?;
+    }
+    anyhow::ensure!(!roots.is_empty(), "no certificates in {}", ca_path.display());
+    WebPkiClientVerifier::builder(Arc::new(roots))
+        .build()
+        .map_err(|e| anyhow::anyhow!("client CA bundle {}: {}", ca_path.display(), e))
+}
+
+#[cfg(feature = "grpc")]
+pub use grpc::GrpcClientCertLayer;
+
+#[cfg(feature = "grpc")]
+mod grpc {
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+
+    use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+    use tonic::{
+        body::BoxBody,
+        codegen::{http, BoxFuture},
+    };
+    use tower::{Layer, Service};
+
+    use super::ClientCert;
+    use crate::config::TenantConfig;
+    use crate::tenants;
+
+    /// Stores the gRPC client's [`ClientCert`] in the request extensions, and runs each
+    /// call as the tenant it maps to.
+    #[derive(Clone)]
+    pub struct GrpcClientCertLayer(pub Arc<[TenantConfig]>);
+
+    impl<S> Layer<S> for GrpcClientCertLayer {
+        type Service = GrpcClientCert<S>;
+
+        fn layer(&self, inner: S) -> Self::Service {
+            GrpcClientCert {
+                tenants: self.0.clone(),
+                inner,
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct GrpcClientCert<S> {
+        tenants: Arc<[TenantConfig]>,
+        inner: S,
+    }
+
+    impl<S, ReqBody> Service<http::Request<ReqBody>> for GrpcClientCert<S>
+    where
+        S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        ReqBody: Send + 'static,
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
+            let Some(cert) = peer_cert(req.extensions()) else {
+                return Box::pin(self.inner.call(req));
+            };
+            let tenant = tenants::for_client_cert(&self.tenants, &cert).map(|t| t.name.as_str().into());
+            req.extensions_mut().insert(cert);
+            let clone = self.inner.clone();
+            let mut inner = std::mem::replace(&mut self.inner, clone);
+            Box::pin(tenants::run_as(tenant, async move { inner.call(req).await }))
+        }
+    }
+
+    /// The leaf certificate tonic recorded for the connection, over TCP or a Unix socket.
+    fn peer_cert(extensions: &http::Extensions) -> Option<ClientCert> {
+        let certs = match extensions.get::<TlsConnectInfo<TcpConnectInfo>>() {
+            Some(info) => info.peer_certs(),
+            #[cfg(unix)]
+            None => extensions
+                .get::<TlsConnectInfo<tonic::transport::server::UdsConnectInfo>>()
+                .and_then(|info| info.peer_certs()),
+            #[cfg(not(unix))]
+            None => None,
+        }?;
+        // tonic keeps the DER bytes in its `Certificate`.
+        ClientCert::from_der(certs.first()?.get_ref())
+    }
+}
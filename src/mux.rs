@@ -0,0 +1,73 @@
+//! Single-port mode: HTTP and gRPC share one listener.
+//!
+//! tonic 0.11 and axum 0.7 sit on different hyper/http majors, so requests can't be
+//! routed inside one service. Instead each connection is sniffed: HTTP/2 with prior
+//! knowledge (how gRPC clients connect) goes to tonic, everything else to axum.
+
+use std::future::Future;
+use std::time::Duration;
+
+use axum::Router;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::listener::{accept, serve_connection};
+
+/// Start of the HTTP/2 connection preface; no HTTP/1 method is `PRI`.
+const H2_PREFACE_START: &[u8] = b"PRI ";
+/// Connections that send nothing within this window are dropped.
+const SNIFF_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Split `listener`: the returned future accepts connections and serves HTTP/1 (and
+/// h2 via upgrade) with `app`; HTTP/2 prior-knowledge connections are yielded by the
+/// returned stream for tonic's `serve_with_incoming`.
+pub fn serve(
+    listener: TcpListener,
+    app: Router,
+) -> (
+    impl Future<Output = anyhow::Result<()>>,
+    impl Stream<Item = std::io::Result<TcpStream>>,
+) {
+    let (grpc_tx, grpc_rx) = mpsc::channel::<TcpStream>(64);
+
+    let accept = async move {
+        loop {
+            let (stream, peer) = accept(&listener).await;
+            let app = app.clone();
+            let grpc_tx = grpc_tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(SNIFF_TIMEOUT, is_h2_prior_knowledge(&stream)).await {
+                    Ok(Ok(true)) => {
+                        let _ = grpc_tx.send(stream).await;
+                    }
+                    Ok(Ok(false)) => serve_connection(stream, peer, app).await,
+                    Ok(Err(e)) => tracing::debug!(%peer, "connection closed while sniffing: {}", e),
+                    Err(_) => tracing::debug!(%peer, "no data within {:?}; closing", SNIFF_TIMEOUT),
+                }
+            });
+        }
+    };
+
+    (accept, ReceiverStream::new(grpc_rx).map(Ok))
+}
+
+/// Peek (without consuming) until the first bytes decide the protocol.
+async fn is_h2_prior_knowledge(stream: &TcpStream) -> std::io::Result<bool> {
+    let mut buf = [0u8; H2_PREFACE_START.len()];
+    loop {
+        let n = stream.peek(&mut buf).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        if buf[..n] != H2_PREFACE_START[..n] {
+            return Ok(false);
+        }
+        if n == buf.len() {
+            return Ok(true);
+        }
+        // Partial preface: peek returns immediately with buffered bytes, so back off.
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
@@ -0,0 +1,104 @@
+//! NATS request/reply interface for services that talk NATS rather than HTTP or gRPC.
+//!
+//! [`SEARCH_SUBJECT`] and [`TRACKS_WITH_FEATURES_SUBJECT`] take the same protobuf
+//! request and reply messages as the `SearchTracks` and `GetTracksWithFeatures` RPCs,
+//! and are answered by the same [`SpotifySearchService`], so validation and errors
+//! match gRPC. A failed request gets an empty reply with the gRPC status code in
+//! `Nats-Service-Error-Code` and the message in `Nats-Service-Error`.
+//!
+//! Subscriptions join the [`QUEUE_GROUP`] queue group, so each request is answered by
+//! one replica.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_nats::{Client, HeaderMap, Message};
+use futures::StreamExt;
+use tonic::{Code, Request, Response, Status};
+use tracing::Instrument;
+
+use crate::grpc::spotify_proto::spotify_search_server::SpotifySearch;
+use crate::grpc::SpotifySearchService;
+use crate::usage;
+
+/// `SearchTracksRequest` → `SearchTracksResponse`.
+pub const SEARCH_SUBJECT: &str = "spotify.search";
+/// `GetTracksWithFeaturesRequest` → `GetTracksWithFeaturesResponse`.
+pub const TRACKS_WITH_FEATURES_SUBJECT: &str = "spotify.tracks_with_features";
+/// Queue group shared by all replicas.
+pub const QUEUE_GROUP: &str = "spotify-search";
+
+/// Connect to the NATS server at `url`, identifying as `name`. Returns once the first
+/// connection is up; later disconnects are retried in the background.
+pub async fn connect(url: &str, name: &str) -> Result<Client, async_nats::ConnectError> {
+    async_nats::ConnectOptions::new().name(name).connect(url).await
+}
+
+/// Answer requests on both subjects until the connection closes for good.
+pub async fn serve(client: Client, service: SpotifySearchService) -> anyhow::Result<()> {
+    let service = Arc::new(service);
+    let search = client.queue_subscribe(SEARCH_SUBJECT, QUEUE_GROUP.into()).await?;
+    let tracks = client.queue_subscribe(TRACKS_WITH_FEATURES_SUBJECT, QUEUE_GROUP.into()).await?;
+    let mut messages = futures::stream::select(search, tracks);
+    while let Some(message) = messages.next().await {
+        let span = tracing::info_span!("nats_request", subject = %message.subject);
+        let route = Some(message.subject.as_str().into());
+        tokio::spawn(usage::run_for(route, handle(client.clone(), service.clone(), message)).instrument(span));
+    }
+    anyhow::bail!("NATS subscriptions closed")
+}
+
+async fn handle(client: Client, service: Arc<SpotifySearchService>, message: Message) {
+    // Plain publishes have nobody to answer.
+    let Some(reply) = message.reply.clone() else {
+        return;
+    };
+    let start = Instant::now();
+    let (subject, result) = if message.subject.as_str() == SEARCH_SUBJECT {
+        (SEARCH_SUBJECT, call(&message.payload, |req| service.search_tracks(req)).await)
+    } else {
+        (
+            TRACKS_WITH_FEATURES_SUBJECT,
+            call(&message.payload, |req| service.get_tracks_with_features(req)).await,
+        )
+    };
+
+    let (code, sent) = match result {
+        Ok(payload) => (Code::Ok, client.publish(reply, payload.into()).await),
+        Err(status) => {
+            tracing::debug!("request failed: {}", status.message());
+            let mut headers = HeaderMap::new();
+            headers.insert("Nats-Service-Error-Code", (status.code() as i32).to_string().as_str());
+            headers.insert("Nats-Service-Error", status.message());
+            (status.code(), client.publish_with_headers(reply, headers, Vec::new().into()).await)
+        }
+    };
+    if let Err(e) = sent {
+        tracing::warn!("failed to send NATS reply: {}", e);
+    }
+    record_request(subject, code, start);
+}
+
+/// Decode a request payload, run the RPC and encode its response.
+async fn call<Req, Resp, F, Fut>(payload: &[u8], rpc: F) -> Result<Vec<u8>, Status>
+where
+    Req: prost::Message + Default,
+    Resp: prost::Message,
+    F: FnOnce(Request<Req>) -> Fut,
+    Fut: Future<Output = Result<Response<Resp>, Status>>,
+{
+    let request =
+        Req::decode(payload).map_err(|e| Status::invalid_argument(format!("invalid request payload: {}", e)))?;
+    Ok(rpc(Request::new(request)).await?.into_inner().encode_to_vec())
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_request(subject: &'static str, code: Code, start: Instant) {
+    #[cfg(feature = "metrics")]
+    {
+        let labels = [("subject", subject.to_string()), ("status", (code as i32).to_string())];
+        metrics::counter!("nats_requests_total", &labels).increment(1);
+        metrics::histogram!("nats_request_duration_seconds", &labels).record(start.elapsed().as_secs_f64());
+    }
+}
@@ -0,0 +1,119 @@
+//! Response content negotiation on the `Accept` header.
+//!
+//! The track endpoints answer in JSON (the default), MessagePack or, with the `grpc`
+//! feature, protobuf. MessagePack bodies have the same fields as the JSON ones.
+//! Protobuf bodies are the gRPC response messages from `proto/spotify.proto`, so
+//! clients decode them with the same generated types. Errors are always JSON.
+
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::error::AppError;
+
+const MSGPACK: &str = "application/msgpack";
+#[cfg(feature = "grpc")]
+const PROTOBUF: &str = "application/x-protobuf";
+
+/// A response encoding the client accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    #[cfg(feature = "grpc")]
+    Protobuf,
+}
+
+impl Format {
+    /// The format for `Accept`: the supported type with the highest quality, earliest
+    /// listed on a tie. JSON if the header is missing or allows anything.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, AppError> {
+        let Some(accept) = headers.get(header::ACCEPT) else {
+            return Ok(Format::Json);
+        };
+        let accept = accept.to_str().unwrap_or_default();
+        if accept.trim().is_empty() {
+            return Ok(Format::Json);
+        }
+        let mut best: Option<(Format, f32)> = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let Some(format) = Format::for_media_type(&media_type) else {
+                continue;
+            };
+            if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+                best = Some((format, quality));
+            }
+        }
+        best.map(|(format, _)| format).ok_or_else(|| {
+            AppError::NotAcceptable(format!(
+                "no supported media type in Accept: '{}' (supported: {})",
+                accept,
+                Format::SUPPORTED
+            ))
+        })
+    }
+
+    #[cfg(feature = "grpc")]
+    const SUPPORTED: &'static str = "application/json, application/msgpack, application/x-protobuf";
+    #[cfg(not(feature = "grpc"))]
+    const SUPPORTED: &'static str = "application/json, application/msgpack";
+
+    fn for_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Format::MessagePack),
+            #[cfg(feature = "grpc")]
+            "application/x-protobuf" | "application/protobuf" | "application/vnd.google.protobuf" => {
+                Some(Format::Protobuf)
+            }
+            _ => None,
+        }
+    }
+
+    /// `body` as JSON or MessagePack. For protobuf, which the endpoint has no message
+    /// for, a 406.
+    pub fn respond<T: Serialize>(self, body: &T) -> Response {
+        match self {
+            Format::Json => Json(body).into_response(),
+            Format::MessagePack => match rmp_serde::to_vec_named(body) {
+                Ok(bytes) => ([(header::CONTENT_TYPE, MSGPACK)], bytes).into_response(),
+                Err(e) => AppError::Internal(format!("encoding MessagePack: {}", e)).into_response(),
+            },
+            #[cfg(feature = "grpc")]
+            Format::Protobuf => AppError::NotAcceptable(format!(
+                "this endpoint has no protobuf representation (supported: application/json, {})",
+                MSGPACK
+            ))
+            .into_response(),
+        }
+    }
+}
+
+/// `message` as a protobuf body.
+#[cfg(feature = "grpc")]
+pub fn protobuf(message: &impl prost::Message) -> Response {
+    ([(header::CONTENT_TYPE, PROTOBUF)], message.encode_to_vec()).into_response()
+}
+
+/// Extractor for the response [`Format`]; rejects with 406 if the client accepts none.
+pub struct Accept(pub Format);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Accept {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Format::from_headers(&parts.headers).map(Accept)
+    }
+}
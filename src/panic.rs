@@ -0,0 +1,94 @@
+//! Panic recovery: a panicking handler returns a structured 500 / INTERNAL
+//! instead of dropping the connection.
+
+use std::any::Any;
+#[cfg(feature = "grpc")]
+use std::panic::AssertUnwindSafe;
+#[cfg(feature = "grpc")]
+use std::task::{Context, Poll};
+
+use axum::response::{IntoResponse, Response};
+#[cfg(feature = "grpc")]
+use futures::FutureExt;
+#[cfg(feature = "grpc")]
+use tonic::{
+    body::BoxBody,
+    codegen::{http, BoxFuture},
+    Status,
+};
+#[cfg(feature = "grpc")]
+use tower::{Layer, Service};
+
+use crate::error::AppError;
+
+/// `CatchPanicLayer` handler for HTTP routes.
+pub fn handle_http_panic(err: Box<dyn Any + Send + 'static>) -> Response {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("panics_total", "protocol" => "http").increment(1);
+    tracing::error!(panic = %panic_message(&*err), "HTTP handler panicked");
+    AppError::Internal("internal server error".into()).into_response()
+}
+
+/// Tower layer turning a panicking gRPC method into an INTERNAL status.
+#[cfg(feature = "grpc")]
+#[derive(Clone, Default)]
+pub struct GrpcCatchPanicLayer;
+
+#[cfg(feature = "grpc")]
+impl<S> Layer<S> for GrpcCatchPanicLayer {
+    type Service = GrpcCatchPanic<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcCatchPanic { inner }
+    }
+}
+
+#[cfg(feature = "grpc")]
+#[derive(Clone)]
+pub struct GrpcCatchPanic<S> {
+    inner: S,
+}
+
+#[cfg(feature = "grpc")]
+impl<S, ReqBody> Service<http::Request<ReqBody>> for GrpcCatchPanic<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            match AssertUnwindSafe(fut).catch_unwind().await {
+                Ok(result) => result,
+                Err(err) => {
+                    #[cfg(feature = "metrics")]
+                    metrics::counter!("panics_total", "protocol" => "grpc").increment(1);
+                    tracing::error!(panic = %panic_message(&*err), "gRPC handler panicked");
+                    Ok(Status::internal(format!("internal error (request_id={})", request_id)).to_http())
+                }
+            }
+        })
+    }
+}
+
+fn panic_message(err: &(dyn Any + Send)) -> &str {
+    err.downcast_ref::<&str>()
+        .copied()
+        .or_else(|| err.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
@@ -0,0 +1,92 @@
+//! Generated protobuf types and gRPC service stubs for `spotify.SpotifySearch`, and
+//! conversions from the [`spotify`](crate::spotify) types.
+
+use crate::spotify;
+
+tonic::include_proto!("spotify");
+
+/// Encoded descriptor set for server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("spotify_descriptor");
+
+/// `missing_embedding_reason` for tracks Spotify returned without audio features.
+pub const REASON_NO_AUDIO_FEATURES: &str = "audio_features_unavailable";
+
+impl From<&spotify::TrackWithFeatures> for TrackWithFeatures {
+    fn from(t: &spotify::TrackWithFeatures) -> Self {
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("spotify_id".into(), t.track.id.clone());
+        metadata.insert("title".into(), t.track.name.clone());
+        metadata.insert(
+            "artist".into(),
+            t.track.artists.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", "),
+        );
+        metadata.insert("album".into(), t.track.album.name.clone());
+        if let Some(ref url) = t.track.external_urls.spotify {
+            metadata.insert("spotify_url".into(), url.clone());
+        }
+        if let Some(ref linked) = t.track.linked_from {
+            metadata.insert("requested_id".into(), linked.id.clone());
+        }
+        if let Some(playable) = t.track.is_playable {
+            metadata.insert("is_playable".into(), playable.to_string());
+        }
+        Self {
+            id: t.track.id.clone(),
+            embedding: t.embedding.clone().unwrap_or_default(),
+            metadata,
+            name: t.track.name.clone(),
+            artists: t
+                .track
+                .artists
+                .iter()
+                .map(|a| Artist {
+                    id: a.id.clone().unwrap_or_default(),
+                    name: a.name.clone(),
+                })
+                .collect(),
+            album: Some(Album {
+                id: t.track.album.id.clone().unwrap_or_default(),
+                name: t.track.album.name.clone(),
+                image_url: t.track.album.images.first().and_then(|i| i.url.clone()).unwrap_or_default(),
+            }),
+            duration_ms: t.track.duration_ms,
+            spotify_url: t.track.external_urls.spotify.clone().unwrap_or_default(),
+            popularity: t.track.popularity,
+            explicit: t.track.explicit,
+            uri: t.track.uri.clone(),
+            audio_features: t.audio_features.as_ref().map(|af| AudioFeatures {
+                acousticness: af.acousticness,
+                danceability: af.danceability,
+                energy: af.energy,
+                instrumentalness: af.instrumentalness,
+                key: af.key,
+                liveness: af.liveness,
+                loudness: af.loudness,
+                mode: af.mode,
+                speechiness: af.speechiness,
+                tempo: af.tempo,
+                time_signature: af.time_signature,
+                valence: af.valence,
+            }),
+            missing_embedding_reason: String::new(),
+        }
+    }
+}
+
+/// Convert tracks fetched with audio features. Tracks without an embedding are
+/// kept with `missing_embedding_reason` set, or dropped if `include_missing` is false.
+pub fn featured_tracks_to_proto(
+    tracks: &[spotify::TrackWithFeatures],
+    include_missing: bool,
+) -> impl Iterator<Item = TrackWithFeatures> + '_ {
+    tracks
+        .iter()
+        .filter(move |t| include_missing || t.embedding.is_some())
+        .map(|t| {
+            let mut proto = TrackWithFeatures::from(t);
+            if t.embedding.is_none() {
+                proto.missing_embedding_reason = REASON_NO_AUDIO_FEATURES.into();
+            }
+            proto
+        })
+}
@@ -0,0 +1,207 @@
+//! Reloading non-structural settings at runtime, on SIGHUP or `POST /admin/reload`.
+//!
+//! The configuration is re-read from the same file, environment and flags as at
+//! startup. Settings that only affect request handling take effect immediately.
+//! Listeners, TLS, credentials and the Spotify client keep their startup values,
+//! so the token and any cached state survive. Changes to those are reported as
+//! needing a restart.
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::cli::Cli;
+use crate::config::Config;
+
+/// The live configuration, swapped on reload.
+pub type SharedConfig = Arc<ArcSwap<Config>>;
+
+/// Handle for replacing the log filter.
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Re-reads the configuration and applies reloadable changes.
+#[derive(Clone)]
+pub struct Reloader {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    cli: Cli,
+    config: SharedConfig,
+    log_filter: LogFilterHandle,
+    /// Serialises reloads so concurrent triggers can't interleave.
+    lock: Mutex<()>,
+}
+
+/// Outcome of a reload, by config key.
+#[derive(Debug, Serialize)]
+pub struct ReloadReport {
+    /// Applied.
+    pub changed: Vec<&'static str>,
+    /// Changed in the sources but ignored until restart.
+    pub requires_restart: Vec<&'static str>,
+}
+
+impl Reloader {
+    pub fn new(cli: Cli, config: SharedConfig, log_filter: LogFilterHandle) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                cli,
+                config,
+                log_filter,
+                lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Re-read the configuration and apply what can change at runtime. On error
+    /// (e.g. an invalid config file) nothing is applied. The file is read on the
+    /// blocking pool, so a slow disk doesn't stall the runtime.
+    pub async fn reload(&self) -> anyhow::Result<ReloadReport> {
+        let _guard = self.inner.lock.lock().await;
+        let inner = self.inner.clone();
+        let new = tokio::task::spawn_blocking(move || inner.cli.load_config()).await??;
+        let current = self.inner.config.load_full();
+        let mut next = (*current).clone();
+        let mut changed = Vec::new();
+
+        if new.log_level != current.log_level {
+            // RUST_LOG, when set, still wins; the process environment doesn't change.
+            if std::env::var_os("RUST_LOG").is_none() {
+                self.inner.log_filter.reload(EnvFilter::new(&new.log_level))?;
+            }
+            next.log_level = new.log_level.clone();
+            changed.push("log.level");
+        }
+        if new.access_log_redact_query != current.access_log_redact_query {
+            next.access_log_redact_query = new.access_log_redact_query;
+            changed.push("access_log.redact_query");
+        }
+        if new.admin_token != current.admin_token {
+            next.admin_token = new.admin_token.clone();
+            changed.push("admin.token");
+        }
+
+        let requires_restart = structural_changes(&current, &new);
+        self.inner.config.store(Arc::new(next));
+        Ok(ReloadReport {
+            changed,
+            requires_restart,
+        })
+    }
+
+    /// Reload on every SIGHUP, logging the outcome.
+    #[cfg(unix)]
+    pub async fn reload_on_sighup(self) -> std::io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        while hangup.recv().await.is_some() {
+            match self.reload().await {
+                Ok(report) => tracing::info!(
+                    changed = ?report.changed,
+                    requires_restart = ?report.requires_restart,
+                    "configuration reloaded on SIGHUP"
+                ),
+                Err(e) => tracing::error!("configuration reload failed, keeping current settings: {:#}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keys that differ between `old` and `new` but are fixed at startup.
+fn structural_changes(old: &Config, new: &Config) -> Vec<&'static str> {
+    [
+        ("port", old.port != new.port),
+        ("grpc_port", old.grpc_port != new.grpc_port),
+        ("bind_addr", old.bind_addr != new.bind_addr),
+        ("grpc_bind_addr", old.grpc_bind_addr != new.grpc_bind_addr),
+        ("http.unix_socket", old.http_unix_socket != new.http_unix_socket),
+        ("grpc.unix_socket", old.grpc_unix_socket != new.grpc_unix_socket),
+        ("http.enabled", old.http_enabled != new.http_enabled),
+        ("grpc.enabled", old.grpc_enabled != new.grpc_enabled),
+        ("single_port", old.single_port != new.single_port),
+        ("startup_check", old.startup_check != new.startup_check),
+        ("log.format", old.log_format != new.log_format),
+        ("spotify.client_id", old.spotify_client_id != new.spotify_client_id),
+        ("spotify.client_secret", old.spotify_client_secret != new.spotify_client_secret),
+        ("spotify.mock", old.spotify_mock != new.spotify_mock),
+        ("spotify.timeout_secs", old.spotify_timeout != new.spotify_timeout),
+        ("spotify.connect_timeout_secs", old.spotify_connect_timeout != new.spotify_connect_timeout),
+        ("spotify.user_agent", old.spotify_user_agent != new.spotify_user_agent),
+        ("spotify.api_base", old.spotify_api_base != new.spotify_api_base),
+        ("spotify.token_url", old.spotify_token_url != new.spotify_token_url),
+        (
+            "spotify.pool_max_idle_per_host",
+            old.spotify_pool_max_idle_per_host != new.spotify_pool_max_idle_per_host,
+        ),
+        ("spotify.pool_idle_timeout_secs", old.spotify_pool_idle_timeout != new.spotify_pool_idle_timeout),
+        ("spotify.tcp_keepalive_secs", old.spotify_tcp_keepalive != new.spotify_tcp_keepalive),
+        ("spotify.http2", old.spotify_http2 != new.spotify_http2),
+        ("spotify.proxy", old.spotify_proxy != new.spotify_proxy),
+        ("egress.proxy", old.egress_proxy != new.egress_proxy),
+        ("egress.no_proxy", old.egress_no_proxy != new.egress_no_proxy),
+        ("spotify.search_chunk_size", old.spotify_search_chunk_size != new.spotify_search_chunk_size),
+        ("spotify.search_concurrency", old.spotify_search_concurrency != new.spotify_search_concurrency),
+        ("spotify.market", old.spotify_market != new.spotify_market),
+        (
+            "spotify.transliterate_queries",
+            old.spotify_transliterate_queries != new.spotify_transliterate_queries,
+        ),
+        ("spotify.lenient_schema", old.spotify_lenient_schema != new.spotify_lenient_schema),
+        ("spotify.schema_samples", old.spotify_schema_samples != new.spotify_schema_samples),
+        ("tenants", old.tenants != new.tenants),
+        ("saved_queries", old.saved_queries != new.saved_queries),
+        ("telemetry.otlp_endpoint", old.otlp_endpoint != new.otlp_endpoint),
+        ("telemetry.service_name", old.service_name != new.service_name),
+        ("http.tls", old.http_tls != new.http_tls),
+        ("http.tls.reload_interval_secs", old.http_tls_reload_interval != new.http_tls_reload_interval),
+        ("grpc.tls", old.grpc_tls != new.grpc_tls),
+        ("grpc.web_allowed_origins", old.grpc_web_allowed_origins != new.grpc_web_allowed_origins),
+        ("database.url", old.database_url != new.database_url),
+        ("database.max_connections", old.database_max_connections != new.database_max_connections),
+        ("offline_mode", old.offline_mode != new.offline_mode),
+        ("database.refresh.max_age_secs", old.refresh_max_age != new.refresh_max_age),
+        ("database.refresh.interval_secs", old.refresh_interval != new.refresh_interval),
+        ("database.refresh.pause_ms", old.refresh_pause != new.refresh_pause),
+        ("kafka.brokers", old.kafka_brokers != new.kafka_brokers),
+        ("kafka.topic", old.kafka_topic != new.kafka_topic),
+        ("nats.url", old.nats_url != new.nats_url),
+        ("nats.events_subject", old.nats_events_subject != new.nats_events_subject),
+        ("jobs.callback_secret", old.job_callback_secret != new.job_callback_secret),
+        ("auth.jwt", old.jwt != new.jwt),
+        ("snapshots", old.snapshots != new.snapshots),
+        ("audit", old.audit != new.audit),
+        ("local_search.enabled", old.local_search != new.local_search),
+        (
+            "local_search.sync_interval_secs",
+            old.local_search_sync_interval != new.local_search_sync_interval,
+        ),
+        ("suggest.cache_ttl_secs", old.suggest_cache_ttl != new.suggest_cache_ttl),
+        ("suggest.debounce_ms", old.suggest_debounce != new.suggest_debounce),
+        ("search.default_limit", old.search_limits.default_limit != new.search_limits.default_limit),
+        ("search.max_limit", old.search_limits.max_limit != new.search_limits.max_limit),
+        ("search.max_offset", old.search_limits.max_offset != new.search_limits.max_offset),
+        ("search.strict_limits", old.search_limits.strict != new.search_limits.strict),
+        ("limits.max_body_bytes", old.request_limits.max_body_bytes != new.request_limits.max_body_bytes),
+        ("limits.max_batch_ids", old.request_limits.max_batch_ids != new.request_limits.max_batch_ids),
+        (
+            "limits.max_query_string_bytes",
+            old.request_limits.max_query_string_bytes != new.request_limits.max_query_string_bytes,
+        ),
+        ("search_cache.ttl_secs", old.search_cache_ttl != new.search_cache_ttl),
+        ("search_cache.stale_secs", old.search_cache_stale != new.search_cache_stale),
+        ("cache_warm.file", old.cache_warm_file != new.cache_warm_file),
+        ("cache_warm.pause_ms", old.cache_warm_pause != new.cache_warm_pause),
+        ("runtime.worker_threads", old.runtime_worker_threads != new.runtime_worker_threads),
+        ("runtime.max_blocking_threads", old.runtime_max_blocking_threads != new.runtime_max_blocking_threads),
+        ("runtime.blocking_keep_alive_secs", old.runtime_blocking_keep_alive != new.runtime_blocking_keep_alive),
+    ]
+    .into_iter()
+    .filter_map(|(key, differs)| differs.then_some(key))
+    .collect()
+}
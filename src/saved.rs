@@ -0,0 +1,255 @@
+//! Saved searches: named `/api/v1/search` queries served at `GET /api/v1/saved/:alias`.
+//!
+//! Admins define them in the config file (`[saved_queries.<alias>]`) or at runtime with
+//! `PUT /admin/saved/:alias`. Each keeps its rendered responses, per tenant and response
+//! format, in its own [`ResultCache`] with its own TTL, so a dashboard embedding a common
+//! search gets it at once. With a prewarm interval, a background task also refreshes the
+//! default tenant's JSON response on that schedule, so it is never cold.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use tokio::task::AbortHandle;
+
+use crate::cache::ResultCache;
+use crate::config::SavedQueryConfig;
+use crate::error::AppError;
+use crate::handlers::{self, SearchQuery};
+use crate::negotiate::{Accept, Format};
+use crate::spotify::{DynSpotifyApi, SearchLimits};
+use crate::tenants;
+use crate::validation::{FieldError, FromRawQuery, Validated};
+
+/// TTL when none is given.
+pub const DEFAULT_TTL_SECS: u64 = 300;
+/// Shortest prewarm interval, so that a typo can't turn into a loop of Spotify searches.
+pub const MIN_PREWARM_SECS: u64 = 10;
+/// Longest alias.
+const MAX_ALIAS_LEN: usize = 64;
+
+/// The registered saved searches and their caches.
+pub struct SavedQueries {
+    spotify: DynSpotifyApi,
+    limits: SearchLimits,
+    /// How long past its TTL a response is served while one request refreshes it.
+    stale: Duration,
+    entries: Mutex<BTreeMap<String, Entry>>,
+}
+
+struct Entry {
+    saved: SavedQueryConfig,
+    cache: Arc<ResultCache<Rendered>>,
+    prewarm: Option<AbortHandle>,
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        if let Some(prewarm) = &self.prewarm {
+            prewarm.abort();
+        }
+    }
+}
+
+/// A saved search as `GET /admin/saved` lists it.
+#[derive(Debug, Serialize)]
+pub struct SavedQueryStatus {
+    pub alias: String,
+    pub query: String,
+    pub ttl_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prewarm_secs: Option<u64>,
+}
+
+impl From<&SavedQueryConfig> for SavedQueryStatus {
+    fn from(saved: &SavedQueryConfig) -> Self {
+        SavedQueryStatus {
+            alias: saved.alias.clone(),
+            query: saved.query.clone(),
+            ttl_secs: saved.ttl.as_secs(),
+            prewarm_secs: saved.prewarm_interval.map(|i| i.as_secs()),
+        }
+    }
+}
+
+impl SavedQueries {
+    /// No saved searches yet; they run against `spotify` with the search `limits`.
+    pub fn new(spotify: DynSpotifyApi, limits: SearchLimits, stale: Duration) -> Self {
+        Self {
+            spotify,
+            limits,
+            stale,
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Register `saved`, replacing the search with its alias, if any, and its cache.
+    /// Fails with every invalid field, including those of the search query.
+    pub fn put(&self, saved: SavedQueryConfig) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let mut invalid = |field: String, message: String| errors.push(FieldError { field, message });
+        let valid_alias = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if saved.alias.is_empty() || saved.alias.len() > MAX_ALIAS_LEN || !saved.alias.chars().all(valid_alias) {
+            invalid("alias".into(), format!("1-{} letters, digits, '-' and '_' required", MAX_ALIAS_LEN));
+        }
+        if saved.ttl.is_zero() {
+            invalid("ttl_secs".into(), "must be at least 1".into());
+        }
+        if saved.prewarm_interval.is_some_and(|i| i.as_secs() < MIN_PREWARM_SECS) {
+            invalid("prewarm_secs".into(), format!("must be at least {}", MIN_PREWARM_SECS));
+        }
+        match parse(&saved.query, &self.limits) {
+            Ok(_) => {}
+            Err(AppError::Validation(fields)) => {
+                for e in fields {
+                    invalid(format!("query.{}", e.field), e.message);
+                }
+            }
+            Err(AppError::BadRequest(message)) => invalid("query".into(), message),
+            Err(e) => invalid("query".into(), format!("{:?}", e)),
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let cache = Arc::new(ResultCache::new(saved.ttl, self.stale));
+        let prewarm = saved.prewarm_interval.map(|interval| {
+            let search = self.search_fn(saved.query.clone(), Format::Json);
+            tokio::spawn(prewarm(saved.alias.clone(), cache.clone(), search, interval)).abort_handle()
+        });
+        tracing::info!(alias = saved.alias.as_str(), query = saved.query.as_str(), "saved search registered");
+        let entry = Entry { saved, cache, prewarm };
+        self.entries.lock().unwrap().insert(entry.saved.alias.clone(), entry);
+        Ok(())
+    }
+
+    /// Remove `alias`; false if there is no such search.
+    pub fn remove(&self, alias: &str) -> bool {
+        let removed = self.entries.lock().unwrap().remove(alias);
+        if removed.is_some() {
+            tracing::info!(alias, "saved search removed");
+        }
+        removed.is_some()
+    }
+
+    /// Every saved search, by alias.
+    pub fn list(&self) -> Vec<SavedQueryStatus> {
+        self.entries.lock().unwrap().values().map(|e| (&e.saved).into()).collect()
+    }
+
+    /// The response to `alias` in `format` for the current tenant, from the cache if
+    /// it has a usable one. Failed searches are answered as `/api/v1/search` would
+    /// answer them, and not cached.
+    pub async fn search(&self, alias: &str, format: Format) -> Result<Response, AppError> {
+        let (cache, query) = {
+            let entries = self.entries.lock().unwrap();
+            let entry = entries
+                .get(alias)
+                .ok_or_else(|| AppError::NotFound(format!("no saved search '{}'", alias)))?;
+            (entry.cache.clone(), entry.saved.query.clone())
+        };
+        let key = cache_key(tenants::current().as_deref(), format);
+        Ok(match cache.get(key, self.search_fn(query, format)).await {
+            Ok(rendered) => rendered.to_response(),
+            Err(rendered) => rendered.to_response(),
+        })
+    }
+
+    /// Runs `query` through the search handler and renders the response in `format`.
+    fn search_fn(&self, query: String, format: Format) -> impl Fn() -> RenderFuture + Send + Sync + 'static {
+        let (spotify, limits) = (self.spotify.clone(), self.limits);
+        move || {
+            let (spotify, query) = (spotify.clone(), query.clone());
+            Box::pin(async move {
+                let response = match parse(&query, &limits) {
+                    Ok(params) => handlers::search(State(spotify), Accept(format), Validated(params))
+                        .await
+                        .unwrap_or_else(IntoResponse::into_response),
+                    Err(e) => e.into_response(),
+                };
+                let rendered = Rendered::read(response).await;
+                if rendered.status.is_success() {
+                    Ok(rendered)
+                } else {
+                    Err(rendered)
+                }
+            })
+        }
+    }
+}
+
+type RenderFuture = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Rendered, Rendered>> + Send>>;
+
+/// Refresh the default tenant's JSON response every `interval`, starting now.
+async fn prewarm<F>(alias: String, cache: Arc<ResultCache<Rendered>>, search: F, interval: Duration)
+where
+    F: Fn() -> RenderFuture,
+{
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        match cache.refresh(cache_key(None, Format::Json), &search).await {
+            Ok(()) => tracing::debug!(alias = alias.as_str(), "saved search prewarmed"),
+            Err(e) => tracing::warn!(alias = alias.as_str(), "prewarming the saved search failed: {}", e),
+        }
+    }
+}
+
+fn cache_key(tenant: Option<&str>, format: Format) -> String {
+    format!("{}\n{:?}", tenant.unwrap_or_default(), format)
+}
+
+/// The `/api/v1/search` parameters in `query`.
+fn parse(query: &str, limits: &SearchLimits) -> Result<SearchQuery, AppError> {
+    let uri: Uri = format!("/?{}", query.trim_start_matches('?'))
+        .parse()
+        .map_err(|_| AppError::BadRequest(format!("'{}' is not a valid query string", query)))?;
+    let Query(raw) = Query::try_from_uri(&uri).map_err(|e| AppError::BadRequest(e.body_text()))?;
+    SearchQuery::validate_with(raw, limits)
+}
+
+/// A response read into memory, as cached.
+pub struct Rendered {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+impl Rendered {
+    async fn read(response: Response) -> Self {
+        let (parts, body) = response.into_parts();
+        match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(body) => Rendered {
+                status: parts.status,
+                content_type: parts.headers.get(header::CONTENT_TYPE).cloned(),
+                body,
+            },
+            Err(e) => Rendered {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                content_type: None,
+                body: format!("reading the search response: {}", e).into(),
+            },
+        }
+    }
+
+    fn to_response(&self) -> Response {
+        let mut response = (self.status, self.body.clone()).into_response();
+        if let Some(content_type) = &self.content_type {
+            response.headers_mut().insert(header::CONTENT_TYPE, content_type.clone());
+        }
+        response
+    }
+}
+
+impl fmt::Display for Rendered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status, String::from_utf8_lossy(&self.body))
+    }
+}
@@ -0,0 +1,43 @@
+//! [`Secret`], a value that is never printed.
+//!
+//! Credentials and tokens are held as `Secret`s in [`Config`](crate::config::Config)
+//! and in the Spotify token handling, so a `{:?}` in a log line, error or panic message
+//! shows `[redacted]` instead of the value. There is no `Display` or `Serialize`:
+//! [`expose`](Secret::expose) is the only way to the value, which keeps every use of it
+//! easy to find.
+
+use serde::Deserialize;
+
+/// A credential or token whose `Debug` output is `[redacted]`.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T = String>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// The value, for the one place that needs to send or check it.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
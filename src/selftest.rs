@@ -0,0 +1,129 @@
+//! `spotify-search --self-test`: a load test of the HTTP stack against the mock catalog.
+//!
+//! Each line of the corpus becomes a `GET` request, a search for the line or, if it starts
+//! with `/`, the line as the path. The requests are replayed in turn through the full
+//! router, middleware included, in process and without a socket, with a fixed number in
+//! flight. The mock answers instantly, so the latencies are the service's own overhead:
+//! routing, validation, mapping and serialization.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::http::Request;
+use axum::Router;
+use futures::StreamExt;
+use tower::ServiceExt;
+
+/// The corpus replayed without `--self-test-corpus`, written for the bundled mock catalog.
+pub const BUNDLED_CORPUS: &str = include_str!("../fixtures/self_test_corpus.txt");
+
+/// Request paths for the non-empty, non-comment lines of `corpus`.
+pub fn parse_corpus(corpus: &str) -> Vec<String> {
+    corpus
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line {
+            path if path.starts_with('/') => path.to_string(),
+            query => format!("/api/v1/search?q={}", urlencoding::encode(query)),
+        })
+        .collect()
+}
+
+/// Latencies of one self-test run.
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    pub requests: usize,
+    pub concurrency: usize,
+    /// Requests answered with a status other than 2xx.
+    pub errors: usize,
+    /// The failed requests counted by path and status.
+    pub failures: BTreeMap<(String, u16), usize>,
+    pub elapsed: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rate = self.requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON);
+        writeln!(
+            f,
+            "{} requests, {} in flight, in {:.2?} ({:.0} requests/s), {} errors",
+            self.requests,
+            self.concurrency,
+            self.elapsed,
+            rate,
+            self.errors
+        )?;
+        write!(
+            f,
+            "latency p50 {:.2?}  p95 {:.2?}  p99 {:.2?}  max {:.2?}",
+            self.p50, self.p95, self.p99, self.max
+        )
+    }
+}
+
+/// Send `requests` requests, cycling through `paths`, to `app` with `concurrency` in
+/// flight, reading each response body to the end.
+pub async fn run(app: Router, paths: &[String], requests: usize, concurrency: usize) -> LoadReport {
+    let concurrency = concurrency.max(1);
+    let start = Instant::now();
+    let results: Vec<(Duration, u16, &str)> = futures::stream::iter(paths.iter().cycle().take(requests))
+        .map(|path| {
+            let app = app.clone();
+            async move {
+                // A path that isn't a valid URI counts as a failed request.
+                let Ok(request) = Request::get(path.as_str()).body(Body::empty()) else {
+                    return (Duration::ZERO, 400, path.as_str());
+                };
+                let sent = Instant::now();
+                let status = match app.oneshot(request).await {
+                    Ok(res) => {
+                        let status = res.status().as_u16();
+                        match axum::body::to_bytes(res.into_body(), usize::MAX).await {
+                            Ok(_) => status,
+                            Err(_) => 500,
+                        }
+                    }
+                    Err(infallible) => match infallible {},
+                };
+                (sent.elapsed(), status, path.as_str())
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    let elapsed = start.elapsed();
+
+    let mut failures = BTreeMap::new();
+    for (_, status, path) in results.iter().filter(|(_, status, _)| !(200..300).contains(status)) {
+        *failures.entry((path.to_string(), *status)).or_default() += 1;
+    }
+    let mut latencies: Vec<Duration> = results.iter().map(|(latency, _, _)| *latency).collect();
+    latencies.sort_unstable();
+    LoadReport {
+        requests: results.len(),
+        concurrency,
+        errors: failures.values().sum(),
+        failures,
+        elapsed,
+        p50: percentile(&latencies, 50.0),
+        p95: percentile(&latencies, 95.0),
+        p99: percentile(&latencies, 99.0),
+        max: latencies.last().copied().unwrap_or_default(),
+    }
+}
+
+/// Nearest-rank percentile of sorted `latencies`.
+fn percentile(latencies: &[Duration], p: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
@@ -0,0 +1,213 @@
+//! Daily snapshots of the local store to object storage (S3 or compatible).
+//!
+//! Once a day, at `SNAPSHOT_HOUR_UTC`, [`Snapshotter`] streams the whole store through
+//! the NDJSON [export](crate::export) into a gzipped object, then writes a manifest
+//! describing it:
+//!
+//! ```text
+//! {prefix}/2026-10-17/tracks.ndjson.gz
+//! {prefix}/2026-10-17/manifest.json
+//! {prefix}/latest.json                  (copy of the newest manifest)
+//! ```
+//!
+//! The manifest is written last, so a day with a manifest has a complete snapshot.
+//! Days that already have one are skipped, which keeps restarts and several replicas
+//! from writing the same day twice, short of two of them starting it at once.
+
+use std::io::Write as _;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+use futures::TryStreamExt;
+use object_store::path::Path;
+use object_store::{ObjectStore, WriteMultipart};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use time::{Date, OffsetDateTime, Time};
+
+use crate::export::{self, ExportError, ExportFormat};
+use crate::storage::DynStorage;
+
+/// Wait before retrying a failed snapshot.
+const RETRY: Duration = Duration::from_secs(15 * 60);
+/// Multipart parts uploaded at once.
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+/// Describes one day's snapshot. Object paths are full keys in the bucket.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    /// UTC day the snapshot was taken, `YYYY-MM-DD`.
+    pub date: String,
+    /// Unix seconds.
+    pub created_at: i64,
+    pub format: &'static str,
+    pub compression: &'static str,
+    pub files: Vec<ManifestFile>,
+}
+
+/// One data object in a snapshot.
+#[derive(Debug, Serialize)]
+pub struct ManifestFile {
+    pub path: String,
+    /// Tracks (NDJSON lines) in the file.
+    pub rows: u64,
+    /// Compressed size.
+    pub bytes: u64,
+    /// Hex SHA-256 of the compressed object.
+    pub sha256: String,
+}
+
+/// Failure writing a snapshot.
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error(transparent)]
+    Export(#[from] ExportError),
+    #[error("object store: {0}")]
+    ObjectStore(#[from] object_store::Error),
+    #[error("compressing snapshot: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("encoding manifest: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Writes a snapshot of `store` to `objects` every day.
+pub struct Snapshotter {
+    store: DynStorage,
+    objects: Arc<dyn ObjectStore>,
+    prefix: Path,
+    at: Time,
+}
+
+impl Snapshotter {
+    /// Snapshots under `snapshots/`, at 03:00 UTC.
+    pub fn new(store: DynStorage, objects: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            store,
+            objects,
+            prefix: Path::from("snapshots"),
+            at: Time::from_hms(3, 0, 0).expect("valid time"),
+        }
+    }
+
+    /// Key prefix for the snapshots; empty writes them at the top of the bucket.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Path::from(prefix);
+        self
+    }
+
+    /// Hour of the day (UTC, 0-23) snapshots are taken at.
+    pub fn hour_utc(mut self, hour: u8) -> Self {
+        self.at = Time::from_hms(hour.min(23), 0, 0).expect("valid time");
+        self
+    }
+
+    /// Take a snapshot every day, forever. A missed or failed day is taken as soon as
+    /// possible on the same day; failures are logged and retried every 15 minutes.
+    pub async fn run(self) {
+        loop {
+            let now = OffsetDateTime::now_utc();
+            let due = now.date().with_time(self.at).assume_utc();
+            let wait = if now < due {
+                (due - now).unsigned_abs()
+            } else {
+                match self.snapshot_if_missing(now.date()).await {
+                    Ok(_) => (due + time::Duration::DAY - OffsetDateTime::now_utc()).unsigned_abs(),
+                    Err(e) => {
+                        record_snapshot("failed");
+                        tracing::warn!("snapshot failed, retrying in {:?}: {}", RETRY, e);
+                        RETRY
+                    }
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Snapshot the store for `date` unless that day already has a manifest. Returns
+    /// the new manifest, if one was written.
+    pub async fn snapshot_if_missing(&self, date: Date) -> Result<Option<Manifest>, SnapshotError> {
+        let day = self.prefix.child(date.to_string());
+        match self.objects.head(&day.child("manifest.json")).await {
+            Ok(_) => return Ok(None),
+            Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.snapshot(date).await.map(Some)
+    }
+
+    /// Snapshot the store for `date`, replacing any existing snapshot of that day.
+    pub async fn snapshot(&self, date: Date) -> Result<Manifest, SnapshotError> {
+        let started = Instant::now();
+        let day = self.prefix.child(date.to_string());
+        let path = day.child("tracks.ndjson.gz");
+        let mut upload = WriteMultipart::new(self.objects.put_multipart(&path).await?);
+        let file = match self.write_tracks(&mut upload).await {
+            Ok((rows, bytes, sha256)) => {
+                upload.finish().await?;
+                ManifestFile {
+                    path: path.to_string(),
+                    rows,
+                    bytes,
+                    sha256,
+                }
+            }
+            Err(e) => {
+                if let Err(abort) = upload.abort().await {
+                    tracing::warn!("aborting snapshot upload {}: {}", path, abort);
+                }
+                return Err(e);
+            }
+        };
+
+        let manifest = Manifest {
+            date: date.to_string(),
+            created_at: OffsetDateTime::now_utc().unix_timestamp(),
+            format: "ndjson",
+            compression: "gzip",
+            files: vec![file],
+        };
+        let body = serde_json::to_vec_pretty(&manifest)?;
+        self.objects.put(&day.child("manifest.json"), body.clone().into()).await?;
+        self.objects.put(&self.prefix.child("latest.json"), body.into()).await?;
+        record_snapshot("written");
+        tracing::info!(
+            date = %manifest.date,
+            rows = manifest.files[0].rows,
+            bytes = manifest.files[0].bytes,
+            "snapshot written to {} in {:?}",
+            day,
+            started.elapsed()
+        );
+        Ok(manifest)
+    }
+
+    /// Stream the export into `upload`, gzipped. Returns the rows, compressed bytes
+    /// and hex SHA-256 written.
+    async fn write_tracks(&self, upload: &mut WriteMultipart) -> Result<(u64, u64, String), SnapshotError> {
+        let mut tracks = Box::pin(export::export_stream(self.store.clone(), ExportFormat::Ndjson, 0..i64::MAX)?);
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut sha256 = Sha256::new();
+        let (mut rows, mut bytes) = (0u64, 0u64);
+        let mut emit = |compressed: Vec<u8>, upload: &mut WriteMultipart| {
+            sha256.update(&compressed);
+            bytes += compressed.len() as u64;
+            upload.write(&compressed);
+        };
+        while let Some(chunk) = tracks.try_next().await? {
+            rows += chunk.iter().filter(|&&b| b == b'\n').count() as u64;
+            gzip.write_all(&chunk)?;
+            emit(std::mem::take(gzip.get_mut()), upload);
+            upload.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+        }
+        emit(gzip.finish()?, upload);
+        let sha256 = sha256.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+        Ok((rows, bytes, sha256))
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_snapshot(result: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("snapshots_total", "result" => result).increment(1);
+}
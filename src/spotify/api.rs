@@ -0,0 +1,223 @@
+//! The [`SpotifyApi`] trait: everything the HTTP and gRPC handlers need from Spotify.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::{
+    AlbumDetail, AlbumTracksPage, ArtistAlbumsPage, ArtistDetail, AudioFeatures, ByteStream, PlaylistTracksPage,
+    RecommendationSeeds, ScoredTrack, SearchTracksResponse, SearchTracksWithFeaturesResponse, SpotifyClient,
+    SpotifyError, TokenStatus, Track, TrackWithFeatures, UpstreamSnapshot,
+};
+
+/// Spotify operations used by the server, implemented by [`SpotifyClient`] and, with the
+/// `mock` feature, by [`MockSpotifyApi`](super::MockSpotifyApi).
+#[async_trait]
+pub trait SpotifyApi: Send + Sync {
+    /// True once a token has been obtained and the latest refresh succeeded.
+    async fn has_token(&self) -> bool;
+
+    /// Per-endpoint upstream status and rate-limit state.
+    fn upstream_status(&self) -> UpstreamSnapshot;
+
+    /// The cached token's remaining lifetime and the outcome of the latest refresh.
+    fn token_status(&self) -> TokenStatus;
+
+    /// Replace the cached token with a new one now.
+    async fn refresh_token(&self) -> Result<(), SpotifyError>;
+
+    async fn search_tracks(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksResponse, SpotifyError>;
+
+    async fn search_tracks_with_features(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksWithFeaturesResponse, SpotifyError>;
+
+    /// Spotify's `/search` response body as received, without decoding it.
+    async fn search_tracks_raw(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ByteStream, SpotifyError>;
+
+    async fn get_tracks(&self, ids: &[String]) -> Result<Vec<Option<Track>>, SpotifyError>;
+
+    /// Spotify's `/tracks` response body for up to 50 IDs, without decoding it.
+    async fn get_tracks_raw(&self, ids: &[String]) -> Result<ByteStream, SpotifyError>;
+
+    async fn get_audio_features(&self, ids: &[String]) -> Result<Vec<Option<AudioFeatures>>, SpotifyError>;
+
+    async fn get_tracks_with_features(&self, ids: &[String]) -> Result<Vec<TrackWithFeatures>, SpotifyError>;
+
+    async fn get_recommendations_with_features(
+        &self,
+        seeds: &RecommendationSeeds,
+        limit: Option<u32>,
+    ) -> Result<Vec<TrackWithFeatures>, SpotifyError>;
+
+    async fn get_similar_tracks(&self, id: &str, limit: Option<u32>) -> Result<Vec<ScoredTrack>, SpotifyError>;
+
+    /// One page of a playlist's tracks (up to 100 from `offset`).
+    async fn get_playlist_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<PlaylistTracksPage, SpotifyError>;
+
+    /// One page of an artist's albums, singles and compilations (up to 50 from `offset`).
+    async fn get_artist_albums(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ArtistAlbumsPage, SpotifyError>;
+
+    /// One page of an album's tracks (up to 50 from `offset`).
+    async fn get_album_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<AlbumTracksPage, SpotifyError>;
+
+    /// Full metadata for one album.
+    async fn get_album(&self, id: &str) -> Result<AlbumDetail, SpotifyError>;
+
+    /// Full artist object for one artist.
+    async fn get_artist(&self, id: &str) -> Result<ArtistDetail, SpotifyError>;
+
+    /// Full metadata for several albums, in order; `None` for unknown IDs.
+    async fn get_albums(&self, ids: &[String]) -> Result<Vec<Option<AlbumDetail>>, SpotifyError>;
+
+    /// Full artist objects for several artists, in order; `None` for unknown IDs.
+    async fn get_artists(&self, ids: &[String]) -> Result<Vec<Option<ArtistDetail>>, SpotifyError>;
+}
+
+/// Shared handle used in server state.
+pub type DynSpotifyApi = Arc<dyn SpotifyApi>;
+
+#[async_trait]
+impl SpotifyApi for SpotifyClient {
+    async fn has_token(&self) -> bool {
+        SpotifyClient::has_token(self).await
+    }
+
+    fn upstream_status(&self) -> UpstreamSnapshot {
+        SpotifyClient::upstream_status(self)
+    }
+
+    fn token_status(&self) -> TokenStatus {
+        SpotifyClient::token_status(self)
+    }
+
+    async fn refresh_token(&self) -> Result<(), SpotifyError> {
+        SpotifyClient::refresh_token(self).await
+    }
+
+    async fn search_tracks(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksResponse, SpotifyError> {
+        SpotifyClient::search_tracks(self, q, limit, offset).await
+    }
+
+    async fn search_tracks_with_features(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksWithFeaturesResponse, SpotifyError> {
+        SpotifyClient::search_tracks_with_features(self, q, limit, offset).await
+    }
+
+    async fn search_tracks_raw(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ByteStream, SpotifyError> {
+        SpotifyClient::search_tracks_raw(self, q, limit, offset).await
+    }
+
+    async fn get_tracks(&self, ids: &[String]) -> Result<Vec<Option<Track>>, SpotifyError> {
+        SpotifyClient::get_tracks(self, ids).await
+    }
+
+    async fn get_tracks_raw(&self, ids: &[String]) -> Result<ByteStream, SpotifyError> {
+        SpotifyClient::get_tracks_raw(self, ids).await
+    }
+
+    async fn get_audio_features(&self, ids: &[String]) -> Result<Vec<Option<AudioFeatures>>, SpotifyError> {
+        SpotifyClient::get_audio_features(self, ids).await
+    }
+
+    async fn get_tracks_with_features(&self, ids: &[String]) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        SpotifyClient::get_tracks_with_features(self, ids).await
+    }
+
+    async fn get_recommendations_with_features(
+        &self,
+        seeds: &RecommendationSeeds,
+        limit: Option<u32>,
+    ) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        SpotifyClient::get_recommendations_with_features(self, seeds, limit).await
+    }
+
+    async fn get_similar_tracks(&self, id: &str, limit: Option<u32>) -> Result<Vec<ScoredTrack>, SpotifyError> {
+        SpotifyClient::get_similar_tracks(self, id, limit).await
+    }
+
+    async fn get_playlist_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<PlaylistTracksPage, SpotifyError> {
+        SpotifyClient::get_playlist_tracks(self, id, limit, offset).await
+    }
+
+    async fn get_artist_albums(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ArtistAlbumsPage, SpotifyError> {
+        SpotifyClient::get_artist_albums(self, id, limit, offset).await
+    }
+
+    async fn get_album_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<AlbumTracksPage, SpotifyError> {
+        SpotifyClient::get_album_tracks(self, id, limit, offset).await
+    }
+
+    async fn get_album(&self, id: &str) -> Result<AlbumDetail, SpotifyError> {
+        SpotifyClient::get_album(self, id).await
+    }
+
+    async fn get_artist(&self, id: &str) -> Result<ArtistDetail, SpotifyError> {
+        SpotifyClient::get_artist(self, id).await
+    }
+
+    async fn get_albums(&self, ids: &[String]) -> Result<Vec<Option<AlbumDetail>>, SpotifyError> {
+        SpotifyClient::get_albums(self, ids).await
+    }
+
+    async fn get_artists(&self, ids: &[String]) -> Result<Vec<Option<ArtistDetail>>, SpotifyError> {
+        SpotifyClient::get_artists(self, ids).await
+    }
+}
@@ -0,0 +1,48 @@
+//! Audit hook for every request [`SpotifyClient`](super::SpotifyClient) sends upstream.
+//!
+//! With an [`UpstreamAudit`] set ([`SpotifyClientBuilder::audit`](super::SpotifyClientBuilder::audit)),
+//! each request to Spotify, token requests and retries included, is reported once its
+//! response arrives or it fails. Replayed cassette traffic is not.
+
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+/// One request sent to Spotify.
+#[derive(Debug, Clone)]
+pub struct UpstreamCall<'a> {
+    /// When the request was sent.
+    pub at: SystemTime,
+    /// `tenant` label of the client.
+    pub tenant: &'a str,
+    /// Endpoint label, as on metrics (`search`, `tracks`, `token`, ...).
+    pub endpoint: &'static str,
+    pub method: &'a str,
+    /// Path and query of the request URL. The token request's form body, with the
+    /// credentials, is not included.
+    pub path_and_query: &'a str,
+    /// Response status; `None` when no response arrived.
+    pub status: Option<u16>,
+    pub latency: Duration,
+}
+
+/// Receives every [`UpstreamCall`]. Called on the request path, so it must not block.
+pub trait UpstreamAudit: Send + Sync {
+    fn record(&self, call: &UpstreamCall<'_>);
+}
+
+/// An [`UpstreamCall`] as written to an audit trail, with its parameters hashed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditRecord {
+    /// Unix milliseconds when the request was sent.
+    pub at_ms: i64,
+    pub tenant: String,
+    pub endpoint: String,
+    pub method: String,
+    /// Hex digest of the path and query, so identical calls can be matched without
+    /// keeping search queries.
+    pub params_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub latency_ms: i64,
+}
@@ -0,0 +1,295 @@
+//! [`SpotifyClientBuilder`]: HTTP client, endpoints and timeouts for [`SpotifyClient`].
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwapOption;
+use reqwest::Client;
+
+#[cfg(feature = "cassette")]
+use super::cassette::Cassette;
+use super::audit::UpstreamAudit;
+use super::rate_limit::UpstreamTracker;
+use super::token::{ClientCredentials, TokenProvider};
+use super::{SearchLimits, SpotifyClient};
+
+/// Spotify Accounts token endpoint.
+pub const DEFAULT_TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+/// Spotify Web API base URL.
+pub const DEFAULT_API_BASE: &str = "https://api.spotify.com/v1";
+/// User-Agent sent unless overridden.
+pub const DEFAULT_USER_AGENT: &str = concat!("spotify-search/", env!("CARGO_PKG_VERSION"));
+/// Tracks per search request in `search_tracks_with_features` unless overridden.
+pub const DEFAULT_SEARCH_CHUNK_SIZE: u32 = 50;
+/// Search chunks in flight at once unless overridden.
+pub const DEFAULT_SEARCH_CONCURRENCY: usize = 4;
+/// `tenant` metric label unless overridden.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Builder for [`SpotifyClient`], from [`SpotifyClient::builder`].
+pub struct SpotifyClientBuilder {
+    /// Client Credentials, used unless `token_provider` is set.
+    credentials: Option<(String, String)>,
+    token_provider: Option<Arc<dyn TokenProvider>>,
+    http: Option<Client>,
+    api_base: String,
+    token_url: String,
+    user_agent: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout: Option<Duration>,
+    tcp_keepalive: Option<Duration>,
+    http1_only: bool,
+    proxy: Option<reqwest::Proxy>,
+    search_chunk_size: u32,
+    search_concurrency: usize,
+    market: Option<String>,
+    transliterate_queries: bool,
+    search_limits: SearchLimits,
+    tenant: String,
+    audits: Vec<Arc<dyn UpstreamAudit>>,
+    lenient_schema: bool,
+    schema_samples: bool,
+    #[cfg(feature = "cassette")]
+    cassette: Option<Cassette>,
+}
+
+impl SpotifyClientBuilder {
+    pub(super) fn new(credentials: Option<(String, String)>) -> Self {
+        Self {
+            credentials,
+            token_provider: None,
+            http: None,
+            api_base: DEFAULT_API_BASE.into(),
+            token_url: DEFAULT_TOKEN_URL.into(),
+            user_agent: None,
+            timeout: None,
+            connect_timeout: None,
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: None,
+            tcp_keepalive: None,
+            http1_only: false,
+            proxy: None,
+            search_chunk_size: DEFAULT_SEARCH_CHUNK_SIZE,
+            search_concurrency: DEFAULT_SEARCH_CONCURRENCY,
+            market: None,
+            transliterate_queries: false,
+            search_limits: SearchLimits::default(),
+            tenant: DEFAULT_TENANT.into(),
+            audits: Vec::new(),
+            lenient_schema: false,
+            schema_samples: false,
+            #[cfg(feature = "cassette")]
+            cassette: None,
+        }
+    }
+
+    /// Use an existing `reqwest::Client` (shared pool, custom TLS roots). Only
+    /// `user_agent` and `timeout` apply to it; the connection settings below don't.
+    pub fn http_client(mut self, client: Client) -> Self {
+        self.http = Some(client);
+        self
+    }
+
+    /// Web API base URL, e.g. a mock server or Spotify's partner sandbox.
+    pub fn api_base(mut self, url: impl Into<String>) -> Self {
+        self.api_base = url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Obtain tokens from `provider` instead of Client Credentials.
+    pub fn token_provider(mut self, provider: impl TokenProvider + 'static) -> Self {
+        self.token_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Client Credentials token endpoint (ignored with a custom `token_provider`).
+    pub fn token_url(mut self, url: impl Into<String>) -> Self {
+        self.token_url = url.into();
+        self
+    }
+
+    /// User-Agent for every Spotify request (default `spotify-search/<version>`).
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Total timeout per Spotify request, including the token fetch.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// TCP/TLS connect timeout for the built-in HTTP client.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Most idle connections kept open to each host (default: unlimited).
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// How long an idle pooled connection is kept open (default: 90s).
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// TCP keepalive interval on connections to Spotify (default: 15s).
+    pub fn tcp_keepalive(mut self, interval: Duration) -> Self {
+        self.tcp_keepalive = Some(interval);
+        self
+    }
+
+    /// Speak HTTP/1.1 only. By default HTTP/2 is used wherever TLS negotiates it.
+    pub fn http1_only(mut self) -> Self {
+        self.http1_only = true;
+        self
+    }
+
+    /// Send Spotify and token requests through `proxy`, e.g. an HTTPS egress proxy.
+    /// Without one, the standard `HTTPS_PROXY` / `NO_PROXY` variables apply.
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Tracks per search request in `search_tracks_with_features`, 1-50 (default 50, the
+    /// whole page in one search). Smaller chunks overlap more of the features calls but
+    /// cost more search and features requests, and the separate searches can disagree
+    /// on the ranking, leaving duplicates or gaps in the page.
+    pub fn search_chunk_size(mut self, size: u32) -> Self {
+        self.search_chunk_size = size.clamp(1, 50);
+        self
+    }
+
+    /// Search chunks, each followed by its features call, in flight at once (default 4).
+    pub fn search_concurrency(mut self, concurrency: usize) -> Self {
+        self.search_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Market (ISO 3166-1 alpha-2, e.g. `"US"`) for search, track, recommendation and
+    /// playlist requests. Spotify then relinks tracks unavailable there to a playable
+    /// version and sets [`Track::linked_from`](super::Track::linked_from) and
+    /// [`Track::is_playable`](super::Track::is_playable).
+    pub fn market(mut self, market: impl Into<String>) -> Self {
+        self.market = Some(market.into());
+        self
+    }
+
+    /// Spell search queries in ASCII (`Beyoncé` as `Beyonce`) after
+    /// [normalizing](super::normalize_query) them. Helps titles typed without accents;
+    /// leave it off for catalogs in non-Latin scripts.
+    pub fn transliterate_queries(mut self, transliterate: bool) -> Self {
+        self.transliterate_queries = transliterate;
+        self
+    }
+
+    /// Default and maximum page size and maximum offset for searches (default 20, 50 and
+    /// 1000, Spotify's standard limits). Raise them only with a Spotify quota that allows it.
+    pub fn search_limits(mut self, limits: SearchLimits) -> Self {
+        self.search_limits = limits;
+        self
+    }
+
+    /// `tenant` label on this client's metrics (default `default`), to tell apart clients
+    /// with different credentials in one process.
+    pub fn tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = tenant.into();
+        self
+    }
+
+    /// Report every request sent to Spotify to `audit`, e.g. for a compliance trail,
+    /// as well as to any set before. Share one between the clients of several tenants.
+    pub fn audit(mut self, audit: Arc<dyn UpstreamAudit>) -> Self {
+        self.audits.push(audit);
+        self
+    }
+
+    /// Tolerate schema drift in Spotify responses: count fields the client doesn't know,
+    /// and skip array elements that fail to decode instead of failing the request. Both
+    /// show per endpoint in [`upstream_status`](SpotifyClient::upstream_status) and as
+    /// `spotify_schema_drift_total`, and each new path is logged once.
+    pub fn lenient_schema(mut self, lenient: bool) -> Self {
+        self.lenient_schema = lenient;
+        self
+    }
+
+    /// Include the offending JSON, truncated, when logging schema drift. Catalog data
+    /// only; token responses are never decoded leniently.
+    pub fn schema_samples(mut self, samples: bool) -> Self {
+        self.schema_samples = samples;
+        self
+    }
+
+    /// Record upstream traffic to, or replay it from, a fixture file.
+    #[cfg(feature = "cassette")]
+    pub fn cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Build the client. Fails only if the built-in HTTP client cannot be created
+    /// (e.g. the TLS backend fails to initialise).
+    pub fn build(self) -> Result<SpotifyClient, reqwest::Error> {
+        let client = match self.http {
+            Some(client) => client,
+            None => {
+                let mut builder = Client::builder();
+                if let Some(t) = self.connect_timeout {
+                    builder = builder.connect_timeout(t);
+                }
+                if let Some(max) = self.pool_max_idle_per_host {
+                    builder = builder.pool_max_idle_per_host(max);
+                }
+                if let Some(t) = self.pool_idle_timeout {
+                    builder = builder.pool_idle_timeout(t);
+                }
+                if let Some(interval) = self.tcp_keepalive {
+                    builder = builder.tcp_keepalive(interval);
+                }
+                if self.http1_only {
+                    builder = builder.http1_only();
+                }
+                if let Some(proxy) = self.proxy {
+                    builder = builder.proxy(proxy);
+                }
+                builder.build()?
+            }
+        };
+        let token_provider = match (self.token_provider, self.credentials) {
+            (Some(provider), _) => provider,
+            (None, Some((id, secret))) => Arc::new(ClientCredentials::new(id, secret, self.token_url)),
+            (None, None) => unreachable!("builder always has credentials or a token provider"),
+        };
+        Ok(SpotifyClient {
+            client,
+            token_provider,
+            api_base: self.api_base,
+            user_agent: self.user_agent.unwrap_or_else(|| DEFAULT_USER_AGENT.into()),
+            timeout: self.timeout,
+            search_chunk_size: self.search_chunk_size,
+            search_concurrency: self.search_concurrency,
+            market: self.market,
+            transliterate_queries: self.transliterate_queries,
+            search_limits: self.search_limits,
+            token: Arc::new(ArcSwapOption::empty()),
+            token_refresh_failed: Arc::new(AtomicBool::new(false)),
+            last_refresh: Arc::default(),
+            upstream: Arc::new(UpstreamTracker::new(self.tenant.clone())),
+            tenant: self.tenant,
+            audits: self.audits,
+            lenient_schema: self.lenient_schema,
+            schema_samples: self.schema_samples,
+            #[cfg(feature = "cassette")]
+            cassette: self.cassette.map(Arc::new),
+        })
+    }
+}
@@ -0,0 +1,194 @@
+//! Record-and-replay of upstream Spotify traffic for deterministic tests.
+//!
+//! A [`Cassette`] in record mode sends requests as usual and appends each
+//! request/response pair to a JSON fixture file; in replay mode it answers from
+//! that file and never touches the network. Record once against the real API:
+//!
+//! ```no_run
+//! use spotify_search::spotify::{Cassette, SpotifyClient};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let spotify = SpotifyClient::builder("id", "secret")
+//!     .cassette(Cassette::record("tests/fixtures/search.json"))
+//!     .build()?;
+//! spotify.search_tracks("daft punk", Some(5), None).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! then replay it in CI with `Cassette::replay("tests/fixtures/search.json")?`
+//! and any credentials.
+//!
+//! Interactions are matched on method and URL; repeated requests are answered
+//! in recorded order, the last one repeating once exhausted. Authorization
+//! headers are never written, and access tokens in token responses are replaced
+//! with a placeholder.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use super::{SpotifyError, UpstreamResponse};
+
+/// Written in place of recorded access tokens.
+const REDACTED_TOKEN: &str = "cassette-redacted-token";
+
+/// Response headers worth keeping; the rest is CDN noise.
+const RECORDED_HEADERS: &[&str] = &[
+    "content-type",
+    "retry-after",
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset",
+];
+
+/// Recording or replaying upstream traffic; see the [module docs](self).
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    interactions: Mutex<Vec<Interaction>>,
+    /// Replay position per `method url`.
+    cursors: Mutex<HashMap<String, usize>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Record,
+    Replay,
+}
+
+/// One recorded request/response pair.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    url: String,
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    /// Send requests to Spotify and write every exchange to `path`, replacing
+    /// any existing file.
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self::with(path.into(), Mode::Record, Vec::new())
+    }
+
+    /// Answer requests from the cassette at `path` without network access.
+    pub fn replay(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let file: CassetteFile = serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(Self::with(path.to_path_buf(), Mode::Replay, file.interactions))
+    }
+
+    fn with(path: PathBuf, mode: Mode, interactions: Vec<Interaction>) -> Self {
+        Self {
+            path,
+            mode,
+            interactions: Mutex::new(interactions),
+            cursors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(super) fn is_replay(&self) -> bool {
+        self.mode == Mode::Replay
+    }
+
+    /// The recorded response for `method url`.
+    pub(super) fn play(&self, method: &str, url: &str) -> Result<UpstreamResponse, SpotifyError> {
+        let interactions = self.interactions.lock().unwrap();
+        let matches: Vec<&Interaction> = interactions
+            .iter()
+            .filter(|i| i.method == method && i.url == url)
+            .collect();
+        let Some(last) = matches.len().checked_sub(1) else {
+            return Err(SpotifyError::Cassette(format!(
+                "no interaction for {} {} in {}",
+                method,
+                url,
+                self.path.display()
+            )));
+        };
+        let mut cursors = self.cursors.lock().unwrap();
+        let cursor = cursors.entry(format!("{} {}", method, url)).or_insert(0);
+        let interaction = matches[(*cursor).min(last)];
+        *cursor += 1;
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &interaction.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::try_from(name.as_str()), HeaderValue::from_str(value)) {
+                headers.insert(name, value);
+            }
+        }
+        Ok(UpstreamResponse {
+            status: StatusCode::from_u16(interaction.status)
+                .map_err(|e| SpotifyError::Cassette(format!("bad recorded status: {}", e)))?,
+            headers,
+            body: interaction.body.clone().into_bytes(),
+        })
+    }
+
+    /// Append an exchange and rewrite the cassette file. Write failures are
+    /// logged rather than failing the request.
+    pub(super) fn record_interaction(&self, endpoint: &str, method: &str, url: &str, res: &UpstreamResponse) {
+        let headers = RECORDED_HEADERS
+            .iter()
+            .filter_map(|&name| {
+                let value = res.headers.get(name)?.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+        let mut body = String::from_utf8_lossy(&res.body).into_owned();
+        if endpoint == "token" {
+            body = redact_token(&body);
+        }
+
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.push(Interaction {
+            method: method.to_string(),
+            url: url.to_string(),
+            status: res.status.as_u16(),
+            headers,
+            body,
+        });
+        let file = CassetteFile {
+            interactions: interactions.clone(),
+        };
+        let written = serde_json::to_vec_pretty(&file)
+            .map_err(std::io::Error::from)
+            .and_then(|json| {
+                if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(&self.path, json)
+            });
+        if let Err(e) = written {
+            tracing::warn!(path = %self.path.display(), error = %e, "failed to write cassette");
+        }
+    }
+}
+
+/// Replace `access_token` / `refresh_token` in a token response body.
+fn redact_token(body: &str) -> String {
+    let Ok(mut json) = serde_json::from_str::<serde_json::Value>(body) else {
+        return body.to_string();
+    };
+    for key in ["access_token", "refresh_token"] {
+        if let Some(value) = json.get_mut(key) {
+            *value = REDACTED_TOKEN.into();
+        }
+    }
+    json.to_string()
+}
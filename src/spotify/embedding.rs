@@ -0,0 +1,137 @@
+//! Embedding versions.
+//!
+//! - `v1` (the default) is the 12-dim audio-feature vector from
+//!   [`AudioFeatures::to_embedding`](super::AudioFeatures::to_embedding).
+//! - `v3` appends [`GENRE_DIMS`] genre dimensions built from the genres of the track's
+//!   artists. Each genre is hashed to one dimension (multi-hot), and the component is
+//!   scaled to a fixed length, so two tracks that sound alike but belong to different
+//!   styles score lower than two that also share genres. Tracks whose artists have no
+//!   genres get an all-zero component, which leaves their scores to the audio features.
+//!
+//! Cosine similarity is only meaningful between embeddings of the same version.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use super::{rank_similar, ScoredTrack, SpotifyApi, SpotifyError, TrackWithFeatures};
+
+/// Genre dimensions appended by `v3`.
+pub const GENRE_DIMS: usize = 32;
+
+/// Similar-track candidates re-ranked by [`similar_tracks_v3`].
+pub const MAX_V3_CANDIDATES: u32 = 50;
+
+/// Length of a non-empty genre component. The audio-feature part is typically around
+/// 1.5 long, so genres shift a score without swamping it.
+const GENRE_WEIGHT: f32 = 1.0;
+
+/// Which embedding to compute.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmbeddingVersion {
+    /// Audio features only (12 dims).
+    #[default]
+    V1,
+    /// Audio features followed by hashed artist genres (12 + [`GENRE_DIMS`] dims).
+    V3,
+}
+
+impl EmbeddingVersion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EmbeddingVersion::V1 => "v1",
+            EmbeddingVersion::V3 => "v3",
+        }
+    }
+}
+
+impl FromStr for EmbeddingVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "v1" => Ok(EmbeddingVersion::V1),
+            "v3" => Ok(EmbeddingVersion::V3),
+            other => Err(format!("unknown embedding version '{}' (expected v1 or v3)", other)),
+        }
+    }
+}
+
+/// The `v3` genre component for `genres`: one hashed dimension per genre, scaled to
+/// [`GENRE_WEIGHT`]; all zeros without genres.
+pub fn genre_component<'a>(genres: impl IntoIterator<Item = &'a str>) -> [f32; GENRE_DIMS] {
+    let mut component = [0.0f32; GENRE_DIMS];
+    for genre in genres {
+        component[genre_bucket(genre)] = 1.0;
+    }
+    let norm = component.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        component.iter_mut().for_each(|x| *x *= GENRE_WEIGHT / norm);
+    }
+    component
+}
+
+/// FNV-1a of the lowercased genre, so buckets are stable across builds and platforms.
+fn genre_bucket(genre: &str) -> usize {
+    let hash = genre
+        .trim()
+        .bytes()
+        .map(|b| b.to_ascii_lowercase())
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+    (hash % GENRE_DIMS as u64) as usize
+}
+
+/// Extend the embeddings of `tracks` to `v3`, looking their artists' genres up on
+/// Spotify. Tracks without an embedding are left alone. If the artists can't be fetched,
+/// the genre components are all zeros.
+pub async fn add_genres(spotify: &dyn SpotifyApi, tracks: &mut [TrackWithFeatures]) {
+    let mut ids: Vec<String> = tracks
+        .iter()
+        .filter(|t| t.embedding.is_some())
+        .flat_map(|t| t.track.artists.iter().filter_map(|a| a.id.clone()))
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let genres: HashMap<String, Vec<String>> = match spotify.get_artists(&ids).await {
+        Ok(artists) => artists.into_iter().flatten().map(|a| (a.id, a.genres)).collect(),
+        Err(e) => {
+            tracing::warn!("fetching artist genres for v3 embeddings failed, leaving them out: {}", e);
+            HashMap::new()
+        }
+    };
+    for t in tracks {
+        let Some(embedding) = &mut t.embedding else { continue };
+        let track_genres = t
+            .track
+            .artists
+            .iter()
+            .filter_map(|a| genres.get(a.id.as_deref()?))
+            .flatten()
+            .map(String::as_str);
+        embedding.extend_from_slice(&genre_component(track_genres));
+    }
+}
+
+/// Tracks similar to `id` ranked by `v3` embeddings: the best
+/// [`MAX_V3_CANDIDATES`] matches by audio features, re-ranked with genres.
+pub async fn similar_tracks_v3(
+    spotify: &dyn SpotifyApi,
+    id: &str,
+    limit: Option<u32>,
+) -> Result<Vec<ScoredTrack>, SpotifyError> {
+    let seed_ids = [id.to_string()];
+    let (seed, candidates) = tokio::join!(
+        spotify.get_tracks_with_features(&seed_ids),
+        spotify.get_similar_tracks(id, Some(MAX_V3_CANDIDATES)),
+    );
+    let seed = seed?
+        .into_iter()
+        .next()
+        .ok_or_else(|| SpotifyError::NotFound(format!("track {}", id)))?;
+    let mut tracks: Vec<TrackWithFeatures> = std::iter::once(seed)
+        .chain(candidates?.into_iter().map(|s| s.track))
+        .collect();
+    add_genres(spotify, &mut tracks).await;
+    let seed = tracks.remove(0);
+    rank_similar(&seed, tracks, limit.unwrap_or(20).clamp(1, 50) as usize)
+}
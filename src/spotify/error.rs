@@ -0,0 +1,54 @@
+//! Errors returned by [`SpotifyClient`](super::SpotifyClient).
+
+use reqwest::StatusCode;
+
+/// Failure talking to the Spotify Web API.
+#[derive(Debug, thiserror::Error)]
+pub enum SpotifyError {
+    /// Token request rejected, or the API answered 401/403.
+    #[error("Spotify authentication failed: {0}")]
+    Auth(String),
+    /// Spotify answered 429; `retry_after` is in seconds when provided.
+    #[error("Spotify rate limit exceeded{}", .retry_after.map(|s| format!(" (retry after {}s)", s)).unwrap_or_default())]
+    RateLimited { retry_after: Option<u64> },
+    /// Spotify answered 404.
+    #[error("not found on Spotify: {0}")]
+    NotFound(String),
+    /// Any other non-success response.
+    #[error("Spotify API error {status}: {body}")]
+    Upstream { status: u16, body: String },
+    /// Response body could not be decoded.
+    #[error("{endpoint} parse failed: {message}")]
+    Decode { endpoint: &'static str, message: String },
+    /// Request never got a response (DNS, connect, TLS, timeout).
+    #[error("{endpoint} request failed: {source}")]
+    Network {
+        endpoint: &'static str,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// Spotify is deliberately not being called (offline mode) or is down, and the
+    /// local store can't answer.
+    #[error("Spotify unavailable: {0}")]
+    Unavailable(String),
+    /// A replaying cassette has no recorded response for the request.
+    #[error("cassette: {0}")]
+    Cassette(String),
+}
+
+impl SpotifyError {
+    /// Classify a non-success API response.
+    pub(crate) fn from_status(status: StatusCode, body: String, retry_after: Option<u64>) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Auth(format!("{}: {}", status, body)),
+            StatusCode::NOT_FOUND => Self::NotFound(body),
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited { retry_after },
+            _ => Self::Upstream { status: status.as_u16(), body },
+        }
+    }
+
+    /// True if the request timed out waiting for Spotify.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::Network { source, .. } if source.is_timeout())
+    }
+}
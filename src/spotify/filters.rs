@@ -0,0 +1,75 @@
+//! Search filters in Spotify's query syntax.
+//!
+//! Spotify narrows a search with `field:value` terms inside `q`. [`SearchFilters`]
+//! appends them for callers, so `year=1990-1999` becomes `year:1990-1999` and new
+//! releases become `tag:new`.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Release years, inclusive: `1990` or `1990-1999`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct YearRange {
+    pub from: u16,
+    pub to: u16,
+}
+
+impl fmt::Display for YearRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.from == self.to {
+            write!(f, "{}", self.from)
+        } else {
+            write!(f, "{}-{}", self.from, self.to)
+        }
+    }
+}
+
+impl FromStr for YearRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let year = |part: &str| {
+            let part = part.trim();
+            if part.len() == 4 && part.bytes().all(|b| b.is_ascii_digit()) {
+                part.parse::<u16>().ok()
+            } else {
+                None
+            }
+        };
+        let (from, to) = match s.split_once('-') {
+            Some((from, to)) => (year(from), year(to)),
+            None => (year(s), year(s)),
+        };
+        match (from, to) {
+            (Some(from), Some(to)) if from <= to => Ok(YearRange { from, to }),
+            (Some(_), Some(_)) => Err(format!("must not end before it starts (got '{}')", s.trim())),
+            _ => Err(format!("must be a year or a range of years, e.g. 1990-1999 (got '{}')", s.trim())),
+        }
+    }
+}
+
+/// Filters added to a track search.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchFilters {
+    /// Tracks released in these years (`year:`).
+    pub year: Option<YearRange>,
+    /// Tracks on albums released in the past two weeks (`tag:new`).
+    pub new_releases_only: bool,
+}
+
+impl SearchFilters {
+    /// `q` with the filters appended.
+    pub fn apply(&self, q: &str) -> String {
+        let mut terms: Vec<String> = Vec::new();
+        if !q.trim().is_empty() {
+            terms.push(q.trim().to_string());
+        }
+        if let Some(year) = self.year {
+            terms.push(format!("year:{}", year));
+        }
+        if self.new_releases_only {
+            terms.push("tag:new".to_string());
+        }
+        terms.join(" ")
+    }
+}
@@ -0,0 +1,41 @@
+//! Metrics, access-log stats and trace propagation for outbound Spotify calls.
+
+use std::time::Duration;
+
+use opentelemetry::propagation::Injector;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Record one upstream call: counters/histogram (`metrics` feature) and the request's access-log stats.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(super) fn record_call(tenant: &str, endpoint: &'static str, status: &str, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        let labels = [
+            ("tenant", tenant.to_string()),
+            ("endpoint", endpoint.to_string()),
+            ("status", status.to_string()),
+        ];
+        metrics::counter!("spotify_requests_total", &labels).increment(1);
+        metrics::histogram!("spotify_request_duration_seconds", &labels).record(elapsed.as_secs_f64());
+    }
+    crate::access_log::record_upstream(elapsed);
+}
+
+/// Inject the current span's context into outbound request headers.
+pub(super) fn inject_trace_context(headers: &mut reqwest::header::HeaderMap) {
+    let cx = tracing::Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|p| p.inject_context(&cx, &mut HeaderInjector(headers)));
+}
+
+struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
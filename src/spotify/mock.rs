@@ -0,0 +1,554 @@
+//! In-memory [`SpotifyApi`] for tests and `SPOTIFY_MOCK` local development (`mock` feature).
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::Deserialize;
+
+use super::{
+    normalize_query, rank_similar, Album, AlbumDetail, AlbumExternalIds, AlbumTracksPage, Artist, ArtistAlbumsPage,
+    ArtistDetail, AudioFeatures, ByteStream, Copyright, ExternalIds, ExternalUrls, Followers, PlaylistTracksPage,
+    RecommendationSeeds, ScoredTrack, SearchTracksResponse, SearchTracksWithFeaturesResponse, SpotifyApi, SpotifyError,
+    TokenStatus, Track, TrackWithFeatures, UpstreamSnapshot,
+};
+
+type ErrorFn = Box<dyn Fn() -> SpotifyError + Send + Sync>;
+
+/// Deterministic catalog served in `SPOTIFY_MOCK` mode.
+const BUNDLED_CATALOG: &str = include_str!("../../fixtures/mock_catalog.json");
+
+/// Fixture file shape: Spotify-format track and audio-features objects.
+#[derive(Deserialize)]
+struct Fixture {
+    tracks: Vec<Track>,
+    #[serde(default)]
+    audio_features: Vec<AudioFeatures>,
+    /// Track IDs returned by recommendation calls.
+    #[serde(default)]
+    recommendations: Vec<String>,
+    /// Track IDs per playlist ID; IDs not in `tracks` stand for unavailable items.
+    #[serde(default)]
+    playlists: HashMap<String, Vec<String>>,
+    /// Genres per artist ID.
+    #[serde(default)]
+    artist_genres: HashMap<String, Vec<String>>,
+}
+
+/// Canned Spotify catalog for handler tests; never touches the network.
+///
+/// Search matches track and artist names case-insensitively (honouring `track:"..."`
+/// and `artist:"..."` filters); recommendations return
+/// the configured IDs; albums and artists are those of the catalog's tracks; every
+/// call is recorded for assertions.
+///
+/// ```
+/// use spotify_search::spotify::{MockSpotifyApi, SpotifyApi};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mock = MockSpotifyApi::new()
+///     .with_track(MockSpotifyApi::track("4uLU6hMCjMI75M1A2tKUQC", "Never Gonna Give You Up"))
+///     .with_audio_features(MockSpotifyApi::audio_features("4uLU6hMCjMI75M1A2tKUQC"));
+/// let found = mock.search_tracks("never gonna", None, None).await.unwrap();
+/// assert_eq!(found.total, 1);
+/// assert_eq!(mock.calls(), ["search"]);
+/// # }
+/// ```
+#[derive(Default)]
+pub struct MockSpotifyApi {
+    /// In insertion order, which is also search result order.
+    tracks: Vec<Track>,
+    features: HashMap<String, AudioFeatures>,
+    recommendations: Vec<String>,
+    playlists: HashMap<String, Vec<String>>,
+    artist_genres: HashMap<String, Vec<String>>,
+    error: Option<ErrorFn>,
+    no_token: bool,
+    calls: Mutex<Vec<&'static str>>,
+}
+
+impl MockSpotifyApi {
+    /// An empty catalog that reports a valid token.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Catalog loaded from fixture JSON (`tracks`, `audio_features`, `recommendations`,
+    /// `playlists`, `artist_genres`).
+    pub fn from_fixture(json: &str) -> Result<Self, serde_json::Error> {
+        let fixture: Fixture = serde_json::from_str(json)?;
+        let mock = fixture
+            .audio_features
+            .into_iter()
+            .fold(Self::new(), Self::with_audio_features)
+            .with_recommendations(fixture.recommendations);
+        let mock = fixture
+            .playlists
+            .into_iter()
+            .fold(mock, |mock, (id, tracks)| mock.with_playlist(id, tracks));
+        let mock = fixture
+            .artist_genres
+            .into_iter()
+            .fold(mock, |mock, (id, genres)| mock.with_artist_genres(id, genres));
+        Ok(fixture.tracks.into_iter().fold(mock, Self::with_track))
+    }
+
+    /// The bundled fixture catalog (`fixtures/mock_catalog.json`): 12 tracks, one
+    /// without audio features, and playlist `MockPlaylist0000000001` holding all of them
+    /// plus one unavailable item.
+    pub fn bundled() -> Self {
+        Self::from_fixture(BUNDLED_CATALOG).expect("bundled mock catalog is valid")
+    }
+
+    /// Add a track to the catalog.
+    pub fn with_track(mut self, track: Track) -> Self {
+        self.tracks.push(track);
+        self
+    }
+
+    /// Add audio features, keyed by `features.id`.
+    pub fn with_audio_features(mut self, features: AudioFeatures) -> Self {
+        let id = features.id.clone().unwrap_or_default();
+        self.features.insert(id, features);
+        self
+    }
+
+    /// Track IDs returned, in order, by recommendation (and similar-track) calls.
+    pub fn with_recommendations<I, S>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.recommendations = ids.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// A playlist of `track_ids`, in order. IDs not in the catalog are served as
+    /// unavailable items.
+    pub fn with_playlist<I, S>(mut self, id: impl Into<String>, track_ids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.playlists.insert(id.into(), track_ids.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Genres of artist `id`, as artist lookups return them.
+    pub fn with_artist_genres<I, S>(mut self, id: impl Into<String>, genres: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.artist_genres.insert(id.into(), genres.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Fail every Spotify call with the error built by `f`.
+    pub fn failing_with(mut self, f: impl Fn() -> SpotifyError + Send + Sync + 'static) -> Self {
+        self.error = Some(Box::new(f));
+        self
+    }
+
+    /// Report no token, as before the first successful token fetch.
+    pub fn without_token(mut self) -> Self {
+        self.no_token = true;
+        self
+    }
+
+    /// Names of the calls made so far (`"search"`, `"tracks"`, ...), in order.
+    pub fn calls(&self) -> Vec<&'static str> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// A minimal track with one artist and album.
+    pub fn track(id: &str, name: &str) -> Track {
+        Track {
+            id: id.to_string(),
+            name: name.to_string(),
+            uri: format!("spotify:track:{}", id),
+            duration_ms: 200_000,
+            explicit: false,
+            popularity: 50,
+            artists: vec![Artist {
+                id: None,
+                name: "Mock Artist".into(),
+                external_urls: ExternalUrls::default(),
+            }],
+            album: Album {
+                name: "Mock Album".into(),
+                ..Default::default()
+            },
+            external_ids: ExternalIds::default(),
+            external_urls: ExternalUrls {
+                spotify: Some(format!("https://open.spotify.com/track/{}", id)),
+            },
+            linked_from: None,
+            is_playable: None,
+            available_markets: Vec::new(),
+        }
+    }
+
+    /// Mid-range audio features for `id`; adjust fields to shape similarity scores.
+    pub fn audio_features(id: &str) -> AudioFeatures {
+        AudioFeatures {
+            id: Some(id.to_string()),
+            acousticness: 0.5,
+            danceability: 0.5,
+            energy: 0.5,
+            instrumentalness: 0.5,
+            key: 5,
+            liveness: 0.5,
+            loudness: -10.0,
+            mode: 1,
+            speechiness: 0.5,
+            tempo: 120.0,
+            time_signature: 4,
+            valence: 0.5,
+        }
+    }
+
+    fn call(&self, name: &'static str) -> Result<(), SpotifyError> {
+        self.calls.lock().unwrap().push(name);
+        match &self.error {
+            Some(f) => Err(f()),
+            None => Ok(()),
+        }
+    }
+
+    fn find(&self, id: &str) -> Option<Track> {
+        self.tracks.iter().find(|t| t.id == id).cloned()
+    }
+
+    fn with_features(&self, tracks: Vec<Track>) -> Vec<TrackWithFeatures> {
+        tracks
+            .into_iter()
+            .map(|track| {
+                let audio_features = self.features.get(&track.id).cloned();
+                let embedding = audio_features.as_ref().map(AudioFeatures::to_embedding);
+                TrackWithFeatures {
+                    track,
+                    audio_features,
+                    embedding,
+                }
+            })
+            .collect()
+    }
+
+    /// The album of the catalog's tracks with ID `id`, with placeholder label, release
+    /// date and copyrights.
+    fn album(&self, id: &str) -> Option<AlbumDetail> {
+        let tracks: Vec<&Track> = self.tracks.iter().filter(|t| t.album.id.as_deref() == Some(id)).collect();
+        let first = tracks.first()?;
+        let copyright = |kind: &str| Copyright {
+            text: "2020 Mock Records".into(),
+            kind: kind.into(),
+        };
+        Some(AlbumDetail {
+            id: id.to_string(),
+            name: first.album.name.clone(),
+            album_type: "album".into(),
+            artists: first.artists.clone(),
+            label: Some("Mock Records".into()),
+            release_date: "2020-01-01".into(),
+            release_date_precision: "day".into(),
+            genres: Vec::new(),
+            copyrights: vec![copyright("C"), copyright("P")],
+            total_tracks: tracks.len() as u32,
+            images: first.album.images.clone(),
+            popularity: tracks.iter().map(|t| t.popularity).max().unwrap_or_default(),
+            external_ids: AlbumExternalIds::default(),
+            external_urls: first.album.external_urls.clone(),
+        })
+    }
+
+    /// The artist with ID `id` among the catalog's tracks, as popular as their most
+    /// popular track.
+    fn artist(&self, id: &str) -> Option<ArtistDetail> {
+        let tracks: Vec<&Track> = self
+            .tracks
+            .iter()
+            .filter(|t| t.artists.iter().any(|a| a.id.as_deref() == Some(id)))
+            .collect();
+        let artist = tracks.first()?.artists.iter().find(|a| a.id.as_deref() == Some(id))?;
+        Some(ArtistDetail {
+            id: id.to_string(),
+            name: artist.name.clone(),
+            genres: self.artist_genres.get(id).cloned().unwrap_or_default(),
+            popularity: tracks.iter().map(|t| t.popularity).max().unwrap_or_default(),
+            followers: Followers::default(),
+            images: Vec::new(),
+            external_urls: artist.external_urls.clone(),
+        })
+    }
+
+    fn recommended(&self, limit: usize) -> Vec<TrackWithFeatures> {
+        let tracks = self.recommendations.iter().filter_map(|id| self.find(id)).take(limit).collect();
+        self.with_features(tracks)
+    }
+}
+
+/// Search string split into Spotify's `track:"..."` and `artist:"..."` field filters
+/// and the remaining free text, all lowercased. `year:` and `tag:` filters are dropped,
+/// as mock tracks have no release dates.
+struct MockQuery {
+    text: String,
+    track: Option<String>,
+    artist: Option<String>,
+}
+
+impl MockQuery {
+    fn parse(q: &str) -> Self {
+        let mut text = q.to_lowercase();
+        let mut filter = |field: &str| {
+            let prefix = format!("{}:\"", field);
+            let start = text.find(&prefix)?;
+            let len = text[start + prefix.len()..].find('"')?;
+            let value = text[start + prefix.len()..start + prefix.len() + len].to_string();
+            text.replace_range(start..start + prefix.len() + len + 1, "");
+            Some(value)
+        };
+        let track = filter("track");
+        let artist = filter("artist");
+        let text = text
+            .split_whitespace()
+            .filter(|term| !term.starts_with("year:") && !term.starts_with("tag:"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self {
+            text,
+            track,
+            artist,
+        }
+    }
+
+    /// Free text matches the track or an artist name; filters match their field.
+    fn matches(&self, t: &Track) -> bool {
+        let name = t.name.to_lowercase();
+        let artist_has = |s: &str| t.artists.iter().any(|a| a.name.to_lowercase().contains(s));
+        (self.text.is_empty() || name.contains(&self.text) || artist_has(&self.text))
+            && self.track.as_deref().is_none_or(|s| name.contains(s))
+            && self.artist.as_deref().is_none_or(artist_has)
+    }
+}
+
+#[async_trait]
+impl SpotifyApi for MockSpotifyApi {
+    async fn has_token(&self) -> bool {
+        !self.no_token
+    }
+
+    fn upstream_status(&self) -> UpstreamSnapshot {
+        UpstreamSnapshot {
+            throttled: false,
+            endpoints: BTreeMap::new(),
+        }
+    }
+
+    fn token_status(&self) -> TokenStatus {
+        TokenStatus {
+            cached: !self.no_token,
+            ..TokenStatus::default()
+        }
+    }
+
+    async fn refresh_token(&self) -> Result<(), SpotifyError> {
+        if self.no_token {
+            return Err(SpotifyError::Auth("mock has no token".into()));
+        }
+        Ok(())
+    }
+
+    async fn search_tracks(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksResponse, SpotifyError> {
+        self.call("search")?;
+        let limit = limit.unwrap_or(20).clamp(1, 50);
+        let offset = offset.unwrap_or(0).min(1000);
+        let query = MockQuery::parse(&normalize_query(q, false));
+        let matches: Vec<&Track> = self.tracks.iter().filter(|t| query.matches(t)).collect();
+        Ok(SearchTracksResponse {
+            tracks: matches.iter().skip(offset as usize).take(limit as usize).map(|t| (*t).clone()).collect(),
+            total: matches.len() as u32,
+            limit,
+            offset,
+        })
+    }
+
+    async fn search_tracks_with_features(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksWithFeaturesResponse, SpotifyError> {
+        let result = self.search_tracks(q, limit, offset).await?;
+        self.call("audio-features")?;
+        Ok(SearchTracksWithFeaturesResponse {
+            tracks: self.with_features(result.tracks),
+            total: result.total,
+            limit: result.limit,
+            offset: result.offset,
+        })
+    }
+
+    /// The catalog search, encoded like Spotify's `/search` response.
+    async fn search_tracks_raw(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ByteStream, SpotifyError> {
+        let result = self.search_tracks(q, limit, offset).await?;
+        let body = serde_json::json!({
+            "tracks": {
+                "items": result.tracks,
+                "total": result.total,
+                "limit": result.limit,
+                "offset": result.offset,
+            }
+        });
+        Ok(single_chunk(body))
+    }
+
+    async fn get_tracks(&self, ids: &[String]) -> Result<Vec<Option<Track>>, SpotifyError> {
+        self.call("tracks")?;
+        Ok(ids.iter().take(50).map(|id| self.find(id)).collect())
+    }
+
+    async fn get_tracks_raw(&self, ids: &[String]) -> Result<ByteStream, SpotifyError> {
+        let tracks = self.get_tracks(ids).await?;
+        Ok(single_chunk(serde_json::json!({ "tracks": tracks })))
+    }
+
+    async fn get_audio_features(&self, ids: &[String]) -> Result<Vec<Option<AudioFeatures>>, SpotifyError> {
+        self.call("audio-features")?;
+        Ok(ids.iter().take(100).map(|id| self.features.get(id).cloned()).collect())
+    }
+
+    async fn get_tracks_with_features(&self, ids: &[String]) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        let tracks = self.get_tracks(ids).await?;
+        self.call("audio-features")?;
+        Ok(self.with_features(tracks.into_iter().flatten().collect()))
+    }
+
+    async fn get_recommendations_with_features(
+        &self,
+        _seeds: &RecommendationSeeds,
+        limit: Option<u32>,
+    ) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        self.call("recommendations")?;
+        Ok(self.recommended(limit.unwrap_or(20).clamp(1, super::MAX_RECOMMENDATIONS) as usize))
+    }
+
+    async fn get_similar_tracks(&self, id: &str, limit: Option<u32>) -> Result<Vec<ScoredTrack>, SpotifyError> {
+        let seed = self
+            .get_tracks_with_features(&[id.to_string()])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| SpotifyError::NotFound(format!("track {}", id)))?;
+        self.call("recommendations")?;
+        let candidates = self.recommended(super::MAX_RECOMMENDATIONS as usize);
+        rank_similar(&seed, candidates, limit.unwrap_or(20).clamp(1, 50) as usize)
+    }
+
+    async fn get_playlist_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<PlaylistTracksPage, SpotifyError> {
+        self.call("playlist-tracks")?;
+        let items = self
+            .playlists
+            .get(id)
+            .ok_or_else(|| SpotifyError::NotFound(format!("playlist {}", id)))?;
+        let limit = limit.unwrap_or(super::MAX_PLAYLIST_PAGE).clamp(1, super::MAX_PLAYLIST_PAGE);
+        let offset = offset.unwrap_or(0);
+        let page: Vec<&String> = items.iter().skip(offset as usize).take(limit as usize).collect();
+        let tracks: Vec<Track> = page.iter().filter_map(|id| self.find(id)).collect();
+        Ok(PlaylistTracksPage {
+            skipped: (page.len() - tracks.len()) as u32,
+            tracks,
+            total: items.len() as u32,
+            limit,
+            offset,
+        })
+    }
+
+    async fn get_artist_albums(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ArtistAlbumsPage, SpotifyError> {
+        self.call("artist-albums")?;
+        self.artist(id).ok_or_else(|| SpotifyError::NotFound(format!("artist {}", id)))?;
+        let mut ids: Vec<&str> = Vec::new();
+        for track in self.tracks.iter().filter(|t| t.artists.iter().any(|a| a.id.as_deref() == Some(id))) {
+            if let Some(album) = track.album.id.as_deref().filter(|album| !ids.contains(album)) {
+                ids.push(album);
+            }
+        }
+        let limit = limit.unwrap_or(super::MAX_ARTIST_ALBUMS_PAGE).clamp(1, super::MAX_ARTIST_ALBUMS_PAGE);
+        let offset = offset.unwrap_or(0);
+        Ok(ArtistAlbumsPage {
+            albums: ids.iter().skip(offset as usize).take(limit as usize).filter_map(|id| self.album(id)).collect(),
+            total: ids.len() as u32,
+            limit,
+            offset,
+        })
+    }
+
+    async fn get_album_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<AlbumTracksPage, SpotifyError> {
+        self.call("album-tracks")?;
+        let tracks: Vec<&Track> = self.tracks.iter().filter(|t| t.album.id.as_deref() == Some(id)).collect();
+        if tracks.is_empty() {
+            return Err(SpotifyError::NotFound(format!("album {}", id)));
+        }
+        let limit = limit.unwrap_or(super::MAX_ALBUM_TRACKS_PAGE).clamp(1, super::MAX_ALBUM_TRACKS_PAGE);
+        let offset = offset.unwrap_or(0);
+        Ok(AlbumTracksPage {
+            tracks: tracks.iter().skip(offset as usize).take(limit as usize).map(|&t| t.clone()).collect(),
+            total: tracks.len() as u32,
+            limit,
+            offset,
+        })
+    }
+
+    async fn get_album(&self, id: &str) -> Result<AlbumDetail, SpotifyError> {
+        self.call("album")?;
+        self.album(id).ok_or_else(|| SpotifyError::NotFound(format!("album {}", id)))
+    }
+
+    async fn get_artist(&self, id: &str) -> Result<ArtistDetail, SpotifyError> {
+        self.call("artist")?;
+        self.artist(id).ok_or_else(|| SpotifyError::NotFound(format!("artist {}", id)))
+    }
+
+    async fn get_albums(&self, ids: &[String]) -> Result<Vec<Option<AlbumDetail>>, SpotifyError> {
+        self.call("albums")?;
+        Ok(ids.iter().map(|id| self.album(id)).collect())
+    }
+
+    async fn get_artists(&self, ids: &[String]) -> Result<Vec<Option<ArtistDetail>>, SpotifyError> {
+        self.call("artists")?;
+        Ok(ids.iter().map(|id| self.artist(id)).collect())
+    }
+}
+
+/// `body` encoded as a one-chunk [`ByteStream`].
+fn single_chunk(body: serde_json::Value) -> ByteStream {
+    let body = bytes::Bytes::from(body.to_string());
+    futures::stream::once(async move { Ok(body) }).boxed()
+}
@@ -2,122 +2,419 @@
 //!
 //! Uses Client Credentials flow for server-to-server authentication.
 
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-use base64::Engine;
+use arc_swap::ArcSwapOption;
+use futures::{StreamExt, TryStreamExt};
 use reqwest::Client;
-use serde::Deserialize;
-use tokio::sync::RwLock;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
-const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
-const API_BASE: &str = "https://api.spotify.com/v1";
+use crate::clock::unix_now;
+use crate::secret::Secret;
+
+mod api;
+mod audit;
+mod builder;
+#[cfg(feature = "cassette")]
+mod cassette;
+mod embedding;
+mod error;
+mod filters;
+mod instrument;
+#[cfg(feature = "mock")]
+mod mock;
+mod query;
+mod rate_limit;
+mod schema;
+mod similarity;
+mod token;
+
+pub use api::{DynSpotifyApi, SpotifyApi};
+pub use audit::{AuditRecord, UpstreamAudit, UpstreamCall};
+pub use builder::{
+    SpotifyClientBuilder, DEFAULT_API_BASE, DEFAULT_SEARCH_CHUNK_SIZE, DEFAULT_SEARCH_CONCURRENCY, DEFAULT_TENANT,
+    DEFAULT_TOKEN_URL, DEFAULT_USER_AGENT,
+};
+#[cfg(feature = "cassette")]
+pub use cassette::Cassette;
+pub use embedding::{
+    add_genres, genre_component, similar_tracks_v3, EmbeddingVersion, GENRE_DIMS, MAX_V3_CANDIDATES,
+};
+pub use error::SpotifyError;
+pub use filters::{SearchFilters, YearRange};
+#[cfg(feature = "mock")]
+pub use mock::MockSpotifyApi;
+pub use query::normalize_query;
+pub use rate_limit::UpstreamSnapshot;
+pub use similarity::{cosine_similarity, cosine_similarity_batch};
+pub use token::{
+    AccessToken, ClientCredentials, ExternalToken, RefreshToken, StaticToken, TokenHttp, TokenProvider, TokenResponse,
+    TokenStatus,
+};
+use rate_limit::{RateLimitInfo, UpstreamTracker};
+
+/// Unix seconds and error of a token refresh.
+type LastRefresh = (u64, Option<String>);
+
+/// A Spotify response body, handed over chunk by chunk as it arrives.
+pub type ByteStream = futures::stream::BoxStream<'static, Result<bytes::Bytes, SpotifyError>>;
 
 /// Spotify API client with token caching.
 #[derive(Clone)]
 pub struct SpotifyClient {
     client: Client,
-    client_id: String,
-    client_secret: String,
-    token: Arc<RwLock<Option<CachedToken>>>,
+    token_provider: Arc<dyn TokenProvider>,
+    api_base: String,
+    user_agent: String,
+    /// Per-request timeout; `None` leaves it to the HTTP client.
+    timeout: Option<Duration>,
+    /// Tracks per search request in `search_tracks_with_features`.
+    search_chunk_size: u32,
+    /// Search chunks in flight at once in `search_tracks_with_features`.
+    search_concurrency: usize,
+    /// ISO 3166-1 alpha-2 market sent with track requests, so Spotify relinks tracks to
+    /// versions playable there.
+    market: Option<String>,
+    /// Spell search queries in ASCII after normalizing them.
+    transliterate_queries: bool,
+    /// Page size and offset bounds for searches.
+    search_limits: SearchLimits,
+    /// Swapped whole on refresh, so reading it on every request takes no lock.
+    token: Arc<ArcSwapOption<CachedToken>>,
+    /// Set when the most recent token refresh failed.
+    token_refresh_failed: Arc<AtomicBool>,
+    last_refresh: Arc<Mutex<Option<LastRefresh>>>,
+    /// `tenant` label on metrics.
+    tenant: String,
+    upstream: Arc<UpstreamTracker>,
+    /// Told about every upstream request.
+    audits: Vec<Arc<dyn UpstreamAudit>>,
+    /// Decode responses with [`schema::decode_lenient`], counting schema drift.
+    lenient_schema: bool,
+    /// Log the offending JSON with schema drift.
+    schema_samples: bool,
+    /// Records or replays upstream traffic.
+    #[cfg(feature = "cassette")]
+    cassette: Option<Arc<Cassette>>,
+}
+
+/// Upstream response with the body read, so it can be recorded or replayed.
+struct UpstreamResponse {
+    status: reqwest::StatusCode,
+    headers: reqwest::header::HeaderMap,
+    body: Vec<u8>,
+}
+
+impl UpstreamResponse {
+    fn retry_after(&self) -> Option<u64> {
+        RateLimitInfo::from_headers(&self.headers).retry_after
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    fn json<T: DeserializeOwned>(&self, endpoint: &'static str) -> Result<T, SpotifyError> {
+        serde_json::from_slice(&self.body).map_err(|e| SpotifyError::Decode {
+            endpoint,
+            message: e.to_string(),
+        })
+    }
 }
 
-#[derive(Clone)]
 struct CachedToken {
-    access_token: String,
-    expires_at: std::time::Instant,
+    access_token: Secret,
+    /// `None` if the token never expires.
+    expires_at: Option<std::time::Instant>,
 }
 
 impl SpotifyClient {
+    /// Client with default endpoints and HTTP settings.
+    ///
+    /// # Panics
+    ///
+    /// Like `reqwest::Client::new`, if the TLS backend cannot be initialised.
     pub fn new(client_id: String, client_secret: String) -> Self {
-        Self {
-            client: Client::new(),
-            client_id,
-            client_secret,
-            token: Arc::new(RwLock::new(None)),
+        Self::builder(client_id, client_secret)
+            .build()
+            .expect("failed to build HTTP client")
+    }
+
+    /// Configure the HTTP client, endpoints, user-agent and timeouts (Client Credentials auth).
+    pub fn builder(client_id: impl Into<String>, client_secret: impl Into<String>) -> SpotifyClientBuilder {
+        SpotifyClientBuilder::new(Some((client_id.into(), client_secret.into())))
+    }
+
+    /// Like [`builder`](Self::builder) with tokens from `provider` instead of Client Credentials.
+    pub fn builder_with_token_provider(provider: impl TokenProvider + 'static) -> SpotifyClientBuilder {
+        SpotifyClientBuilder::new(None).token_provider(provider)
+    }
+
+    /// Apply the per-request user-agent and timeout.
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let req = self
+            .client
+            .request(method, url)
+            .header(reqwest::header::USER_AGENT, &self.user_agent);
+        match self.timeout {
+            Some(t) => req.timeout(t),
+            None => req,
         }
     }
 
-    /// Ensures we have a valid access token, refreshing if needed.
-    async fn ensure_token(&self) -> Result<String, String> {
-        {
-            let guard = self.token.read().await;
-            if let Some(ref t) = *guard {
-                if t.expires_at > std::time::Instant::now() {
-                    return Ok(t.access_token.clone());
-                }
+    /// Send a request, read the body, and record metrics and rate-limit state.
+    /// `endpoint` labels metrics and error messages.
+    async fn send(&self, endpoint: &'static str, req: reqwest::RequestBuilder) -> Result<UpstreamResponse, SpotifyError> {
+        let network = |source| SpotifyError::Network { endpoint, source };
+        let req = req.build().map_err(network)?;
+
+        #[cfg(feature = "cassette")]
+        let (method, url) = (req.method().to_string(), req.url().to_string());
+        #[cfg(feature = "cassette")]
+        if let Some(cassette) = self.cassette.as_ref().filter(|c| c.is_replay()) {
+            let res = cassette.play(&method, &url)?;
+            instrument::record_call(&self.tenant, endpoint, res.status.as_str(), Duration::ZERO);
+            self.upstream.record(endpoint, res.status, &res.headers);
+            return Ok(res);
+        }
+
+        let res = self.execute(endpoint, req).await?;
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = res.bytes().await.map_err(network)?.to_vec();
+        let res = UpstreamResponse { status, headers, body };
+
+        #[cfg(feature = "cassette")]
+        if let Some(cassette) = &self.cassette {
+            cassette.record_interaction(endpoint, &method, &url, &res);
+        }
+        Ok(res)
+    }
+
+    /// Send a request and record metrics and rate-limit state once the response headers
+    /// arrive, leaving the body unread.
+    async fn execute(&self, endpoint: &'static str, req: reqwest::Request) -> Result<reqwest::Response, SpotifyError> {
+        let audit = (!self.audits.is_empty()).then(|| (SystemTime::now(), req.method().clone(), req.url().clone()));
+        let start = std::time::Instant::now();
+        let result = self.client.execute(req).await;
+        if let Some((at, method, url)) = audit {
+            let path_and_query = match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            };
+            let call = UpstreamCall {
+                at,
+                tenant: &self.tenant,
+                endpoint,
+                method: method.as_str(),
+                path_and_query: &path_and_query,
+                status: result.as_ref().ok().map(|res| res.status().as_u16()),
+                latency: start.elapsed(),
+            };
+            for audit in &self.audits {
+                audit.record(&call);
             }
         }
+        let res = result.map_err(|source| {
+            instrument::record_call(&self.tenant, endpoint, "error", start.elapsed());
+            SpotifyError::Network { endpoint, source }
+        })?;
+        instrument::record_call(&self.tenant, endpoint, res.status().as_str(), start.elapsed());
+        self.upstream.record(endpoint, res.status(), res.headers());
+        Ok(res)
+    }
+
+    /// Per-endpoint upstream status and rate-limit state.
+    pub fn upstream_status(&self) -> UpstreamSnapshot {
+        self.upstream.snapshot()
+    }
+
+    /// Fetch a token now so the first request doesn't pay for it.
+    pub async fn warm_up(&self) -> Result<(), SpotifyError> {
+        self.ensure_token().await.map(|_| ())
+    }
 
-        let token = self.fetch_token().await?;
-        {
-            let mut guard = self.token.write().await;
-            *guard = Some(token.clone());
+    /// Replace the cached token with a new one now, e.g. after the credentials were
+    /// rotated or the token was revoked.
+    pub async fn refresh_token(&self) -> Result<(), SpotifyError> {
+        self.refresh().await.map(|_| ())
+    }
+
+    /// Verify credentials with a token fetch and a minimal search call.
+    pub async fn validate_credentials(&self) -> Result<(), SpotifyError> {
+        self.warm_up().await?;
+        self.search_tracks("test", Some(1), None).await?;
+        Ok(())
+    }
+
+    /// The cached token's remaining lifetime and the outcome of the latest refresh.
+    pub fn token_status(&self) -> TokenStatus {
+        let token = self.token.load();
+        let now = std::time::Instant::now();
+        let last_refresh = self.last_refresh.lock().unwrap().clone();
+        TokenStatus {
+            cached: token.is_some(),
+            refresh_in_secs: token
+                .as_ref()
+                .and_then(|t| t.expires_at)
+                .map(|at| at.saturating_duration_since(now).as_secs()),
+            last_refresh_at: last_refresh.as_ref().map(|(at, _)| *at),
+            last_refresh_ok: last_refresh.as_ref().map(|(_, error)| error.is_none()),
+            last_refresh_error: last_refresh.and_then(|(_, error)| error),
         }
-        Ok(token.access_token)
     }
 
-    async fn fetch_token(&self) -> Result<CachedToken, String> {
-        let params = [
-            ("grant_type", "client_credentials"),
-        ];
-        let auth = base64::engine::general_purpose::STANDARD.encode(
-            format!("{}:{}", self.client_id, self.client_secret).as_bytes(),
-        );
+    /// True once a token has been obtained and the latest refresh succeeded.
+    pub async fn has_token(&self) -> bool {
+        self.token.load().is_some() && !self.token_refresh_failed.load(Ordering::Relaxed)
+    }
 
-        let res = self
-            .client
-            .post(TOKEN_URL)
-            .header("Authorization", format!("Basic {}", auth))
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .form(&params)
-            .send()
-            .await
-            .map_err(|e| format!("token request failed: {}", e))?;
-
-        if !res.status().is_success() {
-            let status = res.status();
-            let body = res.text().await.unwrap_or_default();
-            return Err(format!("token request failed: {} - {}", status, body));
+    /// Ensures we have a valid access token, refreshing if needed.
+    async fn ensure_token(&self) -> Result<Secret, SpotifyError> {
+        if let Some(t) = &*self.token.load() {
+            if t.expires_at.is_none_or(|at| at > std::time::Instant::now()) {
+                #[cfg(feature = "metrics")]
+                metrics::counter!("spotify_token_cache_total", "tenant" => self.tenant.clone(), "result" => "hit")
+                    .increment(1);
+                crate::access_log::record_token_cache(true);
+                return Ok(t.access_token.clone());
+            }
         }
 
-        let body: TokenResponse = res.json().await.map_err(|e| format!("token parse failed: {}", e))?;
-        let expires_at = std::time::Instant::now() + std::time::Duration::from_secs(body.expires_in.saturating_sub(60));
+        #[cfg(feature = "metrics")]
+        metrics::counter!("spotify_token_cache_total", "tenant" => self.tenant.clone(), "result" => "miss")
+            .increment(1);
+        crate::access_log::record_token_cache(false);
+        self.refresh().await
+    }
+
+    /// Fetch a new token and cache it, replacing any current one.
+    async fn refresh(&self) -> Result<Secret, SpotifyError> {
+        let result = self.fetch_token().await;
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "spotify_token_refreshes_total",
+            "tenant" => self.tenant.clone(),
+            "result" => if result.is_ok() { "success" } else { "error" }
+        )
+        .increment(1);
+        self.token_refresh_failed.store(result.is_err(), Ordering::Relaxed);
+        let error = result.as_ref().err().map(ToString::to_string);
+        *self.last_refresh.lock().unwrap() = Some((unix_now(), error));
+        let token = result?;
+        let access_token = token.access_token.clone();
+        self.token.store(Some(Arc::new(token)));
+        Ok(access_token)
+    }
 
+    #[tracing::instrument(name = "spotify.token_refresh", skip(self))]
+    async fn fetch_token(&self) -> Result<CachedToken, SpotifyError> {
+        let token = self.token_provider.fetch_token(&TokenHttp { client: self }).await?;
+        // Refresh a minute early so in-flight requests don't race expiry.
+        let expires_at = token
+            .expires_in
+            .map(|d| std::time::Instant::now() + d.saturating_sub(Duration::from_secs(60)));
         Ok(CachedToken {
-            access_token: body.access_token,
+            access_token: token.access_token,
             expires_at,
         })
     }
 
-    /// Search for tracks in the Spotify catalog.
-    pub async fn search_tracks(&self, q: &str, limit: Option<u32>, offset: Option<u32>) -> Result<SearchTracksResponse, String> {
-        let token = self.ensure_token().await?;
+    /// GET an API URL with a bearer token; errors for non-success statuses.
+    /// `endpoint` labels metrics and error messages.
+    #[tracing::instrument(name = "spotify.request", skip(self, url, token))]
+    async fn get(&self, endpoint: &'static str, url: &str, token: &Secret) -> Result<UpstreamResponse, SpotifyError> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        instrument::inject_trace_context(&mut headers);
+
+        let req = self
+            .request(reqwest::Method::GET, url)
+            .headers(headers)
+            .header("Authorization", format!("Bearer {}", token.expose()));
+        let res = self.send(endpoint, req).await?;
+
+        if !res.status.is_success() {
+            return Err(SpotifyError::from_status(res.status, res.text(), res.retry_after()));
+        }
+        Ok(res)
+    }
 
-        let limit = limit.unwrap_or(20).min(50).max(1);
-        let offset = offset.unwrap_or(0).min(1000);
+    /// Like [`get`](Self::get), but hands the body over as it arrives instead of reading
+    /// it first. With a cassette the body is read whole, so it can be recorded or replayed.
+    #[tracing::instrument(name = "spotify.request", skip(self, url, token))]
+    async fn get_stream(&self, endpoint: &'static str, url: &str, token: &Secret) -> Result<ByteStream, SpotifyError> {
+        #[cfg(feature = "cassette")]
+        if self.cassette.is_some() {
+            let body = bytes::Bytes::from(self.get(endpoint, url, token).await?.body);
+            return Ok(futures::stream::once(async move { Ok(body) }).boxed());
+        }
 
-        let url = format!("{}/search?q={}&type=track&limit={}&offset={}",
-            API_BASE,
-            urlencoding::encode(q),
+        let mut headers = reqwest::header::HeaderMap::new();
+        instrument::inject_trace_context(&mut headers);
+        let req = self
+            .request(reqwest::Method::GET, url)
+            .headers(headers)
+            .header("Authorization", format!("Bearer {}", token.expose()))
+            .build()
+            .map_err(|source| SpotifyError::Network { endpoint, source })?;
+        let res = self.execute(endpoint, req).await?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let retry_after = RateLimitInfo::from_headers(res.headers()).retry_after;
+            let text = res.text().await.unwrap_or_default();
+            return Err(SpotifyError::from_status(status, text, retry_after));
+        }
+        Ok(res.bytes_stream().map_err(move |source| SpotifyError::Network { endpoint, source }).boxed())
+    }
+
+    /// GET an API URL with a bearer token and decode the JSON body.
+    async fn get_json<T: DeserializeOwned>(&self, endpoint: &'static str, url: &str, token: &Secret) -> Result<T, SpotifyError> {
+        let res = self.get(endpoint, url, token).await?;
+        if !self.lenient_schema {
+            return res.json(endpoint);
+        }
+        let (body, drift) = schema::decode_lenient(&res.body, self.schema_samples)
+            .map_err(|message| SpotifyError::Decode { endpoint, message })?;
+        for drift in &drift {
+            self.upstream.record_drift(endpoint, drift);
+        }
+        Ok(body)
+    }
+
+    fn search_url(&self, q: &str, limit: Option<u32>, offset: Option<u32>) -> String {
+        let (limit, offset) = self.search_limits.resolve(limit, offset);
+        format!("{}/search?q={}&type=track&limit={}&offset={}{}",
+            self.api_base,
+            urlencoding::encode(&normalize_query(q, self.transliterate_queries)),
             limit,
             offset,
-        );
+            self.market_param(),
+        )
+    }
 
-        let res = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", token))
-            .send()
-            .await
-            .map_err(|e| format!("search request failed: {}", e))?;
-
-        if !res.status().is_success() {
-            let status = res.status();
-            let body = res.text().await.unwrap_or_default();
-            return Err(format!("Spotify API error {}: {}", status, body));
-        }
+    fn tracks_url(&self, ids: &[String]) -> String {
+        let ids = ids[..ids.len().min(50)].join(",");
+        format!("{}/tracks?ids={}{}", self.api_base, urlencoding::encode(&ids), self.market_param())
+    }
+
+    /// `&market=XX` with a market configured, otherwise empty.
+    fn market_param(&self) -> String {
+        self.market
+            .as_deref()
+            .map(|m| format!("&market={}", urlencoding::encode(m)))
+            .unwrap_or_default()
+    }
 
-        let body: SearchResponse = res.json().await.map_err(|e| format!("search parse failed: {}", e))?;
+    /// Search for tracks in the Spotify catalog.
+    #[tracing::instrument(skip(self))]
+    pub async fn search_tracks(&self, q: &str, limit: Option<u32>, offset: Option<u32>) -> Result<SearchTracksResponse, SpotifyError> {
+        let token = self.ensure_token().await?;
+        let url = self.search_url(q, limit, offset);
+        let body: SearchResponse = self.get_json("search", &url, &token).await?;
         Ok(SearchTracksResponse {
             tracks: body.tracks.items,
             total: body.tracks.total,
@@ -126,37 +423,45 @@ impl SpotifyClient {
         })
     }
 
+    /// Like [`search_tracks`](Self::search_tracks), but streams Spotify's response body
+    /// as it arrives, without decoding it.
+    #[tracing::instrument(skip(self))]
+    pub async fn search_tracks_raw(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ByteStream, SpotifyError> {
+        let token = self.ensure_token().await?;
+        let url = self.search_url(q, limit, offset);
+        self.get_stream("search", &url, &token).await
+    }
+
     /// Fetch track metadata for up to 50 IDs. Returns Some for each id, or None if not available.
-    pub async fn get_tracks(&self, ids: &[String]) -> Result<Vec<Option<Track>>, String> {
+    #[tracing::instrument(skip_all, fields(count = ids.len()))]
+    pub async fn get_tracks(&self, ids: &[String]) -> Result<Vec<Option<Track>>, SpotifyError> {
         if ids.is_empty() {
             return Ok(vec![]);
         }
-        let ids: Vec<_> = ids.iter().take(50).cloned().collect();
-        let ids_param = ids.join(","");
-
         let token = self.ensure_token().await?;
-        let url = format!("{}/tracks?ids={}", API_BASE, urlencoding::encode(&ids_param));
+        let body: TracksResponse = self.get_json("tracks", &self.tracks_url(ids), &token).await?;
+        Ok(body.tracks)
+    }
 
-        let res = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", token))
-            .send()
-            .await
-            .map_err(|e| format!("tracks request failed: {}", e))?;
-
-        if !res.status().is_success() {
-            let status = res.status();
-            let body = res.text().await.unwrap_or_default();
-            return Err(format!("Spotify API error {}: {}", status, body));
+    /// Like [`get_tracks`](Self::get_tracks), but streams Spotify's `{"tracks": [...]}`
+    /// body as it arrives, without decoding it.
+    #[tracing::instrument(skip_all, fields(count = ids.len()))]
+    pub async fn get_tracks_raw(&self, ids: &[String]) -> Result<ByteStream, SpotifyError> {
+        if ids.is_empty() {
+            return Ok(futures::stream::once(async { Ok(bytes::Bytes::from_static(br#"{"tracks":[]}"#)) }).boxed());
         }
-
-        let body: TracksResponse = res.json().await.map_err(|e| format!("tracks parse failed: {}", e))?;
-        Ok(body.tracks)
+        let token = self.ensure_token().await?;
+        self.get_stream("tracks", &self.tracks_url(ids), &token).await
     }
 
     /// Fetch track metadata + audio features for given IDs. For Go saga: merge and return with embeddings.
-    pub async fn get_tracks_with_features(&self, ids: &[String]) -> Result<Vec<TrackWithFeatures>, String> {
+    #[tracing::instrument(skip_all, fields(count = ids.len()))]
+    pub async fn get_tracks_with_features(&self, ids: &[String]) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
         let ids: Vec<_> = ids.iter().take(50).cloned().collect();
         if ids.is_empty() {
             return Ok(vec![]);
@@ -188,74 +493,555 @@ impl SpotifyClient {
     }
 
     /// Fetch audio features for up to 100 track IDs. Returns Some for each id, or None if not available.
-    pub async fn get_audio_features(&self, ids: &[String]) -> Result<Vec<Option<AudioFeatures>>, String> {
+    #[tracing::instrument(skip_all, fields(count = ids.len()))]
+    pub async fn get_audio_features(&self, ids: &[String]) -> Result<Vec<Option<AudioFeatures>>, SpotifyError> {
         if ids.is_empty() {
             return Ok(vec![]);
         }
         let ids: Vec<_> = ids.iter().take(100).cloned().collect();
-        let ids_param = ids.join(","");
+        let ids_param = ids.join(",");
 
         let token = self.ensure_token().await?;
-        let url = format!("{}/audio-features?ids={}", API_BASE, urlencoding::encode(&ids_param));
+        let url = format!("{}/audio-features?ids={}", self.api_base, urlencoding::encode(&ids_param));
 
-        let res = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", token))
-            .send()
-            .await
-            .map_err(|e| format!("audio-features request failed: {}", e))?;
-
-        if !res.status().is_success() {
-            let status = res.status();
-            let body = res.text().await.unwrap_or_default();
-            return Err(format!("Spotify API error {}: {}", status, body));
-        }
-
-        let body: AudioFeaturesResponse = res.json().await.map_err(|e| format!("audio-features parse failed: {}", e))?;
+        let body: AudioFeaturesResponse = self.get_json("audio-features", &url, &token).await?;
         Ok(body.audio_features)
     }
 
     /// Search tracks and fetch audio features for each. Returns tracks with embeddings.
+    ///
+    /// The page is searched in chunks of the configured chunk size, by default the whole
+    /// page at once, and each chunk's features are fetched as soon as its IDs arrive, so
+    /// with smaller chunks the later searches and the earlier features calls overlap. At
+    /// most the configured number of chunks are in flight; the tracks come back in search
+    /// order.
+    #[tracing::instrument(skip(self))]
     pub async fn search_tracks_with_features(
         &self,
         q: &str,
         limit: Option<u32>,
         offset: Option<u32>,
-    ) -> Result<SearchTracksWithFeaturesResponse, String> {
-        let result = self.search_tracks(q, limit, offset).await?;
-        let ids: Vec<String> = result.tracks.iter().map(|t| t.id.clone()).collect();
+    ) -> Result<SearchTracksWithFeaturesResponse, SpotifyError> {
+        let (limit, offset) = self.search_limits.resolve(limit, offset);
+        // Spotify rejects offsets past the maximum, so chunks starting there are left out.
+        let chunks: Vec<(u32, u32)> = (offset..offset + limit)
+            .step_by(self.search_chunk_size as usize)
+            .take_while(|&start| start <= self.search_limits.max_offset)
+            .map(|start| (start, self.search_chunk_size.min(offset + limit - start)))
+            .collect();
+        let pages: Vec<(u32, Vec<TrackWithFeatures>)> = futures::stream::iter(chunks)
+            .map(|(start, size)| async move {
+                let page = self.search_tracks(q, Some(size), Some(start)).await?;
+                Ok::<_, SpotifyError>((page.total, self.attach_features(page.tracks).await?))
+            })
+            .buffered(self.search_concurrency)
+            .try_collect()
+            .await?;
+        Ok(SearchTracksWithFeaturesResponse {
+            total: pages.first().map_or(0, |(total, _)| *total),
+            tracks: pages.into_iter().flat_map(|(_, tracks)| tracks).collect(),
+            limit,
+            offset,
+        })
+    }
 
+    /// Fetch audio features for already-loaded tracks and pair them up.
+    async fn attach_features(&self, tracks: Vec<Track>) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        let ids: Vec<String> = tracks.iter().map(|t| t.id.clone()).collect();
         let features = if ids.is_empty() {
             vec![]
         } else {
             self.get_audio_features(&ids).await?
         };
 
-        let mut tracks_with_features = Vec::with_capacity(result.tracks.len());
-        for (i, track) in result.tracks.into_iter().enumerate() {
-            let audio_features = features.get(i).and_then(|f| f.clone());
-            let embedding = audio_features.as_ref().map(|af| af.to_embedding());
-            tracks_with_features.push(TrackWithFeatures {
-                track,
-                audio_features,
-                embedding,
-            });
+        Ok(tracks
+            .into_iter()
+            .enumerate()
+            .map(|(i, track)| {
+                let audio_features = features.get(i).and_then(|f| f.clone());
+                let embedding = audio_features.as_ref().map(|af| af.to_embedding());
+                TrackWithFeatures {
+                    track,
+                    audio_features,
+                    embedding,
+                }
+            })
+            .collect())
+    }
+
+    /// Recommended tracks for up to 5 seeds (tracks, artists and genres combined).
+    #[tracing::instrument(skip(self))]
+    pub async fn get_recommendations(
+        &self,
+        seeds: &RecommendationSeeds,
+        limit: Option<u32>,
+    ) -> Result<Vec<Track>, SpotifyError> {
+        let token = self.ensure_token().await?;
+        let limit = limit.unwrap_or(20).clamp(1, MAX_RECOMMENDATIONS);
+
+        let mut url = format!("{}/recommendations?limit={}{}", self.api_base, limit, self.market_param());
+        for (param, values) in [
+            ("seed_tracks", &seeds.tracks),
+            ("seed_artists", &seeds.artists),
+            ("seed_genres", &seeds.genres),
+        ] {
+            if !values.is_empty() {
+                url.push_str(&format!("&{}={}", param, urlencoding::encode(&values.join(","))));
+            }
         }
 
-        Ok(SearchTracksWithFeaturesResponse {
-            tracks: tracks_with_features,
-            total: result.total,
-            limit: result.limit,
-            offset: result.offset,
+        let body: RecommendationsResponse = self.get_json("recommendations", &url, &token).await?;
+        Ok(body.tracks)
+    }
+
+    /// One page of a playlist's items, up to 100 from `offset`. Local files, podcast
+    /// episodes and tracks no longer available are left out and counted in `skipped`.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_playlist_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<PlaylistTracksPage, SpotifyError> {
+        let token = self.ensure_token().await?;
+        let limit = limit.unwrap_or(MAX_PLAYLIST_PAGE).clamp(1, MAX_PLAYLIST_PAGE);
+        let url = format!(
+            "{}/playlists/{}/tracks?limit={}&offset={}&additional_types=track{}",
+            self.api_base,
+            urlencoding::encode(id),
+            limit,
+            offset.unwrap_or(0),
+            self.market_param(),
+        );
+
+        let body: PlaylistItemsResponse = self.get_json("playlist-tracks", &url, &token).await?;
+        let items = body.items.len() as u32;
+        let tracks: Vec<Track> = body.items.into_iter().filter_map(PlaylistItem::into_track).collect();
+        Ok(PlaylistTracksPage {
+            skipped: items - tracks.len() as u32,
+            tracks,
+            total: body.total,
+            limit: body.limit,
+            offset: body.offset,
+        })
+    }
+
+    /// One page of artist `id`'s albums, singles and compilations, up to 50 from
+    /// `offset`. Albums the artist only appears on are left out.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_artist_albums(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ArtistAlbumsPage, SpotifyError> {
+        let token = self.ensure_token().await?;
+        let limit = limit.unwrap_or(MAX_ARTIST_ALBUMS_PAGE).clamp(1, MAX_ARTIST_ALBUMS_PAGE);
+        let url = format!(
+            "{}/artists/{}/albums?include_groups=album,single,compilation&limit={}&offset={}{}",
+            self.api_base,
+            urlencoding::encode(id),
+            limit,
+            offset.unwrap_or(0),
+            self.market_param(),
+        );
+        let body: PageResponse<AlbumDetail> = self.get_json("artist-albums", &url, &token).await?;
+        Ok(ArtistAlbumsPage {
+            albums: body.items,
+            total: body.total,
+            limit: body.limit,
+            offset: body.offset,
         })
     }
+
+    /// One page of album `id`'s tracks, up to 50 from `offset`.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_album_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<AlbumTracksPage, SpotifyError> {
+        let token = self.ensure_token().await?;
+        let limit = limit.unwrap_or(MAX_ALBUM_TRACKS_PAGE).clamp(1, MAX_ALBUM_TRACKS_PAGE);
+        let url = format!(
+            "{}/albums/{}/tracks?limit={}&offset={}{}",
+            self.api_base,
+            urlencoding::encode(id),
+            limit,
+            offset.unwrap_or(0),
+            self.market_param(),
+        );
+        let body: PageResponse<Track> = self.get_json("album-tracks", &url, &token).await?;
+        Ok(AlbumTracksPage {
+            tracks: body.items,
+            total: body.total,
+            limit: body.limit,
+            offset: body.offset,
+        })
+    }
+
+    /// Full metadata for album `id`: label, release date, genres, copyrights and images.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_album(&self, id: &str) -> Result<AlbumDetail, SpotifyError> {
+        let token = self.ensure_token().await?;
+        let mut url = format!("{}/albums/{}", self.api_base, urlencoding::encode(id));
+        if let Some(market) = &self.market {
+            url.push_str(&format!("?market={}", urlencoding::encode(market)));
+        }
+        self.get_json("album", &url, &token).await
+    }
+
+    /// Full artist object for `id`: genres, popularity, followers and images.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_artist(&self, id: &str) -> Result<ArtistDetail, SpotifyError> {
+        let token = self.ensure_token().await?;
+        let url = format!("{}/artists/{}", self.api_base, urlencoding::encode(id));
+        self.get_json("artist", &url, &token).await
+    }
+
+    /// Full metadata for several albums, in the order of `ids`; `None` for IDs Spotify
+    /// doesn't know. Fetched [`MAX_ALBUM_IDS`] at a time, concurrently.
+    #[tracing::instrument(skip_all, fields(count = ids.len()))]
+    pub async fn get_albums(&self, ids: &[String]) -> Result<Vec<Option<AlbumDetail>>, SpotifyError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let token = self.ensure_token().await?;
+        let chunks = ids.chunks(MAX_ALBUM_IDS).map(|chunk| async {
+            let url = format!("{}{}", self.ids_url("albums", chunk), self.market_param());
+            let body: AlbumsResponse = self.get_json("albums", &url, &token).await?;
+            Ok::<_, SpotifyError>(body.albums)
+        });
+        Ok(futures::future::try_join_all(chunks).await?.into_iter().flatten().collect())
+    }
+
+    /// Full artist objects for several artists, in the order of `ids`; `None` for IDs
+    /// Spotify doesn't know. Fetched [`MAX_ARTIST_IDS`] at a time, concurrently.
+    #[tracing::instrument(skip_all, fields(count = ids.len()))]
+    pub async fn get_artists(&self, ids: &[String]) -> Result<Vec<Option<ArtistDetail>>, SpotifyError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let token = self.ensure_token().await?;
+        let chunks = ids.chunks(MAX_ARTIST_IDS).map(|chunk| async {
+            let body: ArtistsResponse = self.get_json("artists", &self.ids_url("artists", chunk), &token).await?;
+            Ok::<_, SpotifyError>(body.artists)
+        });
+        Ok(futures::future::try_join_all(chunks).await?.into_iter().flatten().collect())
+    }
+
+    /// `{api_base}/{path}?ids=...` for a batch lookup.
+    fn ids_url(&self, path: &str, ids: &[String]) -> String {
+        format!("{}/{}?ids={}", self.api_base, path, urlencoding::encode(&ids.join(",")))
+    }
+
+    /// Recommendations with audio features and embeddings.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_recommendations_with_features(
+        &self,
+        seeds: &RecommendationSeeds,
+        limit: Option<u32>,
+    ) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        let tracks = self.get_recommendations(seeds, limit).await?;
+        self.attach_features(tracks).await
+    }
+
+    /// Tracks similar to `id`: Spotify recommendations seeded by the track, re-ranked by
+    /// cosine similarity of their embeddings to the seed's. Candidates without audio
+    /// features are dropped.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_similar_tracks(&self, id: &str, limit: Option<u32>) -> Result<Vec<ScoredTrack>, SpotifyError> {
+        let limit = limit.unwrap_or(20).clamp(1, 50) as usize;
+        let seeds = RecommendationSeeds {
+            tracks: vec![id.to_string()],
+            ..Default::default()
+        };
+        let (seed, candidates) = tokio::join!(
+            self.get_tracks_with_features(&seeds.tracks),
+            self.get_recommendations_with_features(&seeds, Some(MAX_RECOMMENDATIONS)),
+        );
+        let seed = seed?
+            .into_iter()
+            .next()
+            .ok_or_else(|| SpotifyError::NotFound(format!("track {}", id)))?;
+        rank_similar(&seed, candidates?, limit)
+    }
+}
+
+/// Rank `candidates` by cosine similarity to `seed`'s embedding, best first, keeping
+/// `limit`. The seed itself and candidates without an embedding are dropped;
+/// `NotFound` if the seed has no audio features.
+pub fn rank_similar(
+    seed: &TrackWithFeatures,
+    candidates: Vec<TrackWithFeatures>,
+    limit: usize,
+) -> Result<Vec<ScoredTrack>, SpotifyError> {
+    let seed_embedding = seed
+        .embedding
+        .as_deref()
+        .ok_or_else(|| SpotifyError::NotFound(format!("audio features for track {}", seed.track.id)))?;
+
+    let candidates: Vec<TrackWithFeatures> = candidates
+        .into_iter()
+        .filter(|t| t.track.id != seed.track.id && t.embedding.is_some())
+        .collect();
+    let embeddings: Vec<&[f32]> = candidates.iter().filter_map(|t| t.embedding.as_deref()).collect();
+    let scores = cosine_similarity_batch(seed_embedding, &embeddings);
+    let mut scored: Vec<ScoredTrack> = candidates
+        .into_iter()
+        .zip(scores)
+        .map(|(track, score)| ScoredTrack { track, score })
+        .collect();
+    scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+    scored.truncate(limit);
+    Ok(scored)
+}
+
+/// Group `tracks` by primary artist. Artists are ordered by where their first track
+/// appears in `tracks`, so search relevance carries over; each artist's tracks are
+/// ordered most popular first. Tracks without artists are dropped.
+pub fn group_by_artist(tracks: Vec<TrackWithFeatures>) -> Vec<ArtistGroup> {
+    let mut groups: Vec<ArtistGroup> = Vec::new();
+    for track in tracks {
+        let Some(artist) = track.track.artists.first() else {
+            continue;
+        };
+        // Spotify always sets an ID; the name is a fallback for partial records.
+        let same = |a: &Artist| match (&a.id, &artist.id) {
+            (Some(a), Some(b)) => a == b,
+            _ => a.name.eq_ignore_ascii_case(&artist.name),
+        };
+        match groups.iter_mut().find(|g| same(&g.artist)) {
+            Some(group) => group.tracks.push(track),
+            None => groups.push(ArtistGroup {
+                artist: artist.clone(),
+                tracks: vec![track],
+                embedding: None,
+            }),
+        }
+    }
+    for group in &mut groups {
+        group.tracks.sort_by_key(|t| std::cmp::Reverse(t.track.popularity));
+        group.embedding = mean_embedding(group.tracks.iter().filter_map(|t| t.embedding.as_deref()));
+    }
+    groups
+}
+
+/// Element-wise mean of `embeddings`; `None` if there are none.
+pub fn mean_embedding<'a>(embeddings: impl IntoIterator<Item = &'a [f32]>) -> Option<Vec<f32>> {
+    let mut sum: Vec<f32> = Vec::new();
+    let mut count = 0;
+    for embedding in embeddings {
+        if sum.is_empty() {
+            sum = vec![0.0; embedding.len()];
+        }
+        for (s, x) in sum.iter_mut().zip(embedding) {
+            *s += x;
+        }
+        count += 1;
+    }
+    (count > 0).then(|| sum.into_iter().map(|s| s / count as f32).collect())
+}
+
+/// Tracks by one artist, from [`group_by_artist`].
+#[derive(Clone, Debug)]
+pub struct ArtistGroup {
+    pub artist: Artist,
+    /// Most popular first.
+    pub tracks: Vec<TrackWithFeatures>,
+    /// Mean embedding of the tracks that have one.
+    pub embedding: Option<Vec<f32>>,
+}
+
+/// Releases of the same song whose durations differ by at most this are duplicates.
+pub const DEDUPE_DURATION_TOLERANCE_MS: u32 = 3000;
+
+/// Collapse duplicate releases in `items` (remasters, compilations, re-issues) to the
+/// most popular one. Two tracks are duplicates if they share an ISRC, or if their
+/// normalized titles and primary artists match and their durations are within
+/// [`DEDUPE_DURATION_TOLERANCE_MS`]. Each kept track takes the place of its group's
+/// first member, so the order of the results is otherwise unchanged.
+pub fn dedupe_by<T>(items: Vec<T>, track: impl Fn(&T) -> &Track) -> Vec<T> {
+    let keys: Vec<ReleaseKey> = items.iter().map(|item| ReleaseKey::new(track(item))).collect();
+    // For each item, the index of the first member of its group.
+    let mut group: Vec<usize> = Vec::with_capacity(keys.len());
+    for (i, key) in keys.iter().enumerate() {
+        let first = (0..i).find(|&j| keys[j].same_release(key)).map_or(i, |j| group[j]);
+        group.push(first);
+    }
+    let mut best: Vec<usize> = (0..keys.len()).collect();
+    for (i, &first) in group.iter().enumerate() {
+        if keys[i].popularity > keys[best[first]].popularity {
+            best[first] = i;
+        }
+    }
+    let mut items: Vec<Option<T>> = items.into_iter().map(Some).collect();
+    (0..keys.len())
+        .filter(|&i| group[i] == i)
+        .filter_map(|i| items[best[i]].take())
+        .collect()
+}
+
+/// What [`dedupe_by`] compares tracks on.
+struct ReleaseKey {
+    isrc: Option<String>,
+    title: String,
+    artist: String,
+    duration_ms: u32,
+    popularity: u32,
+}
+
+impl ReleaseKey {
+    fn new(track: &Track) -> Self {
+        Self {
+            isrc: track.external_ids.isrc.as_ref().map(|isrc| isrc.to_ascii_uppercase()),
+            title: normalize_title(&track.name),
+            artist: track.artists.first().map(|a| a.name.to_lowercase()).unwrap_or_default(),
+            duration_ms: track.duration_ms,
+            popularity: track.popularity,
+        }
+    }
+
+    fn same_release(&self, other: &ReleaseKey) -> bool {
+        if self.isrc.is_some() && self.isrc == other.isrc {
+            return true;
+        }
+        !self.title.is_empty()
+            && self.title == other.title
+            && self.artist == other.artist
+            && self.duration_ms.abs_diff(other.duration_ms) <= DEDUPE_DURATION_TOLERANCE_MS
+    }
+}
+
+/// A title without version suffixes: `"Song - Remastered 2011"` and
+/// `"Song (2011 Remaster)"` both become `"song"`. Lowercased, with punctuation and
+/// repeated spaces dropped.
+fn normalize_title(title: &str) -> String {
+    let title = title.split(" - ").next().unwrap_or(title);
+    let mut out = String::with_capacity(title.len());
+    let mut depth = 0usize;
+    for c in title.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            _ if depth > 0 => {}
+            c if c.is_alphanumeric() => out.extend(c.to_lowercase()),
+            _ => {
+                if !out.is_empty() && !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            }
+        }
+    }
+    out.trim_end().to_string()
+}
+
+/// Re-rank `items` by a blend of their current rank and popularity, highest
+/// [`popularity_boost_scores`] first. Ties keep their order.
+pub fn boost_popularity_by<T>(items: Vec<T>, weight: f32, track: impl Fn(&T) -> &Track) -> Vec<T> {
+    let scores = popularity_boost_scores(&items, weight, track);
+    let mut scored: Vec<(f32, T)> = scores.into_iter().zip(items).collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.into_iter().map(|(_, item)| item).collect()
+}
+
+/// Score of each of `items` for [`boost_popularity_by`]:
+/// `(1 - weight) * relevance + weight * popularity / 100`, where relevance falls from 1
+/// for the first item towards 0 for the last. `weight` 0 keeps the order and 1 sorts by
+/// popularity alone.
+pub fn popularity_boost_scores<T>(items: &[T], weight: f32, track: impl Fn(&T) -> &Track) -> Vec<f32> {
+    let weight = weight.clamp(0.0, 1.0);
+    let n = items.len() as f32;
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let relevance = 1.0 - i as f32 / n;
+            let popularity = track(item).popularity.min(100) as f32 / 100.0;
+            (1.0 - weight) * relevance + weight * popularity
+        })
+        .collect()
+}
+
+/// Max items per page of `/playlists/{id}/tracks`.
+pub const MAX_PLAYLIST_PAGE: u32 = 100;
+
+/// Max albums per page of `/artists/{id}/albums`.
+pub const MAX_ARTIST_ALBUMS_PAGE: u32 = 50;
+
+/// Max tracks per page of `/albums/{id}/tracks`.
+pub const MAX_ALBUM_TRACKS_PAGE: u32 = 50;
+
+/// Max tracks Spotify returns from `/recommendations`.
+pub const MAX_RECOMMENDATIONS: u32 = 100;
+
+/// Page sizes and offsets accepted for track searches. The defaults are Spotify's
+/// standard ones; deployments with raised Spotify quotas can allow more.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchLimits {
+    /// Tracks per page when the caller doesn't say.
+    pub default_limit: u32,
+    /// Most tracks per page.
+    pub max_limit: u32,
+    /// Highest offset Spotify accepts; its results end there.
+    pub max_offset: u32,
+    /// Reject pages reaching past `max_offset` instead of letting them come back short.
+    pub strict: bool,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            default_limit: 20,
+            max_limit: 50,
+            max_offset: 1000,
+            strict: false,
+        }
+    }
+}
+
+impl SearchLimits {
+    /// `limit` and `offset` as sent to Spotify: the default page size when `limit` is
+    /// unset, and both capped.
+    pub fn resolve(&self, limit: Option<u32>, offset: Option<u32>) -> (u32, u32) {
+        (
+            limit.unwrap_or(self.default_limit).clamp(1, self.max_limit),
+            offset.unwrap_or(0).min(self.max_offset),
+        )
+    }
+
+    /// Where a page of `limit` tracks from `offset` ends, if that is past `max_offset`,
+    /// so Spotify would return it short.
+    pub fn overrun(&self, limit: u32, offset: u32) -> Option<u32> {
+        let end = offset.saturating_add(limit);
+        (end > self.max_offset).then_some(end)
+    }
+}
+
+/// Max IDs per `/albums` request.
+pub const MAX_ALBUM_IDS: usize = 20;
+/// Max IDs per `/artists` request.
+pub const MAX_ARTIST_IDS: usize = 50;
+
+/// Seeds for `/recommendations`. Spotify accepts at most 5 in total.
+#[derive(Clone, Debug, Default)]
+pub struct RecommendationSeeds {
+    pub tracks: Vec<String>,
+    pub artists: Vec<String>,
+    pub genres: Vec<String>,
+}
+
+/// A track with its similarity to a seed track (cosine, -1..1).
+#[derive(Clone, Debug)]
+pub struct ScoredTrack {
+    pub track: TrackWithFeatures,
+    pub score: f32,
 }
 
 #[derive(Deserialize)]
-struct TokenResponse {
-    access_token: String,
-    expires_in: u64,
+struct RecommendationsResponse {
+    tracks: Vec<Track>,
 }
 
 #[derive(Deserialize)]
@@ -271,7 +1057,73 @@ struct TracksPage {
     offset: u32,
 }
 
+/// A page of Spotify's paging object.
+#[derive(Deserialize)]
+struct PageResponse<T> {
+    items: Vec<T>,
+    total: u32,
+    limit: u32,
+    offset: u32,
+}
+
+#[derive(Deserialize)]
+struct PlaylistItemsResponse {
+    items: Vec<PlaylistItem>,
+    total: u32,
+    limit: u32,
+    offset: u32,
+}
+
+#[derive(Deserialize)]
+struct PlaylistItem {
+    #[serde(default)]
+    is_local: bool,
+    /// A track or episode object; `null` when no longer available.
+    track: Option<serde_json::Value>,
+}
+
+impl PlaylistItem {
+    /// The item's track, if it is a catalog track.
+    fn into_track(self) -> Option<Track> {
+        let track = self.track.filter(|t| !self.is_local && t["type"] == "track")?;
+        serde_json::from_value(track).ok()
+    }
+}
+
+/// One page of a playlist's tracks.
+#[derive(Clone, Debug)]
+pub struct PlaylistTracksPage {
+    pub tracks: Vec<Track>,
+    /// Items on this page that aren't catalog tracks (local files, episodes, removed).
+    pub skipped: u32,
+    /// Items in the whole playlist, including skipped ones.
+    pub total: u32,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// One page of an artist's albums, singles and compilations.
+#[derive(Clone, Debug)]
+pub struct ArtistAlbumsPage {
+    /// Simplified albums: without label, genres, copyrights or popularity.
+    pub albums: Vec<AlbumDetail>,
+    pub total: u32,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// One page of an album's tracks.
+#[derive(Clone, Debug)]
+pub struct AlbumTracksPage {
+    /// Simplified tracks: without album, popularity or external IDs.
+    pub tracks: Vec<Track>,
+    pub total: u32,
+    pub limit: u32,
+    pub offset: u32,
+}
+
 /// Response from track search.
+#[derive(Clone)]
 pub struct SearchTracksResponse {
     pub tracks: Vec<Track>,
     pub total: u32,
@@ -280,7 +1132,7 @@ pub struct SearchTracksResponse {
 }
 
 /// A Spotify track (simplified).
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Track {
     pub id: String,
     pub name: String,
@@ -289,15 +1141,56 @@ pub struct Track {
     pub duration_ms: u32,
     #[serde(default)]
     pub explicit: bool,
+    /// 0-100, relative to other tracks.
+    #[serde(default)]
+    pub popularity: u32,
     #[serde(default)]
     pub artists: Vec<Artist>,
     #[serde(default)]
     pub album: Album,
     #[serde(default)]
+    pub external_ids: ExternalIds,
+    #[serde(default)]
     pub external_urls: ExternalUrls,
+    /// With a market, the track that was asked for when Spotify relinked it to this one,
+    /// the version playable in that market.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linked_from: Option<LinkedTrack>,
+    /// With a market, whether the track can be played there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_playable: Option<bool>,
+    /// ISO 3166-1 alpha-2 codes of the markets the track is available in. Spotify lists
+    /// them only when the request has no market.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub available_markets: Vec<String>,
+}
+
+impl Track {
+    /// The ID this track was requested or found under: the original ID when Spotify
+    /// relinked it, otherwise `id`. Stable across markets.
+    pub fn requested_id(&self) -> &str {
+        self.linked_from.as_ref().map_or(&self.id, |l| &l.id)
+    }
+
+    /// Whether the track is available in `market`, or `None` if Spotify didn't list its
+    /// markets.
+    pub fn playable_in(&self, market: &str) -> Option<bool> {
+        if self.available_markets.is_empty() {
+            return None;
+        }
+        Some(self.available_markets.iter().any(|m| m.eq_ignore_ascii_case(market)))
+    }
+}
+
+/// The original track behind a relinked one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LinkedTrack {
+    pub id: String,
+    #[serde(default)]
+    pub uri: String,
 }
 
-#[derive(Clone, Debug, Deserialize, Default)]
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct Artist {
     pub id: Option<String>,
     pub name: String,
@@ -305,7 +1198,7 @@ pub struct Artist {
     pub external_urls: ExternalUrls,
 }
 
-#[derive(Clone, Debug, Deserialize, Default)]
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct Album {
     pub id: Option<String>,
     pub name: String,
@@ -315,23 +1208,121 @@ pub struct Album {
     pub external_urls: ExternalUrls,
 }
 
-#[derive(Clone, Debug, Deserialize, Default)]
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct Image {
     pub url: Option<String>,
     pub width: Option<u32>,
     pub height: Option<u32>,
 }
 
-#[derive(Clone, Debug, Deserialize, Default)]
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct ExternalUrls {
     pub spotify: Option<String>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct ExternalIds {
+    /// International Standard Recording Code; shared by re-releases of the same recording.
+    pub isrc: Option<String>,
+}
+
+// ---------------------------------------------------------------------------
+// Albums (GET /v1/albums/{id})
+// ---------------------------------------------------------------------------
+
+/// An album with the metadata that track objects leave out.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AlbumDetail {
+    pub id: String,
+    pub name: String,
+    /// `album`, `single` or `compilation`.
+    #[serde(default)]
+    pub album_type: String,
+    #[serde(default)]
+    pub artists: Vec<Artist>,
+    #[serde(default)]
+    pub label: Option<String>,
+    /// `YYYY`, `YYYY-MM` or `YYYY-MM-DD`, depending on `release_date_precision`.
+    #[serde(default)]
+    pub release_date: String,
+    /// `year`, `month` or `day`.
+    #[serde(default)]
+    pub release_date_precision: String,
+    /// Often empty; Spotify assigns genres mostly to artists.
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub copyrights: Vec<Copyright>,
+    #[serde(default)]
+    pub total_tracks: u32,
+    /// Largest first.
+    #[serde(default)]
+    pub images: Vec<Image>,
+    #[serde(default)]
+    pub popularity: u32,
+    #[serde(default)]
+    pub external_ids: AlbumExternalIds,
+    #[serde(default)]
+    pub external_urls: ExternalUrls,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Copyright {
+    pub text: String,
+    /// `C` for the copyright, `P` for the sound recording (performance) copyright.
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct AlbumExternalIds {
+    /// Universal Product Code.
+    pub upc: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AlbumsResponse {
+    albums: Vec<Option<AlbumDetail>>,
+}
+
+// ---------------------------------------------------------------------------
+// Artists (GET /v1/artists)
+// ---------------------------------------------------------------------------
+
+/// A full artist object, with the genres, popularity and followers that the simplified
+/// artists on tracks leave out.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArtistDetail {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub popularity: u32,
+    #[serde(default)]
+    pub followers: Followers,
+    /// Largest first.
+    #[serde(default)]
+    pub images: Vec<Image>,
+    #[serde(default)]
+    pub external_urls: ExternalUrls,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct Followers {
+    pub total: u32,
+}
+
+#[derive(Deserialize)]
+struct ArtistsResponse {
+    artists: Vec<Option<ArtistDetail>>,
+}
+
 // ---------------------------------------------------------------------------
 // Audio Features (GET /v1/audio-features)
 // ---------------------------------------------------------------------------
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AudioFeatures {
     pub id: Option<String>,
     #[serde(default)]
@@ -401,7 +1392,7 @@ struct AudioFeaturesResponse {
 }
 
 /// Track with optional audio features and embedding.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TrackWithFeatures {
     pub track: Track,
     pub audio_features: Option<AudioFeatures>,
@@ -409,9 +1400,110 @@ pub struct TrackWithFeatures {
 }
 
 /// Search response with tracks and audio features/embeddings.
+#[derive(Clone)]
 pub struct SearchTracksWithFeaturesResponse {
     pub tracks: Vec<TrackWithFeatures>,
     pub total: u32,
     pub limit: u32,
     pub offset: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Track `id` by `artist`, as Spotify would send it.
+    fn track(id: &str, name: &str, artist: &str, duration_ms: u32, popularity: u32, isrc: Option<&str>) -> Track {
+        serde_json::from_value(json!({
+            "id": id,
+            "name": name,
+            "uri": format!("spotify:track:{}", id),
+            "duration_ms": duration_ms,
+            "popularity": popularity,
+            "artists": [{ "id": null, "name": artist }],
+            "external_ids": { "isrc": isrc },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn normalize_title_drops_versions_case_and_punctuation() {
+        let cases = [
+            ("Song", "song"),
+            ("Song - Remastered 2011", "song"),
+            ("Song (2011 Remaster)", "song"),
+            ("Song [Live]", "song"),
+            ("SONG!  (Radio Edit) ", "song"),
+            ("Don't Stop Me Now", "don t stop me now"),
+            ("Beyoncé", "beyoncé"),
+            ("Song (Part (Two))", "song"),
+            ("(Intro)", ""),
+            ("Part 1", "part 1"),
+        ];
+        for (title, expected) in cases {
+            assert_eq!(normalize_title(title), expected, "{:?}", title);
+        }
+    }
+
+    #[test]
+    fn dedupe_keeps_the_most_popular_release_of_each_song() {
+        // (tracks, IDs kept in order)
+        let cases: Vec<(Vec<Track>, Vec<&str>)> = vec![
+            (
+                vec![
+                    track("a", "Song", "Band", 200_000, 40, None),
+                    track("b", "Song - Remastered 2011", "Band", 201_000, 70, None),
+                    track("c", "Other", "Band", 200_000, 10, None),
+                ],
+                vec!["b", "c"],
+            ),
+            // The same ISRC merges whatever the titles say.
+            (
+                vec![
+                    track("a", "Song", "Band", 200_000, 60, Some("usabc1234567")),
+                    track("b", "Canción", "Banda", 250_000, 20, Some("USABC1234567")),
+                ],
+                vec!["a"],
+            ),
+            // Same title and artist, but a different length: a different recording.
+            (
+                vec![
+                    track("a", "Song", "Band", 200_000, 40, None),
+                    track("b", "Song (Extended Mix)", "Band", 200_000 + DEDUPE_DURATION_TOLERANCE_MS + 1, 70, None),
+                ],
+                vec!["a", "b"],
+            ),
+            // Same title and length by another artist: a cover.
+            (
+                vec![
+                    track("a", "Song", "Band", 200_000, 40, None),
+                    track("b", "Song", "Other Band", 200_000, 70, None),
+                ],
+                vec!["a", "b"],
+            ),
+            // Different ISRCs don't keep apart releases that otherwise match.
+            (
+                vec![
+                    track("a", "Song", "Band", 200_000, 40, Some("USABC1234567")),
+                    track("b", "Song (Remastered)", "Band", 199_000, 70, Some("USXYZ7654321")),
+                ],
+                vec!["b"],
+            ),
+            // Titles that are only versions normalize to nothing and never merge.
+            (
+                vec![
+                    track("a", "(Intro)", "Band", 60_000, 40, None),
+                    track("b", "[Interlude]", "Band", 60_000, 70, None),
+                ],
+                vec!["a", "b"],
+            ),
+        ];
+        for (tracks, expected) in cases {
+            let names: Vec<_> = tracks.iter().map(|t| t.name.clone()).collect();
+            let kept: Vec<_> = dedupe_by(tracks, |t| t).into_iter().map(|t| t.id).collect();
+            assert_eq!(kept, expected, "{:?}", names);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Search query normalization.
+//!
+//! Titles pasted from other catalogs often differ from Spotify's only in how they are
+//! encoded: decomposed accents (`e` followed by U+0301), typographic quotes and dashes,
+//! non-breaking or zero-width spaces. Spotify matches those poorly, so
+//! [`normalize_query`] rewrites them before every search: NFC composition, ASCII
+//! quotes, apostrophes and hyphens, no invisible characters, single spaces. Letters,
+//! accents included, are kept; transliterating them to ASCII is optional, as it helps
+//! Latin-script titles typed without accents but mangles other scripts.
+
+use unicode_normalization::UnicodeNormalization;
+
+/// `q` as sent to Spotify. With `transliterate`, also spell it in ASCII, e.g.
+/// `Beyoncé` as `Beyonce` and `Motörhead` as `Motorhead`.
+pub fn normalize_query(q: &str, transliterate: bool) -> String {
+    let q: String = q.nfc().filter_map(normalize_char).collect();
+    let q = if transliterate { deunicode::deunicode(&q) } else { q };
+    q.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Typographic variant of a character mapped to the ASCII one Spotify expects;
+/// `None` for invisible characters.
+fn normalize_char(c: char) -> Option<char> {
+    Some(match c {
+        // Single quotes and stand-ins for apostrophes (prime, acute and grave accents).
+        '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' | '\u{00B4}' | '`' => '\'',
+        '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' | '\u{00AB}' | '\u{00BB}' => '"',
+        // Hyphens and dashes.
+        '\u{2010}'..='\u{2015}' | '\u{2212}' => '-',
+        // Zero-width spaces and joiners, byte order mark, soft hyphen.
+        '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}' | '\u{00AD}' => return None,
+        c => c,
+    })
+}
@@ -0,0 +1,181 @@
+//! Upstream rate-limit tracking from Spotify response headers.
+//!
+//! Spotify signals throttling with `429` + `Retry-After`; generic `X-RateLimit-*`
+//! headers are recorded too when a gateway in front of the API adds them.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use super::schema::{Drift, DriftKind};
+use crate::clock::unix_now;
+
+/// Rate-limit headers parsed from a single upstream response.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitInfo {
+    pub retry_after: Option<u64>,
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    pub reset: Option<u64>,
+}
+
+impl RateLimitInfo {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let num = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+        };
+        Self {
+            retry_after: num("retry-after"),
+            limit: num("x-ratelimit-limit"),
+            remaining: num("x-ratelimit-remaining"),
+            reset: num("x-ratelimit-reset"),
+        }
+    }
+}
+
+/// Last observed upstream state for one Spotify endpoint.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EndpointStatus {
+    pub requests: u64,
+    pub rate_limited: u64,
+    pub last_status: Option<u16>,
+    /// Unix seconds of the last response.
+    pub last_response_at: Option<u64>,
+    /// Retry-After (seconds) from the most recent 429.
+    pub last_retry_after: Option<u64>,
+    /// Unix seconds until which Spotify asked us to back off.
+    pub throttled_until: Option<u64>,
+    pub ratelimit_limit: Option<u64>,
+    pub ratelimit_remaining: Option<u64>,
+    /// `X-RateLimit-Reset` as sent (gateway-defined: seconds or a timestamp).
+    pub ratelimit_reset: Option<u64>,
+    /// Responses with each field the client doesn't know, by path (lenient schema only).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub unknown_fields: BTreeMap<String, u64>,
+    /// Array elements that failed to decode and were skipped, by path (lenient schema only).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub invalid_items: BTreeMap<String, u64>,
+}
+
+/// Snapshot returned by `/admin/upstream`.
+#[derive(Clone, Debug, Serialize)]
+pub struct UpstreamSnapshot {
+    /// True if any endpoint is currently inside a Retry-After window.
+    pub throttled: bool,
+    pub endpoints: BTreeMap<&'static str, EndpointStatus>,
+}
+
+/// Per-endpoint upstream status, updated on every Spotify response.
+pub struct UpstreamTracker {
+    /// `tenant` label on the gauges.
+    #[cfg(feature = "metrics")]
+    tenant: String,
+    endpoints: Mutex<BTreeMap<&'static str, EndpointStatus>>,
+}
+
+impl UpstreamTracker {
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub fn new(tenant: impl Into<String>) -> Self {
+        Self {
+            #[cfg(feature = "metrics")]
+            tenant: tenant.into(),
+            endpoints: Mutex::default(),
+        }
+    }
+
+    /// Record a response's status and rate-limit headers, updating gauges.
+    pub fn record(&self, endpoint: &'static str, status: StatusCode, headers: &HeaderMap) {
+        let info = RateLimitInfo::from_headers(headers);
+        let now = unix_now();
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("spotify_rate_limited_total", "tenant" => self.tenant.clone(), "endpoint" => endpoint)
+                .increment(1);
+            tracing::warn!(endpoint, retry_after = ?info.retry_after, "Spotify rate limit hit");
+        }
+        #[cfg(feature = "metrics")]
+        {
+            let labels = [("tenant", self.tenant.clone()), ("endpoint", endpoint.to_string())];
+            metrics::gauge!("spotify_retry_after_seconds", &labels).set(info.retry_after.unwrap_or(0) as f64);
+            if let Some(remaining) = info.remaining {
+                metrics::gauge!("spotify_ratelimit_remaining", &labels).set(remaining as f64);
+            }
+            if let Some(limit) = info.limit {
+                metrics::gauge!("spotify_ratelimit_limit", &labels).set(limit as f64);
+            }
+        }
+
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let e = endpoints.entry(endpoint).or_default();
+        e.requests += 1;
+        e.last_status = Some(status.as_u16());
+        e.last_response_at = Some(now);
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            e.rate_limited += 1;
+            e.last_retry_after = info.retry_after;
+            e.throttled_until = info.retry_after.map(|s| now + s);
+        }
+        if info.limit.is_some() {
+            e.ratelimit_limit = info.limit;
+        }
+        if info.remaining.is_some() {
+            e.ratelimit_remaining = info.remaining;
+        }
+        if info.reset.is_some() {
+            e.ratelimit_reset = info.reset;
+        }
+    }
+
+    /// Count a schema difference in a response from `endpoint`, logging it the first time
+    /// its path is seen.
+    pub(super) fn record_drift(&self, endpoint: &'static str, drift: &Drift) {
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "spotify_schema_drift_total",
+            "tenant" => self.tenant.clone(),
+            "endpoint" => endpoint,
+            "kind" => drift.kind.as_str(),
+        )
+        .increment(1);
+
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let e = endpoints.entry(endpoint).or_default();
+        let counts = match drift.kind {
+            DriftKind::UnknownField => &mut e.unknown_fields,
+            DriftKind::InvalidItem => &mut e.invalid_items,
+        };
+        let count = counts.entry(drift.path.clone()).or_default();
+        *count += 1;
+        if *count == 1 {
+            let sample = drift.sample.as_deref().unwrap_or_default();
+            match drift.kind {
+                DriftKind::UnknownField => {
+                    tracing::warn!(endpoint, field = %drift.path, sample, "Spotify response has an unknown field")
+                }
+                DriftKind::InvalidItem => tracing::warn!(
+                    endpoint,
+                    field = %drift.path,
+                    error = drift.error.as_deref().unwrap_or_default(),
+                    sample,
+                    "skipped a Spotify response element that failed to decode"
+                ),
+            }
+        } else if let Some(sample) = &drift.sample {
+            tracing::debug!(endpoint, field = %drift.path, kind = drift.kind.as_str(), sample, "Spotify schema drift");
+        }
+    }
+
+    pub fn snapshot(&self) -> UpstreamSnapshot {
+        let now = unix_now();
+        let endpoints = self.endpoints.lock().unwrap().clone();
+        let throttled = endpoints.values().any(|e| e.throttled_until.is_some_and(|t| t > now));
+        UpstreamSnapshot { throttled, endpoints }
+    }
+}
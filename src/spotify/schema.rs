@@ -0,0 +1,172 @@
+//! Lenient decoding of Spotify responses, from
+//! [`SpotifyClientBuilder::lenient_schema`](super::SpotifyClientBuilder::lenient_schema).
+//!
+//! Spotify adds fields to its responses and now and then drops or retypes one. By default
+//! new fields are ignored silently and any other mismatch fails the request. Leniently,
+//! every field the types don't know is reported, and an array element that doesn't
+//! decode is replaced with `null` (or removed, where `null` isn't allowed either), so a
+//! malformed track costs that track rather than the whole page. Mismatches outside an
+//! array still fail.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Elements repaired in one response before giving up on it.
+const MAX_REPAIRS: usize = 64;
+/// Longest JSON sample kept for the log.
+const SAMPLE_BYTES: usize = 512;
+
+/// How a response differed from the types it was decoded into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum DriftKind {
+    /// A field the types don't have; ignored.
+    UnknownField,
+    /// An array element that failed to decode; nulled or removed.
+    InvalidItem,
+}
+
+impl DriftKind {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            DriftKind::UnknownField => "unknown_field",
+            DriftKind::InvalidItem => "invalid_item",
+        }
+    }
+}
+
+/// One difference found in a response, reported once per response and path.
+#[derive(Debug)]
+pub(super) struct Drift {
+    pub kind: DriftKind,
+    /// Where in the response, with array indexes as `[]` (`tracks.items[].album.label`).
+    pub path: String,
+    /// Why an invalid element failed to decode, with the failing field.
+    pub error: Option<String>,
+    /// The offending JSON, truncated, when samples are kept.
+    pub sample: Option<String>,
+}
+
+/// Decode `body`, repairing invalid array elements, and report what didn't match `T`.
+pub(super) fn decode_lenient<T: DeserializeOwned>(body: &[u8], samples: bool) -> Result<(T, Vec<Drift>), String> {
+    let mut value: Value = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    let mut drift = Vec::new();
+    for _ in 0..=MAX_REPAIRS {
+        let mut unknown: Vec<(String, String)> = Vec::new();
+        let result: Result<T, _> = {
+            let mut record = |path: serde_ignored::Path<'_>| {
+                let (mut label, mut pointer) = (String::new(), String::new());
+                ignored_path(&path, &mut label, &mut pointer);
+                unknown.push((label, pointer));
+            };
+            serde_path_to_error::deserialize(serde_ignored::Deserializer::new(&value, &mut record))
+        };
+        let err = match result {
+            Ok(decoded) => {
+                unknown.sort();
+                unknown.dedup_by(|a, b| a.0 == b.0);
+                drift.extend(unknown.into_iter().map(|(path, pointer)| Drift {
+                    kind: DriftKind::UnknownField,
+                    path,
+                    error: None,
+                    sample: samples.then(|| value.pointer(&pointer).map(sample)).flatten(),
+                }));
+                return Ok((decoded, drift));
+            }
+            Err(err) => err,
+        };
+
+        // Repair the innermost array element the failing field is in.
+        let Some((array, index, path)) = failing_element(err.path()) else {
+            return Err(err.to_string());
+        };
+        let Some(items) = value.pointer_mut(&array).and_then(Value::as_array_mut) else {
+            return Err(err.to_string());
+        };
+        match items.get_mut(index) {
+            Some(Value::Null) => {
+                items.remove(index);
+            }
+            Some(item) => {
+                drift.push(Drift {
+                    kind: DriftKind::InvalidItem,
+                    path,
+                    error: Some(err.to_string()),
+                    sample: samples.then(|| sample(item)),
+                });
+                *item = Value::Null;
+            }
+            None => return Err(err.to_string()),
+        }
+    }
+    Err(format!("gave up after repairing {} invalid elements", MAX_REPAIRS))
+}
+
+/// JSON pointer to the innermost array on `path`, the element's index and its path label.
+fn failing_element(path: &serde_path_to_error::Path) -> Option<(String, usize, String)> {
+    use serde_path_to_error::Segment;
+
+    let segments: Vec<&Segment> = path.iter().collect();
+    let last = segments.iter().rposition(|s| matches!(s, Segment::Seq { .. }))?;
+    let (mut pointer, mut label) = (String::new(), String::new());
+    for segment in &segments[..last] {
+        match segment {
+            Segment::Seq { index } => {
+                pointer.push_str(&format!("/{}", index));
+                label.push_str("[]");
+            }
+            Segment::Map { key } | Segment::Enum { variant: key } => {
+                push_key(&mut label, &mut pointer, key);
+            }
+            Segment::Unknown => return None,
+        }
+    }
+    let Segment::Seq { index } = segments[last] else {
+        return None;
+    };
+    label.push_str("[]");
+    Some((pointer, *index, label))
+}
+
+/// Build the label and JSON pointer of an ignored field's path.
+fn ignored_path(path: &serde_ignored::Path<'_>, label: &mut String, pointer: &mut String) {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => {}
+        Path::Seq { parent, index } => {
+            ignored_path(parent, label, pointer);
+            pointer.push_str(&format!("/{}", index));
+            label.push_str("[]");
+        }
+        Path::Map { parent, key } => {
+            ignored_path(parent, label, pointer);
+            push_key(label, pointer, key);
+        }
+        Path::Some { parent } | Path::NewtypeStruct { parent } | Path::NewtypeVariant { parent } => {
+            ignored_path(parent, label, pointer);
+        }
+    }
+}
+
+fn push_key(label: &mut String, pointer: &mut String, key: &str) {
+    if !label.is_empty() {
+        label.push('.');
+    }
+    label.push_str(key);
+    pointer.push('/');
+    pointer.push_str(&key.replace('~', "~0").replace('/', "~1"));
+}
+
+/// `value` as JSON, cut to [`SAMPLE_BYTES`].
+fn sample(value: &Value) -> String {
+    let mut json = value.to_string();
+    if json.len() > SAMPLE_BYTES {
+        let mut end = SAMPLE_BYTES;
+        while !json.is_char_boundary(end) {
+            end -= 1;
+        }
+        json.truncate(end);
+        json.push_str("...");
+    }
+    json
+}
@@ -0,0 +1,84 @@
+//! Cosine similarity kernels.
+//!
+//! Similar tracks served from the local store are ranked by brute force against up to
+//! tens of thousands of embeddings, so scoring is batched. Embeddings are short (12
+//! dimensions), too short to vectorize one pair at a time, so the batch kernel scores
+//! eight candidates at once, one per SIMD lane: the query's norm is computed once, and
+//! the candidates' dot products and norms are accumulated dimension by dimension. On
+//! x86_64 it runs a copy compiled for AVX2 and FMA when the CPU has them; other targets
+//! use their baseline vector unit (SSE2, NEON).
+
+/// Candidates scored together; one AVX register of `f32`s.
+const LANES: usize = 8;
+
+/// Cosine similarity of two embeddings; 0 if either is all zeros.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+    let (dot, norm_b) = dot_and_norm(a, b);
+    cosine(dot, dot_and_norm(a, a).1, norm_b)
+}
+
+/// Cosine similarity of `query` to each of `candidates`, in order. Matches calling
+/// [`cosine_similarity`] on each up to rounding, but is faster on large batches.
+pub fn cosine_similarity_batch(query: &[f32], candidates: &[&[f32]]) -> Vec<f32> {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        // SAFETY: the CPU supports the features `batch_avx2` is compiled for.
+        return unsafe { batch_avx2(query, candidates) };
+    }
+    batch(query, candidates)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+fn batch_avx2(query: &[f32], candidates: &[&[f32]]) -> Vec<f32> {
+    batch(query, candidates)
+}
+
+#[inline(always)]
+fn batch(query: &[f32], candidates: &[&[f32]]) -> Vec<f32> {
+    let norm_query = query.iter().map(|x| x * x).sum::<f32>();
+    let mut scores = Vec::with_capacity(candidates.len());
+    // Candidates transposed, `block[d][lane]`, so each step of the loop below works on
+    // dimension `d` of `LANES` candidates at once.
+    let mut block = vec![[0.0f32; LANES]; query.len()];
+    for chunk in candidates.chunks(LANES) {
+        for (lane, candidate) in chunk.iter().enumerate() {
+            for (column, x) in block.iter_mut().zip(candidate.iter()) {
+                column[lane] = *x;
+            }
+        }
+        let (mut dot, mut norm) = ([0.0f32; LANES], [0.0f32; LANES]);
+        for (q, column) in query.iter().zip(&block) {
+            for lane in 0..LANES {
+                dot[lane] += q * column[lane];
+                norm[lane] += column[lane] * column[lane];
+            }
+        }
+        let mut block_scores = [0.0f32; LANES];
+        for lane in 0..LANES {
+            let zero = norm_query == 0.0 || norm[lane] == 0.0;
+            block_scores[lane] = if zero { 0.0 } else { dot[lane] / (norm_query * norm[lane]).sqrt() };
+        }
+        for (candidate, score) in chunk.iter().zip(block_scores) {
+            // Lanes of a candidate with another length hold stale values; compare those
+            // on the common prefix, as `cosine_similarity` does.
+            let exact = candidate.len() == query.len();
+            scores.push(if exact { score } else { cosine_similarity(query, candidate) });
+        }
+    }
+    scores
+}
+
+/// `a · b` and `b · b` over the common length of `a` and `b`.
+fn dot_and_norm(a: &[f32], b: &[f32]) -> (f32, f32) {
+    a.iter().zip(b).fold((0.0, 0.0), |(dot, norm), (x, y)| (dot + x * y, norm + y * y))
+}
+
+fn cosine(dot: f32, norm_a: f32, norm_b: f32) -> f32 {
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
@@ -0,0 +1,228 @@
+//! Access-token acquisition behind [`TokenProvider`].
+//!
+//! [`SpotifyClient`] caches whatever the provider returns and asks for a new token
+//! shortly before it expires.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use super::{instrument, SpotifyClient, SpotifyError};
+use crate::secret::Secret;
+
+/// A bearer token and how long it stays valid.
+#[derive(Clone, Debug)]
+pub struct AccessToken {
+    pub access_token: Secret,
+    /// `None` for tokens that never expire (e.g. a static test token).
+    pub expires_in: Option<Duration>,
+}
+
+/// The cached token and the latest refresh, as reported by `/admin/token`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TokenStatus {
+    /// A token is cached.
+    pub cached: bool,
+    /// Seconds until the cached token is refreshed, a minute before it expires; unset
+    /// without a token or for one that never expires.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_in_secs: Option<u64>,
+    /// Unix seconds of the latest refresh attempt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_refresh_at: Option<u64>,
+    /// Whether the latest refresh attempt succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_refresh_ok: Option<bool>,
+    /// Why the latest refresh attempt failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_refresh_error: Option<String>,
+}
+
+/// Source of Spotify access tokens.
+#[async_trait]
+pub trait TokenProvider: Send + Sync {
+    /// Obtain a fresh token. Called on first use and whenever the cached one expires.
+    async fn fetch_token(&self, http: &TokenHttp<'_>) -> Result<AccessToken, SpotifyError>;
+}
+
+/// HTTP access for providers. Requests carry the client's user-agent, timeout and
+/// trace context, and are recorded under the `token` upstream endpoint.
+pub struct TokenHttp<'a> {
+    pub(super) client: &'a SpotifyClient,
+}
+
+/// Standard OAuth token endpoint response.
+#[derive(Debug, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: Secret,
+    pub expires_in: u64,
+    /// Rotated refresh token, when the server issues one.
+    #[serde(default)]
+    pub refresh_token: Option<Secret>,
+}
+
+impl TokenResponse {
+    fn into_access_token(self) -> AccessToken {
+        AccessToken {
+            access_token: self.access_token,
+            expires_in: Some(Duration::from_secs(self.expires_in)),
+        }
+    }
+}
+
+impl TokenHttp<'_> {
+    /// POST a form-encoded OAuth token request, with HTTP Basic client authentication
+    /// when `basic_auth` is set. 429 maps to `RateLimited`, other 4xx to `Auth`.
+    pub async fn post_token_form(
+        &self,
+        url: &str,
+        basic_auth: Option<(&str, &str)>,
+        form: &[(&str, &str)],
+    ) -> Result<TokenResponse, SpotifyError> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        instrument::inject_trace_context(&mut headers);
+
+        let mut req = self
+            .client
+            .request(reqwest::Method::POST, url)
+            .headers(headers)
+            .form(form);
+        if let Some((id, secret)) = basic_auth {
+            let auth = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", id, secret).as_bytes());
+            req = req.header("Authorization", format!("Basic {}", auth));
+        }
+
+        let res = self.client.send("token", req).await?;
+
+        if !res.status.is_success() {
+            let body = res.text();
+            // The token endpoint answers 400 invalid_client / invalid_grant for bad credentials.
+            return Err(match res.status {
+                reqwest::StatusCode::TOO_MANY_REQUESTS => SpotifyError::RateLimited {
+                    retry_after: res.retry_after(),
+                },
+                s if s.is_client_error() => SpotifyError::Auth(format!("token request failed: {} - {}", s, body)),
+                s => SpotifyError::Upstream { status: s.as_u16(), body },
+            });
+        }
+
+        res.json("token")
+    }
+}
+
+/// Client Credentials flow (server-to-server; no user context). The default.
+pub struct ClientCredentials {
+    client_id: String,
+    client_secret: Secret,
+    token_url: String,
+}
+
+impl ClientCredentials {
+    pub fn new(client_id: impl Into<String>, client_secret: impl Into<String>, token_url: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            client_secret: Secret::new(client_secret.into()),
+            token_url: token_url.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl TokenProvider for ClientCredentials {
+    async fn fetch_token(&self, http: &TokenHttp<'_>) -> Result<AccessToken, SpotifyError> {
+        http.post_token_form(
+            &self.token_url,
+            Some((&self.client_id, self.client_secret.expose())),
+            &[("grant_type", "client_credentials")],
+        )
+        .await
+        .map(TokenResponse::into_access_token)
+    }
+}
+
+/// Authorization Code refresh flow: exchanges a user's refresh token for access
+/// tokens, keeping the rotated refresh token when Spotify issues a new one.
+pub struct RefreshToken {
+    client_id: String,
+    client_secret: Secret,
+    token_url: String,
+    refresh_token: Mutex<Secret>,
+}
+
+impl RefreshToken {
+    pub fn new(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        token_url: impl Into<String>,
+        refresh_token: impl Into<String>,
+    ) -> Self {
+        Self {
+            client_id: client_id.into(),
+            client_secret: Secret::new(client_secret.into()),
+            token_url: token_url.into(),
+            refresh_token: Mutex::new(Secret::new(refresh_token.into())),
+        }
+    }
+}
+
+#[async_trait]
+impl TokenProvider for RefreshToken {
+    async fn fetch_token(&self, http: &TokenHttp<'_>) -> Result<AccessToken, SpotifyError> {
+        let refresh_token = self.refresh_token.lock().unwrap().clone();
+        let res = http
+            .post_token_form(
+                &self.token_url,
+                Some((&self.client_id, self.client_secret.expose())),
+                &[("grant_type", "refresh_token"), ("refresh_token", refresh_token.expose())],
+            )
+            .await?;
+        if let Some(ref rotated) = res.refresh_token {
+            *self.refresh_token.lock().unwrap() = rotated.clone();
+        }
+        Ok(res.into_access_token())
+    }
+}
+
+/// A fixed token, e.g. for tests against a mock server.
+pub struct StaticToken(pub Secret);
+
+#[async_trait]
+impl TokenProvider for StaticToken {
+    async fn fetch_token(&self, _http: &TokenHttp<'_>) -> Result<AccessToken, SpotifyError> {
+        Ok(AccessToken {
+            access_token: self.0.clone(),
+            expires_in: None,
+        })
+    }
+}
+
+type TokenFuture = Pin<Box<dyn Future<Output = Result<AccessToken, SpotifyError>> + Send>>;
+
+/// Tokens from an external source (secrets manager, sidecar, token broker) via a closure.
+pub struct ExternalToken {
+    fetch: Box<dyn Fn() -> TokenFuture + Send + Sync>,
+}
+
+impl ExternalToken {
+    pub fn new<F, Fut>(fetch: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<AccessToken, SpotifyError>> + Send + 'static,
+    {
+        Self {
+            fetch: Box::new(move || Box::pin(fetch())),
+        }
+    }
+}
+
+#[async_trait]
+impl TokenProvider for ExternalToken {
+    async fn fetch_token(&self, _http: &TokenHttp<'_>) -> Result<AccessToken, SpotifyError> {
+        (self.fetch)().await
+    }
+}
@@ -0,0 +1,75 @@
+//! Shared state for the HTTP router.
+
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+#[cfg(feature = "prometheus")]
+use metrics_exporter_prometheus::PrometheusHandle;
+
+use crate::jobs::Jobs;
+use crate::limits::RequestLimits;
+use crate::reload::{Reloader, SharedConfig};
+use crate::saved::SavedQueries;
+use crate::spotify::{DynSpotifyApi, SearchLimits};
+use crate::suggest::Suggester;
+use crate::usage::Usage;
+use crate::warm::CacheWarmer;
+#[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+use crate::local_search::LocalIndex;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+use crate::storage::DynStorage;
+
+/// State shared across HTTP handlers.
+#[derive(Clone)]
+pub struct AppState {
+    /// Live configuration; reloadable settings change on reload.
+    pub config: SharedConfig,
+    pub reloader: Reloader,
+    pub spotify: DynSpotifyApi,
+    /// Background jobs (playlist ingest, batch match).
+    pub jobs: Jobs,
+    /// Page size and offset bounds for searches.
+    pub search_limits: SearchLimits,
+    /// Request body, query string and batch size limits.
+    pub request_limits: RequestLimits,
+    /// Cached typeahead suggestions.
+    pub suggester: Arc<Suggester>,
+    /// Saved searches (`/api/v1/saved/:alias`).
+    pub saved: Arc<SavedQueries>,
+    /// Seed file warm-ups (`/admin/cache/warm`).
+    pub warmer: Arc<CacheWarmer>,
+    /// Spotify requests by route and tenant (`/admin/usage`).
+    pub usage: Arc<Usage>,
+    /// The `DATABASE_URL` store, for endpoints that read it directly (export).
+    #[cfg(any(feature = "sqlite", feature = "postgres"))]
+    pub store: Option<DynStorage>,
+    /// Full-text index of the store, with `LOCAL_SEARCH`.
+    #[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+    pub local_index: Option<Arc<LocalIndex>>,
+    #[cfg(feature = "prometheus")]
+    pub metrics: PrometheusHandle,
+}
+
+impl FromRef<AppState> for SearchLimits {
+    fn from_ref(state: &AppState) -> Self {
+        state.search_limits
+    }
+}
+
+impl FromRef<AppState> for RequestLimits {
+    fn from_ref(state: &AppState) -> Self {
+        state.request_limits
+    }
+}
+
+impl FromRef<AppState> for Jobs {
+    fn from_ref(state: &AppState) -> Self {
+        state.jobs.clone()
+    }
+}
+
+impl FromRef<AppState> for DynSpotifyApi {
+    fn from_ref(state: &AppState) -> Self {
+        state.spotify.clone()
+    }
+}
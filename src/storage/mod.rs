@@ -0,0 +1,636 @@
+//! Local persistence of fetched catalog data (`sqlite` / `postgres` features).
+//!
+//! [`StoredSpotifyApi`] wraps any [`SpotifyApi`] and upserts every track, audio
+//! features object and embedding it sees into a [`Storage`] backend: a SQLite file
+//! for a single instance, or PostgreSQL shared by several replicas. Lookups by ID are
+//! answered from the store first, so repeat imports and restarts only ask Spotify for
+//! IDs it hasn't returned before. Search and recommendations always go upstream, since
+//! their results change, but the tracks they return are stored.
+//!
+//! The store is an optimization: if it fails, the error is logged and the request is
+//! served from Spotify as if there were no store.
+//!
+//! It is also the fallback when Spotify can't be reached. With `OFFLINE_MODE`, or when
+//! Spotify is rate limiting us, failing with a network error or answering 5xx, track
+//! and feature lookups return what the store has (unknown IDs come back missing) and
+//! similar tracks are ranked against the most recently stored ones. Such responses are
+//! marked via [`access_log::record_served_from_cache`]. Search and recommendations
+//! have no offline answer and fail with [`SpotifyError::Unavailable`].
+//!
+//! With an [`OutboxEncoder`] ([`StoredSpotifyApi::outbox`]), an event for every track
+//! resolved with features is written to an outbox table in the same transaction as
+//! the tracks and features fetched for it. A relay claims pending events with
+//! [`Storage::claim_outbox`], delivers them and deletes them, so events survive
+//! crashes on either side of the write.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::access_log;
+use crate::spotify::{
+    rank_similar, AlbumDetail, AlbumTracksPage, ArtistAlbumsPage, ArtistDetail, AuditRecord, AudioFeatures,
+    ByteStream, DynSpotifyApi, PlaylistTracksPage, RecommendationSeeds, ScoredTrack, SearchTracksResponse,
+    SearchTracksWithFeaturesResponse, SpotifyApi, SpotifyError, TokenStatus, Track, TrackWithFeatures, UpstreamSnapshot,
+};
+
+#[cfg(feature = "postgres")]
+mod postgres;
+mod refresh;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+pub use refresh::{RefreshError, RefreshSummary, Refresher};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// Max IDs per `get_tracks` call, as for Spotify's batch endpoint.
+const MAX_TRACK_IDS: usize = 50;
+/// Max IDs per `get_audio_features` call.
+const MAX_FEATURE_IDS: usize = 100;
+/// Stored tracks ranked against a seed when similar tracks are served from the store.
+/// Every candidate is read and deserialized on each request, so this stays small.
+const MAX_OFFLINE_CANDIDATES: u32 = 1_000;
+
+/// Failure reading or writing the local store.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("database migration failed: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+    /// A stored row (or a value being stored) is not valid JSON for its type.
+    #[error("stored data could not be encoded or decoded: {0}")]
+    Serde(#[from] serde_json::Error),
+    /// The URL scheme has no backend in this build.
+    #[error("unsupported database URL scheme '{0}' (expected sqlite: or postgres:)")]
+    UnsupportedUrl(String),
+}
+
+/// An encoded event waiting in the outbox.
+#[derive(Debug, Clone)]
+pub struct OutboxEvent {
+    /// Unique event ID, delivered with the event so consumers can drop redeliveries.
+    pub id: String,
+    /// Message key.
+    pub key: String,
+    pub payload: Vec<u8>,
+}
+
+/// A stored track with its features, as exported.
+#[derive(Debug, Clone)]
+pub struct StoredTrack {
+    pub track: TrackWithFeatures,
+    /// When the track was last fetched from Spotify, in Unix seconds.
+    pub fetched_at: i64,
+}
+
+/// Encodes resolved tracks as outbox events; see [`StoredSpotifyApi::outbox`].
+pub trait OutboxEncoder: Send + Sync {
+    /// Events for `tracks`, resolved by the `source` operation (e.g. `search`).
+    fn encode(&self, source: &str, tracks: &[TrackWithFeatures]) -> Vec<OutboxEvent>;
+}
+
+/// Persistent store for catalog data, keyed by Spotify track ID.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Backend name for logs, e.g. `sqlite`.
+    fn backend(&self) -> &'static str;
+
+    /// Stored tracks among `ids`, by ID.
+    async fn tracks(&self, ids: &[String]) -> Result<HashMap<String, Track>, StorageError>;
+
+    /// Stored audio features among `ids`, by track ID. `None` values are tracks Spotify
+    /// has no features for.
+    async fn audio_features(&self, ids: &[String]) -> Result<HashMap<String, Option<AudioFeatures>>, StorageError>;
+
+    /// Insert or replace `tracks`, stamping them with the current time.
+    async fn upsert_tracks(&self, tracks: &[&Track]) -> Result<(), StorageError>;
+
+    /// Insert or replace audio features (and their embeddings) per track ID; `None`
+    /// records that Spotify has none for the track.
+    async fn upsert_audio_features(&self, features: &[(&str, Option<&AudioFeatures>)]) -> Result<(), StorageError>;
+
+    /// Up to `limit` stored tracks that have audio features, most recently fetched first.
+    async fn recent_tracks_with_features(&self, limit: u32) -> Result<Vec<TrackWithFeatures>, StorageError>;
+
+    /// Up to `limit` IDs of tracks fetched before `fetched_before` (Unix seconds),
+    /// oldest first.
+    async fn stale_track_ids(&self, fetched_before: i64, limit: u32) -> Result<Vec<String>, StorageError>;
+
+    /// Stamp stored tracks with the current time without changing their data.
+    async fn touch_tracks(&self, ids: &[String]) -> Result<(), StorageError>;
+
+    /// Up to `limit` stored tracks fetched within `fetched` (Unix seconds), in ID order
+    /// starting after ID `after`, with their audio features where stored. For paging
+    /// through the whole store.
+    async fn export_tracks(
+        &self,
+        after: Option<&str>,
+        fetched: Range<i64>,
+        limit: u32,
+    ) -> Result<Vec<StoredTrack>, StorageError>;
+
+    /// [`upsert_tracks`](Self::upsert_tracks) and
+    /// [`upsert_audio_features`](Self::upsert_audio_features) that also appends
+    /// `events` to the outbox, all in one transaction.
+    async fn upsert_with_events(
+        &self,
+        tracks: &[&Track],
+        features: &[(&str, Option<&AudioFeatures>)],
+        events: &[OutboxEvent],
+    ) -> Result<(), StorageError>;
+
+    /// Claim up to `limit` outbox events that no relay holds, oldest first, until
+    /// `claimed_until` (Unix seconds). Unless deleted by then, they can be claimed again.
+    async fn claim_outbox(&self, limit: u32, claimed_until: i64) -> Result<Vec<OutboxEvent>, StorageError>;
+
+    /// Remove delivered events from the outbox.
+    async fn delete_outbox(&self, ids: &[String]) -> Result<(), StorageError>;
+
+    /// Append `calls` to the audit trail of upstream Spotify requests.
+    async fn insert_upstream_calls(&self, calls: &[AuditRecord]) -> Result<(), StorageError>;
+}
+
+/// Shared handle to a storage backend.
+pub type DynStorage = Arc<dyn Storage>;
+
+/// Connect to the backend for `url`'s scheme (`sqlite:` or `postgres:`/`postgresql:`)
+/// with a pool of up to `max_connections`, applying embedded migrations.
+pub async fn connect(url: &str, max_connections: u32) -> Result<DynStorage, StorageError> {
+    #[cfg(feature = "sqlite")]
+    if url.starts_with("sqlite:") {
+        return Ok(Arc::new(SqliteStore::connect(url, max_connections).await?));
+    }
+    #[cfg(feature = "postgres")]
+    if url.starts_with("postgres:") || url.starts_with("postgresql:") {
+        return Ok(Arc::new(PostgresStore::connect(url, max_connections).await?));
+    }
+    Err(StorageError::UnsupportedUrl(url.split(':').next().unwrap_or_default().to_string()))
+}
+
+/// A [`SpotifyApi`] that persists what it fetches, serves repeat ID lookups from the
+/// store, and falls back to the store when Spotify is unavailable.
+pub struct StoredSpotifyApi {
+    inner: DynSpotifyApi,
+    store: DynStorage,
+    offline: bool,
+    outbox: Option<Arc<dyn OutboxEncoder>>,
+}
+
+/// Tracks and audio features looked up by ID, with what had to be fetched from Spotify.
+struct Resolved {
+    tracks: Vec<TrackWithFeatures>,
+    fetched_tracks: Vec<Track>,
+    fetched_features: Vec<(String, Option<AudioFeatures>)>,
+}
+
+impl StoredSpotifyApi {
+    pub fn new(inner: DynSpotifyApi, store: DynStorage) -> Self {
+        Self {
+            inner,
+            store,
+            offline: false,
+            outbox: None,
+        }
+    }
+
+    /// Never call Spotify; answer everything the store can and fail the rest.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Write an event from `encoder` for every track an endpoint returns with audio
+    /// features (searches with features, track lookups, recommendations and similar
+    /// tracks) to the outbox, in the same transaction as the data fetched for it.
+    pub fn outbox(mut self, encoder: Arc<dyn OutboxEncoder>) -> Self {
+        self.outbox = Some(encoder);
+        self
+    }
+
+    /// Skip Spotify up front: offline mode, or every call would be throttled anyway.
+    fn skip_upstream(&self) -> bool {
+        self.offline || self.inner.upstream_status().throttled
+    }
+
+    /// Error for calls that need Spotify while it is being skipped.
+    fn unavailable(&self, what: &str) -> SpotifyError {
+        let reason = if self.offline { "offline mode" } else { "Spotify is rate limiting" };
+        SpotifyError::Unavailable(format!("{} needs Spotify ({})", what, reason))
+    }
+
+    /// Similar tracks ranked against recently stored tracks instead of recommendations.
+    async fn similar_from_store(&self, id: &str, limit: Option<u32>) -> Result<Vec<ScoredTrack>, SpotifyError> {
+        let resolved = self.resolve(&[id.to_string()]).await?;
+        self.save_fetched(&resolved, &[]).await;
+        let seed = resolved
+            .tracks
+            .into_iter()
+            .next()
+            .ok_or_else(|| SpotifyError::NotFound(format!("track {} (not in the local store)", id)))?;
+        let candidates = self
+            .store
+            .recent_tracks_with_features(MAX_OFFLINE_CANDIDATES)
+            .await
+            .map_err(|e| SpotifyError::Unavailable(format!("local store: {}", e)))?;
+        access_log::record_served_from_cache();
+        let scored = rank_similar(&seed, candidates, limit.unwrap_or(20).clamp(1, 50) as usize)?;
+        let tracks: Vec<TrackWithFeatures> = scored.iter().map(|s| s.track.clone()).collect();
+        self.save(&[], &[], &self.events("similar", &tracks)).await;
+        Ok(scored)
+    }
+
+    /// Outbox events for `tracks` resolved by `source`; none without an outbox.
+    fn events(&self, source: &str, tracks: &[TrackWithFeatures]) -> Vec<OutboxEvent> {
+        self.outbox
+            .as_ref()
+            .map(|encoder| encoder.encode(source, tracks))
+            .unwrap_or_default()
+    }
+
+    /// Store fetched tracks and features, together with `events` if there are any.
+    async fn save(&self, tracks: &[&Track], features: &[(&str, Option<&AudioFeatures>)], events: &[OutboxEvent]) {
+        if events.is_empty() {
+            log_failure("tracks", self.store.upsert_tracks(tracks).await);
+            log_failure("audio_features", self.store.upsert_audio_features(features).await);
+        } else {
+            log_failure("outbox", self.store.upsert_with_events(tracks, features, events).await);
+        }
+    }
+
+    /// Store what [`resolve`](Self::resolve) fetched, together with `events`.
+    async fn save_fetched(&self, resolved: &Resolved, events: &[OutboxEvent]) {
+        let tracks: Vec<&Track> = resolved.fetched_tracks.iter().collect();
+        let features: Vec<(&str, Option<&AudioFeatures>)> = resolved
+            .fetched_features
+            .iter()
+            .map(|(id, f)| (id.as_str(), f.as_ref()))
+            .collect();
+        self.save(&tracks, &features, events).await;
+    }
+
+    /// Store tracks and their features as returned by an upstream `source` call.
+    async fn persist(&self, source: &str, tracks: &[TrackWithFeatures]) {
+        let stored: Vec<&Track> = tracks.iter().map(|t| &t.track).collect();
+        let features: Vec<(&str, Option<&AudioFeatures>)> = tracks
+            .iter()
+            .map(|t| (t.track.id.as_str(), t.audio_features.as_ref()))
+            .collect();
+        self.save(&stored, &features, &self.events(source, tracks)).await;
+    }
+
+    /// Tracks for `ids` from the store, fetching missing ones from Spotify when it's
+    /// available. Also returns the fetched tracks, for the caller to store.
+    async fn lookup_tracks(&self, ids: &[String]) -> Result<(Vec<Option<Track>>, Vec<Track>), SpotifyError> {
+        if self.offline {
+            access_log::record_served_from_cache();
+        }
+        let mut found = self.store.tracks(ids).await.unwrap_or_else(|e| {
+            log_failure("tracks", Err(e));
+            HashMap::new()
+        });
+        let missing: Vec<String> = ids.iter().filter(|id| !found.contains_key(*id)).cloned().collect();
+        record_lookup("tracks", ids.len() - missing.len(), missing.len());
+
+        let mut fetched_tracks = Vec::new();
+        if !missing.is_empty() {
+            match self.fetch_upstream(self.inner.get_tracks(&missing)).await? {
+                Some(fetched) => {
+                    for (id, track) in missing.into_iter().zip(fetched) {
+                        if let Some(track) = track {
+                            fetched_tracks.push(track.clone());
+                            found.insert(id, track);
+                        }
+                    }
+                }
+                None => access_log::record_served_from_cache(),
+            }
+        }
+        Ok((ids.iter().map(|id| found.get(id).cloned()).collect(), fetched_tracks))
+    }
+
+    /// Audio features for `ids` from the store, fetching missing ones from Spotify when
+    /// it's available. Also returns the fetched features by track ID.
+    async fn lookup_audio_features(
+        &self,
+        ids: &[String],
+    ) -> Result<(Vec<Option<AudioFeatures>>, Vec<(String, Option<AudioFeatures>)>), SpotifyError> {
+        if self.offline {
+            access_log::record_served_from_cache();
+        }
+        let mut found = self.store.audio_features(ids).await.unwrap_or_else(|e| {
+            log_failure("audio_features", Err(e));
+            HashMap::new()
+        });
+        let missing: Vec<String> = ids.iter().filter(|id| !found.contains_key(*id)).cloned().collect();
+        record_lookup("audio_features", ids.len() - missing.len(), missing.len());
+
+        let mut fetched_features = Vec::new();
+        if !missing.is_empty() {
+            match self.fetch_upstream(self.inner.get_audio_features(&missing)).await? {
+                Some(fetched) => {
+                    fetched_features = missing.into_iter().zip(fetched).collect();
+                    found.extend(fetched_features.iter().cloned());
+                }
+                None => access_log::record_served_from_cache(),
+            }
+        }
+        Ok((ids.iter().map(|id| found.get(id).cloned().flatten()).collect(), fetched_features))
+    }
+
+    /// Tracks with features for `ids`, as [`get_tracks_with_features`] returns them,
+    /// without storing anything yet.
+    ///
+    /// [`get_tracks_with_features`]: SpotifyApi::get_tracks_with_features
+    async fn resolve(&self, ids: &[String]) -> Result<Resolved, SpotifyError> {
+        let (tracks, features) = tokio::join!(self.lookup_tracks(ids), self.lookup_audio_features(ids));
+        let (tracks, fetched_tracks) = tracks?;
+        let (features, fetched_features) = features?;
+        Ok(Resolved {
+            tracks: with_features(tracks.into_iter(), features),
+            fetched_tracks,
+            fetched_features,
+        })
+    }
+
+    /// Run an upstream lookup for IDs the store is missing. `None` means Spotify is
+    /// unavailable and the caller should answer with what the store has.
+    async fn fetch_upstream<T>(
+        &self,
+        call: impl std::future::Future<Output = Result<T, SpotifyError>>,
+    ) -> Result<Option<T>, SpotifyError> {
+        if self.skip_upstream() {
+            return Ok(None);
+        }
+        match call.await {
+            Err(e) if is_outage(&e) => {
+                tracing::warn!("Spotify unavailable, answering from the local store: {}", e);
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+}
+
+#[async_trait]
+impl SpotifyApi for StoredSpotifyApi {
+    async fn has_token(&self) -> bool {
+        // Offline, readiness doesn't depend on Spotify.
+        self.offline || self.inner.has_token().await
+    }
+
+    fn upstream_status(&self) -> UpstreamSnapshot {
+        self.inner.upstream_status()
+    }
+
+    fn token_status(&self) -> TokenStatus {
+        self.inner.token_status()
+    }
+
+    async fn refresh_token(&self) -> Result<(), SpotifyError> {
+        self.inner.refresh_token().await
+    }
+
+    async fn search_tracks(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksResponse, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("search"));
+        }
+        let result = self.inner.search_tracks(q, limit, offset).await?;
+        let tracks: Vec<&Track> = result.tracks.iter().collect();
+        log_failure("tracks", self.store.upsert_tracks(&tracks).await);
+        Ok(result)
+    }
+
+    async fn search_tracks_with_features(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksWithFeaturesResponse, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("search"));
+        }
+        let result = self.inner.search_tracks(q, limit, offset).await?;
+        let ids: Vec<String> = result.tracks.iter().map(|t| t.id.clone()).collect();
+        let (features, fetched_features) = self.lookup_audio_features(&ids[..ids.len().min(MAX_FEATURE_IDS)]).await?;
+        let tracks = with_features(result.tracks.iter().cloned().map(Some), features);
+
+        let stored: Vec<&Track> = result.tracks.iter().collect();
+        let stored_features: Vec<(&str, Option<&AudioFeatures>)> =
+            fetched_features.iter().map(|(id, f)| (id.as_str(), f.as_ref())).collect();
+        self.save(&stored, &stored_features, &self.events("search", &tracks)).await;
+        Ok(SearchTracksWithFeaturesResponse {
+            tracks,
+            total: result.total,
+            limit: result.limit,
+            offset: result.offset,
+        })
+    }
+
+    /// Passed through: raw bodies aren't decoded, so they're neither stored nor served
+    /// from the store.
+    async fn search_tracks_raw(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ByteStream, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("search"));
+        }
+        self.inner.search_tracks_raw(q, limit, offset).await
+    }
+
+    async fn get_tracks(&self, ids: &[String]) -> Result<Vec<Option<Track>>, SpotifyError> {
+        let (tracks, fetched) = self.lookup_tracks(&ids[..ids.len().min(MAX_TRACK_IDS)]).await?;
+        log_failure("tracks", self.store.upsert_tracks(&fetched.iter().collect::<Vec<_>>()).await);
+        Ok(tracks)
+    }
+
+    async fn get_tracks_raw(&self, ids: &[String]) -> Result<ByteStream, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("tracks"));
+        }
+        self.inner.get_tracks_raw(ids).await
+    }
+
+    async fn get_audio_features(&self, ids: &[String]) -> Result<Vec<Option<AudioFeatures>>, SpotifyError> {
+        let (features, fetched) = self.lookup_audio_features(&ids[..ids.len().min(MAX_FEATURE_IDS)]).await?;
+        let stored: Vec<(&str, Option<&AudioFeatures>)> =
+            fetched.iter().map(|(id, f)| (id.as_str(), f.as_ref())).collect();
+        log_failure("audio_features", self.store.upsert_audio_features(&stored).await);
+        Ok(features)
+    }
+
+    async fn get_tracks_with_features(&self, ids: &[String]) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        let ids = &ids[..ids.len().min(MAX_TRACK_IDS)];
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let resolved = self.resolve(ids).await?;
+        self.save_fetched(&resolved, &self.events("tracks_with_features", &resolved.tracks))
+            .await;
+        Ok(resolved.tracks)
+    }
+
+    async fn get_recommendations_with_features(
+        &self,
+        seeds: &RecommendationSeeds,
+        limit: Option<u32>,
+    ) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("recommendations"));
+        }
+        let tracks = self.inner.get_recommendations_with_features(seeds, limit).await?;
+        self.persist("recommendations", &tracks).await;
+        Ok(tracks)
+    }
+
+    async fn get_similar_tracks(&self, id: &str, limit: Option<u32>) -> Result<Vec<ScoredTrack>, SpotifyError> {
+        if self.skip_upstream() {
+            return self.similar_from_store(id, limit).await;
+        }
+        let scored = match self.inner.get_similar_tracks(id, limit).await {
+            Err(e) if is_outage(&e) => {
+                tracing::warn!("Spotify unavailable, ranking similar tracks from the local store: {}", e);
+                return self.similar_from_store(id, limit).await;
+            }
+            result => result?,
+        };
+        let tracks: Vec<TrackWithFeatures> = scored.iter().map(|s| s.track.clone()).collect();
+        self.persist("similar", &tracks).await;
+        Ok(scored)
+    }
+
+    async fn get_playlist_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<PlaylistTracksPage, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("playlists"));
+        }
+        let page = self.inner.get_playlist_tracks(id, limit, offset).await?;
+        let tracks: Vec<&Track> = page.tracks.iter().collect();
+        log_failure("tracks", self.store.upsert_tracks(&tracks).await);
+        Ok(page)
+    }
+
+    async fn get_artist_albums(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ArtistAlbumsPage, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("albums"));
+        }
+        self.inner.get_artist_albums(id, limit, offset).await
+    }
+
+    async fn get_album_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<AlbumTracksPage, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("albums"));
+        }
+        self.inner.get_album_tracks(id, limit, offset).await
+    }
+
+    async fn get_album(&self, id: &str) -> Result<AlbumDetail, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("albums"));
+        }
+        self.inner.get_album(id).await
+    }
+
+    async fn get_artist(&self, id: &str) -> Result<ArtistDetail, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("artists"));
+        }
+        self.inner.get_artist(id).await
+    }
+
+    async fn get_albums(&self, ids: &[String]) -> Result<Vec<Option<AlbumDetail>>, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("albums"));
+        }
+        self.inner.get_albums(ids).await
+    }
+
+    async fn get_artists(&self, ids: &[String]) -> Result<Vec<Option<ArtistDetail>>, SpotifyError> {
+        if self.offline {
+            return Err(self.unavailable("artists"));
+        }
+        self.inner.get_artists(ids).await
+    }
+}
+
+/// Pair tracks with the features at the same position, skipping missing tracks.
+fn with_features(
+    tracks: impl Iterator<Item = Option<Track>>,
+    features: Vec<Option<AudioFeatures>>,
+) -> Vec<TrackWithFeatures> {
+    tracks
+        .zip(features)
+        .filter_map(|(track, audio_features)| {
+            let embedding = audio_features.as_ref().map(|af| af.to_embedding());
+            Some(TrackWithFeatures {
+                track: track?,
+                audio_features,
+                embedding,
+            })
+        })
+        .collect()
+}
+
+/// Errors meaning Spotify itself is down or refusing us, rather than a bad request.
+fn is_outage(err: &SpotifyError) -> bool {
+    match err {
+        SpotifyError::Network { .. } | SpotifyError::RateLimited { .. } | SpotifyError::Unavailable(_) => true,
+        SpotifyError::Upstream { status, .. } => *status >= 500,
+        _ => false,
+    }
+}
+
+/// A [`StoredTrack`] from a stored track and features row (JSON).
+fn stored_track(track: &str, features: Option<&str>, fetched_at: i64) -> Result<StoredTrack, StorageError> {
+    let audio_features: Option<AudioFeatures> = features.map(serde_json::from_str).transpose()?;
+    Ok(StoredTrack {
+        track: TrackWithFeatures {
+            track: serde_json::from_str(track)?,
+            embedding: audio_features.as_ref().map(AudioFeatures::to_embedding),
+            audio_features,
+        },
+        fetched_at,
+    })
+}
+
+fn log_failure(table: &'static str, result: Result<(), StorageError>) {
+    if let Err(e) = result {
+        tracing::warn!(table, "local store failed, using Spotify only: {}", e);
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_lookup(table: &'static str, hits: usize, misses: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        metrics::counter!("storage_lookups_total", "table" => table, "result" => "hit").increment(hits as u64);
+        metrics::counter!("storage_lookups_total", "table" => table, "result" => "miss").increment(misses as u64);
+    }
+}
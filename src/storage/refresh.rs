@@ -0,0 +1,158 @@
+//! Background re-fetch of stored tracks that have gone stale.
+//!
+//! Popularity, available markets and audio features change over time, but
+//! [`StoredSpotifyApi`](super::StoredSpotifyApi) answers ID lookups from the store
+//! forever. [`Refresher`] periodically walks the tracks older than a maximum age,
+//! oldest first, and re-fetches them from Spotify in small batches. It pauses between
+//! batches and backs off while Spotify is rate limiting us, so live traffic keeps
+//! priority over the refresh.
+
+use std::time::{Duration, Instant};
+
+use super::{DynStorage, StorageError};
+use crate::clock::unix_now;
+use crate::spotify::{AudioFeatures, DynSpotifyApi, SpotifyError, Track};
+
+/// Tracks per batch: one `/tracks` call and one `/audio-features` call.
+const BATCH_SIZE: u32 = 50;
+/// Wait while Spotify throttles us and gave no `Retry-After`.
+const THROTTLE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Why a refresh pass stopped early.
+#[derive(Debug, thiserror::Error)]
+pub enum RefreshError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Spotify(#[from] SpotifyError),
+}
+
+/// Periodic refresh of stored tracks older than `max_age`.
+pub struct Refresher {
+    spotify: DynSpotifyApi,
+    store: DynStorage,
+    max_age: Duration,
+    interval: Duration,
+    pause: Duration,
+}
+
+/// Outcome of one [`Refresher::refresh_stale`] pass.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RefreshSummary {
+    /// Tracks re-fetched and stored.
+    pub updated: usize,
+    /// Tracks Spotify no longer returns; their stored copy is kept.
+    pub missing: usize,
+}
+
+impl Refresher {
+    /// Refresh through `spotify`, which should call Spotify directly rather than through
+    /// the store. Checks hourly and pauses 1s between batches by default.
+    pub fn new(spotify: DynSpotifyApi, store: DynStorage, max_age: Duration) -> Self {
+        Self {
+            spotify,
+            store,
+            max_age,
+            interval: Duration::from_secs(3600),
+            pause: Duration::from_secs(1),
+        }
+    }
+
+    /// How often to look for stale tracks.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Pause between batches.
+    pub fn pause(mut self, pause: Duration) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Refresh stale tracks every `interval`, forever.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            let start = Instant::now();
+            match self.refresh_stale().await {
+                Ok(summary) if summary.updated + summary.missing > 0 => tracing::info!(
+                    updated = summary.updated,
+                    missing = summary.missing,
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                    "refreshed stale stored tracks"
+                ),
+                Ok(_) => tracing::debug!("no stale stored tracks"),
+                Err(e) => tracing::warn!("stored track refresh stopped early: {}", e),
+            }
+        }
+    }
+
+    /// Re-fetch every track older than `max_age`, batch by batch. Stops at the first
+    /// store failure or Spotify error other than rate limiting; the next pass resumes
+    /// with whatever is still stale.
+    pub async fn refresh_stale(&self) -> Result<RefreshSummary, RefreshError> {
+        let fetched_before = unix_now() as i64 - self.max_age.as_secs() as i64;
+        let mut summary = RefreshSummary::default();
+        loop {
+            if self.spotify.upstream_status().throttled {
+                tokio::time::sleep(THROTTLE_BACKOFF).await;
+                continue;
+            }
+            let ids = self.store.stale_track_ids(fetched_before, BATCH_SIZE).await?;
+            if ids.is_empty() {
+                return Ok(summary);
+            }
+            match self.refresh_batch(&ids).await {
+                Ok(batch) => {
+                    summary.updated += batch.updated;
+                    summary.missing += batch.missing;
+                    record_refresh("updated", batch.updated);
+                    record_refresh("missing", batch.missing);
+                }
+                Err(RefreshError::Spotify(SpotifyError::RateLimited { retry_after })) => {
+                    let wait = retry_after.map(Duration::from_secs).unwrap_or(THROTTLE_BACKOFF);
+                    tracing::debug!("refresh rate limited, waiting {:?}", wait);
+                    tokio::time::sleep(wait).await;
+                    continue;
+                }
+                Err(e) => {
+                    record_refresh("failed", ids.len());
+                    return Err(e);
+                }
+            }
+            tokio::time::sleep(self.pause).await;
+        }
+    }
+
+    /// Re-fetch and store one batch. Tracks Spotify no longer returns are stamped as
+    /// checked so they don't hold up the rest of the backlog.
+    async fn refresh_batch(&self, ids: &[String]) -> Result<RefreshSummary, RefreshError> {
+        let fetched = self.spotify.get_tracks_with_features(ids).await?;
+        let tracks: Vec<&Track> = fetched.iter().map(|t| &t.track).collect();
+        let features: Vec<(&str, Option<&AudioFeatures>)> = fetched
+            .iter()
+            .map(|t| (t.track.id.as_str(), t.audio_features.as_ref()))
+            .collect();
+        let missing: Vec<String> = ids
+            .iter()
+            .filter(|id| !fetched.iter().any(|t| &t.track.id == *id))
+            .cloned()
+            .collect();
+
+        self.store.upsert_tracks(&tracks).await?;
+        self.store.upsert_audio_features(&features).await?;
+        self.store.touch_tracks(&missing).await?;
+        Ok(RefreshSummary {
+            updated: fetched.len(),
+            missing: missing.len(),
+        })
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_refresh(result: &'static str, tracks: usize) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("storage_refreshed_tracks_total", "result" => result).increment(tracks as u64);
+}
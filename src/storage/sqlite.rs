@@ -0,0 +1,253 @@
+//! SQLite [`Storage`] backend (`sqlite` feature).
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+
+use super::{stored_track, OutboxEvent, Storage, StorageError, StoredTrack};
+use crate::clock::unix_now;
+use crate::spotify::{AuditRecord, AudioFeatures, Track, TrackWithFeatures};
+
+/// Tracks, audio features and embeddings persisted in a SQLite database file.
+#[derive(Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Open (creating if needed) the database at `url`, e.g. `sqlite://data/tracks.db`,
+    /// and apply pending migrations.
+    pub async fn connect(url: &str, max_connections: u32) -> Result<Self, StorageError> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+        sqlx::migrate!("migrations/sqlite").run(&pool).await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStore {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn tracks(&self, ids: &[String]) -> Result<HashMap<String, Track>, StorageError> {
+        let rows: Vec<(String, String)> =
+            sqlx::query_as("SELECT id, data FROM tracks WHERE id IN (SELECT value FROM json_each(?))")
+                .bind(serde_json::to_string(ids)?)
+                .fetch_all(&self.pool)
+                .await?;
+        rows.into_iter()
+            .map(|(id, data)| Ok((id, serde_json::from_str(&data)?)))
+            .collect()
+    }
+
+    async fn audio_features(&self, ids: &[String]) -> Result<HashMap<String, Option<AudioFeatures>>, StorageError> {
+        let rows: Vec<(String, Option<String>)> = sqlx::query_as(
+            "SELECT track_id, data FROM audio_features WHERE track_id IN (SELECT value FROM json_each(?))",
+        )
+        .bind(serde_json::to_string(ids)?)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|(id, data)| Ok((id, data.as_deref().map(serde_json::from_str).transpose()?)))
+            .collect()
+    }
+
+    async fn upsert_tracks(&self, tracks: &[&Track]) -> Result<(), StorageError> {
+        if tracks.is_empty() {
+            return Ok(());
+        }
+        let mut tx = self.pool.begin().await?;
+        write_tracks(&mut tx, tracks, unix_now() as i64).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn upsert_audio_features(&self, features: &[(&str, Option<&AudioFeatures>)]) -> Result<(), StorageError> {
+        if features.is_empty() {
+            return Ok(());
+        }
+        let mut tx = self.pool.begin().await?;
+        write_audio_features(&mut tx, features, unix_now() as i64).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn recent_tracks_with_features(&self, limit: u32) -> Result<Vec<TrackWithFeatures>, StorageError> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT t.data, f.data FROM tracks t JOIN audio_features f ON f.track_id = t.id
+             WHERE f.data IS NOT NULL ORDER BY t.fetched_at DESC LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|(track, features)| {
+                let audio_features: AudioFeatures = serde_json::from_str(&features)?;
+                Ok(TrackWithFeatures {
+                    track: serde_json::from_str(&track)?,
+                    embedding: Some(audio_features.to_embedding()),
+                    audio_features: Some(audio_features),
+                })
+            })
+            .collect()
+    }
+
+    async fn stale_track_ids(&self, fetched_before: i64, limit: u32) -> Result<Vec<String>, StorageError> {
+        let ids = sqlx::query_scalar("SELECT id FROM tracks WHERE fetched_at < ? ORDER BY fetched_at LIMIT ?")
+            .bind(fetched_before)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(ids)
+    }
+
+    async fn touch_tracks(&self, ids: &[String]) -> Result<(), StorageError> {
+        sqlx::query("UPDATE tracks SET fetched_at = ? WHERE id IN (SELECT value FROM json_each(?))")
+            .bind(unix_now() as i64)
+            .bind(serde_json::to_string(ids)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn export_tracks(
+        &self,
+        after: Option<&str>,
+        fetched: Range<i64>,
+        limit: u32,
+    ) -> Result<Vec<StoredTrack>, StorageError> {
+        let rows: Vec<(String, Option<String>, i64)> = sqlx::query_as(
+            "SELECT t.data, f.data, t.fetched_at FROM tracks t LEFT JOIN audio_features f ON f.track_id = t.id
+             WHERE t.id > ? AND t.fetched_at >= ? AND t.fetched_at < ? ORDER BY t.id LIMIT ?",
+        )
+        .bind(after.unwrap_or_default())
+        .bind(fetched.start)
+        .bind(fetched.end)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|(track, features, fetched_at)| stored_track(&track, features.as_deref(), fetched_at))
+            .collect()
+    }
+
+    async fn upsert_with_events(
+        &self,
+        tracks: &[&Track],
+        features: &[(&str, Option<&AudioFeatures>)],
+        events: &[OutboxEvent],
+    ) -> Result<(), StorageError> {
+        let now = unix_now() as i64;
+        let mut tx = self.pool.begin().await?;
+        write_tracks(&mut tx, tracks, now).await?;
+        write_audio_features(&mut tx, features, now).await?;
+        for event in events {
+            sqlx::query("INSERT INTO outbox (id, key, payload, created_at) VALUES (?, ?, ?, ?)")
+                .bind(&event.id)
+                .bind(&event.key)
+                .bind(&event.payload)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn claim_outbox(&self, limit: u32, claimed_until: i64) -> Result<Vec<OutboxEvent>, StorageError> {
+        let mut rows: Vec<(i64, String, String, Vec<u8>)> = sqlx::query_as(
+            "UPDATE outbox SET claimed_until = ?
+             WHERE seq IN (SELECT seq FROM outbox WHERE claimed_until < ? ORDER BY seq LIMIT ?)
+             RETURNING seq, id, key, payload",
+        )
+        .bind(claimed_until)
+        .bind(unix_now() as i64)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.sort_by_key(|(seq, ..)| *seq);
+        Ok(rows
+            .into_iter()
+            .map(|(_, id, key, payload)| OutboxEvent { id, key, payload })
+            .collect())
+    }
+
+    async fn delete_outbox(&self, ids: &[String]) -> Result<(), StorageError> {
+        sqlx::query("DELETE FROM outbox WHERE id IN (SELECT value FROM json_each(?))")
+            .bind(serde_json::to_string(ids)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_upstream_calls(&self, calls: &[AuditRecord]) -> Result<(), StorageError> {
+        let mut tx = self.pool.begin().await?;
+        for call in calls {
+            sqlx::query(
+                "INSERT INTO upstream_calls (at_ms, tenant, endpoint, method, params_hash, status, latency_ms)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(call.at_ms)
+            .bind(&call.tenant)
+            .bind(&call.endpoint)
+            .bind(&call.method)
+            .bind(&call.params_hash)
+            .bind(call.status.map(i32::from))
+            .bind(call.latency_ms)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+async fn write_tracks(conn: &mut SqliteConnection, tracks: &[&Track], now: i64) -> Result<(), StorageError> {
+    for track in tracks {
+        sqlx::query(
+            "INSERT INTO tracks (id, data, fetched_at) VALUES (?, ?, ?)
+             ON CONFLICT (id) DO UPDATE SET data = excluded.data, fetched_at = excluded.fetched_at",
+        )
+        .bind(&track.id)
+        .bind(serde_json::to_string(track)?)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn write_audio_features(
+    conn: &mut SqliteConnection,
+    features: &[(&str, Option<&AudioFeatures>)],
+    now: i64,
+) -> Result<(), StorageError> {
+    for (track_id, features) in features {
+        let data = features.map(serde_json::to_string).transpose()?;
+        let embedding = features.map(|f| serde_json::to_string(&f.to_embedding())).transpose()?;
+        sqlx::query(
+            "INSERT INTO audio_features (track_id, data, embedding, fetched_at) VALUES (?, ?, ?, ?)
+             ON CONFLICT (track_id) DO UPDATE SET
+                 data = excluded.data, embedding = excluded.embedding, fetched_at = excluded.fetched_at",
+        )
+        .bind(track_id)
+        .bind(data)
+        .bind(embedding)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
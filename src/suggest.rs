@@ -0,0 +1,210 @@
+//! Typeahead suggestions (`GET /api/v1/suggest`), built for a request per keystroke.
+//!
+//! [`Suggester`] keeps the suggestions for each normalized query for a TTL, and
+//! concurrent requests for the same query share one Spotify search. Callers that pass
+//! a session ID are debounced: a request waits briefly before going upstream and is
+//! dropped as superseded if the same session asks for something else meanwhile, so
+//! only the query the user paused on reaches Spotify. Cache hits are never delayed.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::OnceCell;
+
+use crate::spotify::{DynSpotifyApi, SpotifyError};
+
+/// Most suggestions per query.
+pub const MAX_SUGGESTIONS: u32 = 10;
+/// Cached queries; past this, expired entries and then the oldest are dropped.
+const MAX_CACHED_QUERIES: usize = 10_000;
+/// Sessions tracked for debouncing; past this, idle sessions are dropped.
+const MAX_SESSIONS: usize = 10_000;
+/// A session idle this long no longer supersedes anything.
+const SESSION_IDLE: Duration = Duration::from_secs(60);
+
+/// One suggestion: a track title and its primary artist.
+#[derive(Debug, Clone, Serialize)]
+pub struct Suggestion {
+    /// Spotify track ID.
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+}
+
+/// Outcome of [`Suggester::suggest`].
+#[derive(Debug)]
+pub enum Suggestions {
+    Found {
+        suggestions: Arc<[Suggestion]>,
+        /// Served from the cache (or a search already in flight).
+        cached: bool,
+    },
+    /// A newer request from the same session arrived during the debounce.
+    Superseded,
+}
+
+/// Cached, debounced suggestions over Spotify search.
+pub struct Suggester {
+    spotify: DynSpotifyApi,
+    ttl: Duration,
+    debounce: Duration,
+    cache: Mutex<HashMap<String, Arc<CacheEntry>>>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+struct CacheEntry {
+    created: Instant,
+    /// Filled by the first request; later ones wait for it. Left empty on failure, so
+    /// the next request searches again.
+    suggestions: OnceCell<Arc<[Suggestion]>>,
+}
+
+struct Session {
+    /// Number of the session's latest request.
+    latest: u64,
+    seen: Instant,
+}
+
+impl Suggester {
+    /// Suggestions cached for 10 minutes and debounced by 150ms.
+    pub fn new(spotify: DynSpotifyApi) -> Self {
+        Self {
+            spotify,
+            ttl: Duration::from_secs(600),
+            debounce: Duration::from_millis(150),
+            cache: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long suggestions for a query are reused.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// How long a session's request waits for a newer one before going upstream.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Up to `limit` suggestions for `query`, debounced per `session` when given.
+    pub async fn suggest(
+        &self,
+        query: &str,
+        limit: u32,
+        session: Option<&str>,
+    ) -> Result<Suggestions, SpotifyError> {
+        let key = normalize(query);
+        let entry = self.cache_entry(&key);
+        let found = |suggestions: &Arc<[Suggestion]>, cached| {
+            let limit = (limit.min(MAX_SUGGESTIONS) as usize).min(suggestions.len());
+            Suggestions::Found {
+                suggestions: suggestions[..limit].into(),
+                cached,
+            }
+        };
+        // Even a cache hit supersedes the session's pending request.
+        let request = session.map(|session| (session, self.begin(session)));
+        if let Some(suggestions) = entry.suggestions.get() {
+            record_suggest("hit");
+            return Ok(found(suggestions, true));
+        }
+
+        if let Some((session, request)) = request {
+            tokio::time::sleep(self.debounce).await;
+            if !self.is_latest(session, request) {
+                record_suggest("superseded");
+                return Ok(Suggestions::Superseded);
+            }
+        }
+        let mut searched = false;
+        let suggestions = entry
+            .suggestions
+            .get_or_try_init(|| {
+                searched = true;
+                self.search(&key)
+            })
+            .await?;
+        record_suggest(if searched { "miss" } else { "hit" });
+        Ok(found(suggestions, !searched))
+    }
+
+    /// The live cache entry for `key`, created if missing or expired.
+    fn cache_entry(&self, key: &str) -> Arc<CacheEntry> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(entry) = cache.get(key).filter(|e| e.created.elapsed() < self.ttl) {
+            return entry.clone();
+        }
+        if cache.len() >= MAX_CACHED_QUERIES {
+            cache.retain(|_, e| e.created.elapsed() < self.ttl);
+            if cache.len() >= MAX_CACHED_QUERIES {
+                let oldest = cache.iter().min_by_key(|(_, e)| e.created).map(|(k, _)| k.clone());
+                cache.remove(&oldest.unwrap_or_default());
+            }
+        }
+        let entry = Arc::new(CacheEntry {
+            created: Instant::now(),
+            suggestions: OnceCell::new(),
+        });
+        cache.insert(key.to_string(), entry.clone());
+        entry
+    }
+
+    /// Record a new request from `session` and return its number.
+    fn begin(&self, session: &str) -> u64 {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.len() >= MAX_SESSIONS && !sessions.contains_key(session) {
+            sessions.retain(|_, s| s.seen.elapsed() < SESSION_IDLE);
+        }
+        let entry = sessions.entry(session.to_string()).or_insert(Session {
+            latest: 0,
+            seen: Instant::now(),
+        });
+        entry.latest += 1;
+        entry.seen = Instant::now();
+        entry.latest
+    }
+
+    fn is_latest(&self, session: &str, request: u64) -> bool {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.get(session).is_none_or(|s| s.latest == request)
+    }
+
+    async fn search(&self, query: &str) -> Result<Arc<[Suggestion]>, SpotifyError> {
+        // Ask for more than needed: remasters and compilations repeat title and artist.
+        let result = self.spotify.search_tracks(query, Some(MAX_SUGGESTIONS * 2), None).await?;
+        let mut suggestions: Vec<Suggestion> = Vec::new();
+        for track in result.tracks {
+            let artist = track.artists.first().map(|a| a.name.clone()).unwrap_or_default();
+            let duplicate = suggestions
+                .iter()
+                .any(|s| s.title.eq_ignore_ascii_case(&track.name) && s.artist.eq_ignore_ascii_case(&artist));
+            if !duplicate {
+                suggestions.push(Suggestion {
+                    id: track.id,
+                    title: track.name,
+                    artist,
+                });
+            }
+            if suggestions.len() == MAX_SUGGESTIONS as usize {
+                break;
+            }
+        }
+        Ok(suggestions.into())
+    }
+}
+
+/// Cache key for a query: lowercase, with runs of whitespace collapsed.
+fn normalize(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_suggest(result: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("suggest_requests_total", "result" => result).increment(1);
+}
@@ -0,0 +1,212 @@
+//! Tracing subscriber setup, OTLP export and W3C trace-context propagation.
+
+#[cfg(feature = "grpc")]
+use std::task::{Context, Poll};
+
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::{trace, Resource};
+#[cfg(feature = "grpc")]
+use tonic::codegen::{http, BoxFuture};
+#[cfg(feature = "grpc")]
+use tower::{Layer, Service};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
+
+use crate::config::{Config, LogFormat};
+use crate::reload::LogFilterHandle;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Initialize the global tracing subscriber. When `OTEL_EXPORTER_OTLP_ENDPOINT` is set,
+/// spans are also exported via OTLP/gRPC. Returns a handle for changing the log filter.
+pub fn init(config: &Config) -> anyhow::Result<LogFilterHandle> {
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let otel_layer = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    config.service_name.clone(),
+                )])))
+                .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
+    let (filter, filter_handle) = tracing_subscriber::reload::Layer::new(tracing_subscriber::EnvFilter::new(
+        std::env::var("RUST_LOG").unwrap_or_else(|_| config.log_level.clone()),
+    ));
+    // The log filter applies to the logs and spans only, so that the console layer still
+    // sees the runtime's trace-level task events.
+    let pretty = (config.log_format == LogFormat::Pretty).then(tracing_subscriber::fmt::layer);
+    let json = (config.log_format == LogFormat::Json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+    });
+    let layers = tracing_subscriber::Layer::and_then(pretty, json)
+        .and_then(otel_layer)
+        .with_filter(filter);
+    let registry = tracing_subscriber::registry().with(layers);
+    // The console layer panics without the runtime's task instrumentation.
+    #[cfg(all(feature = "console", tokio_unstable))]
+    let registry = registry.with(console_subscriber::spawn());
+    registry.init();
+
+    if let Some(ref endpoint) = config.otlp_endpoint {
+        tracing::info!("exporting traces via OTLP to {}", endpoint);
+    }
+    #[cfg(all(feature = "console", tokio_unstable))]
+    tracing::info!("serving task instrumentation to tokio-console");
+    #[cfg(all(feature = "console", not(tokio_unstable)))]
+    tracing::warn!("built without RUSTFLAGS=\"--cfg tokio_unstable\", so tokio-console is not served");
+    Ok(filter_handle)
+}
+
+/// Flush pending spans before exit.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Root span for an HTTP request, carrying `request_id` and the matched `route`.
+/// Expects `x-request-id` to have been set by `SetRequestIdLayer`.
+pub fn make_http_span<B>(req: &Request<B>) -> tracing::Span {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "http_request",
+        method = %req.method(),
+        uri = %req.uri(),
+        route,
+        request_id,
+    );
+    set_parent_from_headers(&span, req.headers());
+    span
+}
+
+/// Root span for a gRPC call; `request_id` is filled in by [`GrpcRequestIdLayer`].
+#[cfg(feature = "grpc")]
+pub fn make_grpc_span(req: &http::Request<()>) -> tracing::Span {
+    let span = tracing::info_span!(
+        "grpc_request",
+        route = %req.uri().path(),
+        request_id = tracing::field::Empty,
+    );
+    set_parent_from_grpc_headers(&span, req.headers());
+    span
+}
+
+/// Ensures every gRPC call has `x-request-id` metadata (generating one if absent),
+/// records it on the `grpc_request` span and echoes it in the response headers.
+#[cfg(feature = "grpc")]
+#[derive(Clone, Default)]
+pub struct GrpcRequestIdLayer;
+
+#[cfg(feature = "grpc")]
+impl<S> Layer<S> for GrpcRequestIdLayer {
+    type Service = GrpcRequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcRequestId { inner }
+    }
+}
+
+#[cfg(feature = "grpc")]
+#[derive(Clone)]
+pub struct GrpcRequestId<S> {
+    inner: S,
+}
+
+#[cfg(feature = "grpc")]
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for GrpcRequestId<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
+        let value = match req.headers().get(REQUEST_ID_HEADER) {
+            Some(v) => v.clone(),
+            None => {
+                let v = http::HeaderValue::from_str(&uuid::Uuid::new_v4().to_string())
+                    .expect("uuid is a valid header value");
+                req.headers_mut().insert(REQUEST_ID_HEADER, v.clone());
+                v
+            }
+        };
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            // Polled inside the span created by `make_grpc_span`.
+            tracing::Span::current().record("request_id", value.to_str().unwrap_or_default());
+            let mut res = fut.await?;
+            res.headers_mut().insert(REQUEST_ID_HEADER, value);
+            Ok(res)
+        })
+    }
+}
+
+/// Set the parent of `span` from W3C `traceparent`/`tracestate` headers, if present.
+pub fn set_parent_from_headers(span: &tracing::Span, headers: &axum::http::HeaderMap) {
+    let cx = opentelemetry::global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(headers)));
+    span.set_parent(cx);
+}
+
+/// Same as [`set_parent_from_headers`] for tonic's (http 0.2) header map.
+#[cfg(feature = "grpc")]
+pub fn set_parent_from_grpc_headers(span: &tracing::Span, headers: &http::HeaderMap) {
+    let cx = opentelemetry::global::get_text_map_propagator(|p| p.extract(&GrpcHeaderExtractor(headers)));
+    span.set_parent(cx);
+}
+
+struct HeaderExtractor<'a>(&'a axum::http::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+#[cfg(feature = "grpc")]
+struct GrpcHeaderExtractor<'a>(&'a http::HeaderMap);
+
+#[cfg(feature = "grpc")]
+impl Extractor for GrpcHeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
@@ -0,0 +1,320 @@
+//! Per-tenant Spotify credentials.
+//!
+//! Products sharing one deployment can each bring their own Spotify app, so they don't
+//! share its quota. Each tenant in `[tenants.<name>]` has a client id and secret and,
+//! optionally, the API keys its callers send. The [`scope`] middleware works out the
+//! tenant of an HTTP request, from `X-Api-Key` or else `X-Tenant`, and runs the request
+//! with it in a task-local. [`TenantSpotifyApi`], the innermost layer of the backend,
+//! sends each call through that tenant's client, which has its own token cache,
+//! upstream state and `tenant` metric label. Requests naming no tenant, and the gRPC and
+//! NATS interfaces, use the `SPOTIFY_CLIENT_ID` credentials. With JWT authentication,
+//! the token's tenant claim selects the tenant instead, over HTTP and gRPC alike; a
+//! claimed tenant without credentials of its own uses the default ones. Failing that, a
+//! verified client certificate listed in a tenant's `client_certs` selects it, again
+//! over both (see [`mtls`](crate::mtls)).
+//!
+//! The search cache, store and event publishing sit above the routing and are shared:
+//! catalog data is the same for every tenant, so a result fetched with one tenant's
+//! quota may be served to another.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::admin::constant_time_eq;
+use crate::config::TenantConfig;
+use crate::error::AppError;
+use crate::mtls::ClientCert;
+use crate::spotify::{
+    AlbumDetail, AlbumTracksPage, ArtistAlbumsPage, ArtistDetail, AudioFeatures, ByteStream, DynSpotifyApi,
+    PlaylistTracksPage, RecommendationSeeds, ScoredTrack, SearchTracksResponse, SearchTracksWithFeaturesResponse,
+    SpotifyApi, SpotifyError, TokenStatus, Track, TrackWithFeatures, UpstreamSnapshot,
+};
+use crate::state::AppState;
+use crate::usage;
+
+/// Header naming the tenant, for tenants without API keys.
+pub const TENANT_HEADER: &str = "x-tenant";
+/// Header carrying a tenant's API key.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+tokio::task_local! {
+    static TENANT: Option<Arc<str>>;
+}
+
+/// The tenant of the request being handled; `None` for the default credentials.
+pub fn current() -> Option<Arc<str>> {
+    TENANT.try_with(Clone::clone).ok().flatten()
+}
+
+/// Run `fut` as `tenant`.
+pub async fn run_as<F: Future>(tenant: Option<Arc<str>>, fut: F) -> F::Output {
+    TENANT.scope(tenant, fut).await
+}
+
+/// `fut` run as the current tenant, for work spawned on behalf of a request (jobs,
+/// background cache refreshes), which would otherwise use the default credentials.
+/// Its Spotify requests are also counted against the request's [route](crate::usage).
+pub fn propagate<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    run_as(current(), usage::run_for(usage::current_route(), fut))
+}
+
+/// Middleware running each request as the tenant its JWT or client certificate, or else
+/// its headers, select. An unknown API key, or `X-Tenant` alone for a tenant that has
+/// keys, is rejected with 401; an unknown tenant, or one other than the key's, with 400.
+pub async fn scope(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let cert = req.extensions().get::<ClientCert>();
+    if let Some(name) = cert.and_then(ClientCert::name) {
+        crate::access_log::record_client_cert(name);
+    }
+    #[cfg(feature = "jwt")]
+    if let Some(name) = req.extensions().get::<crate::jwt::Principal>().and_then(|p| p.tenant.clone()) {
+        crate::access_log::record_tenant(&name);
+        return run_as(Some(name.into()), next.run(req)).await;
+    }
+    let config = state.config.load();
+    let tenant = match cert.and_then(|cert| for_client_cert(&config.tenants, cert)) {
+        Some(tenant) => Ok(Some(tenant.name.as_str().into())),
+        None => resolve(&config.tenants, req.headers()),
+    };
+    drop(config);
+    let tenant = match tenant {
+        Ok(tenant) => tenant,
+        Err(e) => return e.into_response(),
+    };
+    if let Some(name) = &tenant {
+        crate::access_log::record_tenant(name);
+    }
+    run_as(tenant, next.run(req)).await
+}
+
+/// The tenant among `tenants` whose `client_certs` lists one of `cert`'s identities.
+pub fn for_client_cert<'a>(tenants: &'a [TenantConfig], cert: &ClientCert) -> Option<&'a TenantConfig> {
+    tenants.iter().find(|t| t.client_certs.iter().any(|name| cert.names.contains(name)))
+}
+
+/// The tenant `headers` select among `tenants`.
+fn resolve(tenants: &[TenantConfig], headers: &HeaderMap) -> Result<Option<Arc<str>>, AppError> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+    let named = header(TENANT_HEADER).filter(|s| !s.is_empty());
+    if let Some(key) = header(API_KEY_HEADER).filter(|s| !s.is_empty()) {
+        let tenant = tenants
+            .iter()
+            .find(|t| t.api_keys.iter().any(|k| constant_time_eq(k.expose().as_bytes(), key.as_bytes())))
+            .ok_or_else(|| AppError::Unauthorized(format!("unknown API key in {}", API_KEY_HEADER)))?;
+        if named.is_some_and(|name| name != tenant.name) {
+            return Err(AppError::BadRequest(format!(
+                "{} names a different tenant than the API key",
+                TENANT_HEADER
+            )));
+        }
+        return Ok(Some(tenant.name.as_str().into()));
+    }
+    let Some(name) = named else {
+        return Ok(None);
+    };
+    match tenants.iter().find(|t| t.name == name) {
+        Some(tenant) if tenant.api_keys.is_empty() => Ok(Some(name.into())),
+        Some(_) => Err(AppError::Unauthorized(format!(
+            "tenant '{}' requires its API key in {}",
+            name, API_KEY_HEADER
+        ))),
+        None => Err(AppError::BadRequest(format!("unknown tenant '{}' in {}", name, TENANT_HEADER))),
+    }
+}
+
+/// [`SpotifyApi`] that sends each call to the backend of the [`current`] tenant, or to
+/// the default one outside a tenant's request.
+pub struct TenantSpotifyApi {
+    default: DynSpotifyApi,
+    tenants: HashMap<String, DynSpotifyApi>,
+}
+
+impl TenantSpotifyApi {
+    /// Route to `tenants` by name, and everything else to `default`.
+    pub fn new(default: DynSpotifyApi, tenants: HashMap<String, DynSpotifyApi>) -> Self {
+        Self { default, tenants }
+    }
+
+    fn current(&self) -> &DynSpotifyApi {
+        current()
+            .and_then(|name| self.tenants.get(&*name))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[async_trait]
+impl SpotifyApi for TenantSpotifyApi {
+    async fn has_token(&self) -> bool {
+        self.current().has_token().await
+    }
+
+    fn upstream_status(&self) -> UpstreamSnapshot {
+        self.current().upstream_status()
+    }
+
+    fn token_status(&self) -> TokenStatus {
+        self.current().token_status()
+    }
+
+    async fn refresh_token(&self) -> Result<(), SpotifyError> {
+        self.current().refresh_token().await
+    }
+
+    async fn search_tracks(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksResponse, SpotifyError> {
+        self.current().search_tracks(q, limit, offset).await
+    }
+
+    async fn search_tracks_with_features(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchTracksWithFeaturesResponse, SpotifyError> {
+        self.current().search_tracks_with_features(q, limit, offset).await
+    }
+
+    async fn search_tracks_raw(
+        &self,
+        q: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ByteStream, SpotifyError> {
+        self.current().search_tracks_raw(q, limit, offset).await
+    }
+
+    async fn get_tracks(&self, ids: &[String]) -> Result<Vec<Option<Track>>, SpotifyError> {
+        self.current().get_tracks(ids).await
+    }
+
+    async fn get_tracks_raw(&self, ids: &[String]) -> Result<ByteStream, SpotifyError> {
+        self.current().get_tracks_raw(ids).await
+    }
+
+    async fn get_audio_features(&self, ids: &[String]) -> Result<Vec<Option<AudioFeatures>>, SpotifyError> {
+        self.current().get_audio_features(ids).await
+    }
+
+    async fn get_tracks_with_features(&self, ids: &[String]) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        self.current().get_tracks_with_features(ids).await
+    }
+
+    async fn get_recommendations_with_features(
+        &self,
+        seeds: &RecommendationSeeds,
+        limit: Option<u32>,
+    ) -> Result<Vec<TrackWithFeatures>, SpotifyError> {
+        self.current().get_recommendations_with_features(seeds, limit).await
+    }
+
+    async fn get_similar_tracks(&self, id: &str, limit: Option<u32>) -> Result<Vec<ScoredTrack>, SpotifyError> {
+        self.current().get_similar_tracks(id, limit).await
+    }
+
+    async fn get_playlist_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<PlaylistTracksPage, SpotifyError> {
+        self.current().get_playlist_tracks(id, limit, offset).await
+    }
+
+    async fn get_artist_albums(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<ArtistAlbumsPage, SpotifyError> {
+        self.current().get_artist_albums(id, limit, offset).await
+    }
+
+    async fn get_album_tracks(
+        &self,
+        id: &str,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<AlbumTracksPage, SpotifyError> {
+        self.current().get_album_tracks(id, limit, offset).await
+    }
+
+    async fn get_album(&self, id: &str) -> Result<AlbumDetail, SpotifyError> {
+        self.current().get_album(id).await
+    }
+
+    async fn get_artist(&self, id: &str) -> Result<ArtistDetail, SpotifyError> {
+        self.current().get_artist(id).await
+    }
+
+    async fn get_albums(&self, ids: &[String]) -> Result<Vec<Option<AlbumDetail>>, SpotifyError> {
+        self.current().get_albums(ids).await
+    }
+
+    async fn get_artists(&self, ids: &[String]) -> Result<Vec<Option<ArtistDetail>>, SpotifyError> {
+        self.current().get_artists(ids).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+
+    use super::*;
+    use crate::secret::Secret;
+
+    fn tenant(name: &str, api_keys: &[&str]) -> TenantConfig {
+        TenantConfig {
+            name: name.into(),
+            client_id: format!("{}-client", name),
+            client_secret: Secret::new(format!("{}-secret", name)),
+            api_keys: api_keys.iter().map(|k| Secret::new(k.to_string())).collect(),
+            client_certs: vec![],
+        }
+    }
+
+    /// `X-Api-Key`, `X-Tenant`, and the tenant they select or the error status.
+    type Case = (Option<&'static str>, Option<&'static str>, Result<Option<&'static str>, StatusCode>);
+
+    #[test]
+    fn resolve_follows_the_header_rules() {
+        let tenants = [tenant("acme", &["acme-key"]), tenant("globex", &[])];
+        let cases: &[Case] = &[
+            (None, None, Ok(None)),
+            (Some(""), Some(" "), Ok(None)),
+            (Some("acme-key"), None, Ok(Some("acme"))),
+            (Some(" acme-key "), Some("acme"), Ok(Some("acme"))),
+            (Some("acme-key"), Some("globex"), Err(StatusCode::BAD_REQUEST)),
+            (Some("wrong-key"), None, Err(StatusCode::UNAUTHORIZED)),
+            (Some("wrong-key"), Some("globex"), Err(StatusCode::UNAUTHORIZED)),
+            (None, Some("globex"), Ok(Some("globex"))),
+            (None, Some("acme"), Err(StatusCode::UNAUTHORIZED)),
+            (None, Some("initech"), Err(StatusCode::BAD_REQUEST)),
+        ];
+        for (key, name, expected) in cases {
+            let mut headers = HeaderMap::new();
+            if let Some(key) = key {
+                headers.insert(API_KEY_HEADER, key.parse().unwrap());
+            }
+            if let Some(name) = name {
+                headers.insert(TENANT_HEADER, name.parse().unwrap());
+            }
+            let resolved = resolve(&tenants, &headers).map_err(|e| e.into_response().status());
+            let resolved = resolved.as_ref().map(|t| t.as_deref());
+            assert_eq!(resolved, expected.as_ref().map(|t| *t), "X-Api-Key {:?}, X-Tenant {:?}", key, name);
+        }
+    }
+}
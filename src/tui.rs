@@ -0,0 +1,405 @@
+//! `spotify-search tui`: a terminal UI for tuning the embedding spec.
+//!
+//! Search on the left, the selected track's audio features and embedding on the right,
+//! and below them the selected track's embedding next to a pinned one, dimension by
+//! dimension, with their cosine similarity. `v` switches between the `v1` and `v3`
+//! embeddings; the `v3` genre components are looked up the first time they're needed.
+//!
+//! The terminal runs on a blocking thread; searches run on the Tokio runtime and report
+//! back over a channel, so the UI stays responsive while Spotify answers.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::runtime::Handle;
+
+use crate::spotify::{
+    add_genres, cosine_similarity, AudioFeatures, DynSpotifyApi, EmbeddingVersion, SpotifyError, TrackWithFeatures,
+};
+
+/// Tracks per search, Spotify's maximum.
+const SEARCH_LIMIT: u32 = 50;
+
+/// How long to wait for a key before checking for finished searches.
+const TICK: Duration = Duration::from_millis(50);
+
+/// Names of the `v1` dimensions, in embedding order.
+const V1_DIMS: [&str; 12] = [
+    "acousticness",
+    "danceability",
+    "energy",
+    "instrumentalness",
+    "key",
+    "liveness",
+    "loudness",
+    "mode",
+    "speechiness",
+    "tempo",
+    "time_signature",
+    "valence",
+];
+
+const HELP_BROWSE: &str = "/ search  ↑↓ select  p pin/unpin  v v1/v3  q quit";
+const HELP_EDIT: &str = "Enter search  Esc cancel";
+
+/// Run the UI against `spotify` until the user quits, restoring the terminal after.
+pub async fn run(spotify: DynSpotifyApi) -> io::Result<()> {
+    let handle = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut terminal = ratatui::init();
+        let result = App::new(spotify, handle).run(&mut terminal);
+        ratatui::restore();
+        result
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// A finished background request.
+enum Message {
+    Searched(String, Result<Vec<TrackWithFeatures>, SpotifyError>),
+    /// Tracks extended to `v3`.
+    Genres(Vec<TrackWithFeatures>),
+}
+
+struct App {
+    spotify: DynSpotifyApi,
+    runtime: Handle,
+    tx: Sender<Message>,
+    rx: Receiver<Message>,
+    query: String,
+    /// The last search sent; replies to earlier ones are dropped.
+    searched: String,
+    editing: bool,
+    version: EmbeddingVersion,
+    results: Vec<TrackWithFeatures>,
+    list: ListState,
+    pinned: Option<TrackWithFeatures>,
+    /// `v3` embeddings by track ID, for the results and the pinned track.
+    v3: HashMap<String, Vec<f32>>,
+    /// Whether a `v3` lookup is in flight.
+    fetching_genres: bool,
+    status: String,
+}
+
+impl App {
+    fn new(spotify: DynSpotifyApi, runtime: Handle) -> Self {
+        let (tx, rx) = mpsc::channel();
+        App {
+            spotify,
+            runtime,
+            tx,
+            rx,
+            query: String::new(),
+            searched: String::new(),
+            editing: true,
+            version: EmbeddingVersion::V1,
+            results: Vec::new(),
+            list: ListState::default(),
+            pinned: None,
+            v3: HashMap::new(),
+            fetching_genres: false,
+            status: "Type a search and press Enter".into(),
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(TICK)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !self.on_key(key) {
+                        return Ok(());
+                    }
+                }
+            }
+            while let Ok(message) = self.rx.try_recv() {
+                self.on_message(message);
+            }
+        }
+    }
+
+    /// Handle a key press; false to quit.
+    fn on_key(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+        if self.editing {
+            match key.code {
+                KeyCode::Char(c) => self.query.push(c),
+                KeyCode::Backspace => {
+                    self.query.pop();
+                }
+                KeyCode::Enter => self.search(),
+                KeyCode::Esc => self.editing = false,
+                _ => {}
+            }
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('/') => self.editing = true,
+            KeyCode::Down | KeyCode::Char('j') => self.list.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+            KeyCode::Home => self.list.select_first(),
+            KeyCode::End => self.list.select_last(),
+            KeyCode::Char('p') => self.toggle_pin(),
+            KeyCode::Char('v') => {
+                self.version = match self.version {
+                    EmbeddingVersion::V1 => EmbeddingVersion::V3,
+                    EmbeddingVersion::V3 => EmbeddingVersion::V1,
+                };
+                self.fetch_genres();
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn on_message(&mut self, message: Message) {
+        match message {
+            Message::Searched(query, _) if query != self.searched => {}
+            Message::Searched(_, Ok(tracks)) => {
+                self.status = format!("{} results", tracks.len());
+                self.results = tracks;
+                self.list.select((!self.results.is_empty()).then_some(0));
+                self.fetch_genres();
+            }
+            Message::Searched(_, Err(e)) => self.status = format!("Search failed: {}", e),
+            Message::Genres(tracks) => {
+                self.fetching_genres = false;
+                for t in tracks {
+                    if let Some(embedding) = t.embedding {
+                        self.v3.insert(t.track.id, embedding);
+                    }
+                }
+                // Tracks that arrived while the lookup was in flight still need theirs.
+                self.fetch_genres();
+            }
+        }
+    }
+
+    fn search(&mut self) {
+        let query = self.query.trim().to_string();
+        if query.is_empty() {
+            return;
+        }
+        self.editing = false;
+        self.status = format!("Searching for {:?}…", query);
+        self.searched = query.clone();
+        let (spotify, tx) = (self.spotify.clone(), self.tx.clone());
+        self.runtime.spawn(async move {
+            let result = spotify.search_tracks_with_features(&query, Some(SEARCH_LIMIT), None).await;
+            let _ = tx.send(Message::Searched(query, result.map(|page| page.tracks)));
+        });
+    }
+
+    /// Look up the `v3` embeddings still missing, when showing `v3`.
+    fn fetch_genres(&mut self) {
+        if self.version != EmbeddingVersion::V3 || self.fetching_genres {
+            return;
+        }
+        let missing: Vec<TrackWithFeatures> = self
+            .results
+            .iter()
+            .chain(&self.pinned)
+            .filter(|t| t.embedding.is_some() && !self.v3.contains_key(&t.track.id))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+        self.fetching_genres = true;
+        let (spotify, tx) = (self.spotify.clone(), self.tx.clone());
+        self.runtime.spawn(async move {
+            let mut tracks = missing;
+            add_genres(spotify.as_ref(), &mut tracks).await;
+            let _ = tx.send(Message::Genres(tracks));
+        });
+    }
+
+    fn toggle_pin(&mut self) {
+        let Some(selected) = self.selected() else {
+            return;
+        };
+        if self.pinned.as_ref().is_some_and(|p| p.track.id == selected.track.id) {
+            self.pinned = None;
+        } else {
+            self.pinned = Some(selected.clone());
+        }
+    }
+
+    fn selected(&self) -> Option<&TrackWithFeatures> {
+        self.list.selected().and_then(|i| self.results.get(i))
+    }
+
+    /// `t`'s embedding in the current version, if it has one yet.
+    fn embedding<'a>(&'a self, t: &'a TrackWithFeatures) -> Option<&'a [f32]> {
+        match self.version {
+            EmbeddingVersion::V1 => t.embedding.as_deref(),
+            EmbeddingVersion::V3 => self.v3.get(&t.track.id).map(Vec::as_slice),
+        }
+    }
+
+    /// `t`'s similarity to the pinned track.
+    fn score(&self, t: &TrackWithFeatures) -> Option<f32> {
+        let pinned = self.embedding(self.pinned.as_ref()?)?;
+        Some(cosine_similarity(pinned, self.embedding(t)?))
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [search, body, status] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [results, detail] = Layout::horizontal([Constraint::Percentage(40), Constraint::Min(0)]).areas(body);
+        let [features, compare] = Layout::vertical([Constraint::Length(15), Constraint::Min(0)]).areas(detail);
+
+        let search_style = if self.editing {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        };
+        frame.render_widget(
+            Paragraph::new(self.query.as_str()).block(Block::bordered().title("Search").border_style(search_style)),
+            search,
+        );
+        if self.editing {
+            let x = search.x + 1 + self.query.chars().count() as u16;
+            frame.set_cursor_position(Position::new(x.min(search.right().saturating_sub(2)), search.y + 1));
+        }
+
+        self.draw_results(frame, results);
+        self.draw_features(frame, features);
+        self.draw_compare(frame, compare);
+
+        let help = if self.editing { HELP_EDIT } else { HELP_BROWSE };
+        let genres = if self.fetching_genres { " (looking up genres…)" } else { "" };
+        frame.render_widget(
+            Paragraph::new(format!(" {}{}  |  {}", self.status, genres, help))
+                .style(Style::default().add_modifier(Modifier::REVERSED)),
+            status,
+        );
+    }
+
+    fn draw_results(&mut self, frame: &mut Frame, area: Rect) {
+        let pinned = self.pinned.as_ref().map(|p| p.track.id.as_str());
+        let items: Vec<ListItem> = self
+            .results
+            .iter()
+            .map(|t| {
+                let score = self.score(t).map_or_else(|| "     ".to_string(), |s| format!("{:.3}", s));
+                let pin = if Some(t.track.id.as_str()) == pinned { "*" } else { " " };
+                let artists: Vec<&str> = t.track.artists.iter().map(|a| a.name.as_str()).collect();
+                ListItem::new(format!("{}{} {} — {}", pin, score, t.track.name, artists.join(", ")))
+            })
+            .collect();
+        let title = match &self.pinned {
+            Some(_) => format!("Results, similarity to * ({})", self.version.as_str()),
+            None => "Results".to_string(),
+        };
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.list);
+    }
+
+    fn draw_features(&self, frame: &mut Frame, area: Rect) {
+        let Some(t) = self.selected() else {
+            frame.render_widget(Block::bordered().title("Audio features"), area);
+            return;
+        };
+        let block = Block::bordered().title(format!("Audio features: {}", t.track.name));
+        let Some(af) = &t.audio_features else {
+            frame.render_widget(Paragraph::new("Spotify has no audio features for this track").block(block), area);
+            return;
+        };
+        let embedding = af.to_embedding();
+        let rows = V1_DIMS
+            .into_iter()
+            .zip(raw_features(af))
+            .zip(embedding)
+            .map(|((name, raw), value)| Row::new([name.to_string(), raw, format!("{:.3}", value), bar(value, 20)]));
+        let widths = [Constraint::Length(16), Constraint::Length(10), Constraint::Length(7), Constraint::Min(0)];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["feature", "value", "v1", ""]).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(block);
+        frame.render_widget(table, area);
+    }
+
+    fn draw_compare(&self, frame: &mut Frame, area: Rect) {
+        let version = self.version.as_str();
+        let (Some(selected), Some(pinned)) = (self.selected(), self.pinned.as_ref()) else {
+            let hint = "Press p to pin the selected track, then select another to compare their embeddings";
+            frame.render_widget(Paragraph::new(hint).block(Block::bordered().title("Compare")), area);
+            return;
+        };
+        let (Some(a), Some(b)) = (self.embedding(selected), self.embedding(pinned)) else {
+            let reason = match self.version {
+                EmbeddingVersion::V3 if self.fetching_genres => "Looking up genres…",
+                _ => "One of the tracks has no embedding",
+            };
+            frame.render_widget(Paragraph::new(reason).block(Block::bordered().title("Compare")), area);
+            return;
+        };
+        let title = Line::from(format!(
+            "{} vs * {}: cosine {:.4} ({})",
+            selected.track.name,
+            pinned.track.name,
+            cosine_similarity(a, b),
+            version
+        ));
+        // Genre dimensions are mostly zero; only those set on either side are listed.
+        let rows = a
+            .iter()
+            .zip(b)
+            .enumerate()
+            .filter(|(i, (x, y))| *i < V1_DIMS.len() || **x != 0.0 || **y != 0.0)
+            .map(|(i, (x, y))| {
+                let name = V1_DIMS.get(i).map_or_else(|| format!("genre[{}]", i - V1_DIMS.len()), |n| n.to_string());
+                let diff = x - y;
+                let style = if diff.abs() >= 0.25 {
+                    Style::default().fg(Color::Red)
+                } else {
+                    Style::default()
+                };
+                Row::new([name, format!("{:.3}", x), format!("{:.3}", y), format!("{:+.3}", diff)]).style(style)
+            });
+        let widths = [Constraint::Length(16), Constraint::Length(9), Constraint::Length(9), Constraint::Length(9)];
+        let table = Table::new(rows, widths)
+            .header(
+                Row::new(["dimension", "selected", "pinned", "diff"]).style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::bordered().title(title));
+        frame.render_widget(table, area);
+    }
+}
+
+/// The audio features behind each `v1` dimension, in Spotify's units.
+fn raw_features(af: &AudioFeatures) -> [String; 12] {
+    [
+        format!("{:.3}", af.acousticness),
+        format!("{:.3}", af.danceability),
+        format!("{:.3}", af.energy),
+        format!("{:.3}", af.instrumentalness),
+        af.key.to_string(),
+        format!("{:.3}", af.liveness),
+        format!("{:.1} dB", af.loudness),
+        af.mode.to_string(),
+        format!("{:.3}", af.speechiness),
+        format!("{:.1} BPM", af.tempo),
+        format!("{}/4", af.time_signature),
+        format!("{:.3}", af.valence),
+    ]
+}
+
+/// A bar `width` cells long at full scale for a value in 0..1.
+fn bar(value: f32, width: usize) -> String {
+    "█".repeat((value.clamp(0.0, 1.0) * width as f32).round() as usize)
+}
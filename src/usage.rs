@@ -0,0 +1,191 @@
+//! Upstream budget accounting: which API routes and tenants spend the Spotify quota.
+//!
+//! Every Spotify client reports its requests to one [`Usage`], which counts each against
+//! the route in scope when it was sent: the HTTP route template, set by the [`scope`]
+//! middleware, the gRPC method ([`GrpcUsageLayer`]) or the NATS subject. Work spawned
+//! for a request, such as jobs and background search-cache refreshes, keeps the
+//! request's route through [`tenants::propagate`](crate::tenants::propagate). Requests
+//! sent outside any route, like the stored-track refresh, cache warm-ups and saved
+//! search prewarming, count as [`BACKGROUND`].
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+
+use crate::clock::unix_now;
+use crate::spotify::{UpstreamAudit, UpstreamCall};
+
+/// Route of Spotify requests sent outside any request.
+pub const BACKGROUND: &str = "background";
+
+tokio::task_local! {
+    static ROUTE: Option<Arc<str>>;
+}
+
+/// The route Spotify requests are counted against here; `None` outside any.
+pub fn current_route() -> Option<Arc<str>> {
+    ROUTE.try_with(Clone::clone).ok().flatten()
+}
+
+/// Run `fut` with its Spotify requests counted against `route`.
+pub async fn run_for<F: Future>(route: Option<Arc<str>>, fut: F) -> F::Output {
+    ROUTE.scope(route, fut).await
+}
+
+/// Middleware counting each request's Spotify requests against its route template.
+pub async fn scope(req: Request, next: Next) -> Response {
+    let route = req.extensions().get::<MatchedPath>().map(|p| p.as_str().into());
+    run_for(route, next.run(req)).await
+}
+
+/// Spotify requests by route and tenant since startup.
+pub struct Usage {
+    since: u64,
+    routes: Mutex<BTreeMap<(Arc<str>, String), RouteUsage>>,
+}
+
+/// The Spotify requests one route sent with one tenant's credentials.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RouteUsage {
+    pub route: String,
+    pub tenant: String,
+    /// Requests sent, token requests and retries included.
+    pub calls: u64,
+    /// Calls Spotify answered with 429.
+    pub rate_limited: u64,
+    /// Calls that got no response or an error status other than 429.
+    pub failed: u64,
+    /// Summed latency of the calls.
+    pub upstream_ms: u64,
+    /// Calls by Spotify endpoint label (`search`, `tracks`, `token`, ...).
+    pub endpoints: BTreeMap<&'static str, u64>,
+}
+
+/// `GET /admin/usage`.
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    /// Unix seconds when counting started.
+    pub since: u64,
+    pub calls: u64,
+    /// Calls by tenant.
+    pub tenants: BTreeMap<String, u64>,
+    /// By route, then tenant.
+    pub routes: Vec<RouteUsage>,
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Usage {
+    pub fn new() -> Self {
+        Self {
+            since: unix_now(),
+            routes: Mutex::default(),
+        }
+    }
+
+    pub fn report(&self) -> UsageReport {
+        let routes: Vec<RouteUsage> = self.routes.lock().unwrap().values().cloned().collect();
+        let mut tenants = BTreeMap::new();
+        for usage in &routes {
+            *tenants.entry(usage.tenant.clone()).or_default() += usage.calls;
+        }
+        UsageReport {
+            since: self.since,
+            calls: routes.iter().map(|usage| usage.calls).sum(),
+            tenants,
+            routes,
+        }
+    }
+}
+
+impl UpstreamAudit for Usage {
+    fn record(&self, call: &UpstreamCall<'_>) {
+        let route = current_route().unwrap_or_else(|| BACKGROUND.into());
+        record_call(&route, call.tenant);
+        let mut routes = self.routes.lock().unwrap();
+        let usage = routes
+            .entry((route.clone(), call.tenant.to_string()))
+            .or_insert_with(|| RouteUsage {
+                route: route.to_string(),
+                tenant: call.tenant.to_string(),
+                ..RouteUsage::default()
+            });
+        usage.calls += 1;
+        match call.status {
+            Some(429) => usage.rate_limited += 1,
+            Some(status) if status < 400 => {}
+            _ => usage.failed += 1,
+        }
+        usage.upstream_ms += call.latency.as_millis() as u64;
+        *usage.endpoints.entry(call.endpoint).or_default() += 1;
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_call(route: &str, tenant: &str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(
+        "spotify_route_requests_total",
+        "route" => route.to_string(),
+        "tenant" => tenant.to_string()
+    )
+    .increment(1);
+}
+
+#[cfg(feature = "grpc")]
+pub use grpc::GrpcUsageLayer;
+
+#[cfg(feature = "grpc")]
+mod grpc {
+    use std::task::{Context, Poll};
+
+    use tonic::codegen::{http, BoxFuture};
+    use tower::{Layer, Service};
+
+    /// Counts each gRPC call's Spotify requests against its method path.
+    #[derive(Clone, Default)]
+    pub struct GrpcUsageLayer;
+
+    impl<S> Layer<S> for GrpcUsageLayer {
+        type Service = GrpcUsage<S>;
+
+        fn layer(&self, inner: S) -> Self::Service {
+            GrpcUsage { inner }
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct GrpcUsage<S> {
+        inner: S,
+    }
+
+    impl<S, ReqBody> Service<http::Request<ReqBody>> for GrpcUsage<S>
+    where
+        S: Service<http::Request<ReqBody>>,
+        S::Future: Send + 'static,
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+            let route = Some(req.uri().path().into());
+            Box::pin(super::run_for(route, self.inner.call(req)))
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Query-parameter validation that reports every invalid field at once.
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::AppError;
+use crate::spotify::SearchLimits;
+
+/// One invalid parameter.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Accumulates field errors while validating a request.
+#[derive(Debug, Default)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    /// `Ok(value)` if nothing was recorded, otherwise all errors.
+    pub fn finish<T>(self, value: T) -> Result<T, AppError> {
+        if self.0.is_empty() {
+            Ok(value)
+        } else {
+            Err(AppError::Validation(self.0))
+        }
+    }
+
+    /// Parse an optional integer parameter and check it lies in `min..=max`.
+    pub fn u32_in_range(&mut self, field: &str, raw: Option<&str>, min: u32, max: u32) -> Option<u32> {
+        let raw = raw?.trim();
+        match raw.parse::<u32>() {
+            Ok(v) if (min..=max).contains(&v) => Some(v),
+            Ok(v) => {
+                self.add(field, format!("must be between {} and {} (got {})", min, max, v));
+                None
+            }
+            Err(_) => {
+                self.add(field, format!("must be an integer (got '{}')", raw));
+                None
+            }
+        }
+    }
+
+    /// Parse an optional number and check it lies in `min..=max`.
+    pub fn f32_in_range(&mut self, field: &str, raw: Option<&str>, min: f32, max: f32) -> Option<f32> {
+        let raw = raw?.trim();
+        match raw.parse::<f32>() {
+            Ok(v) if (min..=max).contains(&v) => Some(v),
+            Ok(v) if v.is_finite() => {
+                self.add(field, format!("must be between {} and {} (got {})", min, max, v));
+                None
+            }
+            _ => {
+                self.add(field, format!("must be a number (got '{}')", raw));
+                None
+            }
+        }
+    }
+
+    /// Parse an optional boolean parameter (`true`/`false`/`1`/`0`).
+    pub fn bool(&mut self, field: &str, raw: Option<&str>) -> Option<bool> {
+        match raw?.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            other => {
+                self.add(field, format!("must be true or false (got '{}')", other));
+                None
+            }
+        }
+    }
+
+    /// Check an optional parameter is a two-letter country code, returned uppercase.
+    pub fn market(&mut self, field: &str, raw: Option<&str>) -> Option<String> {
+        let raw = raw?.trim();
+        if raw.len() == 2 && raw.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Some(raw.to_ascii_uppercase());
+        }
+        self.add(field, format!("must be a two-letter country code (got '{}')", raw));
+        None
+    }
+
+    /// Check an optional parameter is an absolute `http` or `https` URL.
+    pub fn http_url(&mut self, field: &str, raw: Option<&str>) -> Option<String> {
+        let raw = raw?.trim();
+        match reqwest::Url::parse(raw) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Some(url.into()),
+            _ => {
+                self.add(field, format!("must be an absolute http or https URL (got '{}')", raw));
+                None
+            }
+        }
+    }
+}
+
+/// A typed query built from a loosely-deserialized raw form.
+pub trait FromRawQuery: Sized {
+    /// All-string shape of the query string, so deserialization itself never fails on bad values.
+    type Raw: DeserializeOwned + Send;
+
+    fn validate(raw: Self::Raw) -> Result<Self, AppError>;
+
+    /// Like [`validate`](Self::validate), for queries whose bounds follow the configured
+    /// search limits.
+    fn validate_with(raw: Self::Raw, _limits: &SearchLimits) -> Result<Self, AppError> {
+        Self::validate(raw)
+    }
+}
+
+/// Extractor for a validated query, rejecting with a 400 listing every invalid field.
+pub struct Validated<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Validated<T>
+where
+    T: FromRawQuery,
+    S: Send + Sync,
+    SearchLimits: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(raw) = Query::<T::Raw>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| AppError::BadRequest(e.body_text()))?;
+        T::validate_with(raw, &SearchLimits::from_ref(state)).map(Validated)
+    }
+}
+
+/// True for a 22-character base62 Spotify ID.
+pub fn is_spotify_id(id: &str) -> bool {
+    id.len() == 22 && id.bytes().all(|b| b.is_ascii_alphanumeric())
+}
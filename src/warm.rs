@@ -0,0 +1,239 @@
+//! Cache warm-up from a seed file, so a fresh deploy doesn't meet peak traffic cold.
+//!
+//! The seed file lists one entry per line: a Spotify track ID (or `spotify:track:` URI),
+//! or otherwise a search query. Blank lines and lines starting with `#` are skipped.
+//! [`CacheWarmer`] runs each search through the search cache, both the plain and the
+//! with-features page, and looks the track IDs up 50 at a time, which puts them in the
+//! store. Calls go one at a time with a pause between them, and while Spotify is rate
+//! limiting us the warm-up waits, so live traffic keeps priority.
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::clock::unix_now;
+use crate::spotify::{DynSpotifyApi, SpotifyError};
+use crate::validation::is_spotify_id;
+
+/// Track IDs per lookup, Spotify's maximum.
+const BATCH_SIZE: usize = 50;
+/// Wait while Spotify throttles us and gave no `Retry-After`.
+const THROTTLE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The entries of a seed file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Seeds {
+    pub searches: Vec<String>,
+    pub track_ids: Vec<String>,
+}
+
+/// Searches and track IDs in `text`, in file order.
+pub fn parse_seeds(text: &str) -> Seeds {
+    let mut seeds = Seeds::default();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let id = line.strip_prefix("spotify:track:").unwrap_or(line);
+        if is_spotify_id(id) {
+            seeds.track_ids.push(id.to_string());
+        } else {
+            seeds.searches.push(line.to_string());
+        }
+    }
+    seeds
+}
+
+/// Progress of the latest warm-up, as `/admin/cache/warm` reports it.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WarmStatus {
+    pub running: bool,
+    /// Unix seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    /// Searches and track IDs to warm.
+    pub searches: usize,
+    pub tracks: usize,
+    /// Searches and track IDs warmed so far.
+    pub searches_warmed: usize,
+    pub tracks_warmed: usize,
+    /// Searches and track IDs whose Spotify call failed, other than by rate limiting.
+    pub failed: usize,
+}
+
+/// Prefetches the entries of a seed file, one run at a time.
+pub struct CacheWarmer {
+    spotify: DynSpotifyApi,
+    file: Option<PathBuf>,
+    pause: Duration,
+    searches: bool,
+    tracks: bool,
+    status: Mutex<WarmStatus>,
+}
+
+impl CacheWarmer {
+    /// Warm through `spotify`, which should be the full stack requests use, from `file`.
+    /// Warms searches and track IDs, with 200ms between calls, by default.
+    pub fn new(spotify: DynSpotifyApi, file: Option<PathBuf>) -> Self {
+        Self {
+            spotify,
+            file,
+            pause: Duration::from_millis(200),
+            searches: true,
+            tracks: true,
+            status: Mutex::default(),
+        }
+    }
+
+    /// Pause between Spotify calls.
+    pub fn pause(mut self, pause: Duration) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Whether to warm searches; pointless with the search cache off.
+    pub fn searches(mut self, enabled: bool) -> Self {
+        self.searches = enabled;
+        self
+    }
+
+    /// Whether to warm track IDs; pointless without a store.
+    pub fn tracks(mut self, enabled: bool) -> Self {
+        self.tracks = enabled;
+        self
+    }
+
+    /// The seed file, if one is configured.
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+
+    pub fn status(&self) -> WarmStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Read the seed file and warm its entries in the background. With a warm-up already
+    /// running, leaves it be. Either way, answers the status of the warm-up that runs.
+    pub async fn start(self: &Arc<Self>) -> std::io::Result<WarmStatus> {
+        let Some(file) = &self.file else {
+            return Ok(self.status());
+        };
+        let mut seeds = parse_seeds(&tokio::fs::read_to_string(file).await?);
+        if !self.searches && !seeds.searches.is_empty() {
+            tracing::info!(searches = seeds.searches.len(), "search cache is off; not warming the seed searches");
+            seeds.searches.clear();
+        }
+        if !self.tracks && !seeds.track_ids.is_empty() {
+            tracing::info!(tracks = seeds.track_ids.len(), "no track store; not warming the seed track IDs");
+            seeds.track_ids.clear();
+        }
+
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            if status.running {
+                return Ok(status.clone());
+            }
+            *status = WarmStatus {
+                running: true,
+                started_at: Some(unix_now()),
+                searches: seeds.searches.len(),
+                tracks: seeds.track_ids.len(),
+                ..WarmStatus::default()
+            };
+            status.clone()
+        };
+        let warmer = self.clone();
+        tokio::spawn(async move { warmer.warm(seeds).await });
+        Ok(status)
+    }
+
+    async fn warm(&self, seeds: Seeds) {
+        for q in &seeds.searches {
+            let warmed = self.paced(|| self.spotify.search_tracks(q, None, None)).await.is_ok()
+                && self.paced(|| self.spotify.search_tracks_with_features(q, None, None)).await.is_ok();
+            self.update(|status| {
+                if warmed {
+                    status.searches_warmed += 1;
+                } else {
+                    status.failed += 1;
+                }
+            });
+            record_warm("search", warmed, 1);
+        }
+        for ids in seeds.track_ids.chunks(BATCH_SIZE) {
+            let warmed = self.paced(|| self.spotify.get_tracks_with_features(ids)).await.is_ok();
+            self.update(|status| {
+                if warmed {
+                    status.tracks_warmed += ids.len();
+                } else {
+                    status.failed += ids.len();
+                }
+            });
+            record_warm("track", warmed, ids.len());
+        }
+
+        let status = self.update(|status| {
+            status.running = false;
+            status.finished_at = Some(unix_now());
+        });
+        tracing::info!(
+            searches = status.searches_warmed,
+            tracks = status.tracks_warmed,
+            failed = status.failed,
+            elapsed_secs = status.finished_at.unwrap_or_default() - status.started_at.unwrap_or_default(),
+            "cache warm-up finished"
+        );
+    }
+
+    /// Run `call` once Spotify isn't throttling us, again after any rate limit, then
+    /// pause before the next call.
+    async fn paced<T, F, Fut>(&self, call: F) -> Result<T, SpotifyError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, SpotifyError>>,
+    {
+        loop {
+            if self.spotify.upstream_status().throttled {
+                tokio::time::sleep(THROTTLE_BACKOFF).await;
+                continue;
+            }
+            let result = call().await;
+            match result {
+                Err(SpotifyError::RateLimited { retry_after }) => {
+                    let wait = retry_after.map(Duration::from_secs).unwrap_or(THROTTLE_BACKOFF);
+                    tracing::debug!("cache warm-up rate limited, waiting {:?}", wait);
+                    tokio::time::sleep(wait).await;
+                }
+                result => {
+                    if let Err(e) = &result {
+                        tracing::warn!("cache warm-up call failed: {}", e);
+                    }
+                    tokio::time::sleep(self.pause).await;
+                    return result;
+                }
+            }
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut WarmStatus)) -> WarmStatus {
+        let mut status = self.status.lock().unwrap();
+        f(&mut status);
+        status.clone()
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_warm(kind: &'static str, warmed: bool, entries: usize) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(
+        "cache_warm_entries_total",
+        "kind" => kind,
+        "result" => if warmed { "warmed" } else { "failed" }
+    )
+    .increment(entries as u64);
+}
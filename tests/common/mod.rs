@@ -0,0 +1,222 @@
+//! Shared harness: a wiremock fake Spotify and the app's HTTP router served against it.
+
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use axum::{middleware, Router};
+use clap::Parser;
+use figment::providers::Serialized;
+use figment::Figment;
+use serde_json::{json, Value};
+use spotify_search::cache::CachingSpotifyApi;
+use spotify_search::cli::Cli;
+use spotify_search::config::Config;
+use spotify_search::handlers::router;
+use spotify_search::jobs::Jobs;
+use spotify_search::reload::Reloader;
+use spotify_search::saved::SavedQueries;
+use spotify_search::spotify::{DynSpotifyApi, MockSpotifyApi, SpotifyClient, SpotifyClientBuilder};
+use spotify_search::state::AppState;
+use spotify_search::suggest::Suggester;
+use spotify_search::tenants::{self, TenantSpotifyApi};
+use spotify_search::usage::{self, Usage};
+use spotify_search::warm::CacheWarmer;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// A fake Spotify that hands out tokens; tests mount the API responses they need.
+pub async fn fake_spotify() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "test-token",
+            "token_type": "Bearer",
+            "expires_in": 3600,
+        })))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// Configuration pointing at `spotify`, without reading the environment or a config file.
+pub fn config(spotify: &MockServer) -> Config {
+    config_with(spotify, json!({}))
+}
+
+/// Like [`config`], with the settings in `overrides` (nested as in the config file) on top.
+pub fn config_with(spotify: &MockServer, overrides: Value) -> Config {
+    let figment = Figment::new()
+        .merge(Serialized::defaults(json!({
+            "spotify": {
+                "client_id": "test-client",
+                "client_secret": "test-secret",
+                "api_base": format!("{}/v1", spotify.uri()),
+                "token_url": format!("{}/api/token", spotify.uri()),
+            },
+        })))
+        .merge(Serialized::defaults(overrides));
+    Config::from_figment(&figment).expect("test config")
+}
+
+/// Configuration with Spotify's own endpoints, for clients that replay a cassette instead
+/// of reaching them.
+pub fn replay_config() -> Config {
+    let figment = Figment::new().merge(Serialized::defaults(json!({
+        "spotify": { "client_id": "test-client", "client_secret": "test-secret" },
+    })));
+    Config::from_figment(&figment).expect("test config")
+}
+
+/// A Spotify client for `config`'s endpoints.
+pub fn spotify_api(config: &Config) -> DynSpotifyApi {
+    Arc::new(spotify_client(config).build().expect("Spotify client"))
+}
+
+/// A Spotify client builder for `config`'s endpoints.
+pub fn spotify_client(config: &Config) -> SpotifyClientBuilder {
+    SpotifyClient::builder(config.spotify_client_id.clone(), config.spotify_client_secret.expose())
+        .api_base(&config.spotify_api_base)
+        .token_url(&config.spotify_token_url)
+}
+
+/// The HTTP API, served on a local port against a fake Spotify.
+pub struct TestApp {
+    pub addr: SocketAddr,
+    http: reqwest::Client,
+}
+
+impl TestApp {
+    pub async fn start(spotify: &MockServer) -> Self {
+        Self::start_with(config(spotify)).await
+    }
+
+    /// Serve the API with `config`, from [`config_with`].
+    pub async fn start_with(config: Config) -> Self {
+        Self::serve(app(config)).await
+    }
+
+    /// Serve `app`, e.g. from [`app_with_client`].
+    pub async fn serve(app: Router) -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Self {
+            addr,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn get(&self, path_and_query: &str) -> reqwest::Response {
+        self.request(reqwest::Method::GET, path_and_query).send().await.expect("request")
+    }
+
+    /// A request to the app, for tests that need a method, headers or a body.
+    pub fn request(&self, method: reqwest::Method, path_and_query: &str) -> reqwest::RequestBuilder {
+        self.http.request(method, format!("http://{}{}", self.addr, path_and_query))
+    }
+}
+
+/// The HTTP API for `config`, with the layers the server puts around it that pick the
+/// tenant, route and JWT principal.
+pub fn app(config: Config) -> Router {
+    let client = spotify_client(&config);
+    app_with_client(config, client)
+}
+
+/// Like [`app`], with the default tenant's Spotify client built from `client`.
+pub fn app_with_client(config: Config, client: SpotifyClientBuilder) -> Router {
+    let usage = Arc::new(Usage::new());
+    let client = client.audit(usage.clone()).build().expect("Spotify client");
+    let mut spotify: DynSpotifyApi = Arc::new(client);
+    // Each tenant gets its own client, as in the server.
+    if !config.tenants.is_empty() {
+        let tenants = config
+            .tenants
+            .iter()
+            .map(|tenant| {
+                let client = SpotifyClient::builder(tenant.client_id.clone(), tenant.client_secret.expose())
+                    .tenant(&tenant.name)
+                    .api_base(&config.spotify_api_base)
+                    .token_url(&config.spotify_token_url)
+                    .audit(usage.clone())
+                    .build()
+                    .expect("tenant Spotify client");
+                (tenant.name.clone(), Arc::new(client) as DynSpotifyApi)
+            })
+            .collect();
+        spotify = Arc::new(TenantSpotifyApi::new(spotify, tenants));
+    }
+    // Outermost, as in the server.
+    if let Some(ttl) = config.search_cache_ttl {
+        let cache = CachingSpotifyApi::new(spotify, ttl, config.search_cache_stale).search_limits(config.search_limits);
+        spotify = Arc::new(cache);
+    }
+    let (_, log_filter) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+    let shared_config = Arc::new(ArcSwap::from_pointee(config.clone()));
+    let state = AppState {
+        reloader: Reloader::new(Cli::parse_from(["spotify-search"]), shared_config.clone(), log_filter),
+        config: shared_config,
+        spotify: spotify.clone(),
+        jobs: Jobs::new().callback_hosts(config.job_callback_allowed_hosts.clone()),
+        search_limits: config.search_limits,
+        request_limits: config.request_limits,
+        suggester: Arc::new(Suggester::new(spotify.clone())),
+        saved: Arc::new(SavedQueries::new(spotify.clone(), config.search_limits, config.search_cache_stale)),
+        warmer: Arc::new(CacheWarmer::new(spotify, None)),
+        usage,
+        #[cfg(any(feature = "sqlite", feature = "postgres"))]
+        store: None,
+        #[cfg(all(feature = "local-search", any(feature = "sqlite", feature = "postgres")))]
+        local_index: None,
+        #[cfg(feature = "prometheus")]
+        metrics: metrics_exporter_prometheus::PrometheusBuilder::new().build_recorder().handle(),
+    };
+    let app = router()
+        .layer(middleware::from_fn_with_state(state.clone(), tenants::scope))
+        .layer(middleware::from_fn(usage::scope));
+    // Outside the tenant scope, which reads the token's tenant claim.
+    #[cfg(feature = "jwt")]
+    let app = match config.jwt.clone() {
+        Some(jwt) => {
+            let validator = Arc::new(spotify_search::jwt::JwtValidator::new(jwt));
+            app.layer(middleware::from_fn_with_state(validator, spotify_search::jwt::authenticate))
+        }
+        None => app,
+    };
+    app.with_state(state)
+}
+
+/// A valid 22-character Spotify ID for test track `n`.
+pub fn track_id(n: u32) -> String {
+    format!("TestTrack{:013}", n)
+}
+
+/// Spotify's JSON for test track `n`.
+pub fn track(n: u32) -> Value {
+    serde_json::to_value(MockSpotifyApi::track(&track_id(n), &format!("Test Track {}", n))).unwrap()
+}
+
+/// Spotify's JSON for the audio features of test track `n`.
+pub fn audio_features(n: u32) -> Value {
+    serde_json::to_value(MockSpotifyApi::audio_features(&track_id(n))).unwrap()
+}
+
+/// Answer every track search on `server` with `page`, e.g. from [`search_page`].
+pub async fn serve_search(server: &MockServer, page: Value) {
+    Mock::given(method("GET"))
+        .and(path("/v1/search"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(page))
+        .mount(server)
+        .await;
+}
+
+/// Spotify's search response for tracks `offset..offset + limit` of `total`.
+pub fn search_page(offset: u32, limit: u32, total: u32) -> Value {
+    let items: Vec<_> = (offset..(offset + limit).min(total)).map(track).collect();
+    json!({ "tracks": { "items": items, "total": total, "limit": limit, "offset": offset } })
+}
@@ -0,0 +1,190 @@
+//! The gRPC service against a fake Spotify.
+
+mod common;
+
+use serde_json::json;
+use spotify_search::grpc::SpotifySearchService;
+use spotify_search::proto::{
+    GetTracksWithFeaturesRequest, GetTracksWithFeaturesResponse, SearchTracksRequest, SearchTracksResponse,
+};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Channel, Endpoint, Server};
+use tonic::{Code, Status};
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{audio_features, fake_spotify, search_page, track, track_id};
+
+/// Serve the gRPC service on a local port against `spotify` and connect to it.
+async fn start(spotify: &MockServer) -> Channel {
+    let service = SpotifySearchService::new(common::spotify_api(&common::config(spotify)));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(
+        Server::builder()
+            .add_service(service.into_router())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    Endpoint::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .expect("connect")
+}
+
+/// Call `method` (`/spotify.SpotifySearch/...`) on `channel`.
+async fn unary<Req, Res>(channel: &Channel, method: &'static str, request: Req) -> Result<Res, Status>
+where
+    Req: prost::Message + 'static,
+    Res: prost::Message + Default + 'static,
+{
+    let mut grpc = tonic::client::Grpc::new(channel.clone());
+    grpc.ready().await.map_err(|e| Status::unavailable(e.to_string()))?;
+    grpc.unary(tonic::Request::new(request), PathAndQuery::from_static(method), ProstCodec::default())
+        .await
+        .map(tonic::Response::into_inner)
+}
+
+#[tokio::test]
+async fn search_tracks_pages() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/search"))
+        .and(query_param("limit", "2"))
+        .and(query_param("offset", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(search_page(2, 2, 3)))
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    let channel = start(&spotify).await;
+
+    let request = SearchTracksRequest {
+        query: "daft punk".into(),
+        limit: 2,
+        offset: 2,
+        ..Default::default()
+    };
+    let res: SearchTracksResponse = unary(&channel, "/spotify.SpotifySearch/SearchTracks", request).await.unwrap();
+    assert_eq!((res.total, res.limit, res.offset), (3, 2, 2));
+    let ids: Vec<_> = res.tracks.iter().map(|t| t.id.clone()).collect();
+    assert_eq!(ids, vec![track_id(2)]);
+}
+
+#[tokio::test]
+async fn tracks_with_features_reports_missing_features() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/tracks"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "tracks": [track(0), track(1)] })))
+        .mount(&spotify)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/v1/audio-features"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "audio_features": [audio_features(0), null] })))
+        .mount(&spotify)
+        .await;
+    let channel = start(&spotify).await;
+
+    let request = GetTracksWithFeaturesRequest {
+        track_ids: vec![track_id(0), track_id(1)],
+        ..Default::default()
+    };
+    let res: GetTracksWithFeaturesResponse =
+        unary(&channel, "/spotify.SpotifySearch/GetTracksWithFeatures", request.clone()).await.unwrap();
+    assert_eq!(res.tracks.len(), 2);
+    assert_eq!(res.tracks[0].embedding.len(), 12);
+    assert!(res.tracks[0].missing_embedding_reason.is_empty());
+    assert!(res.tracks[1].embedding.is_empty());
+    assert!(!res.tracks[1].missing_embedding_reason.is_empty());
+
+    let request = GetTracksWithFeaturesRequest {
+        include_missing_embeddings: Some(false),
+        ..request
+    };
+    let res: GetTracksWithFeaturesResponse =
+        unary(&channel, "/spotify.SpotifySearch/GetTracksWithFeatures", request).await.unwrap();
+    let ids: Vec<_> = res.tracks.iter().map(|t| t.id.clone()).collect();
+    assert_eq!(ids, vec![track_id(0)]);
+}
+
+#[tokio::test]
+async fn rate_limited_search_is_resource_exhausted() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/search"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7"))
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    let channel = start(&spotify).await;
+
+    let request = SearchTracksRequest {
+        query: "daft punk".into(),
+        ..Default::default()
+    };
+    let err = unary::<_, SearchTracksResponse>(&channel, "/spotify.SpotifySearch/SearchTracks", request)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+}
+
+#[cfg(feature = "jwt")]
+#[tokio::test]
+async fn jwt_is_required_on_the_search_service_only() {
+    use std::sync::Arc;
+
+    use spotify_search::config::{JwtConfig, JwtKey};
+    use spotify_search::jwt::{GrpcJwtLayer, JwtValidator};
+    use spotify_search::secret::Secret;
+    use tonic_health::pb::{health_client::HealthClient, HealthCheckRequest};
+
+    use common::serve_search;
+
+    let spotify = fake_spotify().await;
+    serve_search(&spotify, search_page(0, 1, 1)).await;
+    let validator = JwtValidator::new(JwtConfig {
+        key: JwtKey::Secret(Secret::new("jwt-test-secret".into())),
+        issuer: None,
+        audience: None,
+        tenant_claim: "tenant".into(),
+    });
+    let service = SpotifySearchService::new(common::spotify_api(&common::config(&spotify)));
+    let (_, health) = tonic_health::server::health_reporter();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(
+        Server::builder()
+            .layer(GrpcJwtLayer(Some(Arc::new(validator))))
+            .add_service(health)
+            .add_service(service.into_router())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    let channel = Endpoint::from_shared(format!("http://{}", addr)).unwrap().connect().await.expect("connect");
+
+    let request = || SearchTracksRequest {
+        query: "daft punk".into(),
+        ..Default::default()
+    };
+    let status = unary::<_, SearchTracksResponse>(&channel, "/spotify.SpotifySearch/SearchTracks", request())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    let claims = json!({ "sub": "user-1", "exp": 4_102_444_800_u64 });
+    let key = jsonwebtoken::EncodingKey::from_secret(b"jwt-test-secret");
+    let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap();
+    let mut grpc = tonic::client::Grpc::new(channel.clone());
+    grpc.ready().await.unwrap();
+    let mut authorized = tonic::Request::new(request());
+    authorized.metadata_mut().insert("authorization", format!("Bearer {}", token).parse().unwrap());
+    let path = PathAndQuery::from_static("/spotify.SpotifySearch/SearchTracks");
+    let res: tonic::Response<SearchTracksResponse> = grpc.unary(authorized, path, ProstCodec::default()).await.unwrap();
+    assert_eq!(res.into_inner().tracks.len(), 1);
+
+    // Health stays open.
+    let mut health = HealthClient::new(channel);
+    let res = health.check(HealthCheckRequest { service: String::new() }).await;
+    assert!(res.is_ok(), "{:?}", res);
+}
@@ -0,0 +1,944 @@
+//! The HTTP API against a fake Spotify.
+
+mod common;
+
+use std::ops::Range;
+use std::time::Duration;
+
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use reqwest::Method;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use wiremock::matchers::{basic_auth, bearer_token, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use spotify_search::discography;
+use spotify_search::jobs::{JobStatus, Jobs};
+
+use common::{
+    audio_features, config, config_with, fake_spotify, search_page, serve_search, spotify_api, track, track_id, TestApp,
+};
+
+#[tokio::test]
+async fn search_passes_limit_and_offset_through() {
+    let spotify = fake_spotify().await;
+    for offset in [0, 2, 4] {
+        Mock::given(method("GET"))
+            .and(path("/v1/search"))
+            .and(query_param("q", "daft punk"))
+            .and(query_param("limit", "2"))
+            .and(query_param("offset", offset.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(search_page(offset, 2, 5)))
+            .expect(1)
+            .mount(&spotify)
+            .await;
+    }
+    let app = TestApp::start(&spotify).await;
+
+    let mut ids = Vec::new();
+    for offset in [0, 2, 4] {
+        let res = app.get(&format!("/api/v1/search?q=daft%20punk&limit=2&offset={}", offset)).await;
+        assert_eq!(res.status(), 200);
+        let body: Value = res.json().await.unwrap();
+        assert_eq!(body["total"], 5);
+        assert_eq!(body["limit"], 2);
+        assert_eq!(body["offset"], offset);
+        ids.extend(body["tracks"].as_array().unwrap().iter().map(|t| t["id"].as_str().unwrap().to_string()));
+    }
+    assert_eq!(ids, (0..5).map(track_id).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn concurrent_misses_on_a_cached_search_make_one_upstream_call() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/search"))
+        .and(query_param("q", "daft punk"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(search_page(0, 2, 5))
+                .set_delay(Duration::from_millis(300)),
+        )
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    let app = TestApp::start_with(config_with(&spotify, json!({ "search_cache": { "ttl_secs": 60 } }))).await;
+
+    let requests = (0..10).map(|_| app.get("/api/v1/search?q=daft%20punk&limit=2"));
+    for res in join_all(requests).await {
+        assert_eq!(res.status(), 200);
+        let body: Value = res.json().await.unwrap();
+        assert_eq!(body["tracks"].as_array().unwrap().len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn raw_search_passes_spotify_body_through_untouched() {
+    let spotify = fake_spotify().await;
+    let body = r#"{"tracks":{"items":[],"total":0,"limit":2,"offset":0,"href":"kept as sent"}}"#;
+    Mock::given(method("GET"))
+        .and(path("/v1/search"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/json"))
+        .mount(&spotify)
+        .await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = app.get("/api/v1/search?q=daft%20punk&limit=2&raw=true").await;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["content-type"], "application/json");
+    assert_eq!(res.text().await.unwrap(), body);
+}
+
+#[tokio::test]
+async fn artist_search_pages_follow_the_configured_limits() {
+    let spotify = fake_spotify().await;
+    let search = json!({ "default_limit": 5, "max_limit": 10, "max_offset": 20 });
+    let app = TestApp::start_with(config_with(&spotify, json!({ "search": search }))).await;
+
+    for (query, field) in [("limit=11", "limit"), ("offset=21", "offset")] {
+        let res = app.get(&format!("/api/v1/search/artists?q=daft%20punk&{}", query)).await;
+        assert_eq!(res.status(), 400);
+        let body: Value = res.json().await.unwrap();
+        assert_eq!(body["error"]["fields"][0]["field"], field);
+    }
+}
+
+#[tokio::test]
+async fn token_is_fetched_once_and_reused() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/search"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(search_page(0, 1, 1)))
+        .expect(3)
+        .mount(&spotify)
+        .await;
+    let app = TestApp::start(&spotify).await;
+
+    for q in ["a", "b", "c"] {
+        assert_eq!(app.get(&format!("/api/v1/search?q={}", q)).await.status(), 200);
+    }
+    let token_requests = spotify
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.url.path() == "/api/token")
+        .count();
+    assert_eq!(token_requests, 1);
+}
+
+#[tokio::test]
+async fn search_with_features_keeps_tracks_without_features() {
+    let spotify = fake_spotify().await;
+    serve_search(&spotify, search_page(0, 2, 2)).await;
+    Mock::given(method("GET"))
+        .and(path("/v1/audio-features"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "audio_features": [audio_features(0), null] })))
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = app.get("/api/v1/search?q=daft%20punk&limit=2&include_features=true").await;
+    assert_eq!(res.status(), 200);
+    let body: Value = res.json().await.unwrap();
+    let tracks = body["tracks"].as_array().unwrap();
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[0]["embedding"].as_array().map(Vec::len), Some(12));
+    assert_eq!(tracks[1]["id"], track_id(1));
+    assert!(tracks[1].get("embedding").is_none());
+}
+
+#[tokio::test]
+async fn tracks_with_features_tolerates_null_features() {
+    let spotify = fake_spotify().await;
+    let ids = format!("{},{}", track_id(0), track_id(1));
+    Mock::given(method("GET"))
+        .and(path("/v1/tracks"))
+        .and(query_param("ids", ids.as_str()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "tracks": [track(0), track(1)] })))
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/v1/audio-features"))
+        .and(query_param("ids", ids.as_str()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "audio_features": [null, audio_features(1)] })))
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = app.get(&format!("/api/v1/tracks/with-features?ids={}", ids)).await;
+    assert_eq!(res.status(), 200);
+    let body: Value = res.json().await.unwrap();
+    let tracks = body["tracks"].as_array().unwrap();
+    assert_eq!(tracks.len(), 2);
+    assert!(tracks[0].get("embedding").is_none());
+    assert_eq!(tracks[1]["embedding"].as_array().map(Vec::len), Some(12));
+}
+
+#[tokio::test]
+async fn rate_limited_search_returns_429_with_retry_after() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/search"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7"))
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = app.get("/api/v1/search?q=daft%20punk").await;
+    assert_eq!(res.status(), 429);
+    assert_eq!(res.headers()["retry-after"], "7");
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "rate_limited");
+}
+
+#[tokio::test]
+async fn usage_counts_spotify_requests_by_route() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/search"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(search_page(0, 1, 1)))
+        .expect(2)
+        .mount(&spotify)
+        .await;
+    let app = TestApp::start_with(config_with(&spotify, json!({ "admin": { "token": "admin-secret" } }))).await;
+
+    for q in ["a", "b"] {
+        assert_eq!(app.get(&format!("/api/v1/search?q={}", q)).await.status(), 200);
+    }
+    let report: Value = app
+        .request(Method::GET, "/admin/usage")
+        .bearer_auth("admin-secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["calls"], 3);
+    assert_eq!(report["tenants"], json!({ "default": 3 }));
+    let routes = report["routes"].as_array().unwrap();
+    assert_eq!(routes.len(), 1);
+    assert_eq!(routes[0]["route"], "/api/v1/search");
+    assert_eq!(routes[0]["tenant"], "default");
+    assert_eq!(routes[0]["endpoints"], json!({ "search": 2, "token": 1 }));
+}
+
+#[tokio::test]
+async fn admin_endpoints_are_disabled_without_a_token() {
+    let spotify = fake_spotify().await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = app.get("/admin/usage").await;
+    assert_eq!(res.status(), 503);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "unavailable");
+    let res = app.request(Method::POST, "/admin/token/refresh").bearer_auth("anything").send().await.unwrap();
+    assert_eq!(res.status(), 503);
+}
+
+#[tokio::test]
+async fn admin_endpoints_reject_a_wrong_token() {
+    let spotify = fake_spotify().await;
+    let app = TestApp::start_with(config_with(&spotify, json!({ "admin": { "token": "admin-secret" } }))).await;
+
+    let res = app.get("/admin/usage").await;
+    assert_eq!(res.status(), 401);
+    assert!(res.headers().contains_key("www-authenticate"));
+    let res = app.request(Method::GET, "/admin/usage").bearer_auth("wrong").send().await.unwrap();
+    assert_eq!(res.status(), 401);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "unauthorized");
+}
+
+#[tokio::test]
+async fn admin_endpoints_accept_the_configured_token() {
+    let spotify = fake_spotify().await;
+    let app = TestApp::start_with(config_with(&spotify, json!({ "admin": { "token": "admin-secret" } }))).await;
+
+    let res = app.request(Method::GET, "/admin/usage").bearer_auth("admin-secret").send().await.unwrap();
+    assert_eq!(res.status(), 200);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["calls"], 0);
+}
+
+#[tokio::test]
+async fn search_with_features_makes_one_search_and_one_features_call_by_default() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/search"))
+        .and(query_param("limit", "50"))
+        .and(query_param("offset", "0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(search_page(0, 50, 200)))
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    let features: Vec<_> = (0..50).map(audio_features).collect();
+    Mock::given(method("GET"))
+        .and(path("/v1/audio-features"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "audio_features": features })))
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = app.get("/api/v1/search?q=daft%20punk&limit=50&include_features=true").await;
+    assert_eq!(res.status(), 200);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["tracks"].as_array().unwrap().len(), 50);
+}
+
+/// Mount Spotify's track and audio-features lookups of test tracks `ns`, answered after
+/// `delay`; the first `times` of each only, if given.
+async fn mount_lookup(spotify: &MockServer, ns: Range<u32>, delay: Duration, times: Option<u64>) {
+    let ids = ns.clone().map(track_id).collect::<Vec<_>>().join(",");
+    let tracks = json!({ "tracks": ns.clone().map(track).collect::<Vec<_>>() });
+    let features = json!({ "audio_features": ns.map(audio_features).collect::<Vec<_>>() });
+    for (endpoint, body) in [("/v1/tracks", tracks), ("/v1/audio-features", features)] {
+        let mock = Mock::given(method("GET"))
+            .and(path(endpoint))
+            .and(query_param("ids", ids.as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body).set_delay(delay));
+        let mock = match times {
+            Some(times) => mock.up_to_n_times(times),
+            None => mock,
+        };
+        mock.mount(spotify).await;
+    }
+}
+
+/// `/api/v1/tracks/with-features` for test tracks `ns`, with `timeout_ms`.
+fn batch_url(ns: Range<u32>, timeout_ms: u32) -> String {
+    let ids = ns.map(track_id).collect::<Vec<_>>().join(",");
+    format!("/api/v1/tracks/with-features?ids={}&timeout_ms={}", ids, timeout_ms)
+}
+
+fn ids_of(body: &Value) -> Vec<String> {
+    body["tracks"].as_array().unwrap().iter().map(|t| t["id"].as_str().unwrap().to_string()).collect()
+}
+
+#[tokio::test]
+async fn batch_past_its_timeout_returns_what_resolved() {
+    let spotify = fake_spotify().await;
+    mount_lookup(&spotify, 0..10, Duration::ZERO, None).await;
+    mount_lookup(&spotify, 10..15, Duration::from_secs(10), None).await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = app.get(&batch_url(0..15, 500)).await;
+    assert_eq!(res.status(), 200);
+    let token = res.headers()["resume-token"].to_str().unwrap().to_string();
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["incomplete"], true);
+    assert_eq!(ids_of(&body), (0..10).map(track_id).collect::<Vec<_>>());
+    assert_eq!(body["remaining_ids"], json!((10..15).map(track_id).collect::<Vec<_>>()));
+    assert_eq!(body["resume_token"], token.as_str());
+}
+
+#[cfg(feature = "grpc")]
+#[tokio::test]
+async fn protobuf_batch_past_its_timeout_carries_the_remaining_ids() {
+    use prost::Message;
+    use spotify_search::proto::GetTracksWithFeaturesResponse;
+
+    let spotify = fake_spotify().await;
+    mount_lookup(&spotify, 0..10, Duration::ZERO, None).await;
+    mount_lookup(&spotify, 10..15, Duration::from_secs(10), None).await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = app
+        .request(Method::GET, &batch_url(0..15, 500))
+        .header("accept", "application/x-protobuf")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let token = res.headers()["resume-token"].to_str().unwrap().to_string();
+    let body = GetTracksWithFeaturesResponse::decode(res.bytes().await.unwrap()).unwrap();
+    assert_eq!(body.tracks.len(), 10);
+    assert_eq!(body.remaining_ids, (10..15).map(track_id).collect::<Vec<_>>());
+    assert_eq!(body.resume_token, token);
+}
+
+#[tokio::test]
+async fn resume_token_looks_up_only_the_remaining_ids() {
+    let spotify = fake_spotify().await;
+    mount_lookup(&spotify, 0..10, Duration::ZERO, None).await;
+    mount_lookup(&spotify, 10..15, Duration::from_secs(10), Some(1)).await;
+    mount_lookup(&spotify, 10..15, Duration::ZERO, None).await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = app.get(&batch_url(0..15, 500)).await;
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["incomplete"], true);
+    let token = body["resume_token"].as_str().unwrap();
+
+    let res = app.get(&format!("{}&resume_token={}", batch_url(0..15, 5000), token)).await;
+    assert_eq!(res.status(), 200);
+    assert!(!res.headers().contains_key("resume-token"));
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body.get("incomplete"), None);
+    assert_eq!(body.get("remaining_ids"), None);
+    assert_eq!(ids_of(&body), (0..15).map(track_id).collect::<Vec<_>>());
+    // The first chunk came from the saved progress: one tracks and one features lookup, both from the first request.
+    let first_chunk = (0..10).map(track_id).collect::<Vec<_>>().join(",");
+    let requests = spotify.received_requests().await.unwrap();
+    let lookups = requests.iter().filter(|r| r.url.query_pairs().any(|(k, v)| k == "ids" && v == first_chunk));
+    assert_eq!(lookups.count(), 2);
+    let job: Value = app.get(&format!("/api/v1/jobs/{}", token)).await.json().await.unwrap();
+    assert_eq!(job["status"], "completed");
+}
+
+#[tokio::test]
+async fn resume_token_must_match_the_batch() {
+    let spotify = fake_spotify().await;
+    mount_lookup(&spotify, 0..10, Duration::ZERO, None).await;
+    mount_lookup(&spotify, 10..15, Duration::from_secs(10), None).await;
+    let app = TestApp::start(&spotify).await;
+
+    let body: Value = app.get(&batch_url(0..15, 500)).await.json().await.unwrap();
+    let token = body["resume_token"].as_str().unwrap().to_string();
+    let message = |body: &Value| body["error"]["fields"].to_string();
+
+    // Issued for different IDs.
+    let res = app.get(&format!("{}&resume_token={}", batch_url(0..14, 500), token)).await;
+    assert_eq!(res.status(), 400);
+    assert!(message(&res.json().await.unwrap()).contains("different batch"));
+    // Altered, or no longer kept: either way, not a batch we know.
+    let mut tampered = token.clone().into_bytes();
+    tampered[0] = if tampered[0] == b'a' { b'b' } else { b'a' };
+    let tampered = String::from_utf8(tampered).unwrap();
+    let res = app.get(&format!("{}&resume_token={}", batch_url(0..15, 500), tampered)).await;
+    assert_eq!(res.status(), 400);
+    assert!(message(&res.json().await.unwrap()).contains("unknown or has expired"));
+    let res = app.get(&format!("{}&resume_token=not-a-token", batch_url(0..15, 500))).await;
+    assert_eq!(res.status(), 400);
+}
+
+/// Submit a match job for `titles`, with `key` as its `Idempotency-Key` if given.
+async fn submit_match(app: &TestApp, key: Option<&str>, titles: &[&str]) -> reqwest::Response {
+    let queries: Vec<_> = titles.iter().map(|title| json!({ "title": title })).collect();
+    let mut request = app.request(Method::POST, "/api/v1/jobs/match").json(&json!({ "queries": queries }));
+    if let Some(key) = key {
+        request = request.header("idempotency-key", key);
+    }
+    request.send().await.unwrap()
+}
+
+#[tokio::test]
+async fn match_job_retried_with_its_idempotency_key_is_replayed() {
+    let spotify = fake_spotify().await;
+    serve_search(&spotify, search_page(0, 1, 1)).await;
+    let app = TestApp::start(&spotify).await;
+
+    let res = submit_match(&app, Some("import-42"), &["One More Time"]).await;
+    assert_eq!(res.status(), 202);
+    assert!(!res.headers().contains_key("idempotent-replayed"));
+    let job: Value = res.json().await.unwrap();
+
+    let res = submit_match(&app, Some("import-42"), &["One More Time"]).await;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["idempotent-replayed"], "true");
+    assert_eq!(res.headers()["location"], format!("/api/v1/jobs/{}", job["id"].as_str().unwrap()));
+    let replayed: Value = res.json().await.unwrap();
+    assert_eq!(replayed["id"], job["id"]);
+
+    // The same key for a different request is refused rather than replayed.
+    let res = submit_match(&app, Some("import-42"), &["Around the World"]).await;
+    assert_eq!(res.status(), 422);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "idempotency_key_reused");
+}
+
+#[tokio::test]
+async fn match_jobs_without_an_idempotency_key_are_not_deduplicated() {
+    let spotify = fake_spotify().await;
+    serve_search(&spotify, search_page(0, 1, 1)).await;
+    let app = TestApp::start(&spotify).await;
+
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let res = submit_match(&app, None, &["One More Time"]).await;
+        assert_eq!(res.status(), 202);
+        let job: Value = res.json().await.unwrap();
+        ids.push(job["id"].as_str().unwrap().to_string());
+    }
+    assert_ne!(ids[0], ids[1]);
+    let res = submit_match(&app, Some(""), &["One More Time"]).await;
+    assert_eq!(res.status(), 400);
+}
+
+#[cfg(feature = "cassette")]
+#[tokio::test]
+async fn search_is_served_from_a_cassette() {
+    use spotify_search::spotify::Cassette;
+
+    let config = common::replay_config();
+    let cassette = Cassette::replay("tests/fixtures/cassettes/search.json").unwrap();
+    let client = common::spotify_client(&config).cassette(cassette);
+    let app = TestApp::serve(common::app_with_client(config, client)).await;
+
+    let res = app.get("/api/v1/search?q=daft+punk&limit=2&offset=0").await;
+    assert_eq!(res.status(), 200);
+    let body: Value = res.json().await.unwrap();
+    let names: Vec<_> = body["tracks"].as_array().unwrap().iter().map(|t| t["name"].clone()).collect();
+    assert_eq!(names, [json!("One More Time"), json!("Harder, Better, Faster, Stronger")]);
+
+    // Anything the cassette didn't record fails rather than reaching Spotify.
+    let res = app.get("/api/v1/search?q=justice&limit=2&offset=0").await;
+    assert_eq!(res.status(), 502);
+}
+
+#[tokio::test]
+async fn artist_discography_sync_stores_each_album_track_once() {
+    let spotify = fake_spotify().await;
+    // Two albums, one per page, sharing test track 2.
+    for (offset, album, tracks) in [(0, "TestAlbum0000000000000", 0..3), (1, "TestAlbum0000000000001", 2..4)] {
+        let page = json!({ "items": [{ "id": album, "name": album }], "total": 2, "limit": 1, "offset": offset });
+        Mock::given(method("GET"))
+            .and(path("/v1/artists/TestArtist000000000000/albums"))
+            .and(query_param("offset", offset.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page))
+            .expect(1)
+            .mount(&spotify)
+            .await;
+        let items: Vec<_> = tracks.clone().map(track).collect();
+        let page = json!({ "items": items, "total": tracks.len(), "limit": 50, "offset": 0 });
+        Mock::given(method("GET"))
+            .and(path(format!("/v1/albums/{}/tracks", album)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page))
+            .expect(1)
+            .mount(&spotify)
+            .await;
+    }
+    mount_lookup(&spotify, 0..3, Duration::ZERO, Some(1)).await;
+    mount_lookup(&spotify, 3..4, Duration::ZERO, Some(1)).await;
+
+    let api = spotify_api(&config(&spotify));
+    let jobs = Jobs::new();
+    let job = jobs.submit(discography::KIND, json!({}), None, move |job| {
+        discography::sync_artist(api, "TestArtist000000000000".into(), job)
+    });
+    let job = loop {
+        let job = jobs.get(&job.id).unwrap();
+        if matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+            break job;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.last_error);
+    assert_eq!((job.processed, job.total, job.errors), (2, Some(2), 0));
+    assert_eq!(job.counters["albums"], 2);
+    assert_eq!(job.counters["tracks"], 4);
+    assert_eq!(job.counters["without_features"], 0);
+    let results = jobs.results(&job.id, 0, 10).unwrap().items;
+    assert_eq!(results, (0..4).map(|n| json!(track_id(n))).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn artist_discography_sync_needs_the_local_store() {
+    let spotify = fake_spotify().await;
+    let app = TestApp::start(&spotify).await;
+    let res = app.request(Method::POST, "/api/v1/ingest/artist/TestArtist000000000000").send().await.unwrap();
+    assert_eq!(res.status(), 503);
+    let res = app.request(Method::POST, "/api/v1/ingest/artist/not-an-id").send().await.unwrap();
+    assert_eq!(res.status(), 400);
+}
+
+#[tokio::test]
+async fn job_callback_is_signed_over_its_timestamp_and_body() {
+    let spotify = fake_spotify().await;
+    serve_search(&spotify, search_page(0, 1, 1)).await;
+    let receiver = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hooks/jobs"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&receiver)
+        .await;
+    // The receiver listens on loopback, which only an allowed host may reach.
+    let jobs = json!({ "callback_secret": "hook-secret", "callback_allowed_hosts": ["127.0.0.1"] });
+    let app = TestApp::start_with(config_with(&spotify, json!({ "jobs": jobs }))).await;
+
+    let body = json!({
+        "queries": [{ "title": "One More Time" }],
+        "callback_url": format!("{}/hooks/jobs", receiver.uri()),
+    });
+    let res = app.request(Method::POST, "/api/v1/jobs/match").json(&body).send().await.unwrap();
+    assert_eq!(res.status(), 202);
+    let job: Value = res.json().await.unwrap();
+
+    let mut delivered = Vec::new();
+    for _ in 0..50 {
+        delivered = receiver.received_requests().await.unwrap();
+        if !delivered.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let delivery = delivered.first().expect("a callback delivery");
+    let timestamp = delivery.headers["x-signature-timestamp"].to_str().unwrap();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    assert!(now.abs_diff(timestamp.parse().unwrap()) < 60);
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"hook-secret").unwrap();
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(&delivery.body);
+    let expected: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(delivery.headers["x-signature-256"], format!("sha256={}", expected));
+    let payload: Value = serde_json::from_slice(&delivery.body).unwrap();
+    assert_eq!(payload["id"], job["id"]);
+    assert_eq!(payload["status"], "completed");
+}
+
+/// Submit a match job calling back `url`, expecting it refused; returns the reason.
+async fn refused_callback(app: &TestApp, url: &str) -> String {
+    let body = json!({ "queries": [{ "title": "One More Time" }], "callback_url": url });
+    let res = app.request(Method::POST, "/api/v1/jobs/match").json(&body).send().await.unwrap();
+    assert_eq!(res.status(), 400, "{}", url);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["error"]["fields"][0]["field"], "callback_url");
+    body["error"]["fields"][0]["message"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn job_callback_to_an_internal_address_is_refused() {
+    let spotify = fake_spotify().await;
+    let jobs = json!({ "callback_secret": "hook-secret" });
+    let app = TestApp::start_with(config_with(&spotify, json!({ "jobs": jobs }))).await;
+    for url in [
+        "http://127.0.0.1:8080/hooks",
+        "http://10.0.0.7/hooks",
+        "http://169.254.169.254/latest/meta-data",
+        "http://[::1]/hooks",
+        "http://[::ffff:192.168.0.1]/hooks",
+        "http://[fd00::1]/hooks",
+        "http://[64:ff9b::a00:7]/hooks",
+        "http://[2002:a9fe:a9fe::1]/hooks",
+    ] {
+        let message = refused_callback(&app, url).await;
+        assert!(message.contains("non-public address"), "{}", message);
+    }
+
+    // With an allow-list, only the hosts on it.
+    let jobs = json!({ "callback_secret": "hook-secret", "callback_allowed_hosts": "hooks.internal" });
+    let app = TestApp::start_with(config_with(&spotify, json!({ "jobs": jobs }))).await;
+    let message = refused_callback(&app, "http://127.0.0.1:8080/hooks").await;
+    assert!(message.contains("not in JOB_CALLBACK_ALLOWED_HOSTS"), "{}", message);
+}
+
+/// JWT authentication (`jwt` feature), against HMAC secrets and a JWKS served by the fake.
+#[cfg(feature = "jwt")]
+mod jwt {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use reqwest::Method;
+    use serde_json::{json, Value};
+    use spotify_search::config::{JwtConfig, JwtKey};
+    use spotify_search::jwt::{JwtError, JwtValidator};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::common::{config_with, fake_spotify, search_page, serve_search, TestApp};
+
+    const SECRET: &str = "jwt-test-secret";
+    const RSA_PRIVATE: &[u8] = include_bytes!("fixtures/jwt/rsa_private.pem");
+    const RSA_PUBLIC: &[u8] = include_bytes!("fixtures/jwt/rsa_public.pem");
+    const RSA_JWK: &str = include_str!("fixtures/jwt/rsa_public.jwk.json");
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    /// Claims valid for an hour, with `extra` on top.
+    fn claims(extra: Value) -> Value {
+        let mut claims = json!({ "sub": "user-1", "iss": "test-issuer", "aud": "search-api", "exp": now() + 3600 });
+        claims.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        claims
+    }
+
+    fn hs256(claims: &Value) -> String {
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), claims, &EncodingKey::from_secret(SECRET.as_bytes()))
+            .unwrap()
+    }
+
+    fn rs256(kid: &str, claims: &Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.into());
+        jsonwebtoken::encode(&header, claims, &EncodingKey::from_rsa_pem(RSA_PRIVATE).unwrap()).unwrap()
+    }
+
+    /// The fixture key as a JWKS, once under each of `kids`.
+    fn jwks(kids: &[&str]) -> Value {
+        let jwk: Value = serde_json::from_str(RSA_JWK).unwrap();
+        let keys: Vec<Value> = kids
+            .iter()
+            .map(|kid| {
+                let mut jwk = jwk.clone();
+                jwk["kid"] = json!(kid);
+                jwk
+            })
+            .collect();
+        json!({ "keys": keys })
+    }
+
+    async fn search(app: &TestApp, token: &str) -> reqwest::Response {
+        app.request(Method::GET, "/api/v1/search?q=daft%20punk").bearer_auth(token).send().await.unwrap()
+    }
+
+    async fn hmac_app(spotify: &MockServer) -> TestApp {
+        let jwt = json!({ "secret": SECRET, "issuer": "test-issuer", "audience": "search-api" });
+        TestApp::start_with(config_with(spotify, json!({ "auth": { "jwt": jwt } }))).await
+    }
+
+    #[tokio::test]
+    async fn accepts_a_valid_token() {
+        let spotify = fake_spotify().await;
+        serve_search(&spotify, search_page(0, 1, 1)).await;
+        let app = hmac_app(&spotify).await;
+
+        assert_eq!(search(&app, &hs256(&claims(json!({})))).await.status(), 200);
+        let res = app.get("/api/v1/search?q=daft%20punk").await;
+        assert_eq!(res.status(), 401);
+        let body: Value = res.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn leaves_paths_outside_the_api_open() {
+        let spotify = fake_spotify().await;
+        let app = hmac_app(&spotify).await;
+
+        assert_eq!(app.get("/version").await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_expired_token() {
+        let spotify = fake_spotify().await;
+        serve_search(&spotify, search_page(0, 1, 1)).await;
+        let app = hmac_app(&spotify).await;
+
+        let token = hs256(&claims(json!({ "exp": now() - 3600 })));
+        assert_eq!(search(&app, &token).await.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn rejects_the_wrong_audience_or_issuer() {
+        let spotify = fake_spotify().await;
+        serve_search(&spotify, search_page(0, 1, 1)).await;
+        let app = hmac_app(&spotify).await;
+
+        assert_eq!(search(&app, &hs256(&claims(json!({ "aud": "other-api" })))).await.status(), 401);
+        assert_eq!(search(&app, &hs256(&claims(json!({ "iss": "other-issuer" })))).await.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_hmac_token_against_rsa_keys() {
+        let spotify = fake_spotify().await;
+        serve_search(&spotify, search_page(0, 1, 1)).await;
+        Mock::given(method("GET"))
+            .and(path("/jwks.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(jwks(&["k1"])))
+            .mount(&spotify)
+            .await;
+        let jwt = json!({ "jwks_url": format!("{}/jwks.json", spotify.uri()), "audience": "search-api" });
+        let app = TestApp::start_with(config_with(&spotify, json!({ "auth": { "jwt": jwt } }))).await;
+
+        assert_eq!(search(&app, &rs256("k1", &claims(json!({})))).await.status(), 200);
+        // The classic downgrade: HMAC-sign with the public key as the secret.
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("k1".into());
+        let forged = jsonwebtoken::encode(&header, &claims(json!({})), &EncodingKey::from_secret(RSA_PUBLIC)).unwrap();
+        let res = search(&app, &forged).await;
+        assert_eq!(res.status(), 401);
+        let body: Value = res.json().await.unwrap();
+        assert!(body["error"]["message"].as_str().unwrap().contains("HS256"));
+    }
+
+    /// A validator for the JWKS at `spotify`, which serves `k1` first and `k1` and `k2` after.
+    async fn rotating_jwks(spotify: &MockServer) -> JwtValidator {
+        Mock::given(method("GET"))
+            .and(path("/jwks.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(jwks(&["k1"])))
+            .up_to_n_times(1)
+            .mount(spotify)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/jwks.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(jwks(&["k1", "k2"])))
+            .mount(spotify)
+            .await;
+        JwtValidator::new(JwtConfig {
+            key: JwtKey::JwksUrl(format!("{}/jwks.json", spotify.uri())),
+            issuer: None,
+            audience: Some("search-api".into()),
+            tenant_claim: "tenant".into(),
+        })
+    }
+
+    async fn jwks_fetches(spotify: &MockServer) -> usize {
+        let requests = spotify.received_requests().await.unwrap();
+        requests.iter().filter(|r| r.url.path() == "/jwks.json").count()
+    }
+
+    #[tokio::test]
+    async fn refetches_the_jwks_for_an_unknown_kid() {
+        let spotify = MockServer::start().await;
+        let validator = rotating_jwks(&spotify).await.min_refetch(Duration::ZERO);
+
+        let bearer = |kid| format!("Bearer {}", rs256(kid, &claims(json!({}))));
+        validator.authenticate(Some(&bearer("k1"))).await.unwrap();
+        validator.authenticate(Some(&bearer("k1"))).await.unwrap();
+        assert_eq!(jwks_fetches(&spotify).await, 1);
+        let principal = validator.authenticate(Some(&bearer("k2"))).await.unwrap();
+        assert_eq!(principal.subject.as_deref(), Some("user-1"));
+        assert_eq!(jwks_fetches(&spotify).await, 2);
+    }
+
+    #[tokio::test]
+    async fn throttles_jwks_refetches() {
+        let spotify = MockServer::start().await;
+        let validator = rotating_jwks(&spotify).await;
+
+        let bearer = |kid| format!("Bearer {}", rs256(kid, &claims(json!({}))));
+        validator.authenticate(Some(&bearer("k1"))).await.unwrap();
+        for _ in 0..3 {
+            let e = validator.authenticate(Some(&bearer("k2"))).await.unwrap_err();
+            assert!(matches!(e, JwtError::Invalid(_)), "{:?}", e);
+        }
+        assert_eq!(jwks_fetches(&spotify).await, 1);
+    }
+
+    #[tokio::test]
+    async fn tenant_claim_selects_the_tenant() {
+        let spotify = fake_spotify().await;
+        serve_search(&spotify, search_page(0, 1, 1)).await;
+        let app = TestApp::start_with(config_with(
+            &spotify,
+            json!({
+                "auth": { "jwt": { "secret": SECRET, "issuer": "test-issuer", "audience": "search-api" } },
+                "admin": { "token": "admin-secret" },
+                "tenants": { "acme": { "client_id": "acme-client", "client_secret": "acme-secret" } },
+            }),
+        ))
+        .await;
+
+        // The claim wins over the headers, which alone would be rejected.
+        let token = hs256(&claims(json!({ "tenant": "acme" })));
+        let res = app
+            .request(Method::GET, "/api/v1/search?q=daft%20punk")
+            .bearer_auth(token)
+            .header("x-tenant", "unknown")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let report: Value = app
+            .request(Method::GET, "/admin/usage")
+            .bearer_auth("admin-secret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(report["tenants"], json!({ "acme": 2 }));
+    }
+}
+
+#[tokio::test]
+async fn cached_searches_are_kept_per_tenant() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("POST"))
+        .and(path("/api/token"))
+        .and(basic_auth("acme-client", "acme-secret"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "acme-token",
+            "token_type": "Bearer",
+            "expires_in": 3600,
+        })))
+        .with_priority(1)
+        .mount(&spotify)
+        .await;
+    // Each tenant's client searches once; its repeat is a hit in its own entry.
+    for token in ["acme-token", "test-token"] {
+        Mock::given(method("GET"))
+            .and(path("/v1/search"))
+            .and(bearer_token(token))
+            .respond_with(ResponseTemplate::new(200).set_body_json(search_page(0, 1, 1)))
+            .expect(1)
+            .mount(&spotify)
+            .await;
+    }
+    let tenants = json!({
+        "acme": { "client_id": "acme-client", "client_secret": "acme-secret", "api_keys": ["acme-key"] },
+    });
+    let overrides = json!({
+        "tenants": tenants,
+        "search_cache": { "ttl_secs": 60 },
+        "admin": { "token": "admin-secret" },
+    });
+    let app = TestApp::start_with(config_with(&spotify, overrides)).await;
+
+    for _ in 0..2 {
+        let res = app.request(Method::GET, "/api/v1/search?q=a").header("x-api-key", "acme-key").send().await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(app.get("/api/v1/search?q=a").await.status(), 200);
+    }
+    let usage: Value = app
+        .request(Method::GET, "/admin/usage")
+        .bearer_auth("admin-secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    // A token and a search each.
+    assert_eq!(usage["tenants"], json!({ "acme": 2, "default": 2 }));
+}
+
+#[tokio::test]
+async fn tenant_requests_use_their_own_client_and_token() {
+    let spotify = fake_spotify().await;
+    Mock::given(method("POST"))
+        .and(path("/api/token"))
+        .and(basic_auth("acme-client", "acme-secret"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "acme-token",
+            "token_type": "Bearer",
+            "expires_in": 3600,
+        })))
+        .with_priority(1)
+        .expect(1)
+        .mount(&spotify)
+        .await;
+    for token in ["acme-token", "test-token"] {
+        Mock::given(method("GET"))
+            .and(path("/v1/search"))
+            .and(bearer_token(token))
+            .respond_with(ResponseTemplate::new(200).set_body_json(search_page(0, 1, 1)))
+            .expect(2)
+            .mount(&spotify)
+            .await;
+    }
+    let tenants = json!({
+        "acme": { "client_id": "acme-client", "client_secret": "acme-secret", "api_keys": ["acme-key"] },
+    });
+    // Without the search cache, so that every request reaches Spotify.
+    let overrides = json!({ "tenants": tenants, "search_cache": { "ttl_secs": 0 } });
+    let app = TestApp::start_with(config_with(&spotify, overrides)).await;
+
+    for q in ["a", "b"] {
+        let url = format!("/api/v1/search?q={}", q);
+        let res = app.request(Method::GET, &url).header("x-api-key", "acme-key").send().await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(app.get(&url).await.status(), 200);
+    }
+    let res = app.request(Method::GET, "/api/v1/search?q=c").header("x-api-key", "other-key").send().await.unwrap();
+    assert_eq!(res.status(), 401);
+}
@@ -0,0 +1,295 @@
+//! Client certificate identities, verification and tenant selection.
+
+mod common;
+
+use std::path::PathBuf;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair, SanType};
+use serde_json::{json, Value};
+use spotify_search::config::{TenantConfig, TlsConfig};
+use spotify_search::https;
+use spotify_search::mtls::{self, ClientCert};
+use spotify_search::secret::Secret;
+use spotify_search::tenants;
+use tokio_rustls::rustls::pki_types::{CertificateDer, UnixTime};
+
+use common::{config_with, fake_spotify, search_page, serve_search};
+
+/// A certificate authority that signs test certificates.
+struct Ca {
+    cert: Certificate,
+    key: KeyPair,
+}
+
+impl Ca {
+    fn new(name: &str) -> Self {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        Self { cert, key }
+    }
+
+    /// A certificate with subject CN `cn` and the SAN entries `sans`, and its key.
+    fn sign(&self, cn: &str, sans: Vec<SanType>) -> (Certificate, KeyPair) {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, cn);
+        params.subject_alt_names = sans;
+        let key = KeyPair::generate().unwrap();
+        (params.signed_by(&key, &self.cert, &self.key).unwrap(), key)
+    }
+}
+
+fn dns(name: &str) -> SanType {
+    SanType::DnsName(name.try_into().unwrap())
+}
+
+/// A fresh directory for PEM files.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("spotify-search-mtls-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn tenant(name: &str, client_certs: &[&str]) -> TenantConfig {
+    TenantConfig {
+        name: name.into(),
+        client_id: format!("{}-client", name),
+        client_secret: Secret::new(format!("{}-secret", name)),
+        api_keys: vec![],
+        client_certs: client_certs.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+#[test]
+fn client_cert_names_are_the_sans_then_the_cn() {
+    let ca = Ca::new("Test CA");
+    let (cert, _) = ca.sign(
+        "search-client",
+        vec![
+            dns("client.acme.test"),
+            SanType::URI("spiffe://acme.test/search".try_into().unwrap()),
+            SanType::Rfc822Name("ops@acme.test".try_into().unwrap()),
+            SanType::IpAddress([10, 0, 0, 1].into()),
+        ],
+    );
+
+    let names = ClientCert::from_der(cert.der()).unwrap().names;
+    assert_eq!(
+        names,
+        vec!["client.acme.test", "spiffe://acme.test/search", "ops@acme.test", "search-client"]
+    );
+    assert_eq!(ClientCert { names }.name(), Some("client.acme.test"));
+    assert_eq!(ClientCert::from_der(b"not a certificate"), None);
+}
+
+#[test]
+fn client_cert_selects_the_tenant_listing_one_of_its_names() {
+    let tenants = [
+        tenant("acme", &["client.acme.test"]),
+        tenant("globex", &["spiffe://globex.test/search", "globex-batch"]),
+    ];
+    let cert = |names: &[&str]| ClientCert {
+        names: names.iter().map(|s| s.to_string()).collect(),
+    };
+
+    let selected = |c: &ClientCert| tenants::for_client_cert(&tenants, c).map(|t| t.name.clone());
+    assert_eq!(selected(&cert(&["client.acme.test", "cn"])).as_deref(), Some("acme"));
+    assert_eq!(selected(&cert(&["other.test", "globex-batch"])).as_deref(), Some("globex"));
+    assert_eq!(selected(&cert(&["other.test", "acme"])), None);
+    assert_eq!(selected(&cert(&[])), None);
+}
+
+#[test]
+fn client_verifier_requires_a_cert_from_the_bundle() {
+    let ca = Ca::new("Test CA");
+    let rogue = Ca::new("Rogue CA");
+    let dir = temp_dir("verifier");
+    let ca_path = dir.join("ca.pem");
+    std::fs::write(&ca_path, ca.cert.pem()).unwrap();
+
+    let verifier = mtls::client_verifier(&ca_path).unwrap();
+    assert!(verifier.client_auth_mandatory());
+    let (trusted, _) = ca.sign("client", vec![dns("client.acme.test")]);
+    let (untrusted, _) = rogue.sign("client", vec![dns("client.acme.test")]);
+    let now = UnixTime::now();
+    assert!(verifier.verify_client_cert(&CertificateDer::from(trusted.der().to_vec()), &[], now).is_ok());
+    assert!(verifier.verify_client_cert(&CertificateDer::from(untrusted.der().to_vec()), &[], now).is_err());
+
+    let empty = dir.join("empty.pem");
+    std::fs::write(&empty, "").unwrap();
+    assert!(mtls::client_verifier(&empty).is_err());
+    assert!(mtls::client_verifier(&dir.join("missing.pem")).is_err());
+}
+
+/// The PEM files for a `localhost` server certificate signed by `ca`, and `ca` as the
+/// client CA, in `dir`.
+fn server_tls(ca: &Ca, dir: &std::path::Path) -> TlsConfig {
+    let (cert, key) = ca.sign("localhost", vec![dns("localhost")]);
+    let tls = TlsConfig {
+        cert_path: dir.join("server.pem"),
+        key_path: dir.join("server.key"),
+        client_ca_path: Some(dir.join("ca.pem")),
+    };
+    std::fs::write(&tls.cert_path, cert.pem()).unwrap();
+    std::fs::write(&tls.key_path, key.serialize_pem()).unwrap();
+    std::fs::write(dir.join("ca.pem"), ca.cert.pem()).unwrap();
+    tls
+}
+
+#[tokio::test]
+async fn https_tenant_is_the_token_claim_then_the_client_cert_then_the_headers() {
+    let spotify = fake_spotify().await;
+    serve_search(&spotify, search_page(0, 1, 1)).await;
+    let ca = Ca::new("Test CA");
+    let tls = server_tls(&ca, &temp_dir("https"));
+    #[cfg_attr(not(feature = "jwt"), allow(unused_mut))]
+    let mut overrides = json!({
+        "http": { "tls": { "cert": tls.cert_path, "key": tls.key_path, "client_ca": tls.client_ca_path } },
+        "admin": { "token": "admin-secret" },
+        // Every search reaches Spotify, so usage counts each one.
+        "search_cache": { "ttl_secs": 0 },
+        "tenants": {
+            "acme": {
+                "client_id": "acme-client",
+                "client_secret": "acme-secret",
+                "client_certs": ["client.acme.test"],
+            },
+            "globex": { "client_id": "globex-client", "client_secret": "globex-secret", "api_keys": ["globex-key"] },
+        },
+    });
+    #[cfg(feature = "jwt")]
+    {
+        overrides["auth"] = json!({ "jwt": { "secret": "jwt-test-secret" } });
+    }
+    let config = config_with(&spotify, overrides);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let port = listener.local_addr().expect("local addr").port();
+    let tls = config.http_tls.clone().unwrap();
+    tokio::spawn(https::serve(listener, common::app(config), &tls, None).unwrap());
+
+    let (cert, key) = ca.sign("acme-search", vec![dns("client.acme.test")]);
+    let http = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(ca.cert.pem().as_bytes()).unwrap())
+        .identity(reqwest::Identity::from_pkcs8_pem(cert.pem().as_bytes(), key.serialize_pem().as_bytes()).unwrap())
+        .build()
+        .unwrap();
+    let url = |path: &str| format!("https://localhost:{}{}", port, path);
+    let search = || http.get(url("/api/v1/search?q=daft%20punk"));
+    #[cfg(feature = "jwt")]
+    let search = || {
+        let claims = json!({ "sub": "user-1", "exp": 4_102_444_800_u64 });
+        let key = jsonwebtoken::EncodingKey::from_secret(b"jwt-test-secret");
+        search().bearer_auth(jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap())
+    };
+
+    // The certificate wins over headers naming another tenant, or none that exists.
+    let res = search().header("x-api-key", "globex-key").send().await.unwrap();
+    assert_eq!(res.status(), 200);
+    let res = search().header("x-tenant", "initech").send().await.unwrap();
+    assert_eq!(res.status(), 200);
+    let usage = || async {
+        let res = http.get(url("/admin/usage")).bearer_auth("admin-secret").send().await.unwrap();
+        res.json::<Value>().await.unwrap()
+    };
+    assert_eq!(usage().await["tenants"], json!({ "acme": 3 }));
+
+    // And a token's tenant claim wins over the certificate.
+    #[cfg(feature = "jwt")]
+    {
+        let claims = json!({ "sub": "user-1", "tenant": "globex", "exp": 4_102_444_800_u64 });
+        let key = jsonwebtoken::EncodingKey::from_secret(b"jwt-test-secret");
+        let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap();
+        let res = http.get(url("/api/v1/search?q=daft%20punk")).bearer_auth(token).send().await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(usage().await["tenants"], json!({ "acme": 3, "globex": 2 }));
+    }
+}
+
+/// The gRPC server over mTLS, with [`mtls::GrpcClientCertLayer`] selecting tenants.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use spotify_search::grpc::{self, SpotifySearchService};
+    use spotify_search::mtls::GrpcClientCertLayer;
+    use spotify_search::proto::{SearchTracksRequest, SearchTracksResponse};
+    use spotify_search::spotify::DynSpotifyApi;
+    use spotify_search::tenants::TenantSpotifyApi;
+    use spotify_search::usage::Usage;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::codec::ProstCodec;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity, Server};
+
+    use super::common::{config, fake_spotify, search_page, serve_search, spotify_client};
+    use super::{dns, server_tls, temp_dir, tenant, Ca};
+
+    /// Search once over mTLS, presenting `identity` if any.
+    async fn search(port: u16, ca: &Ca, identity: Option<Identity>) -> Result<SearchTracksResponse, tonic::Status> {
+        let mut tls = ClientTlsConfig::new()
+            .domain_name("localhost")
+            .ca_certificate(Certificate::from_pem(ca.cert.pem()));
+        if let Some(identity) = identity {
+            tls = tls.identity(identity);
+        }
+        let endpoint = Endpoint::from_shared(format!("https://localhost:{}", port)).unwrap().tls_config(tls).unwrap();
+        let channel = endpoint.connect().await.map_err(|e| tonic::Status::unavailable(e.to_string()))?;
+        let mut client = tonic::client::Grpc::new(channel);
+        client.ready().await.map_err(|e| tonic::Status::unavailable(e.to_string()))?;
+        let request = SearchTracksRequest {
+            query: "daft punk".into(),
+            ..Default::default()
+        };
+        let path = PathAndQuery::from_static("/spotify.SpotifySearch/SearchTracks");
+        client
+            .unary(tonic::Request::new(request), path, ProstCodec::default())
+            .await
+            .map(tonic::Response::into_inner)
+    }
+
+    #[tokio::test]
+    async fn client_cert_selects_the_tenant_and_others_are_refused() {
+        let spotify = fake_spotify().await;
+        serve_search(&spotify, search_page(0, 1, 1)).await;
+        let config = config(&spotify);
+        let usage = Arc::new(Usage::new());
+        let acme = tenant("acme", &["client.acme.test"]);
+        let acme_client = spotify_client(&config)
+            .tenant("acme")
+            .audit(usage.clone())
+            .build()
+            .expect("tenant Spotify client");
+        let default: DynSpotifyApi = Arc::new(spotify_client(&config).audit(usage.clone()).build().unwrap());
+        let backend = TenantSpotifyApi::new(default, HashMap::from([("acme".into(), Arc::new(acme_client) as _)]));
+
+        let ca = Ca::new("Test CA");
+        let tls = server_tls(&ca, &temp_dir("grpc"));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let port = listener.local_addr().expect("local addr").port();
+        tokio::spawn(
+            Server::builder()
+                .tls_config(grpc::tls_config(&tls).unwrap())
+                .unwrap()
+                .layer(GrpcClientCertLayer(vec![acme].into()))
+                .add_service(SpotifySearchService::new(Arc::new(backend)).into_router())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let (cert, key) = ca.sign("acme-search", vec![dns("client.acme.test")]);
+        let res = search(port, &ca, Some(Identity::from_pem(cert.pem(), key.serialize_pem()))).await.unwrap();
+        assert_eq!(res.tracks.len(), 1);
+        assert_eq!(usage.report().tenants, [("acme".to_string(), 2)].into());
+
+        // A certificate the CA didn't sign, or none, doesn't get through the handshake.
+        let rogue = Ca::new("Rogue CA");
+        let (cert, key) = rogue.sign("acme-search", vec![dns("client.acme.test")]);
+        let identity = Identity::from_pem(cert.pem(), key.serialize_pem());
+        assert!(search(port, &ca, Some(identity)).await.is_err());
+        assert!(search(port, &ca, None).await.is_err());
+        assert_eq!(usage.report().calls, 2);
+    }
+}
@@ -0,0 +1,195 @@
+//! Contract tests for `proto/spotify.proto`, the API the Go consumer is generated from.
+//!
+//! The field numbers and types of every message are compared with
+//! `tests/fixtures/proto/schema.txt`, and responses fetched with the generated client are
+//! compared with messages encoded by an earlier build. A renumbered or retyped field, a
+//! renamed metadata key or a reordered embedding fails here before it reaches the Go side.
+//! After an intended change, regenerate the fixtures and review their diff:
+//!
+//! ```text
+//! UPDATE_GOLDEN=1 cargo test --features grpc-client --test proto_contract
+//! ```
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FileDescriptorSet};
+use spotify_search::grpc::SpotifySearchService;
+use spotify_search::proto::{
+    GetTracksWithFeaturesRequest, GetTracksWithFeaturesResponse, SearchTracksRequest, SearchTracksResponse,
+    TrackWithFeatures, FILE_DESCRIPTOR_SET,
+};
+use spotify_search::spotify::MockSpotifyApi;
+use spotify_search::SpotifySearchClient;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+
+/// Metadata keys the Go importer reads, on every track of the bundled catalog.
+const METADATA_KEYS: [&str; 5] = ["album", "artist", "spotify_id", "spotify_url", "title"];
+
+/// The golden fixture `name`, first rewritten with `actual` when `UPDATE_GOLDEN` is set.
+fn golden(name: &str, actual: &[u8]) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/proto").join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).expect("write golden fixture");
+    }
+    std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {} (set UPDATE_GOLDEN=1 to create it)", path.display(), e))
+}
+
+/// A generated client for the service, serving the bundled mock catalog.
+async fn client() -> SpotifySearchClient<Channel> {
+    let service = SpotifySearchService::new(Arc::new(MockSpotifyApi::bundled()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(
+        Server::builder()
+            .add_service(service.into_router())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    SpotifySearchClient::connect(format!("http://{}", addr)).await.expect("connect")
+}
+
+/// One line per field (`number name type`) under each message, then the RPCs.
+fn render_schema(set: &FileDescriptorSet) -> String {
+    let mut out = String::new();
+    for file in &set.file {
+        out.push_str(&format!("file {} package {}\n", file.name(), file.package()));
+        for message in &file.message_type {
+            render_message(&mut out, message, "");
+        }
+        for service in &file.service {
+            out.push_str(&format!("service {}\n", service.name()));
+            for rpc in &service.method {
+                let stream = |streaming: bool| if streaming { "stream " } else { "" };
+                out.push_str(&format!(
+                    "  rpc {}({}{}) returns ({}{})\n",
+                    rpc.name(),
+                    stream(rpc.client_streaming()),
+                    rpc.input_type(),
+                    stream(rpc.server_streaming()),
+                    rpc.output_type()
+                ));
+            }
+        }
+    }
+    out
+}
+
+fn render_message(out: &mut String, message: &DescriptorProto, parent: &str) {
+    let name = format!("{}{}", parent, message.name());
+    out.push_str(&format!("message {}\n", name));
+    for field in &message.field {
+        let map = message.nested_type.iter().find(|nested| {
+            nested.options.as_ref().is_some_and(|o| o.map_entry())
+                && field.type_name().ends_with(&format!(".{}", nested.name()))
+        });
+        let ty = match map {
+            Some(entry) => format!("map<{}, {}>", field_type(&entry.field[0]), field_type(&entry.field[1])),
+            None => {
+                let label = match field.label() {
+                    Label::Repeated => "repeated ",
+                    _ if field.proto3_optional() => "optional ",
+                    _ => "",
+                };
+                format!("{}{}", label, field_type(field))
+            }
+        };
+        out.push_str(&format!("  {} {} {}\n", field.number(), field.name(), ty));
+    }
+    for nested in &message.nested_type {
+        if !nested.options.as_ref().is_some_and(|o| o.map_entry()) {
+            render_message(out, nested, &format!("{}.", name));
+        }
+    }
+}
+
+fn field_type(field: &prost_types::FieldDescriptorProto) -> String {
+    match field.r#type() {
+        Type::Message | Type::Enum => field.type_name().to_string(),
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
+
+/// The embedding Spotify's audio features map to, in the documented order.
+fn expected_embedding(track: &TrackWithFeatures) -> Vec<f32> {
+    let Some(af) = &track.audio_features else {
+        return Vec::new();
+    };
+    vec![
+        af.acousticness,
+        af.danceability,
+        af.energy,
+        af.instrumentalness,
+        (af.key + 1) as f32 / 12.0,
+        af.liveness,
+        ((af.loudness + 60.0) / 60.0).clamp(0.0, 1.0),
+        af.mode as f32,
+        af.speechiness,
+        (af.tempo / 250.0).clamp(0.0, 1.0),
+        (af.time_signature - 3) as f32 / 4.0,
+        af.valence,
+    ]
+}
+
+#[test]
+fn field_numbers_match_golden_schema() {
+    let set = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).expect("descriptor set");
+    let schema = render_schema(&set);
+    let golden = String::from_utf8(golden("schema.txt", schema.as_bytes())).unwrap();
+    assert_eq!(
+        schema, golden,
+        "proto schema changed; renumbering or retyping a field breaks the Go consumer"
+    );
+}
+
+#[tokio::test]
+async fn tracks_with_features_match_golden() {
+    let mut client = client().await;
+    let request = GetTracksWithFeaturesRequest {
+        // The last has no audio features.
+        track_ids: [1, 2, 12].iter().map(|n| format!("MockTrack{:013}", n)).collect(),
+        include_missing_embeddings: Some(true),
+    };
+    let res = client.get_tracks_with_features(request).await.unwrap().into_inner();
+    let golden = golden("get_tracks_with_features.binpb", &res.encode_to_vec());
+    assert_eq!(res, GetTracksWithFeaturesResponse::decode(golden.as_slice()).unwrap());
+
+    assert_eq!(res.tracks.len(), 3);
+    assert!(res.tracks[2].embedding.is_empty());
+    assert_eq!(res.tracks[2].missing_embedding_reason, "audio_features_unavailable");
+}
+
+#[tokio::test]
+async fn search_tracks_match_golden() {
+    let mut client = client().await;
+    let request = SearchTracksRequest {
+        query: "neon".into(),
+        limit: 5,
+        offset: 0,
+        include_features: true,
+    };
+    let res = client.search_tracks(request).await.unwrap().into_inner();
+    assert!(!res.tracks.is_empty());
+    let golden = golden("search_tracks.binpb", &res.encode_to_vec());
+    assert_eq!(res, SearchTracksResponse::decode(golden.as_slice()).unwrap());
+}
+
+#[tokio::test]
+async fn metadata_keys_and_embedding_order_are_stable() {
+    let mut client = client().await;
+    let request = GetTracksWithFeaturesRequest {
+        track_ids: (1..=12).map(|n| format!("MockTrack{:013}", n)).collect(),
+        include_missing_embeddings: Some(true),
+    };
+    let res = client.get_tracks_with_features(request).await.unwrap().into_inner();
+    assert_eq!(res.tracks.len(), 12);
+    for track in &res.tracks {
+        let mut keys: Vec<_> = track.metadata.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, METADATA_KEYS, "metadata keys of {}", track.id);
+        assert_eq!(track.metadata["spotify_id"], track.id);
+        assert_eq!(track.embedding, expected_embedding(track), "embedding of {}", track.id);
+    }
+}